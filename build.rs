@@ -0,0 +1,36 @@
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    let git_hash = command_output("git", &["rev-parse", "--short=12", "HEAD"])
+        .unwrap_or_else(|| "unknown".into());
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let rustc_version = command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".into());
+    // SOURCE_DATE_EPOCH keeps package builds reproducible
+    let build_epoch = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default()
+            .to_string()
+    });
+
+    println!("cargo:rustc-env=BOOTKIT_GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=BOOTKIT_RUSTC_VERSION={rustc_version}");
+    println!("cargo:rustc-env=BOOTKIT_BUILD_EPOCH={build_epoch}");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    // the migrations are embedded into the binary
+    println!("cargo:rerun-if-changed=db/migrations");
+}
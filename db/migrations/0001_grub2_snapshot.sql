@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS grub2_snapshot (
+    -- Auto incrementing snapshot id
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- /etc/default/grub config
+    grub_config TEXT NOT NULL,
+    -- selected kernel that's booted to, if it's actually specified
+    selected_kernel TEXT,
+    -- snapshot was applied to the system at least once, instead of only being saved
+    applied BOOLEAN DEFAULT 0 NOT NULL,
+    -- when snapshot was created
+    created DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    -- why the snapshot was taken, if not by applying a config
+    tag TEXT
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS selected_snapshot (
+    -- Id of selected grub2 snapshot, null if none is selected.
+    -- If none is selected, it implies that latest snapshot is being used.
+    grub2_snapshot_id INTEGER
+);
+
+-- The database always has a single value that defaults to null
+-- so it's fine to set it as such when the DB is defined. Databases from
+-- before the versioned migrations already have it.
+INSERT INTO selected_snapshot (grub2_snapshot_id)
+SELECT NULL WHERE NOT EXISTS (SELECT 1 FROM selected_snapshot);
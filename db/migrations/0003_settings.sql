@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS settings (
+    -- Name of the setting
+    key TEXT PRIMARY KEY NOT NULL,
+    -- Value of the setting
+    value TEXT NOT NULL
+);
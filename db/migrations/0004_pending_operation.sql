@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS pending_operation (
+    -- Auto incrementing operation id, operations are applied in id order
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- What the operation does, e.g. "save_config"
+    kind TEXT NOT NULL,
+    -- JSON data of the operation, as received from the client
+    data TEXT NOT NULL,
+    -- Error from the latest attempt to apply the operation
+    last_error TEXT,
+    -- when operation was queued
+    created DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS entry_override (
+    -- Full path of the boot entry, including the submenus
+    entry TEXT PRIMARY KEY NOT NULL,
+    -- Hide the entry from frontends, grub.cfg is not modified
+    hidden BOOLEAN DEFAULT FALSE NOT NULL,
+    -- Kind of the entry set by the user, null uses the detected kind
+    kind TEXT
+);
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS audit_log (
+    -- Auto incrementing entry id
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- What was done, e.g. "save_config"
+    action TEXT NOT NULL,
+    -- JSON list of the changed keys with their old and new values
+    changes TEXT NOT NULL,
+    -- JSON list of the commands the change ran, with their exit codes and durations
+    commands TEXT DEFAULT '[]' NOT NULL,
+    -- when the change was applied
+    created DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    -- Unique bus name of the D-Bus client that made the change, NULL for changes
+    -- the daemon made by itself
+    sender TEXT,
+    -- User id of the client
+    uid INTEGER,
+    -- D-Bus method that made the change, e.g. "SaveConfig"
+    method TEXT
+);
@@ -0,0 +1,2 @@
+-- Pinned snapshots are never removed by the retention policy
+ALTER TABLE grub2_snapshot ADD COLUMN pinned BOOLEAN DEFAULT 0 NOT NULL;
@@ -0,0 +1,7 @@
+CREATE TABLE snapshot_label (
+    -- Id of the labeled grub2 snapshot
+    snapshot_id INTEGER NOT NULL,
+    -- User defined label, e.g. "stable"
+    label TEXT NOT NULL,
+    PRIMARY KEY (snapshot_id, label)
+);
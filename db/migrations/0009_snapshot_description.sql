@@ -0,0 +1,2 @@
+-- Free text of why the snapshot was saved, editable later
+ALTER TABLE grub2_snapshot ADD COLUMN description TEXT;
@@ -0,0 +1,14 @@
+-- Default entry in grubenv when the snapshot was taken, even if it's not a known entry
+ALTER TABLE grub2_snapshot ADD COLUMN saved_entry TEXT;
+-- Older snapshots didn't capture the files besides the grub file, the current
+-- files are kept when they're selected
+ALTER TABLE grub2_snapshot ADD COLUMN files_captured BOOLEAN DEFAULT 0 NOT NULL;
+
+CREATE TABLE IF NOT EXISTS snapshot_file (
+    -- Id of the grub2 snapshot the file was captured with
+    snapshot_id INTEGER NOT NULL,
+    -- Name of the drop-in config in /etc/default/grub.d
+    name TEXT NOT NULL,
+    contents TEXT NOT NULL,
+    PRIMARY KEY (snapshot_id, name)
+);
@@ -0,0 +1,2 @@
+-- SHA-256 of the grub.cfg the daemon generated when it last applied the snapshot
+ALTER TABLE grub2_snapshot ADD COLUMN grub_cfg_sha256 TEXT;
@@ -7,7 +7,7 @@ cd ..
 if [[ ! -e tmp/bootkit.db ]]; then
     mkdir -p tmp
     touch tmp/bootkit.db
-    for db_file in $(find db -type f -name '*.sql'); do
+    for db_file in $(find db/migrations -type f -name '*.sql' | sort); do
         sqlite3 tmp/bootkit.db < "$db_file"
     done
 fi
@@ -0,0 +1,562 @@
+//! grub2, configured through /etc/default/grub and booting from the generated grub.cfg
+
+use std::{
+    collections::BTreeMap,
+    fs::{
+        copy, create_dir_all, metadata, read, read_dir, read_to_string, remove_file, rename, File,
+        Metadata,
+    },
+    io::{self, Write},
+    os::unix::fs::{fchown, MetadataExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use sha2::{Digest, Sha256};
+use xattr::FileExt;
+
+use crate::{
+    bootloader::{Backend, BootEntries, BootEntry, Bootloader},
+    config::{tools::GrubTool, Paths},
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::{GrubBootEntries, GrubBootEntry, GrubFile},
+    services::job::{run_cancellable_command, run_command, run_command_unchecked, ExecutedCommand},
+};
+
+/// Appended to the path of a file for the new file that replaces it once complete
+const NEW_FILE_SUFFIX: &str = ".bootkit-new";
+
+/// Replace `path` with a file of `contents`. It's written next to `path` first
+/// so a crash can't leave it truncated, and keeps the mode and owner of `path`.
+fn replace_file(path: &Path, contents: &str) -> DResult<()> {
+    replace_file_checked(path, contents, || Ok(()))
+}
+
+/// Same as `replace_file`, but `path` is only replaced if `check` passes once
+/// the new file is written, right before the rename
+fn replace_file_checked(
+    path: &Path,
+    contents: &str,
+    check: impl FnOnce() -> DResult<()>,
+) -> DResult<()> {
+    replace_file_like(path, contents, path, check)
+}
+
+/// Same as `replace_file_checked`, but the file gets the mode, owner and SELinux
+/// context of the file `like`, if it exists
+fn replace_file_like(
+    path: &Path,
+    contents: &str,
+    like: &Path,
+    check: impl FnOnce() -> DResult<()>,
+) -> DResult<()> {
+    let previous = match metadata(like) {
+        Ok(previous) => Some(previous),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).ctx(dctx!(), format!("Cannot read {like:?}")),
+    };
+
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(NEW_FILE_SUFFIX);
+    let new_path = PathBuf::from(new_path);
+    let like = previous.as_ref().map(|previous| (like, previous));
+    let replaced = write_new_file(&new_path, contents, like)
+        .and_then(|()| check())
+        .and_then(|()| rename(&new_path, path).ctx(dctx!(), format!("Cannot replace {path:?}")));
+    if replaced.is_err() {
+        if let Err(err) = remove_file(&new_path) {
+            log::debug!("Cannot remove {new_path:?}: {err}");
+        }
+        return replaced;
+    }
+
+    // the rename is only durable once the directory is synced as well
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .ctx(dctx!(), format!("Cannot sync {dir:?}"))?;
+    }
+    Ok(())
+}
+
+/// Label `file` with the SELinux context of `like`. A new file gets the default
+/// context of its directory, which the policy may not allow bootloader tools to read.
+fn copy_security_context(like: &Path, file: &File) {
+    let context = match xattr::get(like, SELINUX_XATTR) {
+        Ok(Some(context)) => context,
+        // not labeled, e.g. SELinux is not used
+        Ok(None) => return,
+        Err(err) => {
+            log::debug!("Cannot read the SELinux context of {like:?}: {err}");
+            return;
+        }
+    };
+    if let Err(err) = file.set_xattr(SELINUX_XATTR, &context) {
+        log::warn!("Cannot copy the SELinux context of {like:?}: {err}");
+    }
+}
+
+/// Write and sync `path` with the permissions, owner and SELinux context of
+/// the file `like` with its metadata
+fn write_new_file(path: &Path, contents: &str, like: Option<(&Path, &Metadata)>) -> DResult<()> {
+    let mut file = File::create(path).ctx(dctx!(), format!("Cannot create {path:?}"))?;
+    file.write_all(contents.as_bytes())
+        .ctx(dctx!(), format!("Cannot write {path:?}"))?;
+    if let Some((like_path, like)) = like {
+        file.set_permissions(like.permissions())
+            .ctx(dctx!(), format!("Cannot set the mode of {path:?}"))?;
+        fchown(&file, Some(like.uid()), Some(like.gid()))
+            .ctx(dctx!(), format!("Cannot set the owner of {path:?}"))?;
+        copy_security_context(like_path, &file);
+    }
+    file.sync_all()
+        .ctx(dctx!(), format!("Cannot sync {path:?}"))
+}
+
+/// Extended attribute with the SELinux context of a file
+const SELINUX_XATTR: &str = "security.selinux";
+
+impl From<&GrubBootEntry> for BootEntry {
+    fn from(entry: &GrubBootEntry) -> Self {
+        Self {
+            id: entry.full_path(),
+            title: entry.entry().into(),
+            kernel_version: entry.kernel_version().map(str::to_string),
+            flavor: entry.flavor().map(str::to_string),
+            microcode: entry.has_microcode(),
+            kind: entry.kind(),
+        }
+    }
+}
+
+pub struct Grub2 {
+    paths: Paths,
+}
+
+impl Grub2 {
+    pub fn new(paths: Paths) -> Self {
+        Self { paths }
+    }
+
+    /// Name of the grub `tool` installed in the system
+    pub fn tool(&self, tool: GrubTool) -> &str {
+        self.paths.grub_tools().program(tool)
+    }
+
+    /// Write /etc/default/grub, through symlinks and bind mounts instead of
+    /// replacing them. `read` is the file as it was read for the change, the
+    /// write is refused if another program changed it since.
+    pub fn write_config(&self, contents: &str, read: &str) -> DResult<()> {
+        // WARN: this triggers FileChanged signal
+        let grub_path = &self.paths.grub_file_target();
+        self.require_unchanged(read)?;
+        if self.backup_config().is_err() {
+            log::warn!("Writing {grub_path:?} without backing it up");
+        }
+        replace_file_checked(grub_path, contents, || self.require_unchanged(read))?;
+        log::debug!("Grub2 config was written to {grub_path:?}");
+        Ok(())
+    }
+
+    /// Put back the grub file written by a failed change. The newest backup
+    /// already holds `contents`, so the backups aren't rotated again.
+    pub fn restore_config(&self, contents: &str) -> DResult<()> {
+        let grub_path = &self.paths.grub_file_target();
+        replace_file(grub_path, contents)?;
+        log::debug!("Grub2 config was restored to {grub_path:?}");
+        Ok(())
+    }
+
+    /// Refuse to overwrite the grub file if it isn't `read` anymore. Programs
+    /// like yast2-bootloader and editors don't take the lock of the change turn.
+    fn require_unchanged(&self, read: &str) -> DResult<()> {
+        let grub_path = self.paths.grub_file_target();
+        let contents = match read_to_string(&grub_path) {
+            Ok(contents) => contents,
+            // a missing file is written like it was before
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err).ctx(dctx!(), format!("Cannot read {grub_path:?}")),
+        };
+        if Sha256::digest(contents) == Sha256::digest(read) {
+            return Ok(());
+        }
+
+        Err(DError::conflict(
+            dctx!(),
+            format!("{grub_path:?} was changed by another program while the change was applied, it was not overwritten"),
+        ))
+    }
+
+    /// Copy the grub file to its newest backup, rotating the older backups
+    fn backup_config(&self) -> DResult<()> {
+        let depth = self.paths.grub_backups();
+        let grub_path = self.paths.grub_file_target();
+        if depth == 0 || !grub_path.exists() {
+            return Ok(());
+        }
+
+        for n in (1..depth).rev() {
+            let older = self.paths.grub_file_backup(n);
+            match rename(&older, self.paths.grub_file_backup(n + 1)) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err).ctx(dctx!(), format!("Cannot rotate {older:?}")),
+            }
+        }
+
+        let backup = self.paths.grub_file_backup(1);
+        copy(&grub_path, &backup).ctx(dctx!(), format!("Cannot back up {grub_path:?}"))?;
+        log::debug!("Grub2 config was backed up to {backup:?}");
+        Ok(())
+    }
+
+    fn mkconfig_command(&self, output: &Path) -> Command {
+        let mut mkconfig = self.paths.grub_command(GrubTool::Mkconfig);
+        mkconfig.arg("-o").arg(output);
+        mkconfig
+    }
+
+    /// Generated grub.cfg that is verified before it replaces the current one
+    fn new_cfg(&self) -> PathBuf {
+        let mut new_cfg = self.paths.grub_cfg().as_os_str().to_owned();
+        new_cfg.push(NEW_FILE_SUFFIX);
+        new_cfg.into()
+    }
+
+    /// Generate grub.cfg next to the current one, killed if the background job
+    /// it runs in is cancelled. The current grub.cfg is not touched.
+    pub fn mkconfig_new(&self, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        // a file left by an earlier run must not be taken for the new one
+        self.discard_new_cfg();
+        let output = self.paths.in_target(&self.new_cfg());
+        run_cancellable_command(self.mkconfig_command(&output), commands)
+    }
+
+    /// Problem with the grub.cfg generated by `mkconfig_new`, `None` if
+    /// grub2-script-check accepts it and it has boot entries
+    pub fn verify_new_cfg(&self, commands: &mut Vec<ExecutedCommand>) -> DResult<Option<String>> {
+        let new_cfg = self.new_cfg();
+        let contents = match read_to_string(&new_cfg) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Some(format!(
+                    "{} failed to generate grub.cfg",
+                    self.tool(GrubTool::Mkconfig)
+                )))
+            }
+            Err(err) => return Err(err).ctx(dctx!(), format!("Cannot read {new_cfg:?}")),
+        };
+
+        let mut script_check = self.paths.grub_command(GrubTool::ScriptCheck);
+        script_check.arg(self.paths.in_target(&new_cfg));
+        run_command_unchecked(script_check, commands)?;
+        if !commands.last().is_some_and(ExecutedCommand::succeeded) {
+            return Ok(Some(format!(
+                "{} found errors in the generated grub.cfg",
+                self.tool(GrubTool::ScriptCheck)
+            )));
+        }
+
+        match GrubBootEntries::parse_cfg(&self.paths, &contents) {
+            Ok(entries) if entries.is_empty() => {
+                Ok(Some("The generated grub.cfg has no boot entries".into()))
+            }
+            Ok(_) => Ok(None),
+            Err(err) => Ok(Some(format!(
+                "Cannot read the boot entries of the generated grub.cfg: {}",
+                err.error()
+            ))),
+        }
+    }
+
+    /// Replace grub.cfg with the verified one generated by `mkconfig_new`
+    pub fn install_new_cfg(&self) -> DResult<()> {
+        let grub_cfg = self.paths.grub_cfg();
+        rename(self.new_cfg(), grub_cfg).ctx(dctx!(), format!("Cannot replace {grub_cfg:?}"))
+    }
+
+    /// Remove the grub.cfg generated by `mkconfig_new` that won't be installed
+    pub fn discard_new_cfg(&self) {
+        let new_cfg = self.new_cfg();
+        match remove_file(&new_cfg) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!("Cannot remove {new_cfg:?}: {err}"),
+        }
+    }
+
+    /// Generate, verify and install a new grub.cfg
+    pub fn replace_cfg(&self, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        self.mkconfig_new(commands)?;
+        if let Some(problem) = self.verify_new_cfg(commands)? {
+            return Err(DError::generic(dctx!(), problem));
+        }
+        self.install_new_cfg()
+    }
+
+    /// Hex SHA-256 of grub.cfg, `None` if it doesn't exist
+    pub fn grub_cfg_sha256(&self) -> DResult<Option<String>> {
+        let grub_cfg = self.paths.grub_cfg();
+        match read(grub_cfg) {
+            Ok(contents) => Ok(Some(format!("{:x}", Sha256::digest(contents)))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).ctx(dctx!(), format!("Cannot read {grub_cfg:?}")),
+        }
+    }
+
+    /// Contents of the `*.cfg` drop-in configs by file name
+    pub fn read_dropins(&self) -> DResult<BTreeMap<String, String>> {
+        let dir = self.paths.grub_dropins();
+        let mut dropins = BTreeMap::new();
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(dropins),
+            Err(err) => return Err(err).ctx(dctx!(), format!("Cannot read {dir:?}")),
+        };
+
+        for entry in entries {
+            let path = entry.ctx(dctx!(), format!("Cannot read {dir:?}"))?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            // grub2-mkconfig only sources the .cfg files
+            if !is_dropin_name(name) || !path.is_file() {
+                continue;
+            }
+            let contents = read_to_string(&path).ctx(dctx!(), format!("Cannot read {path:?}"))?;
+            dropins.insert(name.to_string(), contents);
+        }
+        Ok(dropins)
+    }
+
+    /// Replace the drop-in configs with `dropins`, the other `*.cfg` files are removed
+    pub fn write_dropins(&self, dropins: &BTreeMap<String, String>) -> DResult<()> {
+        let dir = self.paths.grub_dropins();
+        if let Some(name) = dropins.keys().find(|name| !is_dropin_name(name)) {
+            return Err(DError::invalid_data(
+                dctx!(),
+                format!("'{name}' is not a name of a grub drop-in config"),
+            ));
+        }
+
+        for name in self.read_dropins()?.keys() {
+            if !dropins.contains_key(name) {
+                let path = dir.join(name);
+                remove_file(&path).ctx(dctx!(), format!("Cannot remove {path:?}"))?;
+            }
+        }
+        if !dropins.is_empty() {
+            create_dir_all(dir).ctx(dctx!(), format!("Cannot create {dir:?}"))?;
+        }
+        for (name, contents) in dropins {
+            let path = dir.join(name);
+            if path.exists() {
+                replace_file(&path, contents)?;
+            } else {
+                // new drop-ins are labeled like the grub file they override
+                replace_file_like(&path, contents, &self.paths.grub_file_target(), || Ok(()))?;
+            }
+        }
+
+        log::debug!(
+            "{} grub drop-in configs were written to {dir:?}",
+            dropins.len()
+        );
+        Ok(())
+    }
+
+    /// Run grub2-editenv against the grubenv file with the given arguments
+    pub fn edit_env(&self, args: &[&str], commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        let mut edit_env = self.paths.grub_command(GrubTool::Editenv);
+        edit_env
+            .arg(self.paths.in_target(self.paths.grub_env()))
+            .args(args);
+        run_command(edit_env, commands)
+    }
+}
+
+/// File names of the drop-in configs grub2-mkconfig reads, names with paths are refused
+fn is_dropin_name(name: &str) -> bool {
+    name.len() > ".cfg".len() && name.ends_with(".cfg") && !name.contains('/')
+}
+
+impl Bootloader for Grub2 {
+    fn backend(&self) -> Backend {
+        Backend::Grub2
+    }
+
+    fn tools(&self) -> Vec<&str> {
+        self.paths.grub_tools().programs()
+    }
+
+    fn read_config(&self) -> DResult<BTreeMap<String, String>> {
+        let grub = GrubFile::from_file(self.paths.grub_file())?;
+        Ok(grub
+            .keyvalues()
+            .values()
+            .map(|value| (value.key.clone(), value.value.clone()))
+            .collect())
+    }
+
+    fn list_entries(&self) -> DResult<BootEntries> {
+        let grub_entries = GrubBootEntries::new(&self.paths)?;
+        let entries = grub_entries.entries();
+        let position = |selected: Option<&GrubBootEntry>| {
+            let full_path = selected?.full_path();
+            entries
+                .iter()
+                .position(|entry| entry.full_path() == full_path)
+        };
+
+        Ok(BootEntries {
+            entries: entries.iter().map(BootEntry::from).collect(),
+            selected: grub_entries.selected().map(str::to_string),
+            default: position(grub_entries.selected_entry()),
+            next: position(grub_entries.next_entry()),
+            // GRUB_TIMEOUT is part of the config
+            timeout: None,
+        })
+    }
+
+    fn set_default(&self, entry: &str, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        let mut set_default = self.paths.grub_command(GrubTool::SetDefault);
+        set_default.arg(entry);
+        run_command(set_default, commands)
+    }
+
+    fn boot_once(&self, entry: &str, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        let mut reboot = self.paths.grub_command(GrubTool::Reboot);
+        reboot.arg(entry);
+        run_command(reboot, commands)
+    }
+
+    fn apply(
+        &self,
+        changes: &BTreeMap<String, String>,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        let grub_path = self.paths.grub_file();
+        let previous =
+            read_to_string(grub_path).ctx(dctx!(), format!("Cannot read {grub_path:?}"))?;
+        let mut grub = GrubFile::new(&previous)?;
+        for (key, value) in changes {
+            grub.set_key_value(key, value);
+        }
+        self.write_config(&grub.as_string(), &previous)?;
+        if let Err(err) = self.replace_cfg(commands) {
+            log::warn!("grub.cfg was not replaced, restoring the previous config");
+            self.discard_new_cfg();
+            self.restore_config(&previous)?;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, remove_dir_all, set_permissions, write, Permissions},
+        os::unix::fs::PermissionsExt,
+    };
+
+    use super::*;
+    use crate::errors::DErrorType;
+
+    #[test]
+    fn test_replace_file() {
+        let dir = std::env::temp_dir().join(format!("bootkit-replace-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("grub");
+        write(&path, "GRUB_TIMEOUT=8\nGRUB_DEFAULT=saved\n").unwrap();
+        set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        let previous = metadata(&path).unwrap();
+
+        replace_file(&path, "GRUB_TIMEOUT=3\n").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "GRUB_TIMEOUT=3\n");
+        let replaced = metadata(&path).unwrap();
+        assert_ne!(replaced.ino(), previous.ino());
+        assert_eq!(replaced.mode() & 0o7777, 0o600);
+        assert_eq!(
+            (replaced.uid(), replaced.gid()),
+            (previous.uid(), previous.gid())
+        );
+        // the new file was renamed over the old one
+        let mut new_path = path.clone().into_os_string();
+        new_path.push(NEW_FILE_SUFFIX);
+        assert!(!Path::new(&new_path).exists());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_rotation() {
+        let root = std::env::temp_dir().join(format!("bootkit-backups-{}", std::process::id()));
+        let mut paths = Paths::with_root(&root);
+        paths.set_grub_backups(3);
+        create_dir_all(paths.grub_file().parent().unwrap()).unwrap();
+        write(paths.grub_file(), "GRUB_TIMEOUT=0\n").unwrap();
+
+        let grub = Grub2::new(paths.clone());
+        for timeout in 1..=5 {
+            let previous = format!("GRUB_TIMEOUT={}\n", timeout - 1);
+            grub.write_config(&format!("GRUB_TIMEOUT={timeout}\n"), &previous)
+                .unwrap();
+        }
+        assert_eq!(
+            read_to_string(paths.grub_file()).unwrap(),
+            "GRUB_TIMEOUT=5\n"
+        );
+        // the newest backup is the config before the last write
+        for (n, timeout) in [(1, 4), (2, 3), (3, 2)] {
+            assert_eq!(
+                read_to_string(paths.grub_file_backup(n)).unwrap(),
+                format!("GRUB_TIMEOUT={timeout}\n")
+            );
+        }
+        assert!(!paths.grub_file_backup(4).exists());
+
+        // putting back the config of a failed write keeps the backups as they are
+        grub.restore_config("GRUB_TIMEOUT=4\n").unwrap();
+        assert_eq!(
+            read_to_string(paths.grub_file()).unwrap(),
+            "GRUB_TIMEOUT=4\n"
+        );
+        for (n, timeout) in [(1, 4), (2, 3), (3, 2)] {
+            assert_eq!(
+                read_to_string(paths.grub_file_backup(n)).unwrap(),
+                format!("GRUB_TIMEOUT={timeout}\n")
+            );
+        }
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_write_config_refuses_changed_file() {
+        let root = std::env::temp_dir().join(format!("bootkit-changed-{}", std::process::id()));
+        let paths = Paths::with_root(&root);
+        create_dir_all(paths.grub_file().parent().unwrap()).unwrap();
+        write(paths.grub_file(), "GRUB_TIMEOUT=0\n").unwrap();
+
+        let grub = Grub2::new(paths.clone());
+        // edited by another program since it was read
+        let written = grub.write_config("GRUB_TIMEOUT=3\n", "GRUB_TIMEOUT=8\n");
+        assert!(matches!(
+            written.unwrap_err().error(),
+            DErrorType::Conflict(_)
+        ));
+        assert_eq!(
+            read_to_string(paths.grub_file()).unwrap(),
+            "GRUB_TIMEOUT=0\n"
+        );
+
+        grub.write_config("GRUB_TIMEOUT=3\n", "GRUB_TIMEOUT=0\n")
+            .unwrap();
+        assert_eq!(
+            read_to_string(paths.grub_file()).unwrap(),
+            "GRUB_TIMEOUT=3\n"
+        );
+        remove_dir_all(&root).unwrap();
+    }
+}
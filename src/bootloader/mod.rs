@@ -0,0 +1,140 @@
+//! Bootloaders that can be installed on the managed system.
+//!
+//! Each supported bootloader implements [`Bootloader`], so the services read and
+//! change the boot configuration the same way regardless of the backend.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::Serialize;
+
+use crate::{config::Paths, errors::DResult, grub2::EntryKind, services::job::ExecutedCommand};
+
+pub mod grub2;
+pub mod systemd_boot;
+
+/// Bootloader that is in use on the managed system
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    Grub2,
+    SystemdBoot,
+    /// No supported bootloader was found
+    Unknown,
+}
+
+impl Backend {
+    /// Detect the bootloader from the files installed on the system.
+    ///
+    /// systemd-boot takes precedence since grub files are usually left behind
+    /// when switching from grub to systemd-boot.
+    pub fn detect(paths: &Paths) -> Self {
+        if paths
+            .systemd_boot_markers()
+            .iter()
+            .any(|path| path.exists())
+        {
+            Self::SystemdBoot
+        } else if paths.grub_cfg().exists() {
+            Self::Grub2
+        } else {
+            Self::Unknown
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Grub2 => "grub2",
+            Self::SystemdBoot => "systemd-boot",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Boot entry of any bootloader
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BootEntry {
+    /// Stable id of the entry, the full path of grub entries or the file name of
+    /// systemd-boot entries
+    pub id: String,
+    /// Name shown in the boot menu
+    pub title: String,
+    pub kernel_version: Option<String>,
+    pub flavor: Option<String>,
+    /// Entry loads CPU microcode from a separate initrd
+    pub microcode: bool,
+    pub kind: EntryKind,
+}
+
+/// Boot entries of a bootloader, in menu order
+#[derive(Debug, Clone, Default)]
+pub struct BootEntries {
+    pub entries: Vec<BootEntry>,
+    /// Explicitly selected default entry, as clients select it: the title of grub
+    /// entries or the id of systemd-boot entries
+    pub selected: Option<String>,
+    /// Index of the default entry, the first entry is booted without one
+    pub default: Option<usize>,
+    /// Index of the entry for the next boot only
+    pub next: Option<usize>,
+    /// Menu timeout if the bootloader keeps it outside of its config
+    pub timeout: Option<String>,
+}
+
+impl BootEntries {
+    pub fn default_entry(&self) -> Option<&BootEntry> {
+        self.entries.get(self.default?)
+    }
+
+    pub fn next_entry(&self) -> Option<&BootEntry> {
+        self.entries.get(self.next?)
+    }
+
+    pub fn entry(&self, id: &str) -> Option<&BootEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+}
+
+/// Reads and changes the configuration of a bootloader
+pub trait Bootloader: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    /// Programs needed to change the configuration
+    fn tools(&self) -> Vec<&str>;
+
+    /// Settings of the bootloader by key
+    fn read_config(&self) -> DResult<BTreeMap<String, String>>;
+
+    fn list_entries(&self) -> DResult<BootEntries>;
+
+    /// Make the entry with the id `entry` the default
+    fn set_default(&self, entry: &str, commands: &mut Vec<ExecutedCommand>) -> DResult<()>;
+
+    /// Boot the entry with the id `entry` on the next boot only, without changing
+    /// the default
+    fn boot_once(&self, entry: &str, commands: &mut Vec<ExecutedCommand>) -> DResult<()>;
+
+    /// Change the given settings and update the files the bootloader boots from
+    fn apply(
+        &self,
+        changes: &BTreeMap<String, String>,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()>;
+}
+
+impl Backend {
+    /// Implementation of the backend for the system of `paths`, `None` if no
+    /// supported bootloader was found
+    pub fn bootloader(&self, paths: &Paths) -> Option<Box<dyn Bootloader>> {
+        match self {
+            Self::Grub2 => Some(Box::new(grub2::Grub2::new(paths.clone()))),
+            Self::SystemdBoot => Some(Box::new(systemd_boot::SystemdBoot::new(paths.clone()))),
+            Self::Unknown => None,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! systemd-boot, changed with bootctl and booting the entries in `loader/entries`
+
+use std::collections::BTreeMap;
+
+use crate::{
+    bootloader::{Backend, BootEntries, BootEntry, Bootloader},
+    config::Paths,
+    dctx,
+    errors::{DError, DResult},
+    services::job::{run_command, ExecutedCommand},
+    systemd_boot::{LoaderEntry, SystemdBootEntries},
+};
+
+impl From<&LoaderEntry> for BootEntry {
+    fn from(entry: &LoaderEntry) -> Self {
+        Self {
+            id: entry.id.clone(),
+            title: entry.display_title().into(),
+            kernel_version: entry.kernel_version().map(str::to_string),
+            flavor: entry.flavor().map(str::to_string),
+            microcode: entry.has_microcode(),
+            kind: entry.kind(),
+        }
+    }
+}
+
+pub struct SystemdBoot {
+    paths: Paths,
+}
+
+impl SystemdBoot {
+    pub fn new(paths: Paths) -> Self {
+        Self { paths }
+    }
+
+    /// bootctl stores the settings in EFI variables, which only exist for the host
+    fn require_host(&self) -> DResult<()> {
+        if self.paths.is_host() {
+            return Ok(());
+        }
+
+        Err(DError::generic(
+            dctx!(),
+            "systemd-boot settings are stored in EFI variables and can only be changed on the host",
+        ))
+    }
+
+    fn bootctl(&self, args: &[&str], commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        self.require_host()?;
+        let mut bootctl = self.paths.command("bootctl");
+        bootctl.args(args);
+        run_command(bootctl, commands)
+    }
+}
+
+impl Bootloader for SystemdBoot {
+    fn backend(&self) -> Backend {
+        Backend::SystemdBoot
+    }
+
+    fn tools(&self) -> Vec<&str> {
+        vec!["bootctl"]
+    }
+
+    fn read_config(&self) -> DResult<BTreeMap<String, String>> {
+        let sd_entries = SystemdBootEntries::new(&self.paths)?;
+        let mut config = sd_entries.config().clone();
+        if let Some(timeout) = sd_entries.timeout() {
+            config.insert("timeout".into(), timeout.into());
+        }
+        if let Some(entry) = sd_entries.selected_entry() {
+            config.insert("default".into(), entry.id.clone());
+        }
+        Ok(config)
+    }
+
+    fn list_entries(&self) -> DResult<BootEntries> {
+        let sd_entries = SystemdBootEntries::new(&self.paths)?;
+        let entries = sd_entries.entries();
+        let position = |selected: Option<&LoaderEntry>| {
+            let id = &selected?.id;
+            entries.iter().position(|entry| &entry.id == id)
+        };
+
+        Ok(BootEntries {
+            entries: entries.iter().map(BootEntry::from).collect(),
+            selected: sd_entries.selected_entry().map(|entry| entry.id.clone()),
+            default: position(sd_entries.selected_entry()),
+            next: position(sd_entries.next_entry()),
+            timeout: sd_entries.timeout().map(str::to_string),
+        })
+    }
+
+    fn set_default(&self, entry: &str, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        self.bootctl(&["set-default", entry], commands)
+    }
+
+    /// Stored in the LoaderEntryOneShot EFI variable, which systemd-boot removes
+    /// when it boots the entry
+    fn boot_once(&self, entry: &str, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        self.bootctl(&["set-oneshot", entry], commands)
+    }
+
+    /// Only the settings bootctl can change are supported, loader.conf is not written
+    fn apply(
+        &self,
+        changes: &BTreeMap<String, String>,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        if let Some(key) = changes
+            .keys()
+            .find(|key| !matches!(key.as_str(), "default" | "timeout"))
+        {
+            return Err(DError::generic(
+                dctx!(),
+                format!("systemd-boot setting '{key}' cannot be changed with bootctl"),
+            ));
+        }
+        let timeout = changes.get("timeout");
+        if let Some(timeout) = timeout {
+            if timeout.parse::<u32>().is_err()
+                && !matches!(timeout.as_str(), "menu-force" | "menu-hidden")
+            {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Invalid timeout '{timeout}', use seconds, menu-force or menu-hidden"),
+                ));
+            }
+        }
+
+        if let Some(entry) = changes.get("default") {
+            self.set_default(entry, commands)?;
+        }
+        if let Some(timeout) = timeout {
+            self.bootctl(&["set-timeout", timeout], commands)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_refuses_unsupported_changes() {
+        let systemd_boot = SystemdBoot::new(Paths::host());
+        let mut commands = Vec::new();
+        for (key, value) in [("timeout", "soon"), ("editor", "no")] {
+            let changes = BTreeMap::from([(key.to_string(), value.to_string())]);
+            assert!(systemd_boot.apply(&changes, &mut commands).is_err());
+        }
+        assert!(commands.is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+//! Config files that are symlinks or bind mounts, common with configuration
+//! management tools and containers.
+//!
+//! Such files must be written in place, replacing them with a regular file would
+//! silently detach them from where they're managed.
+
+use std::{
+    fs::{canonicalize, symlink_metadata},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::config::mounts::mount_source;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum FileLink {
+    /// File is a symlink, `target` is the fully resolved file
+    Symlink { target: PathBuf },
+    /// File is bind mounted from `source` of the `device` filesystem
+    BindMount { source: PathBuf, device: String },
+}
+
+impl FileLink {
+    /// Check if `path` is a symlink or a bind mount
+    pub fn detect(path: &Path) -> Option<Self> {
+        let metadata = symlink_metadata(path).ok()?;
+        if metadata.file_type().is_symlink() {
+            let target = canonicalize(path).unwrap_or_else(|_| path.into());
+            return Some(Self::Symlink { target });
+        }
+
+        let (source, device) = mount_source(path)?;
+        Some(Self::BindMount { source, device })
+    }
+
+    pub fn description(&self, path: &Path) -> String {
+        match self {
+            Self::Symlink { target } => format!("{path:?} is a symlink to {target:?}"),
+            Self::BindMount { source, device } => {
+                format!("{path:?} is bind mounted from {source:?} on {device}")
+            }
+        }
+    }
+}
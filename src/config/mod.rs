@@ -1,7 +1,24 @@
-use std::str::FromStr;
+use std::{path::PathBuf, str::FromStr};
 
 use clap::Parser;
 
+#[cfg(feature = "rescue")]
+use crate::rescue::RescueCommand;
+use crate::{
+    config::tools::GrubToolOverride,
+    db::StorageKind,
+    dbus::namespace::{DEFAULT_BUS_NAME, DEFAULT_OBJECT_PATH},
+    events::DEFAULT_DEBOUNCE_MS,
+};
+
+mod link;
+pub mod mounts;
+mod paths;
+pub mod tools;
+
+pub use link::FileLink;
+pub use paths::Paths;
+
 /// Log levels that are idententical to `tracing::Level` but includes
 /// `FullTrace` to separate traces that have library traces
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
@@ -99,6 +116,71 @@ pub struct ConfigArgs {
     /// Print pretty logging output that includes colors and timestamps
     #[arg(short, long, default_value_t = false)]
     pub pretty: bool,
+
+    /// Install the default D-Bus policy and polkit action files if they are missing
+    #[arg(long, default_value_t = false)]
+    pub install_policy: bool,
+
+    /// Group whose members may change the bootloader, besides root and wheel.
+    /// polkit still has to authorize each change, and the group needs a D-Bus
+    /// policy of its own that lets it send to the daemon, see the README.
+    #[arg(long)]
+    pub admin_group: Option<String>,
+
+    /// Well-known bus name to own. Other names need their own D-Bus policy
+    #[arg(long, default_value = DEFAULT_BUS_NAME)]
+    pub bus_name: String,
+
+    /// Object path the host system is served at
+    #[arg(long, default_value = DEFAULT_OBJECT_PATH)]
+    pub object_path: String,
+
+    /// Where snapshots and settings are stored: "sqlite", "files" for a JSON file
+    /// on systems without SQLite, or "memory" to lose them on exit
+    #[arg(long, default_value_t = StorageKind::default())]
+    pub storage: StorageKind,
+
+    /// SQLite database of the host system, the JSON file of the "files" storage
+    /// is kept next to it. Created with its directory if it doesn't exist
+    #[arg(long, env = "BOOTKIT_DB_PATH", default_value = DATABASE_PATH)]
+    pub db_path: PathBuf,
+
+    /// Milliseconds to wait for more file events before signaling a change, so
+    /// that one save is signaled once. 0 signals the events as they are read
+    #[arg(long, default_value_t = DEFAULT_DEBOUNCE_MS)]
+    pub debounce_ms: u64,
+
+    /// Most config snapshots kept per system, besides the retention policy.
+    /// The oldest snapshots that aren't selected or pinned are removed first
+    #[arg(long)]
+    pub max_snapshots: Option<usize>,
+
+    /// Copies of the grub file kept as grub.bootkit-bak.N before it's
+    /// overwritten, 1 being the newest. 0 keeps none
+    #[arg(long, default_value_t = DEFAULT_GRUB_BACKUPS)]
+    pub grub_backups: usize,
+
+    /// Seconds grub2-mkconfig and the other commands may run before they're
+    /// killed, e.g. when os-prober hangs on a dead mount. 0 lets them run forever
+    #[arg(long, default_value_t = DEFAULT_COMMAND_TIMEOUT_SECS)]
+    pub command_timeout_secs: u64,
+
+    /// grub tool to run instead of the detected one, as TOOL=PROGRAM where TOOL
+    /// is mkconfig, set-default, reboot, editenv or script-check. The grub2-*
+    /// and grub-* names are detected without it. Can be given several times
+    #[arg(long = "grub-tool", value_name = "TOOL=PROGRAM")]
+    pub grub_tools: Vec<GrubToolOverride>,
+
+    /// Directory of grub.cfg and grubenv, as seen from inside the managed
+    /// system. /boot/grub2, /boot/grub and the EFI/ vendor directories of the
+    /// ESP are looked up without it
+    #[arg(long)]
+    pub grub_dir: Option<PathBuf>,
+
+    /// Run a rescue command instead of the daemon
+    #[cfg(feature = "rescue")]
+    #[command(subcommand)]
+    pub rescue: Option<RescueCommand>,
 }
 
 #[cfg(not(feature = "dev"))]
@@ -111,15 +193,96 @@ pub const GRUB_ROOT_PATH: &str = "/etc/default";
 #[cfg(feature = "dev")]
 pub const GRUB_ROOT_PATH: &str = "tmp";
 
+/// Drop-in configs that grub2-mkconfig reads after the grub file
+#[cfg(not(feature = "dev"))]
+pub const GRUB_DROPIN_PATH: &str = "/etc/default/grub.d";
+#[cfg(feature = "dev")]
+pub const GRUB_DROPIN_PATH: &str = "tmp/grub.d";
+
+/// Directories of grub.cfg and grubenv, in order of preference. openSUSE and
+/// Fedora use /boot/grub2, Debian and Arch /boot/grub. The vendor directories
+/// in EFI/ of the ESP are looked up after them.
+#[cfg(not(feature = "dev"))]
+pub const GRUB_DIR_PATHS: &[&str] = &["/boot/grub2", "/boot/grub"];
+#[cfg(feature = "dev")]
+pub const GRUB_DIR_PATHS: &[&str] = &["tmp"];
+
+pub const GRUB_CFG_NAME: &str = "grub.cfg";
+pub const GRUB_ENV_NAME: &str = "grubenv";
+
+/// Seconds grub2-mkconfig and the other commands may run before they're killed
+pub const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 300;
+
+/// Copies of the grub file kept before it's overwritten
+pub const DEFAULT_GRUB_BACKUPS: usize = 3;
+
+/// Packaged default grub configs, in order of preference
+#[cfg(not(feature = "dev"))]
+pub const GRUB_TEMPLATE_PATHS: &[&str] = &[
+    "/etc/default/grub.rpmnew",
+    "/usr/etc/default/grub",
+    "/usr/share/grub2/default/grub",
+];
+#[cfg(feature = "dev")]
+pub const GRUB_TEMPLATE_PATHS: &[&str] = &["tmp/grub.rpmnew", "test_data/grub_full"];
+
+/// Boot Loader Specification entries that grub reads with the `blscfg` command
+#[cfg(not(feature = "dev"))]
+pub const BLS_ENTRIES_PATH: &str = "/boot/loader/entries";
+#[cfg(feature = "dev")]
+pub const BLS_ENTRIES_PATH: &str = "tmp/loader/entries";
+
+/// Directories the system bus reads policy files from, missing files are installed to the first one
+#[cfg(not(feature = "dev"))]
+pub const DBUS_POLICY_DIRS: &[&str] = &["/etc/dbus-1/system.d", "/usr/share/dbus-1/system.d"];
+#[cfg(feature = "dev")]
+pub const DBUS_POLICY_DIRS: &[&str] = &["tmp/dbus-1/system.d"];
+
+/// Directories polkit reads action files from, missing files are installed to the first one
+#[cfg(not(feature = "dev"))]
+pub const POLKIT_ACTION_DIRS: &[&str] = &["/usr/share/polkit-1/actions"];
+#[cfg(feature = "dev")]
+pub const POLKIT_ACTION_DIRS: &[&str] = &["tmp/polkit-1/actions"];
+
+/// Files that exist when systemd-boot is installed
+#[cfg(not(feature = "dev"))]
+pub const SYSTEMD_BOOT_PATHS: &[&str] = &[
+    "/boot/efi/EFI/systemd",
+    "/boot/efi/loader/entries.srel",
+    "/efi/EFI/systemd",
+    "/boot/EFI/systemd",
+];
+#[cfg(feature = "dev")]
+pub const SYSTEMD_BOOT_PATHS: &[&str] = &["tmp/efi/EFI/systemd"];
+
+/// Exists when the system was booted with UEFI, always the one of the host
+#[cfg(not(feature = "dev"))]
+pub const EFI_FIRMWARE_PATH: &str = "/sys/firmware/efi";
+#[cfg(feature = "dev")]
+pub const EFI_FIRMWARE_PATH: &str = "tmp/sys/firmware/efi";
+
+/// Mount points of the EFI system partition, in order of preference
+#[cfg(not(feature = "dev"))]
+pub const ESP_PATHS: &[&str] = &["/boot/efi", "/efi", "/boot"];
+#[cfg(feature = "dev")]
+pub const ESP_PATHS: &[&str] = &["tmp/efi"];
+
+/// udev database with the properties of the block devices, like their partition types
+#[cfg(not(feature = "dev"))]
+pub const UDEV_DATA_PATH: &str = "/run/udev/data";
+#[cfg(feature = "dev")]
+pub const UDEV_DATA_PATH: &str = "tmp/udev/data";
+
+/// Device nodes, including the /dev/disk/by-* links referenced by root= style parameters
 #[cfg(not(feature = "dev"))]
-pub const GRUB_ENV_PATH: &str = "/boot/grub2/grubenv";
+pub const DEV_PATH: &str = "/dev";
 #[cfg(feature = "dev")]
-pub const GRUB_ENV_PATH: &str = "tmp/grubenv";
+pub const DEV_PATH: &str = "tmp/dev";
 
 #[cfg(not(feature = "dev"))]
-pub const GRUB_CFG_PATH: &str = "/boot/grub2/grub.cfg";
+pub const ZYPP_HISTORY_PATH: &str = "/var/log/zypp/history";
 #[cfg(feature = "dev")]
-pub const GRUB_CFG_PATH: &str = "tmp/grub.cfg";
+pub const ZYPP_HISTORY_PATH: &str = "tmp/zypp-history";
 
 #[cfg(not(feature = "dev"))]
 pub const DATABASE_PATH: &str = "/var/lib/bootkit/bootkit.db";
@@ -0,0 +1,215 @@
+//! Mounts of the system, read from /proc/self/mountinfo
+//!
+//! The EFI system partition (ESP) and the XBOOTLDR partition of the Boot Loader
+//! Specification are found by their GPT partition type, which udev records for
+//! each block device.
+
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use crate::config::UDEV_DATA_PATH;
+
+/// GPT partition type of the EFI system partition
+const ESP_TYPE_GUID: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+/// GPT partition type of the extended boot loader partition
+const XBOOTLDR_TYPE_GUID: &str = "bc13c2ff-59e6-4262-a352-b275fd6f7172";
+
+/// Decode the octal escapes, like `\040` for space, used in mountinfo paths
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = field;
+    while let Some(idx) = rest.find('\\') {
+        unescaped.push_str(&rest[..idx]);
+        let code = rest
+            .get(idx + 1..idx + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+        if let Some(code) = code {
+            unescaped.push(code as char);
+            rest = &rest[idx + 4..];
+        } else {
+            unescaped.push('\\');
+            rest = &rest[idx + 1..];
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Mount of a filesystem on a mount point
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    /// Path in the filesystem that is mounted, `/` unless it's a bind mount
+    pub root: PathBuf,
+    /// Mounted device, like `/dev/vda2`
+    pub device: String,
+    /// Filesystem type, like `btrfs` or `vfat`
+    pub fs_type: String,
+}
+
+/// Line of mountinfo
+struct MountLine {
+    mount_point: PathBuf,
+    /// `major:minor` of the mounted device
+    dev_number: String,
+    mount: Mount,
+}
+
+/// Parse a line of mountinfo, see proc_pid_mountinfo(5) for the format
+fn parse_line(line: &str) -> Option<MountLine> {
+    let (mount, filesystem) = line.split_once(" - ")?;
+    let fields: Vec<_> = mount.split(' ').collect();
+    let dev_number = fields.get(2)?;
+    let root = fields.get(3)?;
+    let mount_point = fields.get(4)?;
+
+    let mut filesystem = filesystem.split(' ');
+    let fs_type = filesystem.next()?;
+    let device = filesystem.next()?;
+    Some(MountLine {
+        mount_point: PathBuf::from(unescape(mount_point)),
+        dev_number: dev_number.to_string(),
+        mount: Mount {
+            root: PathBuf::from(unescape(root)),
+            device: unescape(device),
+            fs_type: unescape(fs_type),
+        },
+    })
+}
+
+/// The mount at `path` in `mountinfo`
+fn parse_mount(mountinfo: &str, path: &Path) -> Option<Mount> {
+    // the last mount on a mount point hides the earlier ones
+    mountinfo
+        .lines()
+        .rev()
+        .filter_map(parse_line)
+        .find(|line| line.mount_point == path)
+        .map(|line| line.mount)
+}
+
+/// The mount at `path`, if `path` is a mount point
+pub fn find_mount(path: &Path) -> Option<Mount> {
+    let mountinfo = read_to_string("/proc/self/mountinfo").ok()?;
+    parse_mount(&mountinfo, path)
+}
+
+/// Mount points of the ESP and the XBOOTLDR partition
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BootPartitions {
+    pub esp: Option<PathBuf>,
+    pub xbootldr: Option<PathBuf>,
+}
+
+/// GPT partition type of the block device `dev_number`, as recorded by udev
+fn partition_type(dev_number: &str) -> Option<String> {
+    let data = read_to_string(Path::new(UDEV_DATA_PATH).join(format!("b{dev_number}"))).ok()?;
+    data.lines()
+        .find_map(|line| line.strip_prefix("E:ID_PART_ENTRY_TYPE="))
+        .map(str::to_lowercase)
+}
+
+/// The boot partitions mounted below `root` in `mountinfo`, the first mount of
+/// each type is used
+fn parse_boot_partitions(
+    mountinfo: &str,
+    root: &Path,
+    partition_type: impl Fn(&str) -> Option<String>,
+) -> BootPartitions {
+    let mut partitions = BootPartitions::default();
+    for line in mountinfo.lines().filter_map(parse_line) {
+        if !line.mount_point.starts_with(root) {
+            continue;
+        }
+        let found = match partition_type(&line.dev_number).as_deref() {
+            Some(ESP_TYPE_GUID) => &mut partitions.esp,
+            Some(XBOOTLDR_TYPE_GUID) => &mut partitions.xbootldr,
+            _ => continue,
+        };
+        found.get_or_insert(line.mount_point);
+    }
+    partitions
+}
+
+/// The ESP and XBOOTLDR partitions mounted in the system at `root`
+pub fn boot_partitions(root: &Path) -> BootPartitions {
+    read_to_string("/proc/self/mountinfo")
+        .map(|mountinfo| parse_boot_partitions(&mountinfo, root, partition_type))
+        .unwrap_or_default()
+}
+
+/// Source path and device of the mount at `path`, if `path` is a mount point
+pub fn mount_source(path: &Path) -> Option<(PathBuf, String)> {
+    find_mount(path).map(|mount| (mount.root, mount.device))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 253:0 / / rw,relatime shared:1 - btrfs /dev/vda2 rw,subvol=/@
+45 22 253:0 /@/etc/default/grub.managed /etc/default/grub rw,relatime shared:1 - btrfs /dev/vda2 rw
+46 22 0:40 /my\\040configs /etc/my\\040dir rw,relatime - tmpfs tmpfs rw
+";
+
+    #[test]
+    fn test_parse_mount() {
+        assert_eq!(
+            parse_mount(MOUNTINFO, Path::new("/etc/default/grub")),
+            Some(Mount {
+                root: PathBuf::from("/@/etc/default/grub.managed"),
+                device: "/dev/vda2".into(),
+                fs_type: "btrfs".into(),
+            })
+        );
+        assert_eq!(
+            parse_mount(MOUNTINFO, Path::new("/etc/my dir")),
+            Some(Mount {
+                root: PathBuf::from("/my configs"),
+                device: "tmpfs".into(),
+                fs_type: "tmpfs".into(),
+            })
+        );
+        assert_eq!(parse_mount(MOUNTINFO, Path::new("/etc/default")), None);
+    }
+
+    #[test]
+    fn test_parse_boot_partitions() {
+        let mountinfo = "\
+22 1 253:2 / / rw,relatime shared:1 - btrfs /dev/vda2 rw,subvol=/@
+30 22 253:1 / /efi rw,relatime shared:2 - vfat /dev/vda1 rw
+31 22 253:3 / /boot rw,relatime shared:3 - vfat /dev/vda3 rw
+40 22 253:4 / /mnt/target/boot/efi rw,relatime shared:4 - vfat /dev/vdb1 rw
+";
+        let partition_type = |dev_number: &str| match dev_number {
+            "253:1" | "253:4" => Some(ESP_TYPE_GUID.to_string()),
+            "253:3" => Some(XBOOTLDR_TYPE_GUID.to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            parse_boot_partitions(mountinfo, Path::new("/"), partition_type),
+            BootPartitions {
+                esp: Some(PathBuf::from("/efi")),
+                xbootldr: Some(PathBuf::from("/boot")),
+            }
+        );
+        assert_eq!(
+            parse_boot_partitions(mountinfo, Path::new("/mnt/target"), partition_type),
+            BootPartitions {
+                esp: Some(PathBuf::from("/mnt/target/boot/efi")),
+                xbootldr: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("/no/escapes"), "/no/escapes");
+        assert_eq!(unescape("/a\\040b\\011c"), "/a b\tc");
+        assert_eq!(unescape("/broken\\9"), "/broken\\9");
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    env,
+    fs::{canonicalize, read_dir},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use nix::unistd::{access, AccessFlags};
+
+use crate::config::{
+    mounts::boot_partitions,
+    tools::{GrubTool, GrubToolOverride, GrubTools},
+    FileLink, BLS_ENTRIES_PATH, DATABASE_PATH, DEFAULT_GRUB_BACKUPS, ESP_PATHS, GRUB_CFG_NAME,
+    GRUB_DIR_PATHS, GRUB_DROPIN_PATH, GRUB_ENV_NAME, GRUB_FILE_PATH, GRUB_ROOT_PATH,
+    GRUB_TEMPLATE_PATHS, SYSTEMD_BOOT_PATHS, ZYPP_HISTORY_PATH,
+};
+
+/// Program search path when PATH isn't set
+const DEFAULT_SEARCH_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// File locations of a single managed system.
+///
+/// The host system uses the configured paths as is, while other target roots
+/// (like image build chroots) have the same layout below their root directory.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    root: PathBuf,
+    grub_file: PathBuf,
+    grub_root: PathBuf,
+    grub_dropins: PathBuf,
+    grub_env: PathBuf,
+    grub_cfg: PathBuf,
+    grub_templates: Vec<PathBuf>,
+    bls_entries: PathBuf,
+    systemd_boot_markers: Vec<PathBuf>,
+    esp_mount_points: Vec<PathBuf>,
+    /// Mount point of the XBOOTLDR partition, if it has one
+    xbootldr: Option<PathBuf>,
+    zypp_history: PathBuf,
+    database: PathBuf,
+    /// Copies of the grub file kept before it's overwritten
+    grub_backups: usize,
+    /// Names of the grub tools installed in the system
+    grub_tools: GrubTools,
+}
+
+impl Paths {
+    /// Paths of the system the daemon is running on
+    pub fn host() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            grub_file: GRUB_FILE_PATH.into(),
+            grub_root: GRUB_ROOT_PATH.into(),
+            grub_dropins: GRUB_DROPIN_PATH.into(),
+            grub_env: Path::new(GRUB_DIR_PATHS[0]).join(GRUB_ENV_NAME),
+            grub_cfg: Path::new(GRUB_DIR_PATHS[0]).join(GRUB_CFG_NAME),
+            grub_templates: GRUB_TEMPLATE_PATHS.iter().map(PathBuf::from).collect(),
+            bls_entries: BLS_ENTRIES_PATH.into(),
+            systemd_boot_markers: SYSTEMD_BOOT_PATHS.iter().map(PathBuf::from).collect(),
+            esp_mount_points: ESP_PATHS.iter().map(PathBuf::from).collect(),
+            xbootldr: None,
+            zypp_history: ZYPP_HISTORY_PATH.into(),
+            database: DATABASE_PATH.into(),
+            grub_backups: DEFAULT_GRUB_BACKUPS,
+            grub_tools: GrubTools::default(),
+        }
+    }
+
+    /// Paths of a system mounted in `root`
+    pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref();
+        let join = |path: &str| root.join(path.trim_start_matches('/'));
+        Self {
+            root: root.into(),
+            grub_file: join(GRUB_FILE_PATH),
+            grub_root: join(GRUB_ROOT_PATH),
+            grub_dropins: join(GRUB_DROPIN_PATH),
+            grub_env: join(GRUB_DIR_PATHS[0]).join(GRUB_ENV_NAME),
+            grub_cfg: join(GRUB_DIR_PATHS[0]).join(GRUB_CFG_NAME),
+            grub_templates: GRUB_TEMPLATE_PATHS.iter().map(|path| join(path)).collect(),
+            bls_entries: join(BLS_ENTRIES_PATH),
+            systemd_boot_markers: SYSTEMD_BOOT_PATHS.iter().map(|path| join(path)).collect(),
+            esp_mount_points: ESP_PATHS.iter().map(|path| join(path)).collect(),
+            xbootldr: None,
+            zypp_history: join(ZYPP_HISTORY_PATH),
+            database: join(DATABASE_PATH),
+            grub_backups: DEFAULT_GRUB_BACKUPS,
+            grub_tools: GrubTools::default(),
+        }
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    pub fn grub_file(&self) -> &Path {
+        &self.grub_file
+    }
+
+    /// Symlink or bind mount the grub file is managed through, if any
+    pub fn grub_file_link(&self) -> Option<FileLink> {
+        FileLink::detect(&self.grub_file)
+    }
+
+    /// The real file behind the grub file, replaced instead of the link to keep it intact
+    pub fn grub_file_target(&self) -> PathBuf {
+        canonicalize(&self.grub_file).unwrap_or_else(|_| self.grub_file.clone())
+    }
+
+    /// Package manager variant of the grub file, like `grub.rpmnew`
+    pub fn grub_file_variant(&self, extension: &str) -> PathBuf {
+        let mut path = self.grub_file.clone().into_os_string();
+        path.push(format!(".{extension}"));
+        path.into()
+    }
+
+    /// Lock file that is locked while the daemon changes the grub file, like
+    /// `/etc/default/grub.lock`. Tools that want to edit the file safely lock it
+    /// too, with `flock /etc/default/grub.lock <command>`.
+    pub fn grub_file_lock(&self) -> PathBuf {
+        let mut path = self.grub_file.clone().into_os_string();
+        path.push(".lock");
+        path.into()
+    }
+
+    /// Backup `n` of the grub file, 1 being the newest
+    pub fn grub_file_backup(&self, n: usize) -> PathBuf {
+        let mut path = self.grub_file.clone().into_os_string();
+        path.push(format!(".bootkit-bak.{n}"));
+        path.into()
+    }
+
+    pub fn grub_backups(&self) -> usize {
+        self.grub_backups
+    }
+
+    /// Keep another number of grub file backups than the default
+    pub fn set_grub_backups(&mut self, grub_backups: usize) {
+        self.grub_backups = grub_backups;
+    }
+
+    pub fn grub_tools(&self) -> &GrubTools {
+        &self.grub_tools
+    }
+
+    /// Use the grub tool names installed in the system, unless overridden
+    pub fn detect_grub_tools(&mut self, overrides: &[GrubToolOverride]) {
+        self.grub_tools = GrubTools::detect(self, overrides);
+        log::debug!(
+            "Using grub tools {} for {:?}",
+            self.grub_tools.programs().join(", "),
+            self.root
+        );
+    }
+
+    pub fn grub_root(&self) -> &Path {
+        &self.grub_root
+    }
+
+    /// Directory of the `*.cfg` drop-in configs of grub2-mkconfig
+    pub fn grub_dropins(&self) -> &Path {
+        &self.grub_dropins
+    }
+
+    pub fn grub_env(&self) -> &Path {
+        &self.grub_env
+    }
+
+    pub fn grub_cfg(&self) -> &Path {
+        &self.grub_cfg
+    }
+
+    /// Directory grub.cfg and grubenv are in
+    pub fn grub_dir(&self) -> &Path {
+        self.grub_cfg.parent().unwrap_or(&self.root)
+    }
+
+    /// Read grub.cfg and grubenv from `grub_dir` inside the system, or from
+    /// the first known directory that has grub.cfg. The EFI/ directories are
+    /// looked up on the partitions found by `detect_boot_partitions`.
+    pub fn detect_grub_dir(&mut self, grub_dir: Option<&Path>) {
+        let grub_dir = match grub_dir {
+            Some(grub_dir) => self
+                .root
+                .join(grub_dir.strip_prefix("/").unwrap_or(grub_dir)),
+            None => self.find_grub_dir(),
+        };
+        log::debug!("Using grub directory {grub_dir:?}");
+        self.grub_cfg = grub_dir.join(GRUB_CFG_NAME);
+        self.grub_env = grub_dir.join(GRUB_ENV_NAME);
+    }
+
+    /// The first grub directory with grub.cfg, the first one that exists when
+    /// grub.cfg hasn't been generated yet
+    fn find_grub_dir(&self) -> PathBuf {
+        let mut boot_dirs: Vec<PathBuf> = GRUB_DIR_PATHS
+            .iter()
+            .map(|path| self.root.join(path.trim_start_matches('/')))
+            .collect();
+        // grub2 or grub on an XBOOTLDR partition mounted outside of /boot
+        if let Some(xbootldr) = &self.xbootldr {
+            let names: Vec<_> = GRUB_DIR_PATHS
+                .iter()
+                .filter_map(|path| Path::new(path).file_name())
+                .collect();
+            boot_dirs.extend(names.into_iter().map(|name| xbootldr.join(name)));
+        }
+        // vendor directories, like EFI/fedora, sorted for a stable choice
+        let efi_dirs = self.esp_mount_points.iter().flat_map(|mount_point| {
+            let mut dirs: Vec<PathBuf> = read_dir(mount_point.join("EFI"))
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect();
+            dirs.sort();
+            dirs
+        });
+
+        let candidates: Vec<PathBuf> = boot_dirs.iter().cloned().chain(efi_dirs).collect();
+        candidates
+            .iter()
+            .find(|dir| dir.join(GRUB_CFG_NAME).is_file())
+            .or_else(|| boot_dirs.iter().find(|dir| dir.is_dir()))
+            .unwrap_or(&boot_dirs[0])
+            .clone()
+    }
+
+    /// Packaged default grub configs, in order of preference
+    pub fn grub_templates(&self) -> &[PathBuf] {
+        &self.grub_templates
+    }
+
+    /// Directory of the BLS entries used by grub.cfg files with `blscfg`
+    pub fn bls_entries(&self) -> &Path {
+        &self.bls_entries
+    }
+
+    /// Files that exist when systemd-boot is installed
+    pub fn systemd_boot_markers(&self) -> &[PathBuf] {
+        &self.systemd_boot_markers
+    }
+
+    /// Mount points the EFI system partition may be mounted on
+    pub fn esp_mount_points(&self) -> &[PathBuf] {
+        &self.esp_mount_points
+    }
+
+    /// Prefer the ESP and XBOOTLDR partitions mounted in the system, found by
+    /// their partition types, over the usual mount points
+    pub fn detect_boot_partitions(&mut self) {
+        let partitions = boot_partitions(&self.root);
+        if let Some(esp) = partitions.esp {
+            log::debug!("EFI system partition is mounted on {esp:?}");
+            self.esp_mount_points
+                .retain(|mount_point| *mount_point != esp);
+            self.esp_mount_points.insert(0, esp);
+        }
+        if let Some(xbootldr) = &partitions.xbootldr {
+            log::debug!("XBOOTLDR partition is mounted on {xbootldr:?}");
+            // both are FAT, the XBOOTLDR partition on /boot is not the ESP
+            self.esp_mount_points
+                .retain(|mount_point| mount_point != xbootldr);
+        }
+        self.xbootldr = partitions.xbootldr;
+    }
+
+    /// systemd-boot `loader` directories that exist on the ESP mount points
+    /// and the XBOOTLDR partition
+    pub fn loader_dirs(&self) -> Vec<PathBuf> {
+        self.esp_mount_points
+            .iter()
+            .chain(&self.xbootldr)
+            .map(|mount_point| mount_point.join("loader"))
+            .filter(|loader_dir| loader_dir.is_dir())
+            .collect()
+    }
+
+    /// Directory of the kernel images and initrds
+    pub fn boot_dir(&self) -> PathBuf {
+        self.root.join("boot")
+    }
+
+    /// Package transaction log of libzypp
+    pub fn zypp_history(&self) -> &Path {
+        &self.zypp_history
+    }
+
+    pub fn database(&self) -> &Path {
+        &self.database
+    }
+
+    /// Use another database than the default one
+    pub fn set_database<P: Into<PathBuf>>(&mut self, database: P) {
+        self.database = database.into();
+    }
+
+    /// File of the storage used instead of the database on systems without SQLite
+    pub fn storage_file(&self) -> PathBuf {
+        self.database.with_extension("json")
+    }
+
+    /// Can the grub file and grub.cfg be written, e.g. /boot is not mounted read-only
+    pub fn is_boot_writable(&self) -> bool {
+        [&self.grub_file_target(), &self.grub_cfg]
+            .iter()
+            .filter_map(|path| path.parent())
+            .all(|dir| access(dir, AccessFlags::W_OK).is_ok())
+    }
+
+    /// Find a file referenced in grub.cfg, like a kernel or an initrd.
+    ///
+    /// grub paths are relative to the partition grub reads them from, so `/boot`
+    /// is left out when it's a separate partition.
+    pub fn boot_file(&self, grub_path: &str) -> Option<PathBuf> {
+        let relative = grub_path.trim_start_matches('/');
+        [
+            self.root.join(relative),
+            self.root.join("boot").join(relative),
+        ]
+        .into_iter()
+        .find(|path| path.is_file())
+    }
+
+    /// Path of `path` as seen from inside the target system
+    pub fn in_target(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => Path::new("/").join(relative),
+            Err(_) => path.into(),
+        }
+    }
+
+    /// Find the executable of `program` from the PATH directories of the target system
+    pub fn find_program(&self, program: &str) -> Option<PathBuf> {
+        let search_path = env::var("PATH").unwrap_or_else(|_| DEFAULT_SEARCH_PATH.into());
+        search_path
+            .split(':')
+            .map(|dir| self.root.join(dir.trim_start_matches('/')).join(program))
+            .find(|path| {
+                path.metadata()
+                    .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            })
+    }
+
+    /// Create a command that runs `program` inside the target system.
+    ///
+    /// Path arguments given to the command should be the ones seen from inside
+    /// the target, e.g. `Paths::in_target(paths.grub_cfg())` instead of `Paths::grub_cfg()`.
+    pub fn command(&self, program: &str) -> Command {
+        if self.is_host() {
+            Command::new(program)
+        } else {
+            let mut command = Command::new("chroot");
+            command.arg(&self.root).arg(program);
+            command
+        }
+    }
+
+    /// Create a command that runs the grub `tool` inside the target system
+    pub fn grub_command(&self, tool: GrubTool) -> Command {
+        self.command(self.grub_tools.program(tool))
+    }
+}
+
+// the dev layout has no /boot
+#[cfg(all(test, not(feature = "dev")))]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use super::*;
+
+    #[test]
+    fn test_detect_grub_dir() {
+        let root = std::env::temp_dir().join(format!("bootkit-grub-dir-{}", std::process::id()));
+        create_dir_all(root.join("boot/grub")).unwrap();
+        create_dir_all(root.join("boot/efi/EFI/BOOT")).unwrap();
+        let mut paths = Paths::with_root(&root);
+
+        // Debian layout before grub.cfg is generated
+        paths.detect_grub_dir(None);
+        assert_eq!(paths.grub_cfg(), root.join("boot/grub/grub.cfg"));
+
+        create_dir_all(root.join("boot/efi/EFI/fedora")).unwrap();
+        write(root.join("boot/efi/EFI/fedora/grub.cfg"), "").unwrap();
+        paths.detect_grub_dir(None);
+        assert_eq!(paths.grub_env(), root.join("boot/efi/EFI/fedora/grubenv"));
+
+        write(root.join("boot/grub/grub.cfg"), "").unwrap();
+        paths.detect_grub_dir(None);
+        let detected = paths.grub_dir().to_path_buf();
+        paths.detect_grub_dir(Some(Path::new("/boot/grub2")));
+        let overridden = paths.in_target(paths.grub_cfg());
+        remove_dir_all(&root).unwrap();
+
+        assert_eq!(detected, root.join("boot/grub"));
+        assert_eq!(overridden, Path::new("/boot/grub2/grub.cfg"));
+    }
+}
@@ -0,0 +1,214 @@
+//! External programs the daemon runs, and the packages that provide them.
+//!
+//! Minimal containers and systems booting with another bootloader often don't
+//! have the grub2 tools, so they're looked up before running anything.
+//!
+//! openSUSE and Fedora name the grub tools grub2-*, while Debian, Ubuntu and
+//! Arch name them grub-*. The names installed on a system are detected once
+//! its paths are set up, and can be overridden with `--grub-tool`.
+
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::config::Paths;
+
+/// Programs the daemon runs besides the grub tools and the packages to install for them
+const TOOLS: &[(&str, &str)] = &[
+    ("lsinitrd", "dracut"),
+    ("bootctl", "systemd-boot"),
+    ("efibootmgr", "efibootmgr"),
+];
+
+/// Prefixes of the grub tool names and the packages that provide them, most common first
+const GRUB_TOOL_PREFIXES: &[(&str, &str)] = &[("grub2", "grub2"), ("grub", "grub")];
+
+/// grub tools the daemon runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrubTool {
+    Mkconfig,
+    SetDefault,
+    Reboot,
+    Editenv,
+    ScriptCheck,
+}
+
+impl GrubTool {
+    pub const ALL: [GrubTool; 5] = [
+        GrubTool::Mkconfig,
+        GrubTool::SetDefault,
+        GrubTool::Reboot,
+        GrubTool::Editenv,
+        GrubTool::ScriptCheck,
+    ];
+
+    /// Name of the tool without the grub prefix
+    fn suffix(self) -> &'static str {
+        match self {
+            GrubTool::Mkconfig => "mkconfig",
+            GrubTool::SetDefault => "set-default",
+            GrubTool::Reboot => "reboot",
+            GrubTool::Editenv => "editenv",
+            GrubTool::ScriptCheck => "script-check",
+        }
+    }
+}
+
+/// Program given with `--grub-tool` to run instead of the detected one, e.g.
+/// `mkconfig=/usr/local/sbin/grub-mkconfig`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrubToolOverride {
+    pub tool: GrubTool,
+    pub program: String,
+}
+
+impl FromStr for GrubToolOverride {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (name, program) = s
+            .split_once('=')
+            .filter(|(_, program)| !program.is_empty())
+            .ok_or_else(|| format!("Argument '{s}' is not in the form TOOL=PROGRAM."))?;
+        let tool = GrubTool::ALL
+            .into_iter()
+            .find(|tool| tool.suffix() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = GrubTool::ALL.iter().map(|tool| tool.suffix()).collect();
+                format!("Tool '{name}' is not any of '{}'.", names.join("', '"))
+            })?;
+        Ok(Self {
+            tool,
+            program: program.into(),
+        })
+    }
+}
+
+/// Names of the grub tools on a managed system
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrubTools {
+    /// Program of each tool, in the order of `GrubTool::ALL`
+    programs: [String; 5],
+    /// Package to install for the tools that are missing
+    package: &'static str,
+}
+
+impl Default for GrubTools {
+    fn default() -> Self {
+        Self::with_prefix(GRUB_TOOL_PREFIXES[0])
+    }
+}
+
+impl GrubTools {
+    fn with_prefix((prefix, package): (&str, &'static str)) -> Self {
+        Self {
+            programs: GrubTool::ALL.map(|tool| format!("{prefix}-{}", tool.suffix())),
+            package,
+        }
+    }
+
+    /// Tools installed on the system of `paths`. The grub2-* names are used
+    /// when neither naming is installed, so that the missing tools are reported
+    /// with the names openSUSE installs.
+    pub fn detect(paths: &Paths, overrides: &[GrubToolOverride]) -> Self {
+        let mut tools = GRUB_TOOL_PREFIXES
+            .iter()
+            .map(|prefix| Self::with_prefix(*prefix))
+            .find(|tools| {
+                paths
+                    .find_program(tools.program(GrubTool::Mkconfig))
+                    .is_some()
+            })
+            .unwrap_or_default();
+        for tool_override in overrides {
+            tools.programs[tool_override.tool as usize] = tool_override.program.clone();
+        }
+        tools
+    }
+
+    /// Program to run for `tool`
+    pub fn program(&self, tool: GrubTool) -> &str {
+        &self.programs[tool as usize]
+    }
+
+    /// Programs of all the tools
+    pub fn programs(&self) -> Vec<&str> {
+        self.programs.iter().map(String::as_str).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MissingTool {
+    pub program: String,
+    /// Package that provides the program
+    pub package: String,
+}
+
+impl MissingTool {
+    pub fn description(&self) -> String {
+        format!(
+            "{} is not installed, install the {} package",
+            self.program, self.package
+        )
+    }
+}
+
+/// The known tools that are not installed on the system of `paths`
+pub fn missing_tools(paths: &Paths) -> Vec<MissingTool> {
+    let grub_tools = paths.grub_tools();
+    let grub_tools = grub_tools
+        .programs()
+        .into_iter()
+        .map(|program| (program, grub_tools.package));
+    grub_tools
+        .chain(TOOLS.iter().copied())
+        .filter(|(program, _)| paths.find_program(program).is_none())
+        .map(|(program, package)| MissingTool {
+            program: program.to_string(),
+            package: package.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, remove_dir_all, set_permissions, write, Permissions},
+        os::unix::fs::PermissionsExt,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_grub_tool_override() {
+        let parsed: GrubToolOverride = "mkconfig=/usr/local/sbin/grub-mkconfig".parse().unwrap();
+        assert_eq!(parsed.tool, GrubTool::Mkconfig);
+        assert_eq!(parsed.program, "/usr/local/sbin/grub-mkconfig");
+        assert!("mkconfig".parse::<GrubToolOverride>().is_err());
+        assert!("mkconfig=".parse::<GrubToolOverride>().is_err());
+        assert!("install=grub-install".parse::<GrubToolOverride>().is_err());
+    }
+
+    #[test]
+    fn test_detect_grub_tools() {
+        let root = std::env::temp_dir().join(format!("bootkit-tools-{}", std::process::id()));
+        let bin = root.join("usr/bin");
+        create_dir_all(&bin).unwrap();
+        let paths = Paths::with_root(&root);
+
+        // Nothing installed, missing tools are reported with the grub2 names
+        let tools = GrubTools::detect(&paths, &[]);
+        assert_eq!(tools.program(GrubTool::Mkconfig), "grub2-mkconfig");
+
+        let mkconfig = bin.join("grub-mkconfig");
+        write(&mkconfig, "#!/bin/sh\n").unwrap();
+        set_permissions(&mkconfig, Permissions::from_mode(0o755)).unwrap();
+        let overrides = ["reboot=my-grub-reboot".parse().unwrap()];
+        let tools = GrubTools::detect(&paths, &overrides);
+        remove_dir_all(&root).unwrap();
+
+        assert_eq!(tools.program(GrubTool::Mkconfig), "grub-mkconfig");
+        assert_eq!(tools.program(GrubTool::SetDefault), "grub-set-default");
+        assert_eq!(tools.program(GrubTool::Reboot), "my-grub-reboot");
+        assert_eq!(tools.package, "grub");
+    }
+}
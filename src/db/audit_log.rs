@@ -0,0 +1,81 @@
+use std::future::Future;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+pub const SAVE_CONFIG: &str = "save_config";
+pub const RESET_TO_DISTRO_DEFAULTS: &str = "reset_to_distro_defaults";
+pub const MERGE_RPMNEW: &str = "merge_rpmnew";
+pub const MAKE_MENU_ACCESSIBLE: &str = "make_menu_accessible";
+pub const SELECT_SNAPSHOT: &str = "select_snapshot";
+pub const SET_DEFAULT_ENTRY: &str = "set_default_entry";
+pub const BOOT_ONCE: &str = "boot_once";
+pub const SET_LOADER_DEFAULT: &str = "set_loader_default";
+pub const SET_LOADER_TIMEOUT: &str = "set_loader_timeout";
+pub const SET_BOOT_ORDER: &str = "set_boot_order";
+pub const SET_BOOT_ENTRY_ACTIVE: &str = "set_boot_entry_active";
+pub const REMOVE_SNAPSHOT: &str = "remove_snapshot";
+pub const PRUNE_SNAPSHOTS: &str = "prune_snapshots";
+pub const SET_RETENTION_POLICY: &str = "set_retention_policy";
+pub const PREFER_FLAVOR: &str = "prefer_flavor";
+pub const SET_ENTRIES_HIDDEN: &str = "set_entries_hidden";
+pub const SET_ENTRIES_KIND: &str = "set_entries_kind";
+pub const SET_FREEZE: &str = "set_freeze";
+pub const UNFREEZE: &str = "unfreeze";
+pub const EXTERNAL_CHANGE: &str = "external_change";
+pub const SET_SNAPSHOT_PINNED: &str = "set_snapshot_pinned";
+pub const SET_SNAPSHOT_LABELS: &str = "set_snapshot_labels";
+pub const SET_SNAPSHOT_DESCRIPTION: &str = "set_snapshot_description";
+pub const IMPORT_SNAPSHOT: &str = "import_snapshot";
+
+/// Change applied to the system
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditEntry {
+    /// Auto incrementing entry id
+    pub id: i64,
+    /// What was done, e.g. "save_config"
+    pub action: String,
+    /// JSON list of the changed keys with their old and new values
+    pub changes: String,
+    /// JSON list of the commands the change ran, with their exit codes and durations
+    pub commands: String,
+    /// when the change was applied
+    pub created: NaiveDateTime,
+    /// Unique bus name of the client that made the change, `None` for changes
+    /// the daemon made by itself
+    #[serde(default)]
+    pub sender: Option<String>,
+    /// User id of the client
+    #[serde(default)]
+    pub uid: Option<i64>,
+    /// D-Bus method that made the change, e.g. "SaveConfig"
+    #[serde(default)]
+    pub method: Option<String>,
+}
+
+/// D-Bus client a change is made for
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// Unique bus name, like `:1.42`
+    pub sender: Option<String>,
+    /// `None` when the credentials of the client are not checked, on the session bus
+    pub uid: Option<u32>,
+    pub method: Option<String>,
+}
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+impl Caller {
+    /// Run `future` for the caller, its audit log entries are recorded as made by it
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CALLER.scope(self, future).await
+    }
+
+    /// Caller of the change being made in the current task, the default for
+    /// changes the daemon makes by itself
+    pub fn current() -> Self {
+        CALLER.try_with(Clone::clone).unwrap_or_default()
+    }
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+
+/// User set overrides of how a boot entry is shown in frontends
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EntryOverride {
+    /// Full path of the boot entry, including the submenus
+    pub entry: String,
+    /// Hide the entry from frontends, grub.cfg is not modified
+    pub hidden: bool,
+    /// Kind of the entry set by the user, null uses the detected kind
+    pub kind: Option<String>,
+}
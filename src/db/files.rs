@@ -0,0 +1,752 @@
+//! Storage in a JSON file, for systems without SQLite, or only in memory.
+//!
+//! The whole file is rewritten on each change, which is fine for the few
+//! hundred rows a system accumulates.
+
+use std::{
+    collections::BTreeMap,
+    fs::{metadata, read_to_string, rename, write, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        audit_log::{AuditEntry, Caller},
+        entry_override::EntryOverride,
+        grub2::{Grub2Snapshot, SnapshotFile, SnapshotFilter, SnapshotLabel, SnapshotPage},
+        pending_operation::PendingOperation,
+        selected_snapshot::SelectedSnapshot,
+        Storage,
+    },
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::GrubFile,
+};
+
+/// Contents of the storage file, the rows of each table are in id order
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct Tables {
+    grub2_snapshots: Vec<Grub2Snapshot>,
+    snapshot_labels: Vec<SnapshotLabel>,
+    snapshot_files: Vec<SnapshotFile>,
+    selected_snapshot: Option<i64>,
+    settings: BTreeMap<String, String>,
+    pending_operations: Vec<PendingOperation>,
+    entry_overrides: Vec<EntryOverride>,
+    audit_log: Vec<AuditEntry>,
+}
+
+impl Tables {
+    /// Check that a restored backup is usable, the snapshots of a system are
+    /// never all removed
+    fn verify(&self) -> DResult<()> {
+        if self.grub2_snapshots.is_empty() {
+            return Err(DError::invalid_data(dctx!(), "Backup has no snapshots"));
+        }
+        if let Some(id) = self.selected_snapshot {
+            if !self
+                .grub2_snapshots
+                .iter()
+                .any(|snapshot| snapshot.id == id)
+            {
+                return Err(DError::invalid_data(
+                    dctx!(),
+                    format!("Selected snapshot {id} of the backup does not exist"),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Id of the next row, reusing the ids of removed rows at the end like SQLite does
+fn next_id<T>(rows: &[T], id: impl Fn(&T) -> i64) -> i64 {
+    rows.iter().map(id).max().unwrap_or(0) + 1
+}
+
+/// Current UTC time with the precision of the SQLite CURRENT_TIMESTAMP
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc().trunc_subsecs(0)
+}
+
+pub struct FileStorage {
+    /// File the tables are saved to, `None` keeps them only in memory
+    path: Option<PathBuf>,
+    tables: Mutex<Tables>,
+}
+
+impl FileStorage {
+    /// Storage in `path`, which is created on the first change
+    pub fn open(path: &Path) -> DResult<Self> {
+        let tables = if path.exists() {
+            let contents =
+                read_to_string(path).ctx(dctx!(), format!("Cannot read storage file {path:?}"))?;
+            serde_json::from_str(&contents)
+                .ctx(dctx!(), format!("Malformed storage file {path:?}"))?
+        } else {
+            log::debug!("Storage file {path:?} was not found, starting with empty storage");
+            Tables::default()
+        };
+
+        Ok(Self {
+            path: Some(path.into()),
+            tables: Mutex::new(tables),
+        })
+    }
+
+    /// Storage that is lost when the daemon exits
+    pub fn memory() -> Self {
+        Self {
+            path: None,
+            tables: Mutex::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn read<T>(&self, read: impl FnOnce(&Tables) -> DResult<T>) -> DResult<T> {
+        read(&self.lock())
+    }
+
+    /// Change the tables and save them, nothing is changed if saving fails
+    fn update<T>(&self, update: impl FnOnce(&mut Tables) -> DResult<T>) -> DResult<T> {
+        let mut tables = self.lock();
+        let mut updated = tables.clone();
+        let result = update(&mut updated)?;
+
+        if let Some(path) = &self.path {
+            let contents =
+                serde_json::to_string(&updated).ctx(dctx!(), "Cannot turn storage into json")?;
+            // write a new file and move it over the old one, so a crash never leaves half a file
+            let new_path = path.with_extension("json.new");
+            write(&new_path, contents)
+                .ctx(dctx!(), format!("Cannot write storage file {new_path:?}"))?;
+            rename(&new_path, path)
+                .ctx(dctx!(), format!("Cannot replace storage file {path:?}"))?;
+        }
+
+        *tables = updated;
+        Ok(result)
+    }
+}
+
+fn snapshot_not_found(id: i64) -> DError {
+    DError::generic(dctx!(), format!("Snapshot with id '{id}' not found"))
+}
+
+/// Override of `entry`, created if the entry has none
+fn entry_override<'a>(overrides: &'a mut Vec<EntryOverride>, entry: &str) -> &'a mut EntryOverride {
+    let idx = match overrides.iter().position(|item| item.entry == entry) {
+        Some(idx) => idx,
+        None => {
+            overrides.push(EntryOverride {
+                entry: entry.into(),
+                hidden: false,
+                kind: None,
+            });
+            overrides.len() - 1
+        }
+    };
+    &mut overrides[idx]
+}
+
+/// Remove overrides that don't override anything anymore
+fn remove_unused_entry_overrides(overrides: &mut Vec<EntryOverride>) {
+    overrides.retain(|item| item.hidden || item.kind.is_some());
+}
+
+#[async_trait]
+impl Storage for FileStorage {
+    async fn migrate(&self) -> DResult<()> {
+        // missing tables are created empty when the file is read
+        Ok(())
+    }
+
+    async fn close(&self) {
+        // every change is already written to the file, nothing is kept open
+    }
+
+    async fn grub2_snapshot_count(&self) -> DResult<i64> {
+        self.read(|tables| Ok(tables.grub2_snapshots.len() as i64))
+    }
+
+    async fn save_grub2(
+        &self,
+        grub: &GrubFile,
+        selected_kernel: Option<&str>,
+        applied: bool,
+        tag: Option<&str>,
+    ) -> DResult<i64> {
+        let id = self.update(|tables| {
+            let snapshots = &mut tables.grub2_snapshots;
+            let id = next_id(snapshots, |snapshot| snapshot.id);
+            snapshots.push(Grub2Snapshot {
+                id,
+                grub_config: grub.as_string(),
+                selected_kernel: selected_kernel.map(str::to_string),
+                applied,
+                created: now(),
+                tag: tag.map(str::to_string),
+                pinned: false,
+                description: None,
+                saved_entry: None,
+                files_captured: false,
+                grub_cfg_sha256: None,
+            });
+            Ok(id)
+        })?;
+
+        log::debug!("New grub2 config snapshot saved");
+        Ok(id)
+    }
+
+    async fn remove_grub2(&self, grub_id: i64) -> DResult<()> {
+        self.update(|tables| {
+            tables
+                .grub2_snapshots
+                .retain(|snapshot| snapshot.id != grub_id);
+            tables
+                .snapshot_labels
+                .retain(|label| label.snapshot_id != grub_id);
+            tables
+                .snapshot_files
+                .retain(|file| file.snapshot_id != grub_id);
+            Ok(())
+        })?;
+
+        log::debug!("Grub2 snapshot with id {grub_id} was removed");
+        Ok(())
+    }
+
+    async fn set_grub2_applied(&self, grub_id: i64) -> DResult<()> {
+        self.update(|tables| {
+            let snapshot = tables
+                .grub2_snapshots
+                .iter_mut()
+                .find(|snapshot| snapshot.id == grub_id)
+                .ok_or_else(|| snapshot_not_found(grub_id))?;
+            snapshot.applied = true;
+            Ok(())
+        })
+    }
+
+    async fn set_grub2_pinned(&self, grub_id: i64, pinned: bool) -> DResult<()> {
+        self.update(|tables| {
+            let snapshot = tables
+                .grub2_snapshots
+                .iter_mut()
+                .find(|snapshot| snapshot.id == grub_id)
+                .ok_or_else(|| snapshot_not_found(grub_id))?;
+            snapshot.pinned = pinned;
+            Ok(())
+        })
+    }
+
+    async fn set_grub2_cfg_hash(&self, grub_id: i64, sha256: &str) -> DResult<()> {
+        self.update(|tables| {
+            let snapshot = tables
+                .grub2_snapshots
+                .iter_mut()
+                .find(|snapshot| snapshot.id == grub_id)
+                .ok_or_else(|| snapshot_not_found(grub_id))?;
+            snapshot.grub_cfg_sha256 = Some(sha256.to_string());
+            Ok(())
+        })
+    }
+
+    async fn set_grub2_description(&self, grub_id: i64, description: Option<&str>) -> DResult<()> {
+        self.update(|tables| {
+            let snapshot = tables
+                .grub2_snapshots
+                .iter_mut()
+                .find(|snapshot| snapshot.id == grub_id)
+                .ok_or_else(|| snapshot_not_found(grub_id))?;
+            snapshot.description = description.map(str::to_string);
+            Ok(())
+        })
+    }
+
+    async fn latest_grub2(&self) -> DResult<Grub2Snapshot> {
+        self.read(|tables| {
+            tables
+                .grub2_snapshots
+                .last()
+                .cloned()
+                .ok_or_else(|| DError::generic(dctx!(), "No snapshots saved"))
+        })
+    }
+
+    async fn grub2_snapshots(&self) -> DResult<Vec<Grub2Snapshot>> {
+        self.read(|tables| Ok(tables.grub2_snapshots.iter().rev().cloned().collect()))
+    }
+
+    async fn grub2_snapshot(&self, id: i64) -> DResult<Grub2Snapshot> {
+        self.read(|tables| {
+            tables
+                .grub2_snapshots
+                .iter()
+                .find(|snapshot| snapshot.id == id)
+                .cloned()
+                .ok_or_else(|| snapshot_not_found(id))
+        })
+    }
+
+    async fn grub2_snapshot_page(&self, filter: &SnapshotFilter) -> DResult<SnapshotPage> {
+        self.read(|tables| {
+            let matching: Vec<&Grub2Snapshot> = tables
+                .grub2_snapshots
+                .iter()
+                .rev()
+                .filter(|snapshot| filter.matches(snapshot, &tables.snapshot_labels))
+                .collect();
+            let limit = filter.limit.map_or(usize::MAX, |limit| limit as usize);
+            Ok(SnapshotPage {
+                total: matching.len() as i64,
+                snapshots: matching
+                    .into_iter()
+                    .skip(filter.offset as usize)
+                    .take(limit)
+                    .cloned()
+                    .collect(),
+            })
+        })
+    }
+
+    async fn snapshot_labels(&self) -> DResult<Vec<SnapshotLabel>> {
+        self.read(|tables| Ok(tables.snapshot_labels.clone()))
+    }
+
+    async fn set_snapshot_labels(&self, grub_id: i64, labels: &[String]) -> DResult<()> {
+        self.update(|tables| {
+            let rows = &mut tables.snapshot_labels;
+            rows.retain(|label| label.snapshot_id != grub_id);
+            rows.extend(labels.iter().map(|label| SnapshotLabel {
+                snapshot_id: grub_id,
+                label: label.clone(),
+            }));
+            rows.sort_by(|a, b| (a.snapshot_id, &a.label).cmp(&(b.snapshot_id, &b.label)));
+            Ok(())
+        })
+    }
+
+    async fn snapshot_files(&self) -> DResult<Vec<SnapshotFile>> {
+        self.read(|tables| Ok(tables.snapshot_files.clone()))
+    }
+
+    async fn set_snapshot_files(
+        &self,
+        grub_id: i64,
+        saved_entry: Option<&str>,
+        dropins: &BTreeMap<String, String>,
+    ) -> DResult<()> {
+        self.update(|tables| {
+            let snapshot = tables
+                .grub2_snapshots
+                .iter_mut()
+                .find(|snapshot| snapshot.id == grub_id)
+                .ok_or_else(|| snapshot_not_found(grub_id))?;
+            snapshot.saved_entry = saved_entry.map(str::to_string);
+            snapshot.files_captured = true;
+
+            let rows = &mut tables.snapshot_files;
+            rows.retain(|file| file.snapshot_id != grub_id);
+            rows.extend(dropins.iter().map(|(name, contents)| SnapshotFile {
+                snapshot_id: grub_id,
+                name: name.clone(),
+                contents: contents.clone(),
+            }));
+            rows.sort_by(|a, b| (a.snapshot_id, &a.name).cmp(&(b.snapshot_id, &b.name)));
+            Ok(())
+        })
+    }
+
+    async fn size_bytes(&self) -> DResult<u64> {
+        match &self.path {
+            // the file is only created on the first change
+            Some(path) if path.exists() => Ok(metadata(path)
+                .ctx(dctx!(), format!("Cannot read metadata of {path:?}"))?
+                .len()),
+            _ => Ok(0),
+        }
+    }
+
+    async fn free_bytes(&self) -> DResult<i64> {
+        // the file is rewritten on each change, so there's never unused space
+        Ok(0)
+    }
+
+    async fn selected_snapshot(&self) -> DResult<SelectedSnapshot> {
+        self.read(|tables| {
+            Ok(SelectedSnapshot {
+                grub2_snapshot_id: tables.selected_snapshot,
+            })
+        })
+    }
+
+    async fn set_selected_snapshot(&self, id: Option<i64>) -> DResult<()> {
+        self.update(|tables| {
+            tables.selected_snapshot = id;
+            Ok(())
+        })
+    }
+
+    async fn setting(&self, key: &str) -> DResult<Option<String>> {
+        self.read(|tables| Ok(tables.settings.get(key).cloned()))
+    }
+
+    async fn set_setting(&self, key: &str, value: Option<&str>) -> DResult<()> {
+        self.update(|tables| {
+            match value {
+                Some(value) => tables.settings.insert(key.into(), value.into()),
+                None => tables.settings.remove(key),
+            };
+            Ok(())
+        })
+    }
+
+    async fn add_pending_operation(&self, kind: &str, data: &str) -> DResult<i64> {
+        let id = self.update(|tables| {
+            let operations = &mut tables.pending_operations;
+            let id = next_id(operations, |operation| operation.id);
+            operations.push(PendingOperation {
+                id,
+                kind: kind.into(),
+                data: data.into(),
+                last_error: None,
+                created: now(),
+            });
+            Ok(id)
+        })?;
+
+        log::debug!("Pending operation {id} ({kind}) saved");
+        Ok(id)
+    }
+
+    async fn pending_operations(&self) -> DResult<Vec<PendingOperation>> {
+        self.read(|tables| Ok(tables.pending_operations.clone()))
+    }
+
+    async fn set_pending_operation_error(&self, id: i64, error: &str) -> DResult<()> {
+        self.update(|tables| {
+            if let Some(operation) = tables
+                .pending_operations
+                .iter_mut()
+                .find(|operation| operation.id == id)
+            {
+                operation.last_error = Some(error.into());
+            }
+            Ok(())
+        })
+    }
+
+    async fn remove_pending_operation(&self, id: i64) -> DResult<()> {
+        self.update(|tables| {
+            tables
+                .pending_operations
+                .retain(|operation| operation.id != id);
+            Ok(())
+        })?;
+
+        log::debug!("Pending operation {id} was removed");
+        Ok(())
+    }
+
+    async fn entry_overrides(&self) -> DResult<Vec<EntryOverride>> {
+        self.read(|tables| Ok(tables.entry_overrides.clone()))
+    }
+
+    async fn set_entries_hidden(&self, entries: &[String], hidden: bool) -> DResult<()> {
+        self.update(|tables| {
+            for entry in entries {
+                entry_override(&mut tables.entry_overrides, entry).hidden = hidden;
+            }
+            remove_unused_entry_overrides(&mut tables.entry_overrides);
+            Ok(())
+        })
+    }
+
+    async fn set_entries_kind(&self, entries: &[String], kind: Option<&str>) -> DResult<()> {
+        self.update(|tables| {
+            for entry in entries {
+                entry_override(&mut tables.entry_overrides, entry).kind = kind.map(str::to_string);
+            }
+            remove_unused_entry_overrides(&mut tables.entry_overrides);
+            Ok(())
+        })
+    }
+
+    async fn add_audit_entry(
+        &self,
+        action: &str,
+        changes: &str,
+        commands: &str,
+        caller: &Caller,
+    ) -> DResult<()> {
+        self.update(|tables| {
+            let audit_log = &mut tables.audit_log;
+            audit_log.push(AuditEntry {
+                id: next_id(audit_log, |entry| entry.id),
+                action: action.into(),
+                changes: changes.into(),
+                commands: commands.into(),
+                created: now(),
+                sender: caller.sender.clone(),
+                uid: caller.uid.map(i64::from),
+                method: caller.method.clone(),
+            });
+            Ok(())
+        })?;
+
+        log::debug!("New {action} entry saved to the audit log");
+        Ok(())
+    }
+
+    async fn audit_entries(&self) -> DResult<Vec<AuditEntry>> {
+        self.read(|tables| Ok(tables.audit_log.iter().rev().cloned().collect()))
+    }
+
+    async fn audit_entry(&self, id: i64) -> DResult<AuditEntry> {
+        self.read(|tables| {
+            tables
+                .audit_log
+                .iter()
+                .find(|entry| entry.id == id)
+                .cloned()
+                .ok_or_else(|| {
+                    DError::generic(dctx!(), format!("Audit log entry with id '{id}' not found"))
+                })
+        })
+    }
+
+    async fn backup(&self, path: &Path) -> DResult<()> {
+        let contents = self.read(|tables| {
+            serde_json::to_string(tables).ctx(dctx!(), "Cannot turn storage into json")
+        })?;
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .ctx(dctx!(), format!("Cannot write backup {path:?}"))?;
+
+        log::info!("Storage backed up to {path:?}");
+        Ok(())
+    }
+
+    async fn restore(&self, path: &Path) -> DResult<()> {
+        let contents = read_to_string(path).ctx(dctx!(), format!("Cannot read backup {path:?}"))?;
+        let restored: Tables = serde_json::from_str(&contents).map_err(|err| {
+            DError::invalid_data(dctx!(), format!("Malformed backup {path:?}: {err}"))
+        })?;
+        restored.verify()?;
+        self.update(|tables| {
+            *tables = restored;
+            Ok(())
+        })?;
+
+        log::info!("Storage restored from {path:?}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_snapshots() {
+        let storage = FileStorage::memory();
+        let grub = GrubFile::new("GRUB_TIMEOUT=8").unwrap();
+        storage.save_grub2(&grub, None, true, None).await.unwrap();
+        storage
+            .save_grub2(&grub, Some("openSUSE"), false, Some("external change"))
+            .await
+            .unwrap();
+        assert_eq!(storage.grub2_snapshot_count().await.unwrap(), 2);
+
+        let latest = storage.latest_grub2().await.unwrap();
+        assert_eq!(latest.id, 2);
+        assert_eq!(latest.selected_kernel.as_deref(), Some("openSUSE"));
+        assert!(!latest.applied);
+        assert_eq!(latest.tag.as_deref(), Some("external change"));
+
+        storage.set_grub2_applied(2).await.unwrap();
+        assert!(storage.grub2_snapshot(2).await.unwrap().applied);
+        storage.set_grub2_pinned(1, true).await.unwrap();
+        assert!(storage.grub2_snapshot(1).await.unwrap().pinned);
+        assert!(storage.set_grub2_pinned(3, true).await.is_err());
+        storage.set_grub2_cfg_hash(2, "0a1b").await.unwrap();
+        let hashed = storage.grub2_snapshot(2).await.unwrap();
+        assert_eq!(hashed.grub_cfg_sha256.as_deref(), Some("0a1b"));
+        storage
+            .set_grub2_description(1, Some("before the nvidia driver"))
+            .await
+            .unwrap();
+        let described = storage.grub2_snapshot(1).await.unwrap();
+        assert_eq!(
+            described.description.as_deref(),
+            Some("before the nvidia driver")
+        );
+
+        let dropins =
+            BTreeMap::from([("50-console.cfg".to_string(), "GRUB_TERMINAL=console".into())]);
+        storage
+            .set_snapshot_files(2, Some("openSUSE"), &dropins)
+            .await
+            .unwrap();
+        let captured = storage.grub2_snapshot(2).await.unwrap();
+        assert!(captured.files_captured);
+        assert_eq!(captured.saved_entry.as_deref(), Some("openSUSE"));
+        assert_eq!(storage.snapshot_files().await.unwrap().len(), 1);
+
+        // like SQLite, the id of the newest row is reused after it's removed
+        storage.remove_grub2(2).await.unwrap();
+        storage.save_grub2(&grub, None, false, None).await.unwrap();
+        let ids: Vec<_> = storage
+            .grub2_snapshots()
+            .await
+            .unwrap()
+            .iter()
+            .map(|snapshot| snapshot.id)
+            .collect();
+        assert_eq!(ids, vec![2, 1]);
+        assert!(!storage.grub2_snapshot(2).await.unwrap().files_captured);
+        assert!(storage.snapshot_files().await.unwrap().is_empty());
+        assert!(storage.grub2_snapshot(5).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_page() {
+        let storage = FileStorage::memory();
+        let grub = GrubFile::new("GRUB_TIMEOUT=8").unwrap();
+        for id in 1..=5 {
+            let kernel = (id % 2 == 0).then_some("openSUSE");
+            storage.save_grub2(&grub, kernel, true, None).await.unwrap();
+        }
+        storage
+            .set_snapshot_labels(4, &["stable".into()])
+            .await
+            .unwrap();
+
+        let page_ids = |page: SnapshotPage| -> Vec<i64> {
+            page.snapshots.iter().map(|snapshot| snapshot.id).collect()
+        };
+        let filter = SnapshotFilter {
+            limit: Some(2),
+            offset: 1,
+            ..SnapshotFilter::default()
+        };
+        let page = storage.grub2_snapshot_page(&filter).await.unwrap();
+        assert_eq!(page.total, 5);
+        assert_eq!(page_ids(page), vec![4, 3]);
+
+        let filter = SnapshotFilter {
+            selected_kernel: Some("openSUSE".into()),
+            ..SnapshotFilter::default()
+        };
+        let page = storage.grub2_snapshot_page(&filter).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page_ids(page), vec![4, 2]);
+
+        let filter = SnapshotFilter {
+            label: Some("stable".into()),
+            ..SnapshotFilter::default()
+        };
+        let page = storage.grub2_snapshot_page(&filter).await.unwrap();
+        assert_eq!(page_ids(page), vec![4]);
+    }
+
+    #[tokio::test]
+    async fn test_backup_restore() {
+        let path = std::env::temp_dir().join(format!("bootkit-backup-{}.json", std::process::id()));
+        let storage = FileStorage::memory();
+        let grub = GrubFile::new("GRUB_TIMEOUT=8").unwrap();
+        storage.save_grub2(&grub, None, true, None).await.unwrap();
+        storage.set_setting("retention", Some("5")).await.unwrap();
+        storage.backup(&path).await.unwrap();
+        // existing files are not overwritten
+        assert!(storage.backup(&path).await.is_err());
+
+        let restored = FileStorage::memory();
+        restored.restore(&path).await.unwrap();
+        assert_eq!(restored.grub2_snapshot_count().await.unwrap(), 1);
+        assert_eq!(
+            restored.setting("retention").await.unwrap().as_deref(),
+            Some("5")
+        );
+
+        // a backup without snapshots is not from a system the daemon managed
+        write(&path, "{}").unwrap();
+        assert!(restored.restore(&path).await.is_err());
+        assert_eq!(restored.grub2_snapshot_count().await.unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_entry_overrides() {
+        let storage = FileStorage::memory();
+        let entries = vec!["a".to_string(), "b".to_string()];
+        storage.set_entries_hidden(&entries, true).await.unwrap();
+        storage
+            .set_entries_kind(&entries[..1], Some("recovery"))
+            .await
+            .unwrap();
+        storage.set_entries_hidden(&entries, false).await.unwrap();
+
+        // only the override with a kind is left
+        let overrides = storage.entry_overrides().await.unwrap();
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[0].entry, "a");
+        assert_eq!(overrides[0].kind.as_deref(), Some("recovery"));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_caller() {
+        let storage = FileStorage::memory();
+        storage
+            .add_audit_entry("save_config", "[]", "[]", &Caller::current())
+            .await
+            .unwrap();
+        let caller = Caller {
+            sender: Some(":1.42".into()),
+            uid: Some(1000),
+            method: Some("SaveConfig".into()),
+        };
+        caller
+            .scope(async {
+                storage
+                    .add_audit_entry("save_config", "[]", "[]", &Caller::current())
+                    .await
+            })
+            .await
+            .unwrap();
+
+        let entries = storage.audit_entries().await.unwrap();
+        assert_eq!(entries[0].sender.as_deref(), Some(":1.42"));
+        assert_eq!(entries[0].uid, Some(1000));
+        assert_eq!(entries[0].method.as_deref(), Some("SaveConfig"));
+        // changes the daemon makes by itself have no caller
+        assert_eq!(entries[1].sender, None);
+        assert_eq!(entries[1].uid, None);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_commands() {
+        let storage = FileStorage::memory();
+        let commands = r#"[{"command":"grub2-mkconfig","exit_code":0,"duration_ms":5}]"#;
+        storage
+            .add_audit_entry("save_config", "[]", commands, &Caller::current())
+            .await
+            .unwrap();
+        assert_eq!(storage.audit_entry(1).await.unwrap().commands, commands);
+    }
+}
@@ -1,7 +1,7 @@
 use chrono::NaiveDateTime;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[allow(dead_code)]
 pub struct Grub2Snapshot {
     /// Auto incrementing snapshot id
@@ -10,6 +10,89 @@ pub struct Grub2Snapshot {
     pub grub_config: String,
     /// selected kernel that's booted to, if it's actually specified
     pub selected_kernel: Option<String>,
+    /// snapshot was applied to the system at least once, instead of only being saved
+    pub applied: bool,
     /// when snapshot was created
     pub created: NaiveDateTime,
+    /// why the snapshot was taken, if not by applying a config
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// snapshot is never removed by the retention policy
+    #[serde(default)]
+    pub pinned: bool,
+    /// why the snapshot was saved, written by the admin
+    #[serde(default)]
+    pub description: Option<String>,
+    /// raw saved_entry of grubenv, captured with the files
+    #[serde(default)]
+    pub saved_entry: Option<String>,
+    /// saved_entry and the drop-in configs were captured, older snapshots only have the grub file
+    #[serde(default)]
+    pub files_captured: bool,
+    /// hex SHA-256 of the grub.cfg generated when the snapshot was last applied
+    #[serde(default)]
+    pub grub_cfg_sha256: Option<String>,
+}
+
+/// Which snapshots are listed, all of them by default
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SnapshotFilter {
+    /// Most snapshots listed, all of them if `None`
+    pub limit: Option<i64>,
+    /// Snapshots skipped from the newest one, after the other filters
+    pub offset: i64,
+    /// Only the snapshots created at or after this time, in UTC
+    pub created_after: Option<NaiveDateTime>,
+    /// Only the snapshots created at or before this time, in UTC
+    pub created_before: Option<NaiveDateTime>,
+    /// Only the snapshots with this tag
+    pub tag: Option<String>,
+    /// Only the snapshots with this selected kernel
+    pub selected_kernel: Option<String>,
+    /// Only the snapshots with this user defined label
+    pub label: Option<String>,
+}
+
+impl SnapshotFilter {
+    /// Snapshot passes the filters other than the limit and the offset
+    pub fn matches(&self, snapshot: &Grub2Snapshot, labels: &[SnapshotLabel]) -> bool {
+        self.created_after
+            .is_none_or(|after| snapshot.created >= after)
+            && self
+                .created_before
+                .is_none_or(|before| snapshot.created <= before)
+            && (self.tag.is_none() || snapshot.tag == self.tag)
+            && (self.selected_kernel.is_none() || snapshot.selected_kernel == self.selected_kernel)
+            && self.label.as_ref().is_none_or(|label| {
+                labels
+                    .iter()
+                    .any(|row| row.snapshot_id == snapshot.id && &row.label == label)
+            })
+    }
+}
+
+/// Snapshots on one page of a filtered list
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotPage {
+    /// Snapshots on the page, newest first
+    pub snapshots: Vec<Grub2Snapshot>,
+    /// Snapshots that pass the filters on all the pages
+    pub total: i64,
+}
+
+/// Drop-in config of grub2-mkconfig captured with a snapshot
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotFile {
+    pub snapshot_id: i64,
+    /// File name in the drop-in directory, like `50-console.cfg`
+    pub name: String,
+    pub contents: String,
+}
+
+/// User defined label of a snapshot, a snapshot can have many
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SnapshotLabel {
+    pub snapshot_id: i64,
+    pub label: String,
 }
@@ -1,186 +1,232 @@
-use std::{fs::File, path::Path};
+use std::{
+    collections::BTreeMap, fmt::Display, fs::create_dir_all, ops::Deref, path::Path, str::FromStr,
+    sync::Arc,
+};
 
-use sqlx::{sqlite::SqlitePoolOptions, Error, Pool, Sqlite};
+use async_trait::async_trait;
 
 use crate::{
-    config::{DATABASE_PATH, GRUB_FILE_PATH},
-    db::{grub2::Grub2Snapshot, selected_snapshot::SelectedSnapshot},
+    config::Paths,
+    db::{
+        audit_log::{AuditEntry, Caller},
+        entry_override::EntryOverride,
+        files::FileStorage,
+        grub2::{Grub2Snapshot, SnapshotFile, SnapshotFilter, SnapshotLabel, SnapshotPage},
+        pending_operation::PendingOperation,
+        selected_snapshot::SelectedSnapshot,
+    },
     dctx,
     errors::{DRes, DResult},
     grub2::{GrubBootEntries, GrubFile},
 };
 
+pub mod audit_log;
+pub mod entry_override;
+mod files;
 pub mod grub2;
+pub mod pending_operation;
 pub mod selected_snapshot;
-
-#[derive(Clone)]
-pub struct Database {
-    pool: Pool<Sqlite>,
+pub mod settings;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+/// Where the snapshots, settings and the audit log are stored
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StorageKind {
+    /// SQLite database, needs the `sqlite` feature
+    Sqlite,
+    /// JSON file next to where the database would be, for systems without SQLite
+    Files,
+    /// Kept in memory and lost when the daemon exits, for development
+    Memory,
 }
 
-impl Database {
-    pub async fn new() -> DResult<Self> {
-        if !Path::new(DATABASE_PATH).exists() {
-            log::debug!("Database file in was not found. Creating it in path {DATABASE_PATH}");
-            File::create(DATABASE_PATH).ctx(
-                dctx!(),
-                format!("Cannot create database in path: {DATABASE_PATH}"),
-            )?;
+impl Default for StorageKind {
+    fn default() -> Self {
+        if cfg!(feature = "sqlite") {
+            Self::Sqlite
+        } else {
+            Self::Files
         }
-
-        // should this failure be fatal or should the snapshot features
-        // just be disabled?
-        let pool = SqlitePoolOptions::new()
-            .max_connections(10)
-            .connect(DATABASE_PATH)
-            .await
-            .ctx(
-                dctx!(),
-                format!("Cannot initialize SQLite database in path: {DATABASE_PATH}"),
-            )?;
-
-        Ok(Self { pool })
     }
+}
 
-    pub async fn initialize(&self) -> DResult<()> {
-        let grub_table = sqlx::query!(
-            "SELECT name FROM sqlite_master WHERE type='table' AND name='grub2_snapshot'"
-        )
-        .fetch_one(&self.pool)
-        .await;
-
-        if let Err(Error::RowNotFound) = grub_table {
-            log::debug!("grub2_snapshot table not found from database, creating it");
-            sqlx::query(include_str!("../../db/grub2.sql"))
-                .execute(&self.pool)
-                .await
-                .ctx(dctx!(), "Cannot initialize grub2_snapshots")?;
-        }
-
-        let snapshot_count = sqlx::query!("SELECT COUNT(*) as count FROM grub2_snapshot")
-            .fetch_one(&self.pool)
-            .await
-            .ctx(dctx!(), "Cannot get count from grub2_snapshot")?;
-
-        if snapshot_count.count == 0 {
-            log::debug!("grub2_snapshot table is empty. Setting first entry to grub2_snapshots");
-            let grub = GrubFile::from_file(GRUB_FILE_PATH)?;
-            if cfg!(feature = "dev") {
-                log::debug!("Setting initial snapshot without selected kernel");
-                self.save_grub2(&grub, None::<&str>).await?;
-            } else {
-                let entry = GrubBootEntries::new()?;
-                self.save_grub2(&grub, entry.selected()).await?;
-            }
-        }
-
-        let grub_table = sqlx::query!(
-            "SELECT name FROM sqlite_master WHERE type='table' AND name='selected_snapshot'"
-        )
-        .fetch_one(&self.pool)
-        .await;
-
-        if let Err(Error::RowNotFound) = grub_table {
-            log::debug!("selected_snapshot table not found from database, creating it");
-            sqlx::query(include_str!("../../db/selected_snapshot.sql"))
-                .execute(&self.pool)
-                .await
-                .ctx(dctx!(), "Cannot initialize selected_snapshots table")?;
+impl FromStr for StorageKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            s if s.eq_ignore_ascii_case("sqlite") => Ok(Self::Sqlite),
+            s if s.eq_ignore_ascii_case("files") => Ok(Self::Files),
+            s if s.eq_ignore_ascii_case("memory") => Ok(Self::Memory),
+            _ => Err(format!(
+                "Argument '{s}' is not any of 'sqlite', 'files' or 'memory' (case insensitive)."
+            )),
         }
+    }
+}
 
-        log::info!("Initialised database at {DATABASE_PATH}");
-        Ok(())
+impl Display for StorageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Sqlite => "sqlite",
+            Self::Files => "files",
+            Self::Memory => "memory",
+        };
+        write!(f, "{name}")
     }
+}
 
-    pub async fn save_grub2<K: Into<String>>(
+/// Storage of the snapshots, settings, pending operations and the audit log
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Create the missing tables and update the old ones
+    async fn migrate(&self) -> DResult<()>;
+    /// Close the storage once nothing uses it anymore
+    async fn close(&self);
+    async fn grub2_snapshot_count(&self) -> DResult<i64>;
+    /// Save a new snapshot, `applied` if the config is, or was, in use on the
+    /// system. `tag` tells why it was taken when it wasn't by applying a config.
+    /// Returns the id of the snapshot.
+    async fn save_grub2(
         &self,
         grub: &GrubFile,
-        selected_kernel: Option<K>,
-    ) -> DResult<()> {
-        let selected_kernel: Option<String> = selected_kernel.map(K::into);
-        let grub_file = grub.as_string();
-
-        sqlx::query!(
-            "INSERT INTO grub2_snapshot (grub_config, selected_kernel) VALUES (?, ?)",
-            grub_file,
-            selected_kernel,
-        )
-        .execute(&self.pool)
-        .await
-        .ctx(dctx!(), "Cannot insert new entry to grub2_snapshot table")?;
-
-        log::debug!("New grub2 config snapshot inserted to grub2_snapshot table");
-        Ok(())
-    }
-
-    pub async fn remove_grub2(&self, grub_id: i64) -> DResult<()> {
-        sqlx::query!("DELETE FROM grub2_snapshot WHERE id=(?)", grub_id)
-            .execute(&self.pool)
-            .await
-            .ctx(dctx!(), "Cannot remove snapshot with id {grub_id}")?;
+        selected_kernel: Option<&str>,
+        applied: bool,
+        tag: Option<&str>,
+    ) -> DResult<i64>;
+    async fn remove_grub2(&self, grub_id: i64) -> DResult<()>;
+    async fn set_grub2_applied(&self, grub_id: i64) -> DResult<()>;
+    /// Keep the snapshot from being removed by the retention policy
+    async fn set_grub2_pinned(&self, grub_id: i64, pinned: bool) -> DResult<()>;
+    /// Record the hash of the grub.cfg generated from the snapshot
+    async fn set_grub2_cfg_hash(&self, grub_id: i64, sha256: &str) -> DResult<()>;
+    async fn set_grub2_description(&self, grub_id: i64, description: Option<&str>) -> DResult<()>;
+    async fn latest_grub2(&self) -> DResult<Grub2Snapshot>;
+    /// All snapshots, newest first
+    async fn grub2_snapshots(&self) -> DResult<Vec<Grub2Snapshot>>;
+    async fn grub2_snapshot(&self, id: i64) -> DResult<Grub2Snapshot>;
+    /// Bytes the storage takes on disk, 0 for storage kept in memory
+    async fn size_bytes(&self) -> DResult<u64>;
+    /// Page of the snapshots that pass the filter, newest first
+    async fn grub2_snapshot_page(&self, filter: &SnapshotFilter) -> DResult<SnapshotPage>;
+    /// Labels of all the snapshots, in snapshot id order
+    async fn snapshot_labels(&self) -> DResult<Vec<SnapshotLabel>>;
+    /// Replace the labels of a snapshot
+    async fn set_snapshot_labels(&self, grub_id: i64, labels: &[String]) -> DResult<()>;
+    /// Drop-in configs of all the snapshots, in snapshot id and file name order
+    async fn snapshot_files(&self) -> DResult<Vec<SnapshotFile>>;
+    /// Record the saved_entry and the drop-in configs, by file name, of the
+    /// system with the snapshot, replacing the earlier ones
+    async fn set_snapshot_files(
+        &self,
+        grub_id: i64,
+        saved_entry: Option<&str>,
+        dropins: &BTreeMap<String, String>,
+    ) -> DResult<()>;
+    /// Bytes of unused space in the storage, reclaimable with VACUUM
+    async fn free_bytes(&self) -> DResult<i64>;
+    async fn selected_snapshot(&self) -> DResult<SelectedSnapshot>;
+    async fn set_selected_snapshot(&self, id: Option<i64>) -> DResult<()>;
+    async fn setting(&self, key: &str) -> DResult<Option<String>>;
+    /// Set or remove (with `None`) a setting
+    async fn set_setting(&self, key: &str, value: Option<&str>) -> DResult<()>;
+    /// Queue an operation, returns the id of the operation
+    async fn add_pending_operation(&self, kind: &str, data: &str) -> DResult<i64>;
+    /// Queued operations, oldest first
+    async fn pending_operations(&self) -> DResult<Vec<PendingOperation>>;
+    async fn set_pending_operation_error(&self, id: i64, error: &str) -> DResult<()>;
+    async fn remove_pending_operation(&self, id: i64) -> DResult<()>;
+    async fn entry_overrides(&self) -> DResult<Vec<EntryOverride>>;
+    async fn set_entries_hidden(&self, entries: &[String], hidden: bool) -> DResult<()>;
+    /// Set the kind of the entries, `None` goes back to the detected kind
+    async fn set_entries_kind(&self, entries: &[String], kind: Option<&str>) -> DResult<()>;
+    /// Record a change, `changes` and `commands` are JSON lists of the changed
+    /// keys and of the commands that were run
+    async fn add_audit_entry(
+        &self,
+        action: &str,
+        changes: &str,
+        commands: &str,
+        caller: &Caller,
+    ) -> DResult<()>;
+    /// All audit log entries, newest first
+    async fn audit_entries(&self) -> DResult<Vec<AuditEntry>>;
+    async fn audit_entry(&self, id: i64) -> DResult<AuditEntry>;
+    /// Write a consistent copy of all the stored data to `path`, which must not exist
+    async fn backup(&self, path: &Path) -> DResult<()>;
+    /// Replace all the stored data with a backup made by [`Storage::backup`],
+    /// once the backup is verified to be intact
+    async fn restore(&self, path: &Path) -> DResult<()>;
+}
 
-        log::debug!("Grub2 snapshot with id {grub_id} was removed");
-        Ok(())
-    }
+/// Storage of a single managed system, the methods of [`Storage`] are called through it
+#[derive(Clone)]
+pub struct Database {
+    kind: StorageKind,
+    storage: Arc<dyn Storage>,
+}
 
-    pub async fn latest_grub2(&self) -> DResult<Grub2Snapshot> {
-        let snapshot = sqlx::query_as!(
-            Grub2Snapshot,
-            "SELECT * FROM grub2_snapshot ORDER BY id DESC LIMIT 1",
-        )
-        .fetch_one(&self.pool)
-        .await
-        .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
+impl Deref for Database {
+    type Target = dyn Storage;
 
-        Ok(snapshot)
+    fn deref(&self) -> &Self::Target {
+        self.storage.as_ref()
     }
+}
 
-    pub async fn grub2_snapshots(&self) -> DResult<Vec<Grub2Snapshot>> {
-        let snapshots = sqlx::query_as!(
-            Grub2Snapshot,
-            "SELECT * FROM grub2_snapshot ORDER BY id DESC",
-        )
-        .fetch_all(&self.pool)
-        .await
-        .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
+impl Database {
+    pub async fn new(paths: &Paths, kind: StorageKind) -> DResult<Self> {
+        let storage: Arc<dyn Storage> = match kind {
+            #[cfg(feature = "sqlite")]
+            StorageKind::Sqlite => {
+                create_parent_dir(paths.database())?;
+                Arc::new(sqlite::SqliteStorage::new(paths.database()).await?)
+            }
+            #[cfg(not(feature = "sqlite"))]
+            StorageKind::Sqlite => {
+                return Err(crate::errors::DError::generic(
+                    crate::dctx!(),
+                    "SQLite storage is not supported by this build, use the files storage",
+                ))
+            }
+            StorageKind::Files => {
+                create_parent_dir(&paths.storage_file())?;
+                Arc::new(FileStorage::open(&paths.storage_file())?)
+            }
+            StorageKind::Memory => Arc::new(FileStorage::memory()),
+        };
 
-        Ok(snapshots)
+        Ok(Self { kind, storage })
     }
 
-    pub async fn grub2_snapshot(&self, id: i64) -> DResult<Grub2Snapshot> {
-        let snapshots = sqlx::query_as!(
-            Grub2Snapshot,
-            "SELECT * FROM grub2_snapshot WHERE id=(?)",
-            id
-        )
-        .fetch_one(&self.pool)
-        .await
-        .ctx(
-            dctx!(),
-            "Cannot fetch snapshot with id '{id}' from grub2_snapshot table",
-        )?;
-
-        Ok(snapshots)
-    }
+    pub async fn initialize(&self, paths: &Paths) -> DResult<()> {
+        self.migrate().await?;
 
-    pub async fn selected_snapshot(&self) -> DResult<SelectedSnapshot> {
-        let snapshot = sqlx::query_as!(SelectedSnapshot, "SELECT * FROM selected_snapshot",)
-            .fetch_one(&self.pool)
-            .await
-            .ctx(
-                dctx!(),
-                "Cannot fetch selected snapshot from selected_snapshot table",
-            )?;
+        if self.grub2_snapshot_count().await? == 0 {
+            log::debug!("No grub2 snapshots stored. Setting first entry to grub2_snapshots");
+            let grub = GrubFile::from_file(paths.grub_file())?;
+            if cfg!(feature = "dev") {
+                log::debug!("Setting initial snapshot without selected kernel");
+                self.save_grub2(&grub, None, true, None).await?;
+            } else {
+                let entry = GrubBootEntries::new(paths)?;
+                self.save_grub2(&grub, entry.selected(), true, None).await?;
+            }
+        }
 
-        Ok(snapshot)
+        log::info!("Initialised {} storage", self.kind);
+        Ok(())
     }
+}
 
-    pub async fn set_selected_snapshot(&self, id: Option<i64>) -> DResult<()> {
-        sqlx::query!("UPDATE selected_snapshot SET grub2_snapshot_id=(?)", id)
-            .execute(&self.pool)
-            .await
-            .ctx(dctx!(), "Cannot snapshot from selected snapshot table")?;
-
-        Ok(())
+/// Create the directory of a storage file, it's missing on the first boot of a
+/// clean system
+fn create_parent_dir(path: &Path) -> DResult<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() && !dir.exists() => {
+            log::debug!("Creating storage directory {dir:?}");
+            create_dir_all(dir).ctx(dctx!(), format!("Cannot create directory {dir:?}"))
+        }
+        _ => Ok(()),
     }
 }
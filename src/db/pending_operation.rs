@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// Operation kind for saving a grub config
+pub const SAVE_CONFIG: &str = "save_config";
+
+/// Change that is waiting for the boot partition to become writable
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PendingOperation {
+    /// Auto incrementing operation id, operations are applied in id order
+    pub id: i64,
+    /// What the operation does, e.g. "save_config"
+    pub kind: String,
+    /// JSON data of the operation, as received from the client
+    pub data: String,
+    /// Error from the latest attempt to apply the operation
+    pub last_error: Option<String>,
+    /// when operation was queued
+    pub created: NaiveDateTime,
+}
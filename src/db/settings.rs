@@ -0,0 +1,16 @@
+//! Keys of the daemon wide settings stored in the settings table
+
+/// Kernel flavor (e.g. `rt`) that the default boot entry is kept on
+pub const PREFERRED_FLAVOR: &str = "preferred_flavor";
+
+/// How many of the newest applied snapshots the retention policy keeps
+pub const KEEP_APPLIED_SNAPSHOTS: &str = "keep_applied_snapshots";
+
+/// How many of the newest never applied snapshots the retention policy keeps
+pub const KEEP_DRAFT_SNAPSHOTS: &str = "keep_draft_snapshots";
+
+/// RFC 3339 time until which bootloader changes are frozen
+pub const FROZEN_UNTIL: &str = "frozen_until";
+
+/// Why bootloader changes are frozen
+pub const FREEZE_REASON: &str = "freeze_reason";
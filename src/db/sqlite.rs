@@ -0,0 +1,854 @@
+//! Storage in an SQLite database, the default
+
+use std::{
+    collections::BTreeMap,
+    fs::{copy, remove_file},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use sqlx::{
+    sqlite::{
+        SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions,
+        SqliteSynchronous,
+    },
+    Connection, Error, Pool, Sqlite,
+};
+
+use crate::{
+    db::{
+        audit_log::{AuditEntry, Caller},
+        entry_override::EntryOverride,
+        grub2::{Grub2Snapshot, SnapshotFile, SnapshotFilter, SnapshotLabel, SnapshotPage},
+        pending_operation::PendingOperation,
+        selected_snapshot::SelectedSnapshot,
+        Storage,
+    },
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::GrubFile,
+};
+
+/// How long a query waits for another connection to release its lock before
+/// failing with SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct SqliteStorage {
+    pool: Pool<Sqlite>,
+    path: PathBuf,
+}
+
+impl SqliteStorage {
+    pub async fn new(database_path: &Path) -> DResult<Self> {
+        if !database_path.exists() {
+            log::debug!("Database file in was not found. Creating it in path {database_path:?}");
+        }
+        // WAL lets the snapshots be read while a change is written, and with it
+        // NORMAL synchronous can only lose the latest commits on a power loss
+        let options = SqliteConnectOptions::new()
+            .filename(database_path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(BUSY_TIMEOUT);
+
+        // should this failure be fatal or should the snapshot features
+        // just be disabled?
+        let pool = SqlitePoolOptions::new()
+            .max_connections(10)
+            .connect_with(options)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot initialize SQLite database in path: {database_path:?}"),
+            )?;
+
+        Ok(Self {
+            pool,
+            path: database_path.into(),
+        })
+    }
+
+    /// Check that the database is intact and has the snapshots of a system
+    async fn verify(&self) -> DResult<()> {
+        let integrity: String = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_one(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot check the integrity of the backup")?;
+        if integrity != "ok" {
+            return Err(DError::invalid_data(
+                dctx!(),
+                format!("Backup is corrupted: {integrity}"),
+            ));
+        }
+
+        let snapshots: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM grub2_snapshot")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|_| DError::invalid_data(dctx!(), "Backup is not a bootkit database"))?;
+        if snapshots == 0 {
+            return Err(DError::invalid_data(dctx!(), "Backup has no snapshots"));
+        }
+        Ok(())
+    }
+
+    /// Replace the tables with the ones of the verified database in `path`
+    async fn restore_from(&self, path: &Path) -> DResult<()> {
+        let backup = Self::new(path).await?;
+        backup.verify().await?;
+        // older backups are brought to the same columns first
+        backup.migrate().await?;
+        backup.pool.close().await;
+
+        let mut conn = self
+            .pool
+            .acquire()
+            .await
+            .ctx(dctx!(), "Cannot get a database connection")?;
+        sqlx::query("ATTACH DATABASE ? AS backup")
+            .bind(path.to_string_lossy())
+            .execute(&mut *conn)
+            .await
+            .ctx(dctx!(), format!("Cannot attach backup {path:?}"))?;
+        let copied = copy_tables(&mut conn).await;
+        // detached even when copying failed, the connection is used again
+        let detached = sqlx::query("DETACH DATABASE backup")
+            .execute(&mut *conn)
+            .await
+            .ctx(dctx!(), "Cannot detach the backup");
+        copied.and(detached.map(|_| ()))
+    }
+
+    /// Whether `table` exists without `column`
+    async fn lacks_column(&self, table: &str, column: &str) -> DResult<bool> {
+        let (tables, columns): (i64, i64) = sqlx::query_as(
+            "SELECT (SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?1), \
+            (SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name=?2)",
+        )
+        .bind(table)
+        .bind(column)
+        .fetch_one(&self.pool)
+        .await
+        .ctx(dctx!(), format!("Cannot get columns of {table}"))?;
+
+        Ok(tables > 0 && columns == 0)
+    }
+
+    /// Add the columns that databases from before the versioned migrations
+    /// may lack. The first migrations only create the tables that are missing.
+    async fn upgrade_unversioned(&self) -> DResult<()> {
+        let versioned: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='_sqlx_migrations'",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot check the database version")?;
+        if versioned > 0 {
+            return Ok(());
+        }
+
+        if self.lacks_column("grub2_snapshot", "applied").await? {
+            log::debug!("Adding applied column to grub2_snapshot table");
+            // every snapshot of older versions was saved after applying it
+            sqlx::query(
+                "ALTER TABLE grub2_snapshot ADD COLUMN applied BOOLEAN DEFAULT 0 NOT NULL; UPDATE grub2_snapshot SET applied=1",
+            )
+            .execute(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot add applied column to grub2_snapshot")?;
+        }
+
+        if self.lacks_column("grub2_snapshot", "tag").await? {
+            log::debug!("Adding tag column to grub2_snapshot table");
+            sqlx::query("ALTER TABLE grub2_snapshot ADD COLUMN tag TEXT")
+                .execute(&self.pool)
+                .await
+                .ctx(dctx!(), "Cannot add tag column to grub2_snapshot")?;
+        }
+
+        if self.lacks_column("audit_log", "sender").await? {
+            log::debug!("Adding caller columns to audit_log table");
+            // the callers of the earlier changes are not known
+            sqlx::query(
+                "ALTER TABLE audit_log ADD COLUMN sender TEXT; ALTER TABLE audit_log ADD COLUMN uid INTEGER; ALTER TABLE audit_log ADD COLUMN method TEXT",
+            )
+            .execute(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot add caller columns to audit_log")?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove overrides that don't override anything anymore
+    async fn remove_unused_entry_overrides(&self) -> DResult<()> {
+        sqlx::query!("DELETE FROM entry_override WHERE hidden=FALSE AND kind IS NULL")
+            .execute(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot remove unused entry overrides")?;
+
+        Ok(())
+    }
+}
+
+/// Replace the rows of every table with the ones in the attached `backup`
+/// database, in a single transaction
+async fn copy_tables(conn: &mut SqliteConnection) -> DResult<()> {
+    let tables: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM main.sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' AND name!='_sqlx_migrations'",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .ctx(dctx!(), "Cannot list the database tables")?;
+
+    let mut transaction = conn
+        .begin()
+        .await
+        .ctx(dctx!(), "Cannot start restoring the backup")?;
+    for table in tables {
+        // both have the same columns after migrating, but maybe in another order
+        let columns: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM pragma_table_info(?, 'main')")
+                .bind(&table)
+                .fetch_all(&mut *transaction)
+                .await
+                .ctx(dctx!(), format!("Cannot get columns of {table}"))?;
+        let columns = columns
+            .iter()
+            .map(|column| format!("\"{column}\""))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        sqlx::query(&format!("DELETE FROM main.\"{table}\""))
+            .execute(&mut *transaction)
+            .await
+            .ctx(dctx!(), format!("Cannot clear {table}"))?;
+        sqlx::query(&format!(
+            "INSERT INTO main.\"{table}\" ({columns}) SELECT {columns} FROM backup.\"{table}\""
+        ))
+        .execute(&mut *transaction)
+        .await
+        .ctx(dctx!(), format!("Cannot restore {table} from the backup"))?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .ctx(dctx!(), "Cannot finish restoring the backup")
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> DResult<()> {
+        self.upgrade_unversioned().await?;
+        sqlx::migrate!("./db/migrations")
+            .run(&self.pool)
+            .await
+            .map_err(Error::from)
+            .ctx(dctx!(), "Cannot migrate the database")?;
+
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    async fn grub2_snapshot_count(&self) -> DResult<i64> {
+        let snapshot_count = sqlx::query!("SELECT COUNT(*) as count FROM grub2_snapshot")
+            .fetch_one(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot get count from grub2_snapshot")?;
+
+        Ok(snapshot_count.count)
+    }
+
+    async fn save_grub2(
+        &self,
+        grub: &GrubFile,
+        selected_kernel: Option<&str>,
+        applied: bool,
+        tag: Option<&str>,
+    ) -> DResult<i64> {
+        let grub_file = grub.as_string();
+
+        let inserted = sqlx::query!(
+            "INSERT INTO grub2_snapshot (grub_config, selected_kernel, applied, tag) VALUES (?, ?, ?, ?)",
+            grub_file,
+            selected_kernel,
+            applied,
+            tag,
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot insert new entry to grub2_snapshot table")?;
+
+        log::debug!("New grub2 config snapshot inserted to grub2_snapshot table");
+        Ok(inserted.last_insert_rowid())
+    }
+
+    async fn remove_grub2(&self, grub_id: i64) -> DResult<()> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .ctx(dctx!(), "Cannot start removing a snapshot")?;
+        sqlx::query!("DELETE FROM snapshot_label WHERE snapshot_id=(?)", grub_id)
+            .execute(&mut *transaction)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot remove labels of snapshot {grub_id}"),
+            )?;
+        sqlx::query!("DELETE FROM snapshot_file WHERE snapshot_id=(?)", grub_id)
+            .execute(&mut *transaction)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot remove files of snapshot {grub_id}"),
+            )?;
+        sqlx::query!("DELETE FROM grub2_snapshot WHERE id=(?)", grub_id)
+            .execute(&mut *transaction)
+            .await
+            .ctx(dctx!(), "Cannot remove snapshot with id {grub_id}")?;
+        transaction
+            .commit()
+            .await
+            .ctx(dctx!(), format!("Cannot remove snapshot with id {grub_id}"))?;
+
+        log::debug!("Grub2 snapshot with id {grub_id} was removed");
+        Ok(())
+    }
+
+    async fn set_grub2_applied(&self, grub_id: i64) -> DResult<()> {
+        sqlx::query!("UPDATE grub2_snapshot SET applied=1 WHERE id=(?)", grub_id)
+            .execute(&self.pool)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot mark snapshot with id {grub_id} as applied"),
+            )?;
+
+        Ok(())
+    }
+
+    async fn set_grub2_pinned(&self, grub_id: i64, pinned: bool) -> DResult<()> {
+        let updated = sqlx::query!(
+            "UPDATE grub2_snapshot SET pinned=(?) WHERE id=(?)",
+            pinned,
+            grub_id
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(dctx!(), format!("Cannot pin snapshot with id {grub_id}"))?;
+        if updated.rows_affected() == 0 {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Snapshot with id '{grub_id}' not found"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn set_grub2_cfg_hash(&self, grub_id: i64, sha256: &str) -> DResult<()> {
+        let updated = sqlx::query!(
+            "UPDATE grub2_snapshot SET grub_cfg_sha256=(?) WHERE id=(?)",
+            sha256,
+            grub_id
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(
+            dctx!(),
+            format!("Cannot set grub.cfg hash of snapshot {grub_id}"),
+        )?;
+        if updated.rows_affected() == 0 {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Snapshot with id '{grub_id}' not found"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn set_grub2_description(&self, grub_id: i64, description: Option<&str>) -> DResult<()> {
+        let updated = sqlx::query!(
+            "UPDATE grub2_snapshot SET description=(?) WHERE id=(?)",
+            description,
+            grub_id
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(
+            dctx!(),
+            format!("Cannot describe snapshot with id {grub_id}"),
+        )?;
+        if updated.rows_affected() == 0 {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Snapshot with id '{grub_id}' not found"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn latest_grub2(&self) -> DResult<Grub2Snapshot> {
+        // Columns are listed, since the columns added to older databases are
+        // in a different order
+        let snapshot = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT id, grub_config, selected_kernel, applied, created, tag, pinned, description, saved_entry, files_captured, grub_cfg_sha256 FROM grub2_snapshot ORDER BY id DESC LIMIT 1",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
+
+        Ok(snapshot)
+    }
+
+    async fn grub2_snapshots(&self) -> DResult<Vec<Grub2Snapshot>> {
+        let snapshots = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT id, grub_config, selected_kernel, applied, created, tag, pinned, description, saved_entry, files_captured, grub_cfg_sha256 FROM grub2_snapshot ORDER BY id DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
+
+        Ok(snapshots)
+    }
+
+    async fn grub2_snapshot(&self, id: i64) -> DResult<Grub2Snapshot> {
+        let snapshots = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT id, grub_config, selected_kernel, applied, created, tag, pinned, description, saved_entry, files_captured, grub_cfg_sha256 FROM grub2_snapshot WHERE id=(?)",
+            id
+        )
+        .fetch_one(&self.pool)
+        .await
+        .ctx(
+            dctx!(),
+            "Cannot fetch snapshot with id '{id}' from grub2_snapshot table",
+        )?;
+
+        Ok(snapshots)
+    }
+
+    async fn grub2_snapshot_page(&self, filter: &SnapshotFilter) -> DResult<SnapshotPage> {
+        // a negative limit is no limit in SQLite
+        let limit = filter.limit.unwrap_or(-1);
+        let snapshots = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT id, grub_config, selected_kernel, applied, created, tag, pinned, description, saved_entry, files_captured, grub_cfg_sha256 FROM grub2_snapshot
+            WHERE (?1 IS NULL OR created >= ?1) AND (?2 IS NULL OR created <= ?2)
+            AND (?3 IS NULL OR tag = ?3) AND (?4 IS NULL OR selected_kernel = ?4)
+            AND (?5 IS NULL OR EXISTS (SELECT 1 FROM snapshot_label WHERE snapshot_id = grub2_snapshot.id AND label = ?5))
+            ORDER BY id DESC LIMIT ?6 OFFSET ?7",
+            filter.created_after,
+            filter.created_before,
+            filter.tag,
+            filter.selected_kernel,
+            filter.label,
+            limit,
+            filter.offset
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch snapshots from grub2_snapshot table")?;
+
+        let total = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM grub2_snapshot
+            WHERE (?1 IS NULL OR created >= ?1) AND (?2 IS NULL OR created <= ?2)
+            AND (?3 IS NULL OR tag = ?3) AND (?4 IS NULL OR selected_kernel = ?4)
+            AND (?5 IS NULL OR EXISTS (SELECT 1 FROM snapshot_label WHERE snapshot_id = grub2_snapshot.id AND label = ?5))",
+            filter.created_after,
+            filter.created_before,
+            filter.tag,
+            filter.selected_kernel,
+            filter.label
+        )
+        .fetch_one(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot count snapshots in grub2_snapshot table")?;
+
+        Ok(SnapshotPage { snapshots, total })
+    }
+
+    async fn snapshot_labels(&self) -> DResult<Vec<SnapshotLabel>> {
+        let labels = sqlx::query_as!(
+            SnapshotLabel,
+            "SELECT snapshot_id, label FROM snapshot_label ORDER BY snapshot_id, label"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch labels from snapshot_label table")?;
+
+        Ok(labels)
+    }
+
+    async fn set_snapshot_labels(&self, grub_id: i64, labels: &[String]) -> DResult<()> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .ctx(dctx!(), "Cannot start setting snapshot labels")?;
+        sqlx::query!("DELETE FROM snapshot_label WHERE snapshot_id=(?)", grub_id)
+            .execute(&mut *transaction)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot remove labels of snapshot {grub_id}"),
+            )?;
+        for label in labels {
+            sqlx::query!(
+                "INSERT INTO snapshot_label (snapshot_id, label) VALUES (?, ?)",
+                grub_id,
+                label
+            )
+            .execute(&mut *transaction)
+            .await
+            .ctx(dctx!(), format!("Cannot label snapshot {grub_id}"))?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .ctx(dctx!(), format!("Cannot save labels of snapshot {grub_id}"))
+    }
+
+    async fn snapshot_files(&self) -> DResult<Vec<SnapshotFile>> {
+        let files = sqlx::query_as!(
+            SnapshotFile,
+            "SELECT snapshot_id, name, contents FROM snapshot_file ORDER BY snapshot_id, name"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch files from snapshot_file table")?;
+
+        Ok(files)
+    }
+
+    async fn set_snapshot_files(
+        &self,
+        grub_id: i64,
+        saved_entry: Option<&str>,
+        dropins: &BTreeMap<String, String>,
+    ) -> DResult<()> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .ctx(dctx!(), "Cannot start setting snapshot files")?;
+        let updated = sqlx::query!(
+            "UPDATE grub2_snapshot SET saved_entry=(?), files_captured=1 WHERE id=(?)",
+            saved_entry,
+            grub_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .ctx(
+            dctx!(),
+            format!("Cannot set saved_entry of snapshot {grub_id}"),
+        )?;
+        if updated.rows_affected() == 0 {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Snapshot with id '{grub_id}' not found"),
+            ));
+        }
+        sqlx::query!("DELETE FROM snapshot_file WHERE snapshot_id=(?)", grub_id)
+            .execute(&mut *transaction)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot remove files of snapshot {grub_id}"),
+            )?;
+        for (name, contents) in dropins {
+            sqlx::query!(
+                "INSERT INTO snapshot_file (snapshot_id, name, contents) VALUES (?, ?, ?)",
+                grub_id,
+                name,
+                contents
+            )
+            .execute(&mut *transaction)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot save file {name} of snapshot {grub_id}"),
+            )?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .ctx(dctx!(), format!("Cannot save files of snapshot {grub_id}"))
+    }
+
+    async fn size_bytes(&self) -> DResult<u64> {
+        let size: i64 = sqlx::query_scalar(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch the size of the database")?;
+
+        Ok(size as u64)
+    }
+
+    async fn free_bytes(&self) -> DResult<i64> {
+        let free: i64 = sqlx::query_scalar(
+            "SELECT freelist_count * page_size FROM pragma_freelist_count(), pragma_page_size()",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch free pages of the database")?;
+
+        Ok(free)
+    }
+
+    async fn selected_snapshot(&self) -> DResult<SelectedSnapshot> {
+        let snapshot = sqlx::query_as!(SelectedSnapshot, "SELECT * FROM selected_snapshot",)
+            .fetch_one(&self.pool)
+            .await
+            .ctx(
+                dctx!(),
+                "Cannot fetch selected snapshot from selected_snapshot table",
+            )?;
+
+        Ok(snapshot)
+    }
+
+    async fn set_selected_snapshot(&self, id: Option<i64>) -> DResult<()> {
+        sqlx::query!("UPDATE selected_snapshot SET grub2_snapshot_id=(?)", id)
+            .execute(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot snapshot from selected snapshot table")?;
+
+        Ok(())
+    }
+
+    async fn setting(&self, key: &str) -> DResult<Option<String>> {
+        let setting = sqlx::query!("SELECT value FROM settings WHERE key=(?)", key)
+            .fetch_optional(&self.pool)
+            .await
+            .ctx(dctx!(), format!("Cannot fetch setting '{key}'"))?;
+
+        Ok(setting.map(|setting| setting.value))
+    }
+
+    async fn set_setting(&self, key: &str, value: Option<&str>) -> DResult<()> {
+        if let Some(value) = value {
+            sqlx::query!(
+                "INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value=excluded.value",
+                key,
+                value
+            )
+            .execute(&self.pool)
+            .await
+            .ctx(dctx!(), format!("Cannot save setting '{key}'"))?;
+        } else {
+            sqlx::query!("DELETE FROM settings WHERE key=(?)", key)
+                .execute(&self.pool)
+                .await
+                .ctx(dctx!(), format!("Cannot remove setting '{key}'"))?;
+        }
+
+        Ok(())
+    }
+
+    async fn add_pending_operation(&self, kind: &str, data: &str) -> DResult<i64> {
+        let id = sqlx::query!(
+            "INSERT INTO pending_operation (kind, data) VALUES (?, ?)",
+            kind,
+            data
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(
+            dctx!(),
+            "Cannot insert new entry to pending_operation table",
+        )?
+        .last_insert_rowid();
+
+        log::debug!("Pending operation {id} ({kind}) inserted to pending_operation table");
+        Ok(id)
+    }
+
+    async fn pending_operations(&self) -> DResult<Vec<PendingOperation>> {
+        let operations = sqlx::query_as!(
+            PendingOperation,
+            "SELECT * FROM pending_operation ORDER BY id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(
+            dctx!(),
+            "Cannot fetch operations from pending_operation table",
+        )?;
+
+        Ok(operations)
+    }
+
+    async fn set_pending_operation_error(&self, id: i64, error: &str) -> DResult<()> {
+        sqlx::query!(
+            "UPDATE pending_operation SET last_error=(?) WHERE id=(?)",
+            error,
+            id
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(dctx!(), format!("Cannot update pending operation {id}"))?;
+
+        Ok(())
+    }
+
+    async fn remove_pending_operation(&self, id: i64) -> DResult<()> {
+        sqlx::query!("DELETE FROM pending_operation WHERE id=(?)", id)
+            .execute(&self.pool)
+            .await
+            .ctx(dctx!(), format!("Cannot remove pending operation {id}"))?;
+
+        log::debug!("Pending operation {id} was removed");
+        Ok(())
+    }
+
+    async fn entry_overrides(&self) -> DResult<Vec<EntryOverride>> {
+        let overrides = sqlx::query_as!(
+            EntryOverride,
+            r#"SELECT entry, hidden as "hidden: bool", kind FROM entry_override"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch overrides from entry_override table")?;
+
+        Ok(overrides)
+    }
+
+    async fn set_entries_hidden(&self, entries: &[String], hidden: bool) -> DResult<()> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .ctx(dctx!(), "Cannot start entry_override transaction")?;
+
+        for entry in entries {
+            sqlx::query!(
+                "INSERT INTO entry_override (entry, hidden) VALUES (?, ?) ON CONFLICT(entry) DO UPDATE SET hidden=excluded.hidden",
+                entry,
+                hidden
+            )
+            .execute(&mut *transaction)
+            .await
+            .ctx(dctx!(), format!("Cannot set hidden state of entry '{entry}'"))?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .ctx(dctx!(), "Cannot commit entry_override transaction")?;
+        self.remove_unused_entry_overrides().await
+    }
+
+    async fn set_entries_kind(&self, entries: &[String], kind: Option<&str>) -> DResult<()> {
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .ctx(dctx!(), "Cannot start entry_override transaction")?;
+
+        for entry in entries {
+            sqlx::query!(
+                "INSERT INTO entry_override (entry, kind) VALUES (?, ?) ON CONFLICT(entry) DO UPDATE SET kind=excluded.kind",
+                entry,
+                kind
+            )
+            .execute(&mut *transaction)
+            .await
+            .ctx(dctx!(), format!("Cannot set kind of entry '{entry}'"))?;
+        }
+
+        transaction
+            .commit()
+            .await
+            .ctx(dctx!(), "Cannot commit entry_override transaction")?;
+        self.remove_unused_entry_overrides().await
+    }
+
+    async fn add_audit_entry(
+        &self,
+        action: &str,
+        changes: &str,
+        commands: &str,
+        caller: &Caller,
+    ) -> DResult<()> {
+        let uid = caller.uid.map(i64::from);
+        sqlx::query!(
+            "INSERT INTO audit_log (action, changes, commands, sender, uid, method) VALUES (?, ?, ?, ?, ?, ?)",
+            action,
+            changes,
+            commands,
+            caller.sender,
+            uid,
+            caller.method
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot insert new entry to audit_log table")?;
+
+        log::debug!("New {action} entry inserted to audit_log table");
+        Ok(())
+    }
+
+    async fn audit_entries(&self) -> DResult<Vec<AuditEntry>> {
+        let entries = sqlx::query_as!(AuditEntry, "SELECT * FROM audit_log ORDER BY id DESC")
+            .fetch_all(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot fetch entries from audit_log table")?;
+
+        Ok(entries)
+    }
+
+    async fn audit_entry(&self, id: i64) -> DResult<AuditEntry> {
+        let entry = sqlx::query_as!(AuditEntry, "SELECT * FROM audit_log WHERE id=(?)", id)
+            .fetch_one(&self.pool)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot fetch entry with id '{id}' from audit_log table"),
+            )?;
+
+        Ok(entry)
+    }
+
+    async fn backup(&self, path: &Path) -> DResult<()> {
+        // VACUUM INTO writes a consistent copy and refuses to overwrite files
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy())
+            .execute(&self.pool)
+            .await
+            .ctx(dctx!(), format!("Cannot back up the database to {path:?}"))?;
+
+        log::info!("Database backed up to {path:?}");
+        Ok(())
+    }
+
+    async fn restore(&self, path: &Path) -> DResult<()> {
+        // the backup is verified and migrated in a copy, the given file is left as is
+        let restored = self.path.with_extension("restore");
+        copy(path, &restored).ctx(dctx!(), format!("Cannot read backup {path:?}"))?;
+        let result = self.restore_from(&restored).await;
+        for suffix in ["", "-wal", "-shm"] {
+            let mut file = restored.clone().into_os_string();
+            file.push(suffix);
+            let _ = remove_file(file);
+        }
+        result?;
+
+        log::info!("Database restored from {path:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! Authorization of the methods that change the bootloader.
+//!
+//! Callers must be root, or in the wheel group or the configured admin group,
+//! and then be authorized by polkit. Each kind of change has its own polkit
+//! action, so administrators can allow e.g. selecting snapshots without
+//! allowing arbitrary config changes.
+
+use std::{collections::HashMap, ffi::CString};
+
+use nix::unistd::{getgrouplist, Gid, Group, User};
+use zbus::{
+    fdo::DBusProxy,
+    message::Header,
+    names::{BusName, UniqueName},
+    proxy,
+    zvariant::Value,
+    Connection,
+};
+
+use crate::{
+    db::audit_log::Caller,
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Saving, resetting and merging the grub config
+pub const SAVE_CONFIG: &str = "org.opensuse.bootkit.save-config";
+/// Selecting an earlier config snapshot
+pub const SELECT_SNAPSHOT: &str = "org.opensuse.bootkit.select-snapshot";
+/// Removing and pruning config snapshots
+pub const REMOVE_SNAPSHOT: &str = "org.opensuse.bootkit.remove-snapshot";
+/// Changing the default boot entry, or the entry of the next boot
+pub const SET_DEFAULT: &str = "org.opensuse.bootkit.set-default";
+/// Other changes, like the firmware boot order and the daemon settings
+pub const MANAGE: &str = "org.opensuse.bootkit.manage";
+pub const RESTART: &str = "org.opensuse.bootkit.restart";
+
+/// Let polkit ask the caller for authentication
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// Group of the administrators, allowed to make changes besides root
+const WHEEL_GROUP: &str = "wheel";
+
+#[proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    /// Returns whether the subject is authorized, whether the authorization was
+    /// dismissed and details of the result
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+/// Is the user `uid` in `groups` allowed to make changes
+fn is_privileged(uid: u32, groups: &[u32], admin_groups: &[u32]) -> bool {
+    uid == 0 || groups.iter().any(|gid| admin_groups.contains(gid))
+}
+
+/// All the groups of the user, for buses that don't tell the groups of the caller
+fn user_groups(uid: u32) -> Vec<u32> {
+    let Ok(Some(user)) = User::from_uid(uid.into()) else {
+        return Vec::new();
+    };
+    let Ok(name) = CString::new(user.name) else {
+        return Vec::new();
+    };
+    getgrouplist(&name, user.gid)
+        .map(|groups| groups.into_iter().map(Gid::as_raw).collect())
+        .unwrap_or_default()
+}
+
+/// Checks the callers of the methods that change the bootloader
+#[derive(Debug, Clone)]
+pub struct Authorizer {
+    /// Callers are not checked on the session bus, they already own the daemon
+    enabled: bool,
+    /// Groups whose members may make changes besides root
+    admin_groups: Vec<u32>,
+}
+
+impl Authorizer {
+    pub fn new(session: bool, admin_group: Option<&str>) -> Self {
+        let mut admin_groups = Vec::new();
+        for name in std::iter::once(WHEEL_GROUP).chain(admin_group) {
+            match Group::from_name(name) {
+                Ok(Some(group)) => admin_groups.push(group.gid.as_raw()),
+                _ if name == WHEEL_GROUP => {}
+                _ => log::warn!("Admin group '{name}' does not exist, only root and {WHEEL_GROUP} can make changes"),
+            }
+        }
+
+        Self {
+            enabled: !session,
+            admin_groups,
+        }
+    }
+
+    /// Make sure the sender of the message is allowed, and authorized by polkit,
+    /// to do `action`. Returns the caller the changes are recorded for.
+    pub async fn check(
+        &self,
+        connection: &Connection,
+        header: &Header<'_>,
+        action: &str,
+    ) -> DResult<Caller> {
+        let mut caller = Caller {
+            sender: header.sender().map(|sender| sender.to_string()),
+            uid: None,
+            method: header.member().map(|member| member.to_string()),
+        };
+        if !self.enabled {
+            return Ok(caller);
+        }
+
+        let Some(sender) = header.sender() else {
+            return Err(DError::not_authorized(
+                dctx!(),
+                "Message has no sender to authorize",
+            ));
+        };
+        caller.uid = Some(self.check_credentials(connection, sender).await?);
+
+        let authority = AuthorityProxy::new(connection)
+            .await
+            .ctx(dctx!(), "Cannot connect to polkit")?;
+        let subject = (
+            "system-bus-name",
+            HashMap::from([("name", Value::from(sender.as_str()))]),
+        );
+        let (authorized, _, _) = authority
+            .check_authorization(&subject, action, HashMap::new(), ALLOW_USER_INTERACTION, "")
+            .await
+            .ctx(dctx!(), format!("Cannot check authorization of {action}"))?;
+
+        if !authorized {
+            log::warn!("{sender} is not authorized for {action}");
+            return Err(DError::not_authorized(
+                dctx!(),
+                format!("Not authorized for {action}"),
+            ));
+        }
+
+        log::debug!("{sender} is authorized for {action}");
+        Ok(caller)
+    }
+
+    /// Refuse the senders that are not root or in one of the admin groups,
+    /// returns the user id of the sender
+    async fn check_credentials(
+        &self,
+        connection: &Connection,
+        sender: &UniqueName<'_>,
+    ) -> DResult<u32> {
+        let bus = DBusProxy::new(connection)
+            .await
+            .ctx(dctx!(), "Cannot connect to the message bus")?;
+        let credentials = bus
+            .get_connection_credentials(BusName::from(sender.as_ref()))
+            .await
+            .map_err(zbus::Error::from)
+            .ctx(dctx!(), format!("Cannot get the credentials of {sender}"))?;
+        let Some(uid) = credentials.unix_user_id() else {
+            return Err(DError::not_authorized(
+                dctx!(),
+                format!("User of {sender} is not known"),
+            ));
+        };
+        let groups = match credentials.unix_group_ids() {
+            Some(groups) => groups.clone(),
+            None => user_groups(uid),
+        };
+
+        if !is_privileged(uid, &groups, &self.admin_groups) {
+            log::warn!("Refusing a change from {sender} of unprivileged user {uid}");
+            return Err(DError::not_authorized(
+                dctx!(),
+                "Only root and the admin group members can change the bootloader",
+            ));
+        }
+
+        Ok(uid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_privileged() {
+        assert!(is_privileged(0, &[], &[]));
+        assert!(is_privileged(1000, &[100, 10], &[10]));
+        assert!(!is_privileged(1000, &[100], &[10]));
+        assert!(!is_privileged(1000, &[100], &[]));
+    }
+}
@@ -1,87 +1,1480 @@
-use zbus::{connection::Builder, fdo, interface, object_server::SignalEmitter, Connection};
+use std::{
+    collections::HashMap,
+    fs::create_dir_all,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
-use crate::{config::ConfigArgs, db::Database, dbus::handler::DbusHandler};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::unbounded_channel;
+use zbus::{
+    connection::Builder,
+    fdo, interface,
+    message::Header,
+    object_server::{Interface, SignalEmitter},
+    zvariant::OwnedFd,
+    Connection, ObjectServer,
+};
 
-struct BootKitInfo {}
+use crate::{
+    bootloader::Backend,
+    config::{
+        tools::{GrubToolOverride, MissingTool},
+        ConfigArgs, FileLink, Paths,
+    },
+    db::{Database, StorageKind},
+    dbus::{
+        auth::{self, Authorizer},
+        fd::{payload_fd, read_payload},
+        from_json,
+        namespace::Namespace,
+        to_json,
+        typed::{BootEntryV2, BootKitConfigV2},
+    },
+    dctx,
+    errors::{BootkitError, DError, DRes, DResult},
+    events::{pause::MAX_PAUSE, reconcile},
+    policy::PolicyStatus,
+    restart::{restart_process, InFlight, RESTART_DELAY, RESTART_DRAIN_TIMEOUT},
+    services::{
+        background::{BackgroundJobs, JobIdData},
+        config::{ConfigService, DiffOptions, RawConfigData},
+        entry::EntryService,
+        job::with_job,
+        queue::ChangeQueue,
+        snapshot::{DatabaseFileData, PinSnapshotData, SnapshotService},
+        uefi::UefiService,
+        Freeze, Services,
+    },
+};
+
+#[derive(Debug, Serialize)]
+struct StatusData {
+    version: String,
+    /// Bootloader in use on the host system
+    backend: Backend,
+    /// "system" or "session"
+    bus: String,
+    /// D-Bus policy and polkit action files, empty on the session bus
+    policy: Vec<PolicyStatus>,
+    /// Misconfigurations found on startup
+    problems: Vec<String>,
+    /// Symlink or bind mount /etc/default/grub is managed through, if any
+    grub_file_link: Option<FileLink>,
+    /// Seconds until the paused file watchers resume, `null` if they aren't paused
+    watchers_paused: Option<u64>,
+    /// Active change freeze of the host system, `null` if changes aren't frozen
+    freeze: Option<Freeze>,
+    /// Tools that are not installed, operations that need them fail
+    missing_tools: Vec<MissingTool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PauseWatchersData {
+    /// How long to pause the watchers, at most an hour
+    seconds: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct BuildInfo {
+    version: String,
+    git_hash: String,
+    /// RFC 3339 time of the build
+    build_date: String,
+    /// Enabled cargo features
+    features: Vec<String>,
+    rustc_version: String,
+}
+
+impl BuildInfo {
+    fn new() -> Self {
+        let build_date = env!("BOOTKIT_BUILD_EPOCH")
+            .parse::<i64>()
+            .ok()
+            .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+            .map_or_else(|| "unknown".into(), |date| date.to_rfc3339());
+
+        let mut features = Vec::new();
+        if cfg!(feature = "dev") {
+            features.push("dev".into());
+        }
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").into(),
+            git_hash: env!("BOOTKIT_GIT_HASH").into(),
+            build_date,
+            features,
+            rustc_version: env!("BOOTKIT_RUSTC_VERSION").into(),
+        }
+    }
+}
+
+pub struct BootKitInfo {
+    bus: String,
+    policy: Vec<PolicyStatus>,
+    started: Instant,
+    services: Services,
+    auth: Authorizer,
+}
 
 #[interface(name = "org.opensuse.bootkit.Info")]
 impl BootKitInfo {
-    async fn get_version(&self) -> Result<String, fdo::Error> {
+    async fn get_version(&self) -> Result<String, BootkitError> {
         log::debug!("Calling org.opensuse.bootkit.Info GetVersion");
         Ok(env!("CARGO_PKG_VERSION").into())
     }
+
+    async fn get_status(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info GetStatus");
+        let data = StatusData {
+            version: env!("CARGO_PKG_VERSION").into(),
+            backend: self.services.state.backend(),
+            grub_file_link: self.services.state.paths.grub_file_link(),
+            watchers_paused: self.services.state.watchers.remaining(),
+            freeze: self.services.state.freeze().await?,
+            missing_tools: self.services.state.missing_tools(),
+            bus: self.bus.clone(),
+            policy: self.policy.clone(),
+            problems: self
+                .policy
+                .iter()
+                .filter_map(|status| status.problem.clone())
+                .collect(),
+        };
+        Ok(to_json(&data)?)
+    }
+
+    async fn get_build_info(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info GetBuildInfo");
+        Ok(to_json(&BuildInfo::new())?)
+    }
+
+    /// Stop signaling file changes until ResumeWatchers is called or the pause runs out
+    async fn pause_watchers(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info PauseWatchers");
+        self.auth.check(connection, &header, auth::MANAGE).await?;
+        let data: PauseWatchersData = from_json(data)?;
+        let duration = Duration::from_secs(data.seconds);
+        if duration.is_zero() || duration > MAX_PAUSE {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "Watchers can be paused for 1 to {} seconds",
+                    MAX_PAUSE.as_secs()
+                ),
+            )
+            .into());
+        }
+
+        self.services.state.watchers.pause(duration);
+        log::info!("Watchers paused for {} seconds", data.seconds);
+        Ok("ok".into())
+    }
+
+    /// Resume the watchers and signal the changes made while they were paused
+    async fn resume_watchers(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info ResumeWatchers");
+        self.auth.check(connection, &header, auth::MANAGE).await?;
+        let Some(suppressed) = self.services.state.watchers.resume() else {
+            return Err(DError::generic(dctx!(), "Watchers are not paused").into());
+        };
+        reconcile(&emitter, &self.services, suppressed).await?;
+        Ok("ok".into())
+    }
+
+    /// Refuse all changes to the bootloader of the host system until the given time
+    async fn set_freeze(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info SetFreeze");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let data: Freeze = from_json(data)?;
+        caller.scope(self.services.state.set_freeze(&data)).await?;
+        Ok("ok".into())
+    }
+
+    /// End the change freeze before its end time
+    async fn unfreeze(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info Unfreeze");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        caller.scope(self.services.state.unfreeze()).await?;
+        Ok("ok".into())
+    }
+
+    /// Report of the whole boot configuration as text or JSON, for support tickets
+    async fn generate_report(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info GenerateReport");
+        Ok(self
+            .services
+            .report
+            .generate_report(from_json(data)?)
+            .await?)
+    }
+
+    /// Wait for the changes in progress to finish and restart the daemon.
+    /// New changes are refused until the daemon is back on the bus.
+    async fn restart_service(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Info RestartService");
+        self.auth.check(connection, &header, auth::RESTART).await?;
+        let in_flight = self.services.state.in_flight.clone();
+        in_flight.drain(RESTART_DRAIN_TIMEOUT).await?;
+
+        log::info!("Restarting the daemon");
+        if let Err(err) = Self::service_restarting(&emitter, RESTART_DELAY.as_millis() as u64).await
+        {
+            log::warn!("Cannot signal the restart: {err}");
+        }
+
+        // let the reply reach the caller before going away
+        tokio::spawn(async move {
+            tokio::time::sleep(RESTART_DELAY).await;
+            // the error is logged when it's dropped
+            if restart_process().is_err() {
+                in_flight.resume();
+            }
+        });
+        Ok("ok".into())
+    }
+
+    /// Name of the bootloader in use, like `grub2`
+    #[zbus(property)]
+    async fn bootloader_type(&self) -> String {
+        self.services.state.backend().name().into()
+    }
+
+    /// Seconds since the daemon was started
+    #[zbus(property)]
+    async fn uptime(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    /// Signal for the bootloader of the host system being changed, e.g. to systemd-boot
+    #[zbus(signal)]
+    async fn backend_changed(emitter: &SignalEmitter<'_>, backend: &str) -> zbus::Result<()>;
+
+    /// Signal for the daemon restarting in `delay_ms` milliseconds. Clients should wait
+    /// for the bus name to have an owner again and then reload their state.
+    #[zbus(signal)]
+    async fn service_restarting(emitter: &SignalEmitter<'_>, delay_ms: u64) -> zbus::Result<()>;
 }
 
 pub struct BootKitSnapshots {
-    handler: DbusHandler,
+    snapshots: SnapshotService,
+    changes: ChangeQueue,
+    auth: Authorizer,
 }
 
 #[interface(name = "org.opensuse.bootkit.Snapshot")]
 impl BootKitSnapshots {
-    async fn get_snapshots(&self) -> Result<String, fdo::Error> {
+    /// Id of the selected snapshot, 0 when the latest snapshot is used
+    #[zbus(property)]
+    async fn selected_snapshot_id(&self) -> fdo::Result<i64> {
+        Ok(self.snapshots.selected_snapshot_id().await?)
+    }
+
+    async fn get_snapshots(&self) -> Result<String, BootkitError> {
         log::debug!("Calling org.opensuse.bootkit.Snapshot GetSnapshots");
-        let data = self.handler.get_snapshots_json().await?;
-        Ok(data)
+        let data = self.snapshots.snapshots(DiffOptions::default()).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Same as GetSnapshots, with the diffs in the format the client asks for
+    async fn get_snapshots_with_options(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot GetSnapshotsWithOptions");
+        let data = self.snapshots.snapshots(from_json(data)?).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Same as GetSnapshots, but the JSON is read from the returned file descriptor
+    async fn get_snapshots_fd(&self) -> Result<OwnedFd, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot GetSnapshotsFd");
+        let data = self.snapshots.snapshots(DiffOptions::default()).await?;
+        Ok(payload_fd("snapshots", to_json(&data)?.as_bytes())?)
+    }
+
+    /// Write a snapshot to a file that ImportSnapshot reads on another machine
+    async fn export_snapshot(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot ExportSnapshot");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        caller
+            .scope(self.snapshots.export_snapshot(from_json(data)?))
+            .await?;
+        Ok("ok".into())
+    }
+
+    /// Save an exported snapshot as a draft
+    async fn import_snapshot(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot ImportSnapshot");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.snapshots.import_snapshot(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Unified diff and the changed keys between any two snapshots
+    async fn compare_snapshots(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot CompareSnapshots");
+        let data = self.snapshots.compare_snapshots(from_json(data)?).await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn get_storage_stats(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot GetStorageStats");
+        let data = self.snapshots.storage_stats().await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn get_retention_policy(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot GetRetentionPolicy");
+        let data = self.snapshots.retention_policy().await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn set_retention_policy(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot SetRetentionPolicy");
+        self.auth.check(connection, &header, auth::MANAGE).await?;
+        // the snapshots the new policy doesn't keep are pruned right away
+        let caller = self
+            .auth
+            .check(connection, &header, auth::REMOVE_SNAPSHOT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.snapshots.set_retention_policy(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
     }
 
-    async fn remove_snapshot(&self, data: &str) -> Result<String, fdo::Error> {
+    async fn prune_snapshots(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot PruneSnapshots");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::REMOVE_SNAPSHOT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller.scope(self.snapshots.prune_snapshots()).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Remove a snapshot that is neither selected nor pinned
+    async fn remove_snapshot(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
         log::debug!("Calling org.opensuse.bootkit.Snapshot RemoveSnapshot");
-        let data = self.handler.remove_snapshot(data).await?;
-        Ok(data)
+        let caller = self
+            .auth
+            .check(connection, &header, auth::REMOVE_SNAPSHOT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.snapshots.remove_snapshot(from_json(data)?))
+            .await?;
+        Ok("ok".into())
     }
 
-    async fn select_snapshot(&self, data: &str) -> Result<String, fdo::Error> {
+    async fn select_snapshot(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
         log::debug!("Calling org.opensuse.bootkit.Snapshot SelectSnapshot");
-        let data = self.handler.select_snapshot(data).await?;
-        Ok(data)
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SELECT_SNAPSHOT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.snapshots.select_snapshot(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Select and apply the snapshot applied before the selected one
+    async fn revert_to_previous(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot RevertToPrevious");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SELECT_SNAPSHOT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller.scope(self.snapshots.revert_to_previous()).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Replace the user defined labels of a snapshot
+    async fn set_snapshot_labels(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot SetSnapshotLabels");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.snapshots.set_snapshot_labels(from_json(data)?))
+            .await?;
+        Ok("ok".into())
+    }
+
+    /// Set the free text description of a snapshot, empty text removes it
+    async fn set_snapshot_description(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot SetSnapshotDescription");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.snapshots.set_snapshot_description(from_json(data)?))
+            .await?;
+        Ok("ok".into())
+    }
+
+    /// Keep a snapshot from being removed by the retention policy
+    async fn set_snapshot_pinned(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot SetSnapshotPinned");
+        let pin_data: PinSnapshotData = from_json(data)?;
+        let mut caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        if !pin_data.pinned {
+            // unpinned snapshots the policy doesn't keep are pruned right away
+            caller = self
+                .auth
+                .check(connection, &header, auth::REMOVE_SNAPSHOT)
+                .await?;
+        }
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.snapshots.set_snapshot_pinned(pin_data))
+            .await?;
+        Ok("ok".into())
+    }
+
+    /// Pin a snapshot, same as SetSnapshotPinned with pinned set to true
+    async fn pin_snapshot(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot PinSnapshot");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.snapshots.pin_snapshot(from_json(data)?))
+            .await?;
+        Ok("ok".into())
+    }
+
+    /// Unpin a snapshot, same as SetSnapshotPinned with pinned set to false
+    async fn unpin_snapshot(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot UnpinSnapshot");
+        self.auth.check(connection, &header, auth::MANAGE).await?;
+        // the snapshot may be pruned right away
+        let caller = self
+            .auth
+            .check(connection, &header, auth::REMOVE_SNAPSHOT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.snapshots.unpin_snapshot(from_json(data)?))
+            .await?;
+        Ok("ok".into())
+    }
+
+    /// Signal for snapshots being created, removed or selected
+    #[zbus(signal)]
+    async fn snapshots_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+pub struct BootKitMaintenance {
+    snapshots: SnapshotService,
+    changes: ChangeQueue,
+    auth: Authorizer,
+}
+
+#[interface(name = "org.opensuse.bootkit.Maintenance")]
+impl BootKitMaintenance {
+    /// Copy the stored snapshots, settings and audit log to a new file
+    async fn backup_database(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Maintenance BackupDatabase");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let data: DatabaseFileData = from_json(data)?;
+        caller.scope(self.snapshots.backup_database(data)).await?;
+        Ok("ok".into())
+    }
+
+    /// Replace the stored snapshots, settings and audit log with a verified backup
+    async fn restore_database(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Maintenance RestoreDatabase");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        let data: DatabaseFileData = from_json(data)?;
+        caller.scope(self.snapshots.restore_database(data)).await?;
+        Ok("ok".into())
     }
 }
 
 pub struct BootKitConfig {
-    handler: DbusHandler,
+    config: ConfigService,
+    background: BackgroundJobs,
+    changes: ChangeQueue,
+    auth: Authorizer,
 }
 
 #[interface(name = "org.opensuse.bootkit.Config")]
 impl BootKitConfig {
-    async fn get_config(&self) -> Result<String, fdo::Error> {
+    /// Boot menu timeout in seconds, empty when it's not set
+    #[zbus(property)]
+    async fn timeout(&self) -> fdo::Result<String> {
+        Ok(self.config.timeout().await?)
+    }
+
+    async fn get_config(&self) -> Result<String, BootkitError> {
         log::debug!("Calling org.opensuse.bootkit.Config GetConfig");
-        let data = self.handler.get_grub2_config_json().await?;
-        Ok(data)
+        let data = self.config.config(DiffOptions::default()).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Same as GetConfig, with the diff in the format the client asks for
+    async fn get_config_with_options(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetConfigWithOptions");
+        let data = self.config.config(from_json(data)?).await?;
+        Ok(to_json(&data)?)
     }
 
-    async fn save_config(&self, data: &str) -> Result<String, fdo::Error> {
+    /// Documented config keys and the formats of their values
+    async fn get_key_schema(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetKeySchema");
+        Ok(to_json(&self.config.key_schema())?)
+    }
+
+    /// Check a config like SaveConfig, returning the file it would write and
+    /// its diff without applying anything
+    async fn validate_config(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config ValidateConfig");
+        let data = self.config.validate_config(from_json(data)?).await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn save_config(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
         log::debug!("Calling org.opensuse.bootkit.Config SaveConfig");
-        let data = self.handler.save_grub2_config(data).await?;
-        Ok(data)
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.config.save_config(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Same as SaveConfig, but returns the id of a background job right away.
+    /// The progress is signaled with JobProgress and the result with JobFinished.
+    async fn start_save_config(
+        &self,
+        data: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config StartSaveConfig");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let config_data = from_json(data)?;
+        let (progress, mut stages) = unbounded_channel();
+        let (id, control) = self.background.start(progress);
+        let config = self.config.clone();
+        let background = self.background.clone();
+        let emitter = emitter.into_owned();
+        let changes = self.changes.clone();
+        tokio::spawn(async move {
+            let save = caller.scope(with_job(control, async {
+                let _turn = changes.turn().await?;
+                config.save_config(config_data).await
+            }));
+            tokio::pin!(save);
+            let result = loop {
+                tokio::select! {
+                    result = &mut save => break result,
+                    Some(stage) = stages.recv() => {
+                        background.set_stage(id, stage);
+                        if let Err(err) = Self::job_progress(&emitter, id, stage.name()).await {
+                            log::warn!("Cannot signal the progress of job {id}: {err}");
+                        }
+                    }
+                }
+            };
+
+            let state = background.finish(id, result);
+            let signal = match to_json(&state) {
+                Ok(state) => Self::job_finished(&emitter, id, &state).await,
+                Err(_) => return,
+            };
+            if let Err(err) = signal {
+                log::warn!("Cannot signal the end of job {id}: {err}");
+            }
+        });
+        Ok(to_json(&JobIdData { id })?)
+    }
+
+    /// Stop a background job and restore the previous config. Jobs can't be
+    /// cancelled once grub.cfg is regenerated.
+    async fn cancel_job(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config CancelJob");
+        self.auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let id = from_json::<JobIdData>(data)?.id;
+        self.background.cancel(id)?;
+        Ok("ok".into())
+    }
+
+    /// State of a job started with StartSaveConfig
+    async fn get_job(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetJob");
+        let id = from_json::<JobIdData>(data)?.id;
+        let Some(state) = self.background.job(id) else {
+            return Err(DError::generic(dctx!(), format!("Job {id} is not found")).into());
+        };
+        Ok(to_json(&state)?)
+    }
+
+    async fn save_raw_config(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SaveRawConfig");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.config.save_raw_config(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Same as SaveRawConfig, but the config file contents are read from `fd`
+    /// and `options` has the apply options as JSON
+    async fn save_raw_config_fd(
+        &self,
+        fd: OwnedFd,
+        options: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SaveRawConfigFd");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        // a slow client must not hold up the changes of the others
+        let raw = RawConfigData {
+            contents: read_payload(fd).await?,
+            apply_options: Some(from_json(options)?),
+        };
+        let _turn = self.changes.turn().await?;
+        let data = caller.scope(self.config.save_raw_config(raw)).await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn reset_to_distro_defaults(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config ResetToDistroDefaults");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.config.reset_to_distro_defaults(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn get_config_variants(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetConfigVariants");
+        let data = self.config.config_variants().await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn merge_rpmnew(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config MergeRpmnew");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.config.merge_rpmnew(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn make_menu_accessible(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config MakeMenuAccessible");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller.scope(self.config.make_menu_accessible()).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Set a kernel parameter, keeping the order and quoting of the other parameters
+    async fn add_kernel_param(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config AddKernelParam");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.config.add_kernel_param(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Remove a kernel parameter, keeping the order and quoting of the other parameters
+    async fn remove_kernel_param(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config RemoveKernelParam");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.config.remove_kernel_param(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn preview_boot_behavior(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config PreviewBootBehavior");
+        let data = self.config.preview_boot_behavior().await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn get_audit_entry_diff(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetAuditEntryDiff");
+        let data = self.config.audit_entry_diff(from_json(data)?).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Audit log entries matching the filters, newest first
+    async fn get_audit_log(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetAuditLog");
+        let data = self.config.audit_log(from_json(data)?).await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn list_pending_operations(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config ListPendingOperations");
+        let data = self.config.pending_operations().await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn apply_pending_operations(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Config ApplyPendingOperations");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SAVE_CONFIG)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller.scope(self.config.apply_pending_operations()).await?;
+        Ok(to_json(&data)?)
     }
 
     /// Signal for grub file being changed, provided by zbus macro
     #[zbus(signal)]
     async fn file_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// Same as FileChanged, with the unified diff from the snapshot the config
+    /// is compared against to the changed file
+    #[zbus(signal)]
+    async fn file_changed_detailed(emitter: &SignalEmitter<'_>, diff: &str) -> zbus::Result<()>;
+
+    /// Signal for grub.rpmnew or grub.rpmsave appearing or being removed
+    #[zbus(signal)]
+    async fn config_variants_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// Signal for a background job reaching an apply stage, like `mkconfig`
+    #[zbus(signal)]
+    async fn job_progress(emitter: &SignalEmitter<'_>, id: u64, stage: &str) -> zbus::Result<()>;
+
+    /// Signal for a background job finishing, `state` is the same JSON as GetJob returns
+    #[zbus(signal)]
+    async fn job_finished(emitter: &SignalEmitter<'_>, id: u64, state: &str) -> zbus::Result<()>;
 }
 
 pub struct BootEntry {
-    handler: DbusHandler,
+    entries: EntryService,
+    config: ConfigService,
+    changes: ChangeQueue,
+    auth: Authorizer,
 }
 
 #[interface(name = "org.opensuse.bootkit.BootEntry")]
 impl BootEntry {
-    async fn get_entries(&self) -> Result<String, fdo::Error> {
+    /// Title of the entry booted by default
+    #[zbus(property)]
+    async fn default_kernel(&self) -> fdo::Result<String> {
+        Ok(self.entries.default_kernel().await?)
+    }
+
+    async fn get_entries(&self) -> Result<String, BootkitError> {
         log::debug!("Calling org.opensuse.bootkit.BootEntry GetEntries");
-        let data = self.handler.get_grub2_boot_entries_json().await?;
+        let data = self.entries.boot_entries().await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn prefer_flavor(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry PreferFlavor");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SET_DEFAULT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.entries.prefer_flavor(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Make the grub entry the default, saving the config as a new snapshot
+    async fn set_default(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetDefault");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SET_DEFAULT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.config.set_default_entry(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Boot the entry on the next boot only, without changing the default entry
+    async fn boot_once(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry BootOnce");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SET_DEFAULT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.entries.boot_once(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Set the default entry of systemd-boot with bootctl
+    async fn set_loader_default(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetLoaderDefault");
+        let caller = self
+            .auth
+            .check(connection, &header, auth::SET_DEFAULT)
+            .await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.entries.set_loader_default(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Set the menu timeout of systemd-boot with bootctl
+    async fn set_loader_timeout(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetLoaderTimeout");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.entries.set_loader_timeout(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn set_entries_hidden(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetEntriesHidden");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.entries.set_entries_hidden(from_json(data)?))
+            .await?;
+        Ok("ok".into())
+    }
+
+    async fn set_entries_kind(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetEntriesKind");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        caller
+            .scope(self.entries.set_entries_kind(from_json(data)?))
+            .await?;
+        Ok("ok".into())
+    }
+
+    /// Translated display names of the entries, with the kernel versions marked
+    async fn get_entry_titles(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetEntryTitles");
+        let data = self.entries.entry_titles(from_json(data)?).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Kernel package transactions, snapshots and config changes, newest first
+    async fn get_timeline(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetTimeline");
+        let data = self.entries.timeline().await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn get_initrd_summaries(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetInitrdSummaries");
+        let data = self.entries.initrd_summaries().await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Raw contents of the generated grub.cfg, read from the returned file descriptor
+    async fn get_grub_cfg_fd(&self) -> Result<OwnedFd, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetGrubCfgFd");
+        let grub_cfg = self.entries.grub_cfg().await?;
+        Ok(payload_fd("grub.cfg", grub_cfg.as_bytes())?)
+    }
+
+    async fn export_entries_as_bls(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry ExportEntriesAsBls");
+        self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        let data = self.entries.export_bls(from_json(data)?).await?;
+        Ok(to_json(&data)?)
+    }
+
+    /// Signal for grub.cfg being regenerated, e.g. by a kernel package install
+    #[zbus(signal)]
+    async fn boot_entries_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// Signal for kernel images or initrds being installed or removed in /boot.
+    /// The entries change with them once grub.cfg is regenerated.
+    #[zbus(signal)]
+    async fn kernels_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// Signal for saved_entry in grubenv being changed, e.g. with grub2-set-default.
+    /// The Rust name differs so it doesn't clash with the DefaultKernel property.
+    #[zbus(signal, name = "DefaultKernelChanged")]
+    async fn default_entry_changed(
+        emitter: &SignalEmitter<'_>,
+        new_entry: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Firmware boot manager of the host, only served for the host system
+pub struct BootKitUefi {
+    uefi: UefiService,
+    changes: ChangeQueue,
+    auth: Authorizer,
+}
+
+#[interface(name = "org.opensuse.bootkit.Uefi")]
+impl BootKitUefi {
+    /// Boot#### options, the BootOrder and the current and next boot options
+    async fn get_boot_entries(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Uefi GetBootEntries");
+        let data = self.uefi.boot_entries()?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn set_boot_order(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Uefi SetBootOrder");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.uefi.set_boot_order(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+
+    async fn set_boot_entry_active(
+        &self,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Uefi SetBootEntryActive");
+        let caller = self.auth.check(connection, &header, auth::MANAGE).await?;
+        let _turn = self.changes.turn().await?;
+        let data = caller
+            .scope(self.uefi.set_boot_entry_active(from_json(data)?))
+            .await?;
+        Ok(to_json(&data)?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetData {
+    /// Root directory of the managed system, e.g. an image build chroot
+    root: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TargetInfo {
+    root: PathBuf,
+    object_path: String,
+}
+
+/// A registered target and the database it was served with
+struct Target {
+    object_path: String,
+    db: Database,
+}
+
+/// Other systems, like image build chroots, managed by this daemon instance.
+/// Each target gets its own database and is served in its own object path.
+pub struct BootKitTargets {
+    namespace: Namespace,
+    targets: HashMap<PathBuf, Target>,
+    next_id: usize,
+    in_flight: InFlight,
+    storage: StorageKind,
+    max_snapshots: Option<usize>,
+    grub_backups: usize,
+    grub_tools: Vec<GrubToolOverride>,
+    grub_dir: Option<PathBuf>,
+    auth: Authorizer,
+}
+
+impl BootKitTargets {
+    async fn register(&mut self, server: &ObjectServer, data: &str) -> DResult<String> {
+        let target: TargetData = from_json(data)?;
+
+        if !target.root.is_absolute() || !target.root.is_dir() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Target root {:?} is not an absolute directory", target.root),
+            ));
+        }
+
+        let root = target.root.canonicalize().ctx(
+            dctx!(),
+            format!("Cannot resolve target root {:?}", target.root),
+        )?;
+        let mut paths = Paths::with_root(&root);
+        paths.set_grub_backups(self.grub_backups);
+        paths.detect_grub_tools(&self.grub_tools);
+        paths.detect_boot_partitions();
+        paths.detect_grub_dir(self.grub_dir.as_deref());
+        if paths.is_host() {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "Host system is always served at {}",
+                    self.namespace.object_path()
+                ),
+            ));
+        }
+
+        if self.targets.contains_key(&root) {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Target {root:?} is already registered"),
+            ));
+        }
+
+        if let Some(db_dir) = paths.database().parent() {
+            create_dir_all(db_dir).ctx(
+                dctx!(),
+                format!("Cannot create database directory {db_dir:?}"),
+            )?;
+        }
+
+        let db = Database::new(&paths, self.storage).await?;
+        if let Err(err) = db.initialize(&paths).await {
+            db.close().await;
+            return Err(err);
+        }
+
+        // a path is never handed out twice, not even after a failed serve,
+        // so a client can't mistake a new target for an old one
+        let object_path = self.namespace.target_path(self.next_id);
+        self.next_id += 1;
+        let services = Services::new(
+            db.clone(),
+            paths,
+            self.in_flight.clone(),
+            self.max_snapshots,
+        );
+        if let Err(err) = serve_services(server, &object_path, services, self.auth.clone()).await {
+            if let Err(remove_err) = remove_services(server, &object_path).await {
+                log::warn!("Cannot clean up target at {object_path}: {remove_err:?}");
+            }
+            db.close().await;
+            return Err(err).ctx(dctx!(), format!("Cannot serve target at {object_path}"));
+        }
+
+        log::info!("Registered target {root:?} at {object_path}");
+        self.targets.insert(
+            root.clone(),
+            Target {
+                object_path: object_path.clone(),
+                db,
+            },
+        );
+
+        to_json(&TargetInfo { root, object_path })
+    }
+
+    async fn unregister(&mut self, server: &ObjectServer, data: &str) -> DResult<String> {
+        let target: TargetData = from_json(data)?;
+        let root = target.root.canonicalize().unwrap_or(target.root);
+
+        let Some(target) = self.targets.get(&root) else {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Target {root:?} is not registered"),
+            ));
+        };
+        let object_path = target.object_path.clone();
+
+        // the target is kept until all of its interfaces are gone, so a
+        // failed removal can be retried
+        remove_services(server, &object_path).await?;
+        if let Some(target) = self.targets.remove(&root) {
+            target.db.close().await;
+        }
+
+        log::info!("Unregistered target {root:?} from {object_path}");
+        Ok("ok".into())
+    }
+
+    fn list(&self) -> DResult<String> {
+        let targets: Vec<TargetInfo> = self
+            .targets
+            .iter()
+            .map(|(root, target)| TargetInfo {
+                root: root.clone(),
+                object_path: target.object_path.clone(),
+            })
+            .collect();
+
+        to_json(&targets)
+    }
+}
+
+#[interface(name = "org.opensuse.bootkit.Targets")]
+impl BootKitTargets {
+    async fn register_target(
+        &mut self,
+        #[zbus(object_server)] server: &ObjectServer,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Targets RegisterTarget");
+        self.auth.check(connection, &header, auth::MANAGE).await?;
+        let data = self.register(server, data).await?;
+        Ok(data)
+    }
+
+    async fn unregister_target(
+        &mut self,
+        #[zbus(object_server)] server: &ObjectServer,
+        data: &str,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Targets UnregisterTarget");
+        self.auth.check(connection, &header, auth::MANAGE).await?;
+        let data = self.unregister(server, data).await?;
+        Ok(data)
+    }
+
+    async fn list_targets(&self) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Targets ListTargets");
+        let data = self.list()?;
         Ok(data)
     }
 }
 
-pub async fn create_connection(args: &ConfigArgs, db: &Database) -> zbus::Result<Connection> {
-    let handler = DbusHandler::new(db.clone());
+/// Development helpers, only served on dev builds
+#[cfg(feature = "dev")]
+pub struct BootKitDev {
+    jobs: crate::services::job::JobService,
+}
+
+#[cfg(feature = "dev")]
+#[interface(name = "org.opensuse.bootkit.Dev")]
+impl BootKitDev {
+    /// Make the next apply fail at the given stage
+    async fn simulate_failure(&self, data: &str) -> Result<String, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.Dev SimulateFailure");
+        let data: crate::services::job::SimulateFailureData = from_json(data)?;
+        self.jobs.simulate_failure(data.stage);
+        Ok("ok".into())
+    }
+}
+
+/// Serve the config, boot entry and snapshot interfaces of a single system
+async fn serve_services(
+    server: &ObjectServer,
+    object_path: &str,
+    services: Services,
+    auth: Authorizer,
+) -> zbus::Result<()> {
+    let bootentry_v2 = BootEntryV2 {
+        entries: services.entries.clone(),
+    };
+    let config_v2 = BootKitConfigV2 {
+        config: services.config.clone(),
+    };
+    let changes = services.state.changes.clone();
+    let bootentry = BootEntry {
+        entries: services.entries,
+        config: services.config.clone(),
+        changes: changes.clone(),
+        auth: auth.clone(),
+    };
     let config = BootKitConfig {
-        handler: handler.clone(),
+        config: services.config,
+        background: BackgroundJobs::default(),
+        changes: changes.clone(),
+        auth: auth.clone(),
+    };
+    let maintenance = BootKitMaintenance {
+        snapshots: services.snapshots.clone(),
+        changes: changes.clone(),
+        auth: auth.clone(),
     };
     let snapshots = BootKitSnapshots {
-        handler: handler.clone(),
+        snapshots: services.snapshots,
+        changes,
+        auth,
+    };
+
+    server.at(object_path, config).await?;
+    server.at(object_path, bootentry).await?;
+    server.at(object_path, snapshots).await?;
+    server.at(object_path, maintenance).await?;
+    server.at(object_path, config_v2).await?;
+    server.at(object_path, bootentry_v2).await?;
+    #[cfg(feature = "dev")]
+    server
+        .at(
+            object_path,
+            BootKitDev {
+                jobs: services.jobs,
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+/// Remove the interfaces `serve_services` serves at `object_path`. Interfaces
+/// that aren't served are skipped, so a partly served path is cleaned up too.
+async fn remove_services(server: &ObjectServer, object_path: &str) -> DResult<()> {
+    remove_interface::<BootKitConfig>(server, object_path, "config").await?;
+    remove_interface::<BootEntry>(server, object_path, "boot entry").await?;
+    remove_interface::<BootKitSnapshots>(server, object_path, "snapshot").await?;
+    remove_interface::<BootKitMaintenance>(server, object_path, "maintenance").await?;
+    remove_interface::<BootKitConfigV2>(server, object_path, "v2 config").await?;
+    remove_interface::<BootEntryV2>(server, object_path, "v2 boot entry").await?;
+    #[cfg(feature = "dev")]
+    remove_interface::<BootKitDev>(server, object_path, "dev").await?;
+    Ok(())
+}
+
+async fn remove_interface<I: Interface>(
+    server: &ObjectServer,
+    object_path: &str,
+    name: &str,
+) -> DResult<()> {
+    match server.remove::<I, _>(object_path).await {
+        Ok(_) | Err(zbus::Error::InterfaceNotFound) => Ok(()),
+        Err(err) => Err(err).ctx(dctx!(), format!("Cannot remove target {name} interface")),
+    }
+}
+
+pub async fn create_connection(
+    args: &ConfigArgs,
+    namespace: &Namespace,
+    services: Services,
+    policy: Vec<PolicyStatus>,
+) -> zbus::Result<Connection> {
+    let auth = Authorizer::new(args.session, args.admin_group.as_deref());
+    let targets = BootKitTargets {
+        namespace: namespace.clone(),
+        targets: HashMap::new(),
+        next_id: 0,
+        in_flight: services.state.in_flight.clone(),
+        storage: args.storage,
+        max_snapshots: args.max_snapshots,
+        grub_backups: args.grub_backups,
+        grub_tools: args.grub_tools.clone(),
+        grub_dir: args.grub_dir.clone(),
+        auth: auth.clone(),
     };
-    let bootentry = BootEntry { handler };
 
     let (connection, contype) = if args.session {
         (Builder::session()?, "session")
@@ -89,16 +1482,41 @@ pub async fn create_connection(args: &ConfigArgs, db: &Database) -> zbus::Result
         (Builder::system()?, "system")
     };
 
+    let uefi = BootKitUefi {
+        uefi: UefiService::new(services.state.clone()),
+        changes: services.state.changes.clone(),
+        auth: auth.clone(),
+    };
+
+    let info = BootKitInfo {
+        bus: contype.into(),
+        policy,
+        started: Instant::now(),
+        services: services.clone(),
+        auth: auth.clone(),
+    };
+
     let connection = connection
-        .name("org.opensuse.bootkit")?
-        .serve_at("/org/opensuse/bootkit", BootKitInfo {})?
-        .serve_at("/org/opensuse/bootkit", config)?
-        .serve_at("/org/opensuse/bootkit", bootentry)?
-        .serve_at("/org/opensuse/bootkit", snapshots)?
+        .name(namespace.bus_name())?
+        .serve_at(namespace.object_path(), info)?
+        .serve_at(namespace.object_path(), targets)?
+        .serve_at(namespace.object_path(), uefi)?
         .build()
         .await?;
 
-    log::info!("Started dbus {contype} connection");
+    serve_services(
+        connection.object_server(),
+        namespace.object_path(),
+        services,
+        auth,
+    )
+    .await?;
+
+    log::info!(
+        "Started dbus {contype} connection as {} at {}",
+        namespace.bus_name(),
+        namespace.object_path()
+    );
 
     Ok(connection)
 }
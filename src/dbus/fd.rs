@@ -0,0 +1,138 @@
+//! Large payloads passed as unix file descriptors.
+//!
+//! Inlining megabytes of data into a method call or return blocks the bus while
+//! the message is routed, so big payloads are sent as a memory backed file that
+//! the receiver reads on its own time.
+
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    os::fd::AsFd,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::memfd::{memfd_create, MFdFlags},
+};
+use zbus::zvariant::OwnedFd;
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+    services::job::run_blocking,
+};
+
+/// Largest payload read from a file descriptor sent by a client
+const MAX_FD_PAYLOAD: u64 = 64 * 1024 * 1024;
+
+/// How long a client has to write the payload and close its end of the file descriptor
+const FD_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// File descriptor of a memory backed file with `data`, positioned at the start
+pub fn payload_fd(name: &str, data: &[u8]) -> DResult<OwnedFd> {
+    let fd = memfd_create(name, MFdFlags::MFD_CLOEXEC).map_err(|err| {
+        DError::generic(dctx!(), format!("Cannot create memory file {name}: {err}"))
+    })?;
+
+    let mut file = File::from(fd);
+    file.write_all(data).ctx(
+        dctx!(),
+        format!("Cannot write payload to memory file {name}"),
+    )?;
+    file.seek(SeekFrom::Start(0))
+        .ctx(dctx!(), format!("Cannot rewind memory file {name}"))?;
+    Ok(std::os::fd::OwnedFd::from(file).into())
+}
+
+/// Read the text payload from a file descriptor sent by a client, on the
+/// blocking thread pool. Read it before taking a turn of the change queue, a
+/// client that never closes its end of a pipe is only refused after the timeout.
+pub async fn read_payload(fd: OwnedFd) -> DResult<String> {
+    run_blocking(move || read_payload_until(fd, Instant::now() + FD_READ_TIMEOUT)).await
+}
+
+/// Read the payload without blocking past `deadline`. The file description is
+/// shared with the client, so it is polled instead of made non-blocking.
+fn read_payload_until(fd: OwnedFd, deadline: Instant) -> DResult<String> {
+    let mut file = File::from(std::os::fd::OwnedFd::from(fd));
+    let mut payload = Vec::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let timeout = PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX);
+        let mut fds = [PollFd::new(file.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(0) => {
+                return Err(DError::invalid_data(
+                    dctx!(),
+                    format!(
+                        "Client did not close the payload file descriptor in {} seconds",
+                        FD_READ_TIMEOUT.as_secs()
+                    ),
+                ))
+            }
+            Ok(_) | Err(nix::errno::Errno::EINTR) => {}
+            Err(err) => {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Cannot wait for payload from client file descriptor: {err}"),
+                ))
+            }
+        }
+
+        // readable or hung up, so the read doesn't block
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                payload.extend_from_slice(&buffer[..read]);
+                if payload.len() as u64 > MAX_FD_PAYLOAD {
+                    return Err(DError::invalid_data(
+                        dctx!(),
+                        format!("Payload is larger than {MAX_FD_PAYLOAD} bytes"),
+                    ));
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => {
+                return Err(err).ctx(dctx!(), "Cannot read payload from client file descriptor")
+            }
+        }
+    }
+
+    String::from_utf8(payload)
+        .map_err(|_| DError::invalid_data(dctx!(), "Payload is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_payload_roundtrip() {
+        let data = "GRUB_TIMEOUT=8\n".repeat(10_000);
+        let fd = payload_fd("test", data.as_bytes()).unwrap();
+        assert_eq!(read_payload(fd).await.unwrap(), data);
+    }
+
+    #[test]
+    fn test_unclosed_pipe_times_out() {
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        writer.write_all(b"GRUB_TIMEOUT=8\n").unwrap();
+        let fd = std::os::fd::OwnedFd::from(reader).into();
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(100);
+        assert!(read_payload_until(fd, deadline).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        writer.write_all(b"GRUB_TIMEOUT=8\n").unwrap();
+        drop(writer);
+        let fd = std::os::fd::OwnedFd::from(reader).into();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert_eq!(
+            read_payload_until(fd, deadline).unwrap(),
+            "GRUB_TIMEOUT=8\n"
+        );
+    }
+}
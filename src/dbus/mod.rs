@@ -1,2 +1,27 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+pub mod auth;
 pub mod connection;
-mod handler;
+pub mod fd;
+pub mod namespace;
+pub mod typed;
+
+/// Parse the JSON data received from a client
+fn from_json<T: DeserializeOwned>(data: &str) -> DResult<T> {
+    serde_json::from_str(data).map_err(|err| {
+        DError::invalid_data(
+            dctx!(),
+            format!("Malformed JSON data received from client: {err}"),
+        )
+    })
+}
+
+/// Serialize data that is sent back to the client
+fn to_json<T: Serialize>(data: &T) -> DResult<String> {
+    serde_json::to_string(data).ctx(dctx!(), "Failed to serialize data for the client")
+}
@@ -0,0 +1,39 @@
+use crate::config::ConfigArgs;
+
+pub const DEFAULT_BUS_NAME: &str = "org.opensuse.bootkit";
+pub const DEFAULT_OBJECT_PATH: &str = "/org/opensuse/bootkit";
+
+/// Bus name and object paths the daemon is served at.
+///
+/// Interface names are not part of the namespace, they're fixed by the
+/// `#[interface]` attributes so clients can rely on them. Code that needs them,
+/// like the PropertiesChanged signals, gets them with `Interface::name()` of
+/// the served type instead of repeating them.
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    bus_name: String,
+    object_path: String,
+}
+
+impl Namespace {
+    pub fn new(args: &ConfigArgs) -> Self {
+        Self {
+            bus_name: args.bus_name.clone(),
+            object_path: args.object_path.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn bus_name(&self) -> &str {
+        &self.bus_name
+    }
+
+    /// Object path of the host system
+    pub fn object_path(&self) -> &str {
+        &self.object_path
+    }
+
+    /// Object path of a registered target system
+    pub fn target_path(&self, id: usize) -> String {
+        format!("{}/targets/{id}", self.object_path)
+    }
+}
@@ -0,0 +1,133 @@
+//! Interfaces with typed D-Bus signatures.
+//!
+//! The `org.opensuse.bootkit` interfaces pass their data as JSON strings. The
+//! `org.opensuse.bootkit.v2` ones return the same data as D-Bus types, so clients
+//! written in C, Python or QML can use it without a JSON parser.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use zbus::{
+    interface,
+    zvariant::{OwnedValue, Type, Value},
+};
+
+use crate::{
+    errors::BootkitError,
+    grub2::diff::DiffFormat,
+    services::{
+        config::{ConfigService, DiffOptions},
+        entry::{BootEntryDetails, EntryService},
+    },
+};
+
+/// Boot entry as `(ssas)`: full path, title and flags
+#[derive(Debug, Serialize, Type)]
+pub struct EntryRow {
+    full_path: String,
+    title: String,
+    /// Kind of the entry, like `linux` or `recovery`, followed by `microcode`
+    /// and `hidden` when they apply
+    flags: Vec<String>,
+}
+
+impl From<&BootEntryDetails> for EntryRow {
+    fn from(details: &BootEntryDetails) -> Self {
+        let mut flags = vec![details.kind.name().to_string()];
+        if details.microcode {
+            flags.push("microcode".into());
+        }
+        if details.hidden {
+            flags.push("hidden".into());
+        }
+        Self {
+            full_path: details.full_path.clone(),
+            title: details.entry.clone(),
+            flags,
+        }
+    }
+}
+
+fn owned(value: Value<'_>) -> Result<OwnedValue, BootkitError> {
+    Ok(OwnedValue::try_from(value).map_err(zbus::Error::from)?)
+}
+
+pub struct BootKitConfigV2 {
+    pub config: ConfigService,
+}
+
+#[interface(name = "org.opensuse.bootkit.v2.Config")]
+impl BootKitConfigV2 {
+    /// Current config with the keys:
+    /// - `values` (`a{ss}`): value of each config key
+    /// - `comments` (`a{sas}`): documentation comments of the keys that have them
+    /// - `selected_kernel` (`s`): left out when no entry is selected
+    /// - `parse_errors` (`a(uss)`): line number, raw line and message
+    /// - `invalid_values` (`a(sss)`): key, value and the problem of the value
+    async fn get_config(&self) -> Result<HashMap<String, OwnedValue>, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.v2.Config GetConfig");
+        let options = DiffOptions {
+            diff_format: DiffFormat::None,
+            ..Default::default()
+        };
+        let data = self.config.config(options).await?;
+
+        let parse_errors: Vec<_> = data
+            .parse_errors()
+            .iter()
+            .map(|error| {
+                (
+                    error.line as u32,
+                    error.raw_line.clone(),
+                    error.message.clone(),
+                )
+            })
+            .collect();
+        let invalid_values: Vec<_> = data
+            .invalid_values()
+            .iter()
+            .map(|invalid| {
+                (
+                    invalid.key.clone(),
+                    invalid.value.clone(),
+                    invalid.problem.clone(),
+                )
+            })
+            .collect();
+
+        let mut config = HashMap::new();
+        config.insert("values".into(), owned(Value::from(data.values()))?);
+        config.insert("comments".into(), owned(Value::from(data.comments()))?);
+        if let Some(selected) = data.selected_kernel() {
+            config.insert("selected_kernel".into(), owned(Value::from(selected))?);
+        }
+        config.insert("parse_errors".into(), owned(Value::from(parse_errors))?);
+        config.insert("invalid_values".into(), owned(Value::from(invalid_values))?);
+        Ok(config)
+    }
+}
+
+pub struct BootEntryV2 {
+    pub entries: EntryService,
+}
+
+#[interface(name = "org.opensuse.bootkit.v2.BootEntry")]
+impl BootEntryV2 {
+    async fn get_entries(&self) -> Result<Vec<EntryRow>, BootkitError> {
+        log::debug!("Calling org.opensuse.bootkit.v2.BootEntry GetEntries");
+        let data = self.entries.boot_entries().await?;
+        Ok(data.details().iter().map(EntryRow::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zbus::zvariant::Type;
+
+    use super::EntryRow;
+
+    #[test]
+    fn test_entry_row_signature() {
+        assert_eq!(EntryRow::SIGNATURE.to_string(), "(ssas)");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::services::job::ExecutedCommand;
+
 mod macros;
 
 /// Error context that should be created with `dctx!()` macro
@@ -23,7 +25,20 @@ pub enum DErrorType {
     /// Generic error when nothing else is applicable
     Error(String),
     GrubParse(String),
+    /// Bootloader changes are frozen, with the reason of the freeze
+    Frozen(String),
+    /// Program needed by the operation is not installed
+    ToolMissing(String),
+    /// Caller is not authorized by polkit to do the operation
+    NotAuthorized(String),
+    /// Data sent by the client is malformed
+    InvalidData(String),
+    /// Another change is running and didn't finish in time
+    Busy(String),
+    /// The file was changed since the client read it
+    Conflict(String),
     Io(String, Box<std::io::Error>),
+    #[cfg(feature = "sqlite")]
     Sqlx(String, Box<sqlx::Error>),
     Zbus(String, Box<zbus::Error>),
     Serde(String, Box<serde_json::Error>),
@@ -36,7 +51,14 @@ impl DErrorType {
             DErrorType::GrubParse(msg) => {
                 format!("Internal Parse: Failed to parse grub config: {msg}")
             }
+            DErrorType::Frozen(msg) => format!("Frozen: {msg}"),
+            DErrorType::ToolMissing(msg) => format!("ToolMissing: {msg}"),
+            DErrorType::NotAuthorized(msg) => format!("NotAuthorized: {msg}"),
+            DErrorType::InvalidData(msg) => format!("InvalidData: {msg}"),
+            DErrorType::Busy(msg) => format!("Busy: {msg}"),
+            DErrorType::Conflict(msg) => format!("Conflict: {msg}"),
             DErrorType::Io(msg, error) => format!("Internal IO error: {msg} ({error})"),
+            #[cfg(feature = "sqlite")]
             DErrorType::Sqlx(msg, error) => format!("Interal database error: {msg} ({error})"),
             DErrorType::Zbus(msg, error) => format!("Internal zbus error: {msg} ({error})"),
             DErrorType::Serde(msg, error) => format!("Json handling error: {msg} ({error})"),
@@ -58,6 +80,8 @@ pub struct DError {
     /// Additional places and messages where error was propagated, excluding the origin
     trace: Vec<(String, DCtx)>,
     error: DErrorType,
+    /// Commands that were run before the failure, so it can be reproduced manually
+    commands: Vec<ExecutedCommand>,
 }
 
 impl DError {
@@ -66,6 +90,7 @@ impl DError {
             ctx,
             error,
             trace: Vec::new(),
+            commands: Vec::new(),
         }
     }
 
@@ -83,9 +108,55 @@ impl DError {
         Self::new(ctx, DErrorType::GrubParse(message.into()))
     }
 
+    pub fn frozen<M: Into<String>>(ctx: DCtx, message: M) -> Self {
+        Self::new(ctx, DErrorType::Frozen(message.into()))
+    }
+
+    pub fn tool_missing<M: Into<String>>(ctx: DCtx, message: M) -> Self {
+        Self::new(ctx, DErrorType::ToolMissing(message.into()))
+    }
+
+    pub fn not_authorized<M: Into<String>>(ctx: DCtx, message: M) -> Self {
+        Self::new(ctx, DErrorType::NotAuthorized(message.into()))
+    }
+
+    pub fn invalid_data<M: Into<String>>(ctx: DCtx, message: M) -> Self {
+        Self::new(ctx, DErrorType::InvalidData(message.into()))
+    }
+
+    pub fn busy<M: Into<String>>(ctx: DCtx, message: M) -> Self {
+        Self::new(ctx, DErrorType::Busy(message.into()))
+    }
+
+    pub fn conflict<M: Into<String>>(ctx: DCtx, message: M) -> Self {
+        Self::new(ctx, DErrorType::Conflict(message.into()))
+    }
+
+    /// Record the commands that were run before the failure
+    pub fn with_commands(mut self, commands: &[ExecutedCommand]) -> Self {
+        self.commands = commands.to_vec();
+        self
+    }
+
     pub fn error(&self) -> &DErrorType {
         &self.error
     }
+
+    pub fn commands(&self) -> &[ExecutedCommand] {
+        &self.commands
+    }
+
+    /// Message of the error with the commands that were run before it, one per line
+    pub fn message(&self) -> String {
+        let mut message = self.error.as_string();
+        if !self.commands.is_empty() {
+            message.push_str("\nCommands run:");
+            for command in &self.commands {
+                message.push_str(&format!("\n{command}"));
+            }
+        }
+        message
+    }
 }
 
 /// We know that DError propagation stops when it's dropped so it's the perfect
@@ -99,9 +170,55 @@ impl Drop for DError {
     }
 }
 
+/// Error returned to D-Bus callers, named `org.opensuse.bootkit.Error.<Variant>`
+/// so that clients can tell the failures apart without parsing the message
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.opensuse.bootkit.Error")]
+pub enum BootkitError {
+    #[zbus(error)]
+    Zbus(zbus::Error),
+    /// Operation failed, nothing more specific is known
+    Failed(String),
+    /// Grub config could not be parsed
+    GrubParse(String),
+    /// Bootloader changes are frozen
+    Frozen(String),
+    /// Program needed by the operation is not installed
+    ToolMissing(String),
+    /// Caller is not allowed to do the operation
+    NotAuthorized(String),
+    /// JSON data of the call is malformed
+    InvalidData(String),
+    /// Another change is running, the call can be tried again later
+    Busy(String),
+    /// Changes were made since the client read the data, it has to be read again
+    Conflict(String),
+}
+
+impl From<DError> for BootkitError {
+    fn from(value: DError) -> Self {
+        let message = value.message();
+        match value.error() {
+            DErrorType::GrubParse(_) => Self::GrubParse(message),
+            DErrorType::Frozen(_) => Self::Frozen(message),
+            DErrorType::ToolMissing(_) => Self::ToolMissing(message),
+            DErrorType::NotAuthorized(_) => Self::NotAuthorized(message),
+            DErrorType::InvalidData(_) => Self::InvalidData(message),
+            DErrorType::Busy(_) => Self::Busy(message),
+            DErrorType::Conflict(_) => Self::Conflict(message),
+            _ => Self::Failed(message),
+        }
+    }
+}
+
+/// Properties are read through org.freedesktop.DBus.Properties, which only has
+/// the standard errors
 impl From<DError> for zbus::fdo::Error {
     fn from(value: DError) -> Self {
-        Self::Failed(value.error().as_string())
+        match value.error() {
+            DErrorType::NotAuthorized(_) => Self::AccessDenied(value.error().as_string()),
+            _ => Self::Failed(value.error().as_string()),
+        }
     }
 }
 
@@ -129,6 +246,7 @@ impl<T> DRes<T> for std::io::Result<T> {
     }
 }
 
+#[cfg(feature = "sqlite")]
 impl<T> DRes<T> for sqlx::Result<T> {
     fn ctx<M: Into<String>>(self, ctx: DCtx, msg: M) -> DResult<T> {
         match self {
@@ -0,0 +1,101 @@
+//! Tracking changes of the config files so what's read from them can be cached.
+//!
+//! Frontends poll the config whenever they get focus, and reading grub.cfg and
+//! diffing the config against a snapshot on every call is wasted work when
+//! nothing changed in between.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+#[derive(Debug, Default, Clone)]
+pub struct ConfigChanges {
+    /// The file watchers are running, without them changes cannot be noticed
+    watched: Arc<AtomicBool>,
+    /// Incremented on every change
+    generation: Arc<AtomicU64>,
+    /// Wakes up the task signaling the changed D-Bus properties
+    notify: Arc<Notify>,
+}
+
+impl ConfigChanges {
+    /// Mark whether the config files are watched, cached data can only be
+    /// trusted when they are
+    pub fn set_watched(&self, watched: bool) {
+        self.watched.store(watched, Ordering::SeqCst);
+    }
+
+    /// Something that's read with the config changed, the cached data is stale
+    pub fn changed(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+
+    /// Wait until something changes. Changes made between the waits are seen
+    /// as a single change.
+    pub async fn wait(&self) {
+        self.notify.notified().await;
+    }
+
+    /// Current generation of the config, `None` if changes aren't watched and
+    /// nothing should be cached
+    pub fn generation(&self) -> Option<u64> {
+        if self.watched.load(Ordering::SeqCst) {
+            Some(self.generation.load(Ordering::SeqCst))
+        } else {
+            None
+        }
+    }
+}
+
+/// Snapshots being created, removed or selected
+#[derive(Debug, Default, Clone)]
+pub struct SnapshotChanges {
+    notify: Arc<Notify>,
+}
+
+impl SnapshotChanges {
+    pub fn changed(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Wait until the snapshots change. Changes made between the waits are seen
+    /// as a single change.
+    pub async fn wait(&self) {
+        self.notify.notified().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation() {
+        let changes = ConfigChanges::default();
+        changes.changed();
+        assert_eq!(changes.generation(), None);
+
+        changes.set_watched(true);
+        let generation = changes.generation();
+        assert!(generation.is_some());
+        assert_eq!(changes.generation(), generation);
+
+        changes.clone().changed();
+        assert_ne!(changes.generation(), generation);
+    }
+
+    #[tokio::test]
+    async fn test_wait_after_change() {
+        let changes = ConfigChanges::default();
+        changes.changed();
+        changes.changed();
+        // both changes are seen by one wait
+        changes.wait().await;
+        let second = tokio::time::timeout(std::time::Duration::from_millis(10), changes.wait());
+        assert!(second.await.is_err());
+    }
+}
@@ -1,39 +1,344 @@
-use inotify::{EventMask, Inotify, WatchMask};
-use zbus::Connection;
+use std::{borrow::Cow, collections::HashMap, fs::read_to_string, time::Duration};
 
-use crate::{config::GRUB_ROOT_PATH, dbus::connection::BootKitConfigSignals};
+use zbus::{
+    fdo::Properties,
+    names::InterfaceName,
+    object_server::{Interface, SignalEmitter},
+    zvariant::Value,
+    Connection,
+};
 
-pub async fn listen_files(connection: &Connection) -> zbus::Result<()> {
-    let mut inotify = Inotify::init().expect("Failed to initialize inotify");
-    inotify
-        .watches()
-        .add(GRUB_ROOT_PATH, WatchMask::MODIFY)
-        .expect("Failed to watch /etc/default/grub");
+use crate::{
+    bootloader::Backend,
+    config::Paths,
+    dbus::{
+        connection::{
+            BootEntry, BootEntrySignals, BootKitConfig, BootKitConfigSignals, BootKitInfo,
+            BootKitInfoSignals, BootKitSnapshots, BootKitSnapshotsSignals,
+        },
+        namespace::Namespace,
+    },
+    dctx,
+    errors::{DRes, DResult},
+    events::{
+        pause::ChangedFiles,
+        watcher::{FileWatcher, RETRY_INTERVAL},
+    },
+    grub2::grub_env_value,
+    services::{
+        config::{DRIFT_TAG, EXTERNAL_CHANGE_TAG},
+        Services,
+    },
+};
 
+pub mod changes;
+pub mod pause;
+pub mod watcher;
+
+/// Milliseconds to wait for more file events before signaling a change
+pub const DEFAULT_DEBOUNCE_MS: u64 = 200;
+
+pub async fn listen_files(
+    connection: &Connection,
+    namespace: &Namespace,
+    services: &Services,
+    debounce: Duration,
+) -> DResult<()> {
+    let state = &services.state;
+    let mut watcher = FileWatcher::new(state)?;
     log::info!("Listening to config changes");
 
     loop {
-        let mut buffer = [0; 4096];
-        let events = inotify
-            .read_events_blocking(&mut buffer)
-            .expect("Failed to read inotify events");
-
-        // prevent duplicate modify event triggers
-        let mut signaled = false;
-        for event in events {
-            if event.mask.contains(EventMask::MODIFY)
-                && !signaled
-                && event.name.is_some_and(|name| name == "grub")
-            {
-                signaled = true;
-                connection
-                    .object_server()
-                    .interface("/org/opensuse/bootkit")
-                    .await?
-                    .file_changed()
-                    .await?;
-                log::debug!("{GRUB_ROOT_PATH} contents was modified. Signaling dbus");
+        let changed = match watcher.changes(state, debounce).await {
+            Ok(changed) => changed,
+            Err(_) => {
+                // the error is logged when it's dropped
+                log::warn!(
+                    "Watching the config files failed, starting again in {RETRY_INTERVAL:?}"
+                );
+                state.config_changes.set_watched(false);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+                if let Ok(restarted) = FileWatcher::new(state) {
+                    watcher = restarted;
+                }
+                continue;
             }
+        };
+
+        if state.watchers.suppress(changed) {
+            log::debug!("Watchers are paused, handling the changes after resuming");
+            continue;
+        }
+
+        let emitter = SignalEmitter::new(connection, namespace.object_path())
+            .ctx(dctx!(), "Invalid object path for the signals")?;
+        if let Err(err) = signal_changes(&emitter, services, changed).await {
+            log::warn!("Failed to signal file changes: {err}");
+        }
+    }
+}
+
+/// saved_entry in grubenv, empty when it's not set
+fn saved_entry(paths: &Paths) -> String {
+    read_to_string(paths.grub_env())
+        .ok()
+        .and_then(|grub_env| grub_env_value(&grub_env, "saved_entry").map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Signal clients about the changed files
+async fn signal_changes(
+    emitter: &SignalEmitter<'_>,
+    services: &Services,
+    changed: ChangedFiles,
+) -> zbus::Result<()> {
+    if changed.variants_changed {
+        emitter.config_variants_changed().await?;
+        log::info!("Package manager config variant of grub changed. Signaling dbus");
+    }
+
+    if changed.file_changed {
+        // diffed before the edit is snapshotted, after which there's nothing to diff
+        let diff = services.config.file_diff().await;
+        // errors are logged when they're dropped, the change is signaled anyway
+        let _ = services
+            .config
+            .snapshot_external_change(EXTERNAL_CHANGE_TAG)
+            .await;
+        emitter.file_changed().await?;
+        // the diff is only extra information, FileChanged is enough without it
+        if let Ok(diff) = diff {
+            emitter.file_changed_detailed(&diff).await?;
+        }
+        log::debug!("Grub config contents was modified. Signaling dbus");
+    }
+
+    if changed.entries_changed {
+        emitter.boot_entries_changed().await?;
+        log::debug!("grub.cfg was regenerated. Signaling dbus");
+    }
+
+    if changed.kernels_changed {
+        services.entries.prune_initrd_cache();
+        emitter.kernels_changed().await?;
+        log::debug!("Kernels in /boot changed. Signaling dbus");
+    }
+
+    if changed.default_changed {
+        emitter
+            .default_entry_changed(&saved_entry(&services.state.paths))
+            .await?;
+        log::debug!("Default boot entry in grubenv changed. Signaling dbus");
+    }
+
+    Ok(())
+}
+
+/// Snapshot and signal the edits made to the grub file while the daemon wasn't running
+pub async fn signal_drift(
+    connection: &Connection,
+    namespace: &Namespace,
+    services: &Services,
+) -> zbus::Result<()> {
+    let diff = services.config.file_diff().await;
+    // errors are logged when they're dropped
+    let Ok(true) = services.config.snapshot_external_change(DRIFT_TAG).await else {
+        return Ok(());
+    };
+
+    log::info!("Grub config was edited while the daemon wasn't running. Signaling dbus");
+    let emitter = SignalEmitter::new(connection, namespace.object_path())?;
+    emitter.file_changed().await?;
+    if let Ok(diff) = diff {
+        emitter.file_changed_detailed(&diff).await?;
+    }
+    Ok(())
+}
+
+/// Handle the changes that happened while the watchers were paused, all at once
+pub async fn reconcile(
+    emitter: &SignalEmitter<'_>,
+    services: &Services,
+    suppressed: ChangedFiles,
+) -> zbus::Result<()> {
+    log::info!("Watchers resumed, reconciling changes made while paused");
+    signal_changes(emitter, services, suppressed).await?;
+    // properties are not signaled while paused either
+    services.state.config_changes.changed();
+    if let Some(backend) = services.refresh_backend() {
+        signal_backend(emitter, backend).await?;
+    }
+
+    Ok(())
+}
+
+/// Signal BackendChanged and the changed BootloaderType property
+async fn signal_backend(emitter: &SignalEmitter<'_>, backend: Backend) -> zbus::Result<()> {
+    emitter.backend_changed(backend.name()).await?;
+    property_changed(
+        emitter,
+        BootKitInfo::name(),
+        "BootloaderType",
+        backend.name().into(),
+    )
+    .await
+}
+
+/// Signal PropertiesChanged for a single property of `interface`, the name
+/// is the one given in its `#[interface]` attribute
+async fn property_changed(
+    emitter: &SignalEmitter<'_>,
+    interface: InterfaceName<'static>,
+    name: &str,
+    value: Value<'_>,
+) -> zbus::Result<()> {
+    Properties::properties_changed(
+        emitter,
+        interface,
+        HashMap::from([(name, value)]),
+        Cow::Borrowed(&[]),
+    )
+    .await
+}
+
+/// Values of the D-Bus properties that are read from the config files,
+/// `None` when they couldn't be read
+#[derive(Debug, Default, PartialEq)]
+struct PropertyValues {
+    default_kernel: Option<String>,
+    timeout: Option<String>,
+    selected_snapshot_id: Option<i64>,
+}
+
+impl PropertyValues {
+    async fn read(services: &Services) -> Self {
+        Self {
+            default_kernel: services.entries.default_kernel().await.ok(),
+            timeout: services.config.timeout().await.ok(),
+            selected_snapshot_id: services.snapshots.selected_snapshot_id().await.ok(),
+        }
+    }
+}
+
+/// Signal the property if it has a new value
+async fn signal_if_changed<T>(
+    emitter: &SignalEmitter<'_>,
+    interface: InterfaceName<'static>,
+    name: &str,
+    previous: &Option<T>,
+    current: &Option<T>,
+) -> zbus::Result<()>
+where
+    T: PartialEq + Clone + Into<Value<'static>>,
+{
+    match current {
+        Some(value) if previous != current => {
+            property_changed(emitter, interface, name, value.clone().into()).await
         }
+        _ => Ok(()),
+    }
+}
+
+/// Signal PropertiesChanged when the properties read from the config files change
+pub async fn watch_properties(
+    connection: Connection,
+    namespace: Namespace,
+    services: Services,
+) -> zbus::Result<()> {
+    let mut previous = PropertyValues::read(&services).await;
+    loop {
+        services.state.config_changes.wait().await;
+        // Resuming the watchers marks the config changed
+        if services.state.watchers.is_paused() {
+            continue;
+        }
+
+        let current = PropertyValues::read(&services).await;
+        if current == previous {
+            continue;
+        }
+        let emitter = SignalEmitter::new(&connection, namespace.object_path())?;
+        signal_if_changed(
+            &emitter,
+            BootEntry::name(),
+            "DefaultKernel",
+            &previous.default_kernel,
+            &current.default_kernel,
+        )
+        .await?;
+        signal_if_changed(
+            &emitter,
+            BootKitConfig::name(),
+            "Timeout",
+            &previous.timeout,
+            &current.timeout,
+        )
+        .await?;
+        signal_if_changed(
+            &emitter,
+            BootKitSnapshots::name(),
+            "SelectedSnapshotId",
+            &previous.selected_snapshot_id,
+            &current.selected_snapshot_id,
+        )
+        .await?;
+        previous = current;
+    }
+}
+
+/// How often an expired watcher pause is checked
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resume the watchers when their pause runs out
+pub async fn watch_pause(
+    connection: Connection,
+    namespace: Namespace,
+    services: Services,
+) -> zbus::Result<()> {
+    let mut interval = tokio::time::interval(PAUSE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Some(suppressed) = services.state.watchers.resume_expired() {
+            log::info!("Watcher pause ran out");
+            let emitter = SignalEmitter::new(&connection, namespace.object_path())?;
+            reconcile(&emitter, &services, suppressed).await?;
+        }
+    }
+}
+
+/// How often the installed bootloader is checked
+const BACKEND_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Signal BackendChanged when the bootloader of the system changes
+pub async fn watch_backend(
+    connection: Connection,
+    namespace: Namespace,
+    services: Services,
+) -> zbus::Result<()> {
+    let mut interval = tokio::time::interval(BACKEND_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        // Resuming the watchers checks the bootloader as well
+        if services.state.watchers.is_paused() {
+            continue;
+        }
+        if let Some(backend) = services.refresh_backend() {
+            let emitter = SignalEmitter::new(&connection, namespace.object_path())?;
+            signal_backend(&emitter, backend).await?;
+        }
+    }
+}
+
+/// Signal SnapshotsChanged when snapshots are created, removed or selected
+pub async fn watch_snapshots(
+    connection: Connection,
+    namespace: Namespace,
+    services: Services,
+) -> zbus::Result<()> {
+    loop {
+        services.state.snapshot_changes.wait().await;
+        let emitter = SignalEmitter::new(&connection, namespace.object_path())?;
+        emitter.snapshots_changed().await?;
+        log::debug!("Snapshots changed. Signaling dbus");
     }
 }
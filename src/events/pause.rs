@@ -0,0 +1,146 @@
+//! Temporarily pausing the file watchers.
+//!
+//! Tools doing maintenance on several files at once can pause the watchers so
+//! clients don't react to every intermediate state. The events seen during the
+//! pause are collected and handled once when the watchers are resumed.
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Longest allowed pause, so a crashed tool cannot leave the watchers paused
+pub const MAX_PAUSE: Duration = Duration::from_secs(3600);
+
+/// Watched files that changed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChangedFiles {
+    pub file_changed: bool,
+    pub variants_changed: bool,
+    /// grub.cfg was regenerated, so the boot entries may have changed
+    pub entries_changed: bool,
+    /// saved_entry in grubenv changed
+    pub default_changed: bool,
+    /// Kernel images or initrds were installed or removed in /boot
+    pub kernels_changed: bool,
+}
+
+#[derive(Debug, Default)]
+struct PauseState {
+    until: Option<Instant>,
+    suppressed: ChangedFiles,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct WatcherPause {
+    state: Arc<Mutex<PauseState>>,
+}
+
+impl WatcherPause {
+    fn lock(&self) -> MutexGuard<'_, PauseState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Pause the watchers for `duration`, extending or shortening an existing pause
+    pub fn pause(&self, duration: Duration) {
+        self.lock().until = Some(Instant::now() + duration);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().until.is_some()
+    }
+
+    /// Seconds until the watchers resume on their own
+    pub fn remaining(&self) -> Option<u64> {
+        self.lock()
+            .until
+            .map(|until| until.saturating_duration_since(Instant::now()).as_secs())
+    }
+
+    /// Record the events if the watchers are paused, returns true if they were suppressed
+    pub fn suppress(&self, events: ChangedFiles) -> bool {
+        let mut state = self.lock();
+        if state.until.is_none() {
+            return false;
+        }
+
+        state.suppressed.file_changed |= events.file_changed;
+        state.suppressed.variants_changed |= events.variants_changed;
+        state.suppressed.entries_changed |= events.entries_changed;
+        state.suppressed.default_changed |= events.default_changed;
+        state.suppressed.kernels_changed |= events.kernels_changed;
+        true
+    }
+
+    /// Resume the watchers, returns the suppressed events or `None` if they weren't paused
+    pub fn resume(&self) -> Option<ChangedFiles> {
+        let mut state = self.lock();
+        state.until.take()?;
+        Some(std::mem::take(&mut state.suppressed))
+    }
+
+    /// Resume the watchers if the pause has run out
+    pub fn resume_expired(&self) -> Option<ChangedFiles> {
+        let expired = self
+            .lock()
+            .until
+            .is_some_and(|until| until <= Instant::now());
+        if expired {
+            self.resume()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suppress_only_while_paused() {
+        let pause = WatcherPause::default();
+        let changed = ChangedFiles {
+            file_changed: true,
+            variants_changed: false,
+            entries_changed: true,
+            default_changed: false,
+            kernels_changed: true,
+        };
+        assert!(!pause.suppress(changed));
+        assert_eq!(pause.resume(), None);
+
+        pause.pause(Duration::from_secs(60));
+        assert!(pause.is_paused());
+        assert!(pause.suppress(changed));
+        assert!(pause.suppress(ChangedFiles::default()));
+        assert_eq!(pause.resume_expired(), None);
+        assert_eq!(pause.resume(), Some(changed));
+        assert!(!pause.is_paused());
+        assert!(!pause.suppress(changed));
+    }
+
+    #[test]
+    fn test_resume_expired() {
+        let pause = WatcherPause::default();
+        pause.pause(Duration::ZERO);
+        assert!(pause.suppress(ChangedFiles {
+            file_changed: false,
+            variants_changed: true,
+            entries_changed: false,
+            default_changed: true,
+            kernels_changed: false,
+        }));
+        assert_eq!(
+            pause.resume_expired(),
+            Some(ChangedFiles {
+                file_changed: false,
+                variants_changed: true,
+                entries_changed: false,
+                default_changed: true,
+                kernels_changed: false,
+            })
+        );
+        assert_eq!(pause.resume_expired(), None);
+    }
+}
@@ -0,0 +1,512 @@
+//! inotify watches of the config files, grub.cfg and the kernels in /boot.
+//!
+//! Directories are watched instead of the files, since editors and package
+//! managers replace the files instead of writing them in place. Watches of
+//! directories that are removed are added back once the directories exist again.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fs::canonicalize,
+    io,
+    path::PathBuf,
+    time::Duration,
+};
+
+use futures_util::StreamExt;
+use inotify::{EventMask, EventOwned, EventStream, Inotify, WatchDescriptor, WatchMask};
+
+use crate::{
+    config::Paths,
+    dctx,
+    errors::{DError, DRes, DResult},
+    events::{pause::ChangedFiles, saved_entry},
+    services::{config::CONFIG_VARIANTS, AppState},
+};
+
+/// How often the directories that cannot be watched are tried again
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// File name prefixes of the kernel images and initrds in /boot
+const KERNEL_FILE_PREFIXES: &[&str] = &["vmlinuz-", "Image-", "initrd-", "initramfs-"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchedDir {
+    /// /etc/default, with the grub file and its package manager variants
+    GrubRoot,
+    /// Directory of the file a symlinked grub file points to
+    GrubTarget,
+    /// /etc/default/grub.d, with the drop-in configs that override the grub file
+    Dropins,
+    /// Directory of grub.cfg and grubenv, like /boot/grub2
+    Cfg,
+    /// /boot, with the kernel images and initrds
+    Boot,
+}
+
+struct DirWatch {
+    kind: WatchedDir,
+    path: PathBuf,
+    mask: WatchMask,
+    /// `None` while the directory cannot be watched
+    wd: Option<WatchDescriptor>,
+}
+
+pub struct FileWatcher {
+    stream: EventStream<[u8; 4096]>,
+    paths: Paths,
+    dirs: Vec<DirWatch>,
+    grub_name: Option<OsString>,
+    target_name: Option<OsString>,
+    /// grub.rpmnew and grub.rpmsave file names
+    variant_names: Vec<OsString>,
+    entries_name: Option<OsString>,
+    env_name: Option<OsString>,
+    /// saved_entry in grubenv when it was last read
+    default_entry: String,
+}
+
+impl FileWatcher {
+    pub fn new(state: &AppState) -> DResult<Self> {
+        let paths = &state.paths;
+        let inotify = Inotify::init().ctx(dctx!(), "Failed to initialize inotify")?;
+        let stream = inotify
+            .into_event_stream([0; 4096])
+            .ctx(dctx!(), "Failed to create inotify event stream")?;
+
+        let file_name = |path: &std::path::Path| path.file_name().map(OsStr::to_owned);
+        let grub_root = paths.grub_root();
+        let mut dirs = vec![DirWatch {
+            kind: WatchedDir::GrubRoot,
+            path: grub_root.into(),
+            mask: WatchMask::MODIFY
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::CREATE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_TO
+                | WatchMask::MOVED_FROM,
+            wd: None,
+        }];
+
+        // Most systems have no drop-ins, the directory is watched once it's created
+        dirs.push(DirWatch {
+            kind: WatchedDir::Dropins,
+            path: paths.grub_dropins().into(),
+            mask: WatchMask::CLOSE_WRITE
+                | WatchMask::CREATE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_TO
+                | WatchMask::MOVED_FROM,
+            wd: None,
+        });
+
+        // Changes to a symlinked grub file are only seen in the directory of its target
+        let target = paths.grub_file_target();
+        let real_root = canonicalize(grub_root).unwrap_or_else(|_| grub_root.into());
+        if let Some(target_dir) = target.parent().filter(|dir| *dir != real_root) {
+            log::info!("Watching {target:?} that {:?} links to", paths.grub_file());
+            dirs.push(DirWatch {
+                kind: WatchedDir::GrubTarget,
+                path: target_dir.into(),
+                mask: WatchMask::MODIFY
+                    | WatchMask::CLOSE_WRITE
+                    | WatchMask::CREATE
+                    | WatchMask::MOVED_TO,
+                wd: None,
+            });
+        }
+
+        // grub.cfg is watched for the boot entries and grubenv for the default entry,
+        // both for caching the config read from them as well
+        dirs.push(DirWatch {
+            kind: WatchedDir::Cfg,
+            path: paths.grub_dir().into(),
+            mask: WatchMask::MODIFY
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::CREATE
+                | WatchMask::MOVED_TO,
+            wd: None,
+        });
+
+        // Kernel packages install their images and initrds to /boot, the entries
+        // change once grub.cfg is regenerated
+        dirs.push(DirWatch {
+            kind: WatchedDir::Boot,
+            path: paths.boot_dir(),
+            mask: WatchMask::CREATE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_TO
+                | WatchMask::MOVED_FROM,
+            wd: None,
+        });
+
+        let mut watcher = Self {
+            stream,
+            paths: paths.clone(),
+            dirs,
+            grub_name: file_name(paths.grub_file()),
+            target_name: file_name(&target),
+            variant_names: CONFIG_VARIANTS
+                .iter()
+                .filter_map(|kind| file_name(&paths.grub_file_variant(kind)))
+                .collect(),
+            entries_name: file_name(paths.grub_cfg()),
+            env_name: file_name(paths.grub_env()),
+            default_entry: saved_entry(paths),
+        };
+        watcher.add_missing(state, &mut ChangedFiles::default(), true);
+        Ok(watcher)
+    }
+
+    fn is_watched(&self, kind: WatchedDir) -> bool {
+        self.dirs
+            .iter()
+            .any(|dir| dir.kind == kind && dir.wd.is_some())
+    }
+
+    /// Watch the directories that are not watched yet. Changes made while a
+    /// directory wasn't watched are unknown, so they are assumed.
+    fn add_missing(&mut self, state: &AppState, changed: &mut ChangedFiles, first: bool) {
+        for dir in self.dirs.iter_mut().filter(|dir| dir.wd.is_none()) {
+            // the same directory may be watched for several reasons
+            let mask = dir.mask | WatchMask::MOVE_SELF | WatchMask::MASK_ADD;
+            match self.stream.watches().add(&dir.path, mask) {
+                Ok(wd) => {
+                    dir.wd = Some(wd);
+                    if first {
+                        continue;
+                    }
+                    log::info!("Watching {:?} again", dir.path);
+                    match dir.kind {
+                        WatchedDir::GrubRoot | WatchedDir::GrubTarget | WatchedDir::Dropins => {
+                            changed.file_changed = true
+                        }
+                        WatchedDir::Cfg => changed.entries_changed = true,
+                        WatchedDir::Boot => changed.kernels_changed = true,
+                    }
+                    state.config_changes.changed();
+                }
+                Err(err) if first && dir.kind != WatchedDir::Dropins => {
+                    log::warn!(
+                        "Cannot watch {:?}, changes in it are not signaled: {err}",
+                        dir.path
+                    );
+                }
+                Err(err) => log::debug!("Cannot watch {:?} yet: {err}", dir.path),
+            }
+        }
+
+        // Cached config can only be trusted when all its changes are seen
+        state
+            .config_changes
+            .set_watched(self.is_watched(WatchedDir::GrubRoot) && self.is_watched(WatchedDir::Cfg));
+    }
+
+    /// Forget the watch that inotify removed, e.g. because its directory was deleted
+    fn watch_removed(&mut self, state: &AppState, wd: &WatchDescriptor) {
+        for dir in &mut self.dirs {
+            if dir.wd.as_ref() == Some(wd) {
+                log::warn!(
+                    "{:?} is no longer watched, watching it again once it exists",
+                    dir.path
+                );
+                dir.wd = None;
+            }
+        }
+        state.config_changes.set_watched(false);
+    }
+
+    /// Directories watched with the descriptor
+    fn watched_dirs(&self, wd: &WatchDescriptor) -> Vec<WatchedDir> {
+        self.dirs
+            .iter()
+            .filter(|dir| dir.wd.as_ref() == Some(wd))
+            .map(|dir| dir.kind)
+            .collect()
+    }
+
+    /// Record what the event changed, returns true if grubenv was written
+    fn handle_event(
+        &mut self,
+        state: &AppState,
+        event: EventOwned,
+        changed: &mut ChangedFiles,
+    ) -> bool {
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            log::warn!("inotify events were lost, assuming all the files changed");
+            changed.file_changed = true;
+            changed.entries_changed = true;
+            changed.kernels_changed = true;
+            state.config_changes.changed();
+            return true;
+        }
+        if event.mask.contains(EventMask::MOVE_SELF) {
+            // the directory was renamed, the path it's expected in is no longer watched
+            if let Err(err) = self.stream.watches().remove(event.wd.clone()) {
+                log::debug!("Cannot remove the watch of a moved directory: {err}");
+            }
+            return false;
+        }
+        if event.mask.contains(EventMask::IGNORED) {
+            self.watch_removed(state, &event.wd);
+            return false;
+        }
+
+        let dirs = self.watched_dirs(&event.wd);
+        let in_dir = |kind: WatchedDir| dirs.contains(&kind);
+        let name = event.name.as_deref();
+
+        let variant_mask =
+            EventMask::CREATE | EventMask::DELETE | EventMask::MOVED_TO | EventMask::MOVED_FROM;
+        if event.mask.intersects(variant_mask)
+            && in_dir(WatchedDir::GrubRoot)
+            && name.is_some_and(|name| self.variant_names.iter().any(|variant| variant == name))
+        {
+            changed.variants_changed = true;
+        }
+
+        let is_grub_file = (in_dir(WatchedDir::GrubRoot) && name == self.grub_name.as_deref())
+            || (in_dir(WatchedDir::GrubTarget) && name == self.target_name.as_deref());
+        // Editors save by renaming a temporary file over the grub file, and
+        // writes in place are signaled once the file is closed
+        let saved = event
+            .mask
+            .intersects(EventMask::CLOSE_WRITE | EventMask::CREATE | EventMask::MOVED_TO);
+        if saved && is_grub_file {
+            changed.file_changed = true;
+        }
+
+        // any change of a drop-in changes the config in effect
+        let is_dropin = in_dir(WatchedDir::Dropins)
+            && name
+                .and_then(OsStr::to_str)
+                .is_some_and(|name| name.ends_with(".cfg"));
+        if is_dropin {
+            changed.file_changed = true;
+            state.config_changes.changed();
+        }
+
+        let is_entries_file = in_dir(WatchedDir::Cfg) && name == self.entries_name.as_deref();
+        let is_env_file = in_dir(WatchedDir::Cfg) && name == self.env_name.as_deref();
+        if is_grub_file || is_entries_file || is_env_file {
+            state.config_changes.changed();
+        }
+        // grub2-mkconfig renames the new grub.cfg in place, other tools may
+        // write it directly. Either way clients only see the complete file.
+        if saved && is_entries_file {
+            changed.entries_changed = true;
+        }
+
+        let is_kernel_file = in_dir(WatchedDir::Boot)
+            && name.and_then(OsStr::to_str).is_some_and(|name| {
+                KERNEL_FILE_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+            });
+        if is_kernel_file {
+            changed.kernels_changed = true;
+        }
+
+        saved && is_env_file
+    }
+
+    /// Wait for the next change of the watched files. Events that follow each
+    /// other within `debounce` are collected into the same change.
+    pub async fn changes(&mut self, state: &AppState, debounce: Duration) -> DResult<ChangedFiles> {
+        loop {
+            // prevent duplicate modify event triggers
+            let mut changed = ChangedFiles::default();
+            let mut env_changed = false;
+
+            let event = if self.dirs.iter().any(|dir| dir.wd.is_none()) {
+                match tokio::time::timeout(RETRY_INTERVAL, self.stream.next()).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        self.add_missing(state, &mut changed, false);
+                        if changed != ChangedFiles::default() {
+                            return Ok(changed);
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                self.stream.next().await
+            };
+            env_changed |= self.handle_event(state, next_event(event)?, &mut changed);
+
+            // A single save can be seen in several events, so wait until they
+            // stop coming and signal them as one change
+            if !debounce.is_zero() && (changed != ChangedFiles::default() || env_changed) {
+                while let Ok(event) = tokio::time::timeout(debounce, self.stream.next()).await {
+                    env_changed |= self.handle_event(state, next_event(event)?, &mut changed);
+                }
+            }
+
+            // grubenv is also rewritten for other variables, like next_entry
+            if env_changed {
+                let current = saved_entry(&self.paths);
+                if current != self.default_entry {
+                    self.default_entry = current;
+                    changed.default_changed = true;
+                }
+            }
+
+            if changed != ChangedFiles::default() {
+                return Ok(changed);
+            }
+        }
+    }
+}
+
+fn next_event(event: Option<io::Result<EventOwned>>) -> DResult<EventOwned> {
+    event
+        .ok_or_else(|| DError::generic(dctx!(), "inotify event stream ended"))?
+        .ctx(dctx!(), "Failed to read inotify events")
+}
+
+#[cfg(all(test, not(feature = "dev")))]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir, remove_dir_all, write};
+
+    use super::*;
+    use crate::{
+        db::{Database, StorageKind},
+        restart::InFlight,
+        services::Services,
+    };
+
+    async fn test_state(root: &std::path::Path) -> AppState {
+        for dir in ["etc/default/grub.d", "boot/grub2"] {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        write(root.join("etc/default/grub"), "GRUB_TIMEOUT=8\n").unwrap();
+        let paths = Paths::with_root(root);
+        let db = Database::new(&paths, StorageKind::Memory).await.unwrap();
+        Services::new(db, paths, InFlight::default(), None).state
+    }
+
+    fn event(watcher: &FileWatcher, kind: WatchedDir, mask: EventMask, name: &str) -> EventOwned {
+        let wd = watcher
+            .dirs
+            .iter()
+            .find(|dir| dir.kind == kind)
+            .and_then(|dir| dir.wd.clone())
+            .unwrap();
+        EventOwned {
+            wd,
+            mask,
+            cookie: 0,
+            name: Some(name.into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_event() {
+        let root = std::env::temp_dir().join(format!("bootkit-watcher-{}", std::process::id()));
+        let state = test_state(&root).await;
+        let mut watcher = FileWatcher::new(&state).unwrap();
+        let mut handle = |kind, mask, name| {
+            let mut changed = ChangedFiles::default();
+            let event = event(&watcher, kind, mask, name);
+            let env_changed = watcher.handle_event(&state, event, &mut changed);
+            (changed, env_changed)
+        };
+        let only = |set: fn(&mut ChangedFiles)| {
+            let mut changed = ChangedFiles::default();
+            set(&mut changed);
+            (changed, false)
+        };
+
+        assert_eq!(
+            handle(WatchedDir::GrubRoot, EventMask::CLOSE_WRITE, "grub"),
+            only(|changed| changed.file_changed = true)
+        );
+        assert_eq!(
+            handle(WatchedDir::GrubRoot, EventMask::CREATE, "grub.rpmnew"),
+            only(|changed| changed.variants_changed = true)
+        );
+        assert_eq!(
+            handle(WatchedDir::GrubRoot, EventMask::CLOSE_WRITE, "locale"),
+            only(|_| {})
+        );
+        assert_eq!(
+            handle(WatchedDir::Dropins, EventMask::DELETE, "50_console.cfg"),
+            only(|changed| changed.file_changed = true)
+        );
+        assert_eq!(
+            handle(WatchedDir::Dropins, EventMask::CLOSE_WRITE, "README"),
+            only(|_| {})
+        );
+        assert_eq!(
+            handle(WatchedDir::Cfg, EventMask::MOVED_TO, "grub.cfg"),
+            only(|changed| changed.entries_changed = true)
+        );
+        assert_eq!(
+            handle(WatchedDir::Cfg, EventMask::CLOSE_WRITE, "grubenv"),
+            (ChangedFiles::default(), true)
+        );
+        assert_eq!(
+            handle(
+                WatchedDir::Boot,
+                EventMask::CREATE,
+                "vmlinuz-6.4.0-1-default"
+            ),
+            only(|changed| changed.kernels_changed = true)
+        );
+        assert_eq!(
+            handle(
+                WatchedDir::Boot,
+                EventMask::CREATE,
+                "config-6.4.0-1-default"
+            ),
+            only(|_| {})
+        );
+
+        let (changed, env_changed) = handle(WatchedDir::Boot, EventMask::Q_OVERFLOW, "");
+        assert!(changed.file_changed && changed.entries_changed && changed.kernels_changed);
+        assert!(env_changed);
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watch_added_back() {
+        let root = std::env::temp_dir().join(format!("bootkit-rewatch-{}", std::process::id()));
+        let state = test_state(&root).await;
+        let mut watcher = FileWatcher::new(&state).unwrap();
+        assert!(watcher.is_watched(WatchedDir::Cfg));
+        assert!(state.config_changes.generation().is_some());
+
+        let cfg_dir = root.join("boot/grub2");
+        remove_dir(&cfg_dir).unwrap();
+        let mut changed = ChangedFiles::default();
+        while watcher.is_watched(WatchedDir::Cfg) {
+            let event = tokio::time::timeout(Duration::from_secs(5), watcher.stream.next())
+                .await
+                .unwrap();
+            watcher.handle_event(&state, next_event(event).unwrap(), &mut changed);
+        }
+        assert!(state.config_changes.generation().is_none());
+
+        // still missing, nothing changes
+        watcher.add_missing(&state, &mut changed, false);
+        assert!(!watcher.is_watched(WatchedDir::Cfg));
+        assert_eq!(changed, ChangedFiles::default());
+
+        create_dir_all(&cfg_dir).unwrap();
+        watcher.add_missing(&state, &mut changed, false);
+        assert!(watcher.is_watched(WatchedDir::Cfg));
+        assert!(state.config_changes.generation().is_some());
+        assert!(changed.entries_changed);
+        assert!(!changed.kernels_changed);
+
+        // events of the new directory are seen
+        write(cfg_dir.join("grub.cfg"), "menuentry 'openSUSE' {\n}\n").unwrap();
+        let mut changed = ChangedFiles::default();
+        while !changed.entries_changed {
+            let event = tokio::time::timeout(Duration::from_secs(5), watcher.stream.next())
+                .await
+                .unwrap();
+            watcher.handle_event(&state, next_event(event).unwrap(), &mut changed);
+        }
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,408 @@
+//! Firmware the system was booted with, and the EFI system partition (ESP)
+//! that UEFI firmware loads the bootloader from.
+
+use std::{
+    fs::{read, read_dir},
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    sys::statvfs::statvfs,
+    unistd::{access, AccessFlags},
+};
+use serde::Serialize;
+
+use crate::config::{mounts::find_mount, Paths, EFI_FIRMWARE_PATH};
+
+/// Vendor GUID of the variables defined by the UEFI specification
+const EFI_GLOBAL_GUID: &str = "8be4df61-93ca-11d0-aa0d-00a0c904b1a5";
+
+/// Boot option is shown in the firmware boot menu and tried in the boot order
+const LOAD_OPTION_ACTIVE: u32 = 0x1;
+/// Boot option is not shown in the firmware boot menu
+const LOAD_OPTION_HIDDEN: u32 = 0x8;
+
+/// Free space on the ESP below which bootloader and shim updates may fail
+const MIN_ESP_FREE_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FirmwareMode {
+    Uefi,
+    Bios,
+}
+
+impl FirmwareMode {
+    /// Firmware the host system was booted with, targets have no firmware of their own
+    pub fn detect() -> Self {
+        if Path::new(EFI_FIRMWARE_PATH).is_dir() {
+            Self::Uefi
+        } else {
+            Self::Bios
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Uefi => "uefi",
+            Self::Bios => "bios",
+        }
+    }
+}
+
+/// Value of a boolean EFI variable, efivarfs files start with 4 bytes of attributes
+fn parse_efi_bool(contents: &[u8]) -> Option<bool> {
+    contents.get(4).map(|value| *value == 1)
+}
+
+/// Contents of the EFI variable `name` of the host, including the attributes
+fn efi_var(name: &str) -> Option<Vec<u8>> {
+    read(Path::new(EFI_FIRMWARE_PATH).join("efivars").join(name)).ok()
+}
+
+/// Is secure boot enabled on the host, `None` if the firmware doesn't tell
+pub fn secure_boot() -> Option<bool> {
+    parse_efi_bool(&efi_var(&format!("SecureBoot-{EFI_GLOBAL_GUID}"))?)
+}
+
+/// Value of a UTF-16 string EFI variable, without the attributes and the trailing NUL
+fn parse_efi_string(contents: &[u8]) -> Option<String> {
+    Some(read_utf16(contents.get(4..)?).0)
+}
+
+/// String EFI variable of the host, `name` is the efivarfs file name like
+/// `LoaderEntryDefault-4a67b082-0a4c-41cf-b6c7-440b29bb8c4f`
+pub fn efi_string_var(name: &str) -> Option<String> {
+    parse_efi_string(&efi_var(name)?)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// UTF-16 string that ends at a NUL or the end of `bytes`, and the bytes it used
+/// including the NUL
+fn read_utf16(bytes: &[u8]) -> (String, usize) {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    let used = (units.len() + 1) * 2;
+    (String::from_utf16_lossy(&units), used.min(bytes.len()))
+}
+
+/// Boot option numbers of a BootOrder, BootCurrent or BootNext variable
+fn parse_efi_u16_list(contents: &[u8]) -> Vec<u16> {
+    contents
+        .get(4..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect()
+}
+
+/// Format a boot option number the way efibootmgr shows it, like `000A`
+pub fn boot_number(number: u16) -> String {
+    format!("{number:04X}")
+}
+
+/// Boot option `Boot####` of the firmware boot manager
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EfiBootEntry {
+    /// Hexadecimal number of the option, like `0001`
+    pub number: String,
+    pub description: String,
+    /// Option is tried when booting in the boot order
+    pub active: bool,
+    /// Option is not shown in the firmware boot menu
+    pub hidden: bool,
+    /// GUID of the partition the option boots from
+    pub partition: Option<String>,
+    /// File the option loads, like `\EFI\opensuse\shimx64.efi`
+    pub path: Option<String>,
+}
+
+/// GUID in its mixed-endian binary form, as stored in device paths
+fn format_guid(bytes: &[u8]) -> Option<String> {
+    let bytes: [u8; 16] = bytes.try_into().ok()?;
+    Some(format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}",
+        u32::from_le_bytes(bytes[0..4].try_into().ok()?),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8],
+        bytes[9],
+        bytes[10..]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>()
+    ))
+}
+
+/// Parse an EFI_LOAD_OPTION of a `Boot####` variable, with the attributes of the
+/// variable in the first 4 bytes
+fn parse_load_option(number: u16, contents: &[u8]) -> Option<EfiBootEntry> {
+    let option = contents.get(4..)?;
+    let attributes = read_u32(option, 0)?;
+    let file_path_len = usize::from(read_u16(option, 4)?);
+    let (description, used) = read_utf16(option.get(6..)?);
+    let device_path = option.get(6 + used..6 + used + file_path_len)?;
+
+    let mut entry = EfiBootEntry {
+        number: boot_number(number),
+        description,
+        active: attributes & LOAD_OPTION_ACTIVE != 0,
+        hidden: attributes & LOAD_OPTION_HIDDEN != 0,
+        partition: None,
+        path: None,
+    };
+
+    // walk the device path nodes: type, subtype and the length of the whole node
+    let mut offset = 0;
+    while let (Some(node_type), Some(subtype), Some(length)) = (
+        device_path.get(offset),
+        device_path.get(offset + 1),
+        read_u16(device_path, offset + 2),
+    ) {
+        let length = usize::from(length);
+        let Some(data) = device_path.get(offset + 4..offset + length) else {
+            break;
+        };
+        match (node_type, subtype) {
+            // end of the device path
+            (0x7f, 0xff) => break,
+            // hard drive media with a GPT partition signature
+            (0x04, 0x01) if data.get(37) == Some(&0x02) => {
+                entry.partition = data.get(20..36).and_then(format_guid);
+            }
+            // file path media
+            (0x04, 0x04) => entry.path = Some(read_utf16(data).0),
+            _ => {}
+        }
+        if length < 4 {
+            break;
+        }
+        offset += length;
+    }
+
+    Some(entry)
+}
+
+/// Boot options and boot order of the firmware boot manager of the host
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EfiBootManager {
+    /// Boot options, the ones in the boot order first
+    pub entries: Vec<EfiBootEntry>,
+    pub boot_order: Vec<String>,
+    /// Option the system was booted with
+    pub boot_current: Option<String>,
+    /// Option booted on the next boot only
+    pub boot_next: Option<String>,
+}
+
+impl EfiBootManager {
+    /// Read the boot manager variables from efivarfs
+    pub fn read() -> Self {
+        let global = |name: &str| efi_var(&format!("{name}-{EFI_GLOBAL_GUID}"));
+        let suffix = format!("-{EFI_GLOBAL_GUID}");
+        let mut entries: Vec<(u16, EfiBootEntry)> =
+            read_dir(Path::new(EFI_FIRMWARE_PATH).join("efivars"))
+                .into_iter()
+                .flatten()
+                .filter_map(|file| {
+                    let name = file.ok()?.file_name().into_string().ok()?;
+                    let number = name.strip_prefix("Boot")?.strip_suffix(&suffix)?;
+                    if number.len() != 4 {
+                        return None;
+                    }
+                    let number = u16::from_str_radix(number, 16).ok()?;
+                    Some((number, parse_load_option(number, &efi_var(&name)?)?))
+                })
+                .collect();
+
+        let boot_order = global("BootOrder")
+            .map(|contents| parse_efi_u16_list(&contents))
+            .unwrap_or_default();
+        // options in the boot order first, in that order, then the rest by number
+        entries.sort_by_key(|(number, _)| {
+            let position = boot_order.iter().position(|ordered| ordered == number);
+            (position.unwrap_or(usize::MAX), *number)
+        });
+        let single = |name: &str| {
+            global(name)
+                .and_then(|contents| parse_efi_u16_list(&contents).first().copied())
+                .map(boot_number)
+        };
+
+        Self {
+            entries: entries.into_iter().map(|(_, entry)| entry).collect(),
+            boot_order: boot_order.into_iter().map(boot_number).collect(),
+            boot_current: single("BootCurrent"),
+            boot_next: single("BootNext"),
+        }
+    }
+
+    pub fn entry(&self, number: &str) -> Option<&EfiBootEntry> {
+        self.entries.iter().find(|entry| entry.number == number)
+    }
+}
+
+/// Mounted EFI system partition
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EspStatus {
+    pub mount_point: PathBuf,
+    pub device: String,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub writable: bool,
+    /// Whether the `EFI` directory with the bootloaders exists
+    pub efi_dir: bool,
+    pub problems: Vec<String>,
+}
+
+impl EspStatus {
+    /// Find the ESP of the managed system, the first FAT filesystem mounted on
+    /// one of the ESP mount points
+    pub fn detect(paths: &Paths) -> Option<Self> {
+        paths.esp_mount_points().iter().find_map(|mount_point| {
+            let mount = find_mount(mount_point)?;
+            if mount.fs_type != "vfat" {
+                return None;
+            }
+
+            let (total_bytes, free_bytes) = statvfs(mount_point.as_path())
+                .map(|stat| {
+                    let block = stat.fragment_size();
+                    (stat.blocks() * block, stat.blocks_available() * block)
+                })
+                .unwrap_or_default();
+            let mut status = Self {
+                mount_point: mount_point.clone(),
+                device: mount.device,
+                total_bytes,
+                free_bytes,
+                writable: access(mount_point.as_path(), AccessFlags::W_OK).is_ok(),
+                efi_dir: mount_point.join("EFI").is_dir(),
+                problems: Vec::new(),
+            };
+            status.problems = status.find_problems();
+            Some(status)
+        })
+    }
+
+    fn find_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mount_point = &self.mount_point;
+        if self.free_bytes < MIN_ESP_FREE_BYTES {
+            problems.push(format!(
+                "EFI system partition {mount_point:?} has only {} KiB free",
+                self.free_bytes / 1024
+            ));
+        }
+        if !self.writable {
+            problems.push(format!(
+                "EFI system partition {mount_point:?} is not writable"
+            ));
+        }
+        if !self.efi_dir {
+            problems.push(format!(
+                "EFI system partition {mount_point:?} has no EFI directory"
+            ));
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_efi_bool() {
+        assert_eq!(parse_efi_bool(&[6, 0, 0, 0, 1]), Some(true));
+        assert_eq!(parse_efi_bool(&[6, 0, 0, 0, 0]), Some(false));
+        assert_eq!(parse_efi_bool(&[6, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_parse_efi_string() {
+        let mut contents = vec![7, 0, 0, 0];
+        contents.extend("arch.conf\0".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(parse_efi_string(&contents), Some("arch.conf".into()));
+        assert_eq!(parse_efi_string(&[7, 0]), None);
+    }
+
+    #[test]
+    fn test_parse_load_option() {
+        let mut contents = vec![7, 0, 0, 0];
+        contents.extend(LOAD_OPTION_ACTIVE.to_le_bytes());
+        let mut device_path = Vec::new();
+        // hard drive node of partition 1 with a GPT signature
+        device_path.extend([0x04, 0x01, 42, 0]);
+        device_path.extend(1u32.to_le_bytes());
+        device_path.extend(2048u64.to_le_bytes());
+        device_path.extend(1_048_576u64.to_le_bytes());
+        device_path.extend([
+            0x5d, 0x38, 0xbc, 0x0a, 0xed, 0xdb, 0x40, 0x8e, 0x8d, 0xb1, 0x11, 0x78, 0xf9, 0x4b,
+            0x17, 0x7c,
+        ]);
+        device_path.extend([0x02, 0x02]);
+        let path: Vec<u8> = "\\EFI\\opensuse\\shim.efi\0"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        device_path.extend([0x04, 0x04, (path.len() + 4) as u8, 0]);
+        device_path.extend(path);
+        device_path.extend([0x7f, 0xff, 4, 0]);
+        contents.extend((device_path.len() as u16).to_le_bytes());
+        contents.extend("opensuse\0".encode_utf16().flat_map(u16::to_le_bytes));
+        contents.extend(device_path);
+
+        let entry = parse_load_option(1, &contents).unwrap();
+        assert_eq!(entry.number, "0001");
+        assert_eq!(entry.description, "opensuse");
+        assert!(entry.active);
+        assert!(!entry.hidden);
+        assert_eq!(
+            entry.partition.as_deref(),
+            Some("0abc385d-dbed-8e40-8db1-1178f94b177c")
+        );
+        assert_eq!(entry.path.as_deref(), Some("\\EFI\\opensuse\\shim.efi"));
+
+        assert_eq!(
+            parse_efi_u16_list(&[7, 0, 0, 0, 1, 0, 0x0a, 0]),
+            vec![1, 10]
+        );
+        assert_eq!(boot_number(10), "000A");
+    }
+
+    #[test]
+    fn test_esp_problems() {
+        let mut esp = EspStatus {
+            mount_point: PathBuf::from("/boot/efi"),
+            device: "/dev/vda1".into(),
+            total_bytes: 512 * 1024 * 1024,
+            free_bytes: 400 * 1024 * 1024,
+            writable: true,
+            efi_dir: true,
+            problems: Vec::new(),
+        };
+        assert!(esp.find_problems().is_empty());
+
+        esp.free_bytes = 1024 * 1024;
+        esp.efi_dir = false;
+        let problems = esp.find_problems();
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("1024 KiB free"));
+        assert!(problems[1].contains("no EFI directory"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Boot Loader Specification (BLS) entries converted from grub menu entries,
+//! for moving a system from grub to systemd-boot, and the BLS entries that grub
+//! itself boots with the `blscfg` command on Fedora style systems.
+//!
+//! See <https://uapi-group.org/specifications/specs/boot_loader_specification/>
+
+use std::path::Path;
+
+use crate::{
+    errors::DResult,
+    grub2::{EntryKind, GrubBootEntry},
+    systemd_boot::{menu_order, read_entries, LoaderEntry},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlsEntry {
+    /// Name of the fragment file, like `6.17.5-1-default.conf`
+    pub file_name: String,
+    /// Full path of the grub entry the fragment was made from
+    pub source: String,
+    pub contents: String,
+}
+
+impl BlsEntry {
+    /// Convert a grub entry that boots a Linux kernel, other entries have no BLS equivalent
+    pub fn from_grub(entry: &GrubBootEntry) -> Option<Self> {
+        let kind = entry.kind();
+        if !matches!(kind, EntryKind::Linux | EntryKind::Recovery) {
+            return None;
+        }
+
+        let kernel = entry.kernel.as_deref()?;
+        let version = entry.kernel_version();
+        let mut name = version
+            .or_else(|| kernel.rsplit('/').next())
+            .unwrap_or(kernel)
+            .to_string();
+        if kind == EntryKind::Recovery {
+            name.push_str("-recovery");
+        }
+
+        let mut contents = format!("title {}\n", entry.entry());
+        if let Some(version) = version {
+            contents.push_str(&format!("version {version}\n"));
+        }
+        contents.push_str(&format!("linux {kernel}\n"));
+        for initrd in &entry.initrds {
+            contents.push_str(&format!("initrd {initrd}\n"));
+        }
+        if let Some(options) = &entry.options {
+            contents.push_str(&format!("options {options}\n"));
+        }
+
+        Some(Self {
+            file_name: format!("{name}.conf"),
+            source: entry.full_path(),
+            contents,
+        })
+    }
+
+    /// Grub variables, like `$early_ucode`, cannot be expanded by other bootloaders
+    pub fn uses_grub_variables(&self) -> bool {
+        self.contents.contains('$')
+    }
+}
+
+impl GrubBootEntry {
+    /// Menu entry that `blscfg` creates for a BLS entry
+    fn from_bls(entry: &LoaderEntry) -> Self {
+        let mut grub_entry = Self::new(entry.display_title().into(), Vec::new());
+        grub_entry.kernel = entry.linux.clone();
+        grub_entry.options = entry.options.clone();
+        grub_entry.initrds = entry.initrds.clone();
+        grub_entry.kind = entry.kind();
+        grub_entry.bls_id = Some(entry.id.trim_end_matches(".conf").into());
+        grub_entry
+    }
+}
+
+/// Menu entries that `blscfg` creates from the BLS entries in `entries_dir`, in
+/// menu order
+pub fn read_bls_entries(entries_dir: &Path) -> DResult<Vec<GrubBootEntry>> {
+    if !entries_dir.is_dir() {
+        log::warn!("grub.cfg uses blscfg but {entries_dir:?} doesn't exist");
+        return Ok(Vec::new());
+    }
+
+    let mut entries = read_entries(entries_dir)?;
+    entries.sort_by(menu_order);
+    Ok(entries.iter().map(GrubBootEntry::from_bls).collect())
+}
+
+/// Convert the entries to BLS fragments, keeping the first entry of each kernel.
+///
+/// grub lists the same kernel both at the top level and in the advanced options
+/// submenu, but BLS only needs one fragment per kernel.
+pub fn bls_entries(entries: &[GrubBootEntry]) -> Vec<BlsEntry> {
+    let mut bls: Vec<BlsEntry> = Vec::new();
+    for entry in entries.iter().filter_map(BlsEntry::from_grub) {
+        if !bls.iter().any(|other| other.file_name == entry.file_name) {
+            bls.push(entry);
+        }
+    }
+
+    bls
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+    use crate::grub2::{uses_blscfg, GrubBootEntries};
+
+    #[test]
+    fn test_bls_entries() {
+        let config = read_to_string("test_data/grub.cfg").unwrap();
+        let entries = GrubBootEntries::from_contents(&config, "", &[]).unwrap();
+        let bls = bls_entries(entries.entries());
+
+        assert_eq!(bls.len(), 2);
+        assert_eq!(bls[0].file_name, "6.17.5-1-default.conf");
+        assert_eq!(bls[0].source, "openSUSE Tumbleweed Minimal");
+        assert_eq!(
+            bls[0].contents,
+            "title openSUSE Tumbleweed Minimal\n\
+             version 6.17.5-1-default\n\
+             linux /boot/vmlinuz-6.17.5-1-default\n\
+             initrd /boot/initrd-6.17.5-1-default\n\
+             options root=UUID=0abc385d-dbed-8e40-8db1-1178f94b177c no_timer_check net.ifnames=0 console=tty1 console=ttyS0,115200n8\n"
+        );
+        assert!(!bls[0].uses_grub_variables());
+        assert_eq!(bls[1].file_name, "6.17.5-1-default-recovery.conf");
+        assert!(bls[1]
+            .contents
+            .contains("options root=UUID=0abc385d-dbed-8e40-8db1-1178f94b177c single "));
+    }
+
+    #[test]
+    fn test_bls_entry_grub_variables() {
+        let config = "menuentry 'Arch Linux' {\n\tlinux /vmlinuz-linux root=/dev/sda2 $extra\n\tinitrd /intel-ucode.img /initramfs-linux.img\n}\nmenuentry 'Windows' {\n\tchainloader /EFI/Microsoft/Boot/bootmgfw.efi\n}\n";
+        let entries = GrubBootEntries::from_contents(config, "", &[]).unwrap();
+        let bls = bls_entries(entries.entries());
+
+        assert_eq!(bls.len(), 1);
+        assert_eq!(bls[0].file_name, "linux.conf");
+        assert_eq!(
+            bls[0].contents,
+            "title Arch Linux\n\
+             version linux\n\
+             linux /vmlinuz-linux\n\
+             initrd /intel-ucode.img\n\
+             initrd /initramfs-linux.img\n\
+             options root=/dev/sda2 $extra\n"
+        );
+        assert!(bls[0].uses_grub_variables());
+    }
+
+    #[test]
+    fn test_blscfg_entries() {
+        let bls_entries: Vec<_> = [
+            LoaderEntry::parse(
+                "fedora-6.5.6.conf",
+                "title Fedora Linux (6.5.6)\nversion 6.5.6\nlinux /vmlinuz-6.5.6\ninitrd /initramfs-6.5.6.img\noptions root=/dev/vda3",
+            ),
+            LoaderEntry::parse(
+                "fedora-6.7.1.conf",
+                "title Fedora Linux (6.7.1)\nversion 6.7.1\nlinux /vmlinuz-6.7.1\ninitrd /initramfs-6.7.1.img\noptions root=/dev/vda3",
+            ),
+        ]
+        .iter()
+        .map(GrubBootEntry::from_bls)
+        .collect();
+        let config = "insmod blscfg\nblscfg\nmenuentry 'UEFI Firmware Settings' {\n\tfwsetup\n}\n";
+        let grub_env = "saved_entry=fedora-6.5.6\n";
+        let entries = GrubBootEntries::from_contents(config, grub_env, &bls_entries).unwrap();
+
+        let names: Vec<_> = entries
+            .entries()
+            .iter()
+            .map(|entry| entry.entry())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "Fedora Linux (6.5.6)",
+                "Fedora Linux (6.7.1)",
+                "UEFI Firmware Settings"
+            ]
+        );
+        let selected = entries.selected_entry().unwrap();
+        assert_eq!(selected.full_path(), "fedora-6.5.6");
+        assert_eq!(selected.kernel_version(), Some("6.5.6"));
+        assert_eq!(selected.kind(), EntryKind::Linux);
+        assert!(uses_blscfg(config));
+    }
+}
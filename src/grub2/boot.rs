@@ -0,0 +1,173 @@
+//! What grub does on the next boot with a given config
+
+use serde::Serialize;
+
+use crate::grub2::{menu::MenuPreview, GrubBootEntries, GrubBootEntry, GrubFile};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootPreview {
+    /// Entry booted when nothing is selected from the menu
+    pub default_entry: Option<String>,
+    /// How the default entry was chosen
+    pub default_source: String,
+    /// One-shot entry set with grub2-reboot, booted instead of the default once
+    pub next_entry: Option<String>,
+    /// Entry that boots on the next boot if nothing is selected from the menu
+    pub next_boot: Option<String>,
+    pub menu: MenuPreview,
+    pub warnings: Vec<String>,
+}
+
+enum MenuItem<'a> {
+    Entry(&'a GrubBootEntry),
+    Submenu(&'a str),
+}
+
+/// Items of the (sub)menu in `path`, in menu order
+fn menu_items<'a>(entries: &'a [GrubBootEntry], path: &[String]) -> Vec<MenuItem<'a>> {
+    let mut items = Vec::new();
+    for entry in entries
+        .iter()
+        .filter(|entry| entry.submenus.starts_with(path))
+    {
+        if let Some(submenu) = entry.submenus.get(path.len()) {
+            if !matches!(items.last(), Some(MenuItem::Submenu(name)) if *name == submenu) {
+                items.push(MenuItem::Submenu(submenu));
+            }
+        } else {
+            items.push(MenuItem::Entry(entry));
+        }
+    }
+
+    items
+}
+
+/// Find the entry of a GRUB_DEFAULT value like `0`, `1>2` or
+/// `Advanced options for openSUSE>openSUSE, with Linux 6.17.5-1-default`
+fn resolve_default<'a>(entries: &'a [GrubBootEntry], default: &str) -> Option<&'a GrubBootEntry> {
+    let parts: Vec<&str> = default.split('>').collect();
+    let mut path: Vec<String> = Vec::new();
+
+    for (idx, part) in parts.iter().enumerate() {
+        let items = menu_items(entries, &path);
+        let item = if let Ok(index) = part.parse::<usize>() {
+            items.get(index)
+        } else {
+            items.iter().find(|item| match item {
+                MenuItem::Entry(entry) => entry.entry() == *part,
+                MenuItem::Submenu(name) => name == part,
+            })
+        }?;
+
+        let is_last = idx == parts.len() - 1;
+        match item {
+            MenuItem::Entry(entry) if is_last => return Some(entry),
+            MenuItem::Submenu(name) if !is_last => path.push(name.to_string()),
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+impl BootPreview {
+    pub fn new(grub: &GrubFile, entries: &GrubBootEntries) -> Self {
+        let mut warnings = Vec::new();
+        let first = entries.entries().first();
+
+        let (default_entry, default_source) = match grub.value("GRUB_DEFAULT") {
+            None | Some("") => (first, "GRUB_DEFAULT is not set, first entry".to_string()),
+            Some("saved") => match entries.selected_entry() {
+                Some(entry) => (Some(entry), "saved_entry in grubenv".to_string()),
+                None => (
+                    first,
+                    "GRUB_DEFAULT=saved without saved_entry, first entry".to_string(),
+                ),
+            },
+            Some(default) => match resolve_default(entries.entries(), default) {
+                Some(entry) => (Some(entry), format!("GRUB_DEFAULT={default}")),
+                None => {
+                    warnings.push(format!(
+                        "GRUB_DEFAULT '{default}' doesn't match any boot entry, grub boots the first entry"
+                    ));
+                    (
+                        first,
+                        format!("GRUB_DEFAULT={default} not found, first entry"),
+                    )
+                }
+            },
+        };
+
+        let next_entry = entries.next_entry();
+        if next_entry.is_some() {
+            warnings
+                .push("One-shot next_entry overrides the default entry on the next boot".into());
+        }
+
+        let menu = MenuPreview::new(grub);
+        warnings.extend(menu.warnings.iter().cloned());
+
+        Self {
+            default_entry: default_entry.map(GrubBootEntry::full_path),
+            default_source,
+            next_entry: next_entry.map(GrubBootEntry::full_path),
+            next_boot: next_entry.or(default_entry).map(GrubBootEntry::full_path),
+            menu,
+            warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+
+    #[test]
+    fn test_boot_preview_saved() {
+        let config = read_to_string("test_data/grub.cfg").unwrap();
+        let grub_env = read_to_string("test_data/grubenv_saved").unwrap();
+        let entries = GrubBootEntries::from_contents(&config, &grub_env, &[]).unwrap();
+        let grub = GrubFile::new("GRUB_DEFAULT=saved\nGRUB_TIMEOUT=8\n").unwrap();
+
+        let preview = BootPreview::new(&grub, &entries);
+        assert_eq!(
+            preview.next_boot.as_deref(),
+            Some("Advanced options for openSUSE Tumbleweed Minimal>openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default")
+        );
+        assert_eq!(preview.default_source, "saved_entry in grubenv");
+        assert_eq!(preview.menu.timeout, Some(8));
+        assert!(preview.warnings.is_empty());
+
+        let grub_env = format!("{grub_env}\nnext_entry=UEFI Firmware Settings\n");
+        let entries = GrubBootEntries::from_contents(&config, &grub_env, &[]).unwrap();
+        let preview = BootPreview::new(&grub, &entries);
+        assert_eq!(preview.next_boot.as_deref(), Some("UEFI Firmware Settings"));
+        assert_eq!(preview.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_boot_preview_grub_default() {
+        let config = read_to_string("test_data/grub.cfg").unwrap();
+        let grub_env = read_to_string("test_data/grubenv_empty").unwrap();
+        let entries = GrubBootEntries::from_contents(&config, &grub_env, &[]).unwrap();
+        let preview = |default: &str| {
+            let grub = GrubFile::new(&format!("GRUB_DEFAULT=\"{default}\"\n")).unwrap();
+            BootPreview::new(&grub, &entries).next_boot
+        };
+
+        assert_eq!(preview("0").as_deref(), Some("openSUSE Tumbleweed Minimal"));
+        assert_eq!(
+            preview("1>1").as_deref(),
+            Some("Advanced options for openSUSE Tumbleweed Minimal>openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default (recovery mode)")
+        );
+        assert_eq!(preview("2").as_deref(), Some("UEFI Firmware Settings"));
+        assert_eq!(
+            preview("UEFI Firmware Settings").as_deref(),
+            Some("UEFI Firmware Settings")
+        );
+        // Submenus can't be booted, grub falls back to the first entry
+        assert_eq!(preview("1").as_deref(), Some("openSUSE Tumbleweed Minimal"));
+    }
+}
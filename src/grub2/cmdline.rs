@@ -0,0 +1,187 @@
+//! Kernel command lines of GRUB_CMDLINE_LINUX and GRUB_CMDLINE_LINUX_DEFAULT.
+//!
+//! Parameters are edited in place, the other parameters keep their order,
+//! quoting and the whitespace between them.
+
+/// Parameter as written on the command line, like `quiet` or `acpi_osi="Windows 2015"`
+#[derive(Debug, Clone, PartialEq)]
+struct Param {
+    /// Whitespace before the parameter
+    space: String,
+    raw: String,
+}
+
+impl Param {
+    fn name(&self) -> &str {
+        self.raw
+            .split_once('=')
+            .map_or(self.raw.as_str(), |(name, _)| name)
+    }
+}
+
+/// The parameter `name=value`, the value quoted if it has spaces
+fn format_param(name: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) if value.contains(char::is_whitespace) => format!("{name}=\"{value}\""),
+        Some(value) => format!("{name}={value}"),
+        None => name.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cmdline {
+    params: Vec<Param>,
+    /// Whitespace after the last parameter
+    trailing: String,
+}
+
+impl Cmdline {
+    /// Split the command line into parameters. Spaces in double quotes don't
+    /// split them, like the kernel does, also when the quotes are escaped for the shell.
+    pub fn parse(cmdline: &str) -> Self {
+        let mut params = Vec::new();
+        let mut space = String::new();
+        let mut raw = String::new();
+        let mut quoted = false;
+        for c in cmdline.chars() {
+            if c.is_whitespace() && !quoted {
+                if !raw.is_empty() {
+                    params.push(Param {
+                        space: std::mem::take(&mut space),
+                        raw: std::mem::take(&mut raw),
+                    });
+                }
+                space.push(c);
+                continue;
+            }
+            if c == '"' {
+                quoted = !quoted;
+            }
+            raw.push(c);
+        }
+        if !raw.is_empty() {
+            params.push(Param {
+                space: std::mem::take(&mut space),
+                raw,
+            });
+        }
+
+        Self {
+            params,
+            trailing: space,
+        }
+    }
+
+    /// Parameters as written, in order
+    pub fn params(&self) -> impl Iterator<Item = &str> {
+        self.params.iter().map(|param| param.raw.as_str())
+    }
+
+    /// Set the parameter `name`, `value` being `None` for flags like `quiet`.
+    /// The first parameter of the name is replaced in place and the later ones
+    /// are removed, a new parameter is added at the end. Returns whether the
+    /// command line changed.
+    pub fn set(&mut self, name: &str, value: Option<&str>) -> bool {
+        let raw = format_param(name, value);
+        let same: Vec<usize> = self
+            .params
+            .iter()
+            .enumerate()
+            .filter(|(_, param)| param.name() == name)
+            .map(|(idx, _)| idx)
+            .collect();
+        match same.split_first() {
+            Some((&first, [])) if self.params[first].raw == raw => false,
+            Some((&first, rest)) => {
+                self.params[first].raw = raw;
+                for &idx in rest.iter().rev() {
+                    self.remove_at(idx);
+                }
+                true
+            }
+            None => {
+                let space = if self.params.is_empty() { "" } else { " " };
+                self.params.push(Param {
+                    space: space.into(),
+                    raw,
+                });
+                true
+            }
+        }
+    }
+
+    /// Remove all the parameters of `name`, returns whether any were removed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.params.len();
+        for idx in (0..self.params.len()).rev() {
+            if self.params[idx].name() == name {
+                self.remove_at(idx);
+            }
+        }
+        self.params.len() != before
+    }
+
+    fn remove_at(&mut self, idx: usize) {
+        let removed = self.params.remove(idx);
+        // the first parameter keeps the leading whitespace of the line
+        if idx == 0 {
+            if let Some(next) = self.params.first_mut() {
+                next.space = removed.space;
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Cmdline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for param in &self.params {
+            write!(f, "{}{}", param.space, param.raw)?;
+        }
+        write!(f, "{}", self.trailing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmdline_parse() {
+        let line = " splash=silent  acpi_osi=\"Windows 2015\" quiet ";
+        let cmdline = Cmdline::parse(line);
+        assert_eq!(
+            cmdline.params().collect::<Vec<_>>(),
+            vec!["splash=silent", "acpi_osi=\"Windows 2015\"", "quiet"]
+        );
+        assert_eq!(cmdline.to_string(), line);
+
+        // quotes escaped in a double quoted grub file value
+        let cmdline = Cmdline::parse("acpi_osi=\\\"Windows 2015\\\" quiet");
+        assert_eq!(cmdline.params().count(), 2);
+    }
+
+    #[test]
+    fn test_cmdline_edit() {
+        let mut cmdline = Cmdline::parse("splash=silent  console=ttyS0 quiet console=tty0");
+        assert!(!cmdline.set("splash", Some("silent")));
+        assert!(cmdline.set("splash", Some("verbose")));
+        assert!(cmdline.set("mitigations", Some("auto,nosmt")));
+        assert!(cmdline.set("acpi_osi", Some("Windows 2015")));
+        assert_eq!(
+            cmdline.to_string(),
+            "splash=verbose  console=ttyS0 quiet console=tty0 mitigations=auto,nosmt acpi_osi=\"Windows 2015\""
+        );
+
+        assert!(cmdline.set("console", Some("tty0")));
+        assert!(cmdline.remove("splash"));
+        assert!(!cmdline.remove("nomodeset"));
+        assert_eq!(
+            cmdline.to_string(),
+            "console=tty0 quiet mitigations=auto,nosmt acpi_osi=\"Windows 2015\""
+        );
+
+        let mut empty = Cmdline::parse("");
+        assert!(empty.set("quiet", None));
+        assert_eq!(empty.to_string(), "quiet");
+    }
+}
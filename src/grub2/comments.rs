@@ -0,0 +1,147 @@
+//! Structure of the comments in the grub file.
+//!
+//! Comment lines before a key document that key, and comment blocks that are
+//! followed by an empty line work as section headings for the keys after them.
+//! Commented out keys (`# GRUB_SAVEDEFAULT="true"`) are neither, but they end
+//! the documentation of the previous block. A comment after the value of a key
+//! documents that key as well.
+
+use serde::{Deserialize, Serialize};
+
+use crate::grub2::GrubLine;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    /// First line of the section heading
+    pub name: String,
+    /// Line index of the heading
+    pub line: usize,
+    /// Keys in the section, in file order
+    pub keys: Vec<String>,
+}
+
+/// Key name of a commented out key like `# GRUB_BADRAM="0x01234567"`
+pub fn disabled_key(line: &str) -> Option<&str> {
+    let line = line.trim().strip_prefix('#')?.trim_start();
+    let (key, _) = line.split_once('=')?;
+    let is_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+
+    is_key.then_some(key)
+}
+
+fn comment_text(line: &str) -> String {
+    line.trim().trim_start_matches('#').trim().to_string()
+}
+
+/// Attach documentation comments to the keys in `lines` and return the sections
+pub fn attach_comments(lines: &mut [GrubLine]) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    // comments since the previous (disabled) key
+    let mut pending: Vec<String> = Vec::new();
+    // current block of consecutive comment lines
+    let mut block: Vec<(usize, String)> = Vec::new();
+
+    for (idx, line) in lines.iter_mut().enumerate() {
+        match line {
+            GrubLine::KeyValue(keyval) => {
+                pending.extend(block.drain(..).map(|(_, text)| text));
+                keyval.comments = std::mem::take(&mut pending);
+                keyval.comments.extend(keyval.inline_comment());
+                if let Some(section) = sections.last_mut() {
+                    section.keys.push(keyval.key.clone());
+                }
+            }
+            GrubLine::String { raw_line } => {
+                let trimmed = raw_line.trim();
+                if disabled_key(trimmed).is_some() {
+                    block.clear();
+                    pending.clear();
+                } else if trimmed.starts_with('#') {
+                    block.push((idx, comment_text(trimmed)));
+                } else if trimmed.is_empty() && !block.is_empty() {
+                    // A block starting the file is the file header, not a section
+                    if block[0].0 != 0 {
+                        sections.push(Section {
+                            name: block[0].1.clone(),
+                            line: block[0].0,
+                            keys: Vec::new(),
+                        });
+                        pending.extend(block.iter().map(|(_, text)| text.clone()));
+                    }
+                    block.clear();
+                }
+            }
+        }
+    }
+
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+    use crate::grub2::GrubFile;
+
+    #[test]
+    fn test_disabled_keys() {
+        assert_eq!(
+            disabled_key("# GRUB_SAVEDEFAULT=\"true\""),
+            Some("GRUB_SAVEDEFAULT")
+        );
+        assert_eq!(
+            disabled_key("#GRUB_DISABLE_LINUX_UUID=true"),
+            Some("GRUB_DISABLE_LINUX_UUID")
+        );
+        assert_eq!(disabled_key("# Uncomment to set GRUB_X=1"), None);
+        assert_eq!(disabled_key("GRUB_TIMEOUT=1"), None);
+    }
+
+    #[test]
+    fn test_comment_attachments() {
+        let file_data = read_to_string("test_data/grub_full").unwrap();
+        let file = GrubFile::new(&file_data).unwrap();
+        let comments = |key: &str| file.keyvalues()[key].comments.clone();
+
+        assert_eq!(
+            comments("GRUB_DISTRIBUTOR"),
+            vec![
+                "Uncomment to set your own custom distributor. If you leave it unset or empty, the default",
+                "policy is to determine the value from /etc/os-release"
+            ]
+        );
+        assert!(comments("GRUB_DEFAULT").is_empty());
+        assert_eq!(
+            comments("GRUB_TERMINAL"),
+            vec!["Uncomment to disable graphical terminal (grub-pc only)"]
+        );
+        assert_eq!(
+            comments("GRUB_GFXMODE"),
+            vec![
+                "The resolution used on graphical terminal",
+                "note that you can use only modes which your graphic card supports via VBE",
+                "you can see them in real GRUB with the command `vbeinfo'"
+            ]
+        );
+        assert!(comments("GRUB_BACKGROUND").is_empty());
+
+        let inline = GrubFile::new("# Menu\n# shown for\nGRUB_TIMEOUT=5 # seconds\n").unwrap();
+        assert_eq!(
+            inline.keyvalues()["GRUB_TIMEOUT"].comments,
+            vec!["Menu", "shown for", "seconds"]
+        );
+
+        let sections = file.sections();
+        assert_eq!(sections.len(), 6);
+        assert_eq!(
+            sections[2].name,
+            "Uncomment to disable graphical terminal (grub-pc only)"
+        );
+        assert_eq!(sections[2].keys, vec!["GRUB_TERMINAL"]);
+        assert_eq!(sections[3].keys, vec!["GRUB_GFXMODE"]);
+    }
+}
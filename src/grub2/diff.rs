@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use similar::{DiffTag, TextDiff};
+
+use crate::grub2::{GrubFile, GrubLine};
+
+/// How a client wants config differences represented
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffFormat {
+    /// Unified diff text
+    #[default]
+    Unified,
+    /// Changed keys with their old and new values
+    Keys,
+    /// Lines of both configs aligned next to each other
+    SideBySide,
+    /// No diff, for clients that don't show it
+    None,
+}
+
+/// Line of a side-by-side diff
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SideBySideLine {
+    /// Line of the old config, `None` if the line was added
+    pub old: Option<String>,
+    /// Line of the new config, `None` if the line was removed
+    pub new: Option<String>,
+}
+
+/// Difference between two configs in the format the client asked for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ConfigDiff {
+    Unified(String),
+    Keys(Vec<KeyChange>),
+    SideBySide(Vec<SideBySideLine>),
+}
+
+/// Change of a single key between two grub configs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyChange {
+    pub key: String,
+    /// Value in the old config, `None` if the key was added
+    pub old: Option<String>,
+    /// Value in the new config, `None` if the key was removed
+    pub new: Option<String>,
+}
+
+fn keys(grub: &GrubFile) -> impl Iterator<Item = &str> {
+    grub.lines().iter().filter_map(|line| match line {
+        GrubLine::KeyValue(keyval) => Some(keyval.key.as_str()),
+        GrubLine::String { .. } => None,
+    })
+}
+
+/// Keys that differ between `old` and `new`, in the order they appear in the files
+pub fn key_changes(old: &GrubFile, new: &GrubFile) -> Vec<KeyChange> {
+    let mut changes = Vec::new();
+
+    for key in keys(old) {
+        let old_value = old.value(key);
+        let new_value = new.value(key);
+        if old_value != new_value {
+            changes.push(KeyChange {
+                key: key.to_string(),
+                old: old_value.map(str::to_string),
+                new: new_value.map(str::to_string),
+            });
+        }
+    }
+
+    for key in keys(new).filter(|key| old.value(key).is_none()) {
+        changes.push(KeyChange {
+            key: key.to_string(),
+            old: None,
+            new: new.value(key).map(str::to_string),
+        });
+    }
+
+    changes
+}
+
+fn side_by_side(old: &str, new: &str) -> Vec<SideBySideLine> {
+    let diff = TextDiff::from_lines(old, new);
+    let line = |slice: &&str| Some(slice.trim_end_matches('\n').to_string());
+    let mut lines = Vec::new();
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        let old_lines = &diff.old_slices()[old_range.clone()];
+        let new_lines = &diff.new_slices()[new_range.clone()];
+        if tag == DiffTag::Equal {
+            lines.extend(old_lines.iter().map(|old| SideBySideLine {
+                old: line(old),
+                new: line(old),
+            }));
+            continue;
+        }
+
+        // replaced lines are paired up, the rest of the longer side stands alone
+        for idx in 0..old_lines.len().max(new_lines.len()) {
+            lines.push(SideBySideLine {
+                old: old_lines.get(idx).and_then(line),
+                new: new_lines.get(idx).and_then(line),
+            });
+        }
+    }
+
+    lines
+}
+
+/// Difference from the `old` to the `new` config, `None` if they're the same or
+/// the client doesn't want a diff
+pub fn config_diff(old: &str, new: &str, format: DiffFormat) -> Option<ConfigDiff> {
+    if old == new {
+        return None;
+    }
+
+    let diff = match format {
+        DiffFormat::None => return None,
+        DiffFormat::Unified => {
+            let diff = TextDiff::from_lines(old, new).unified_diff().to_string();
+            if diff.trim().is_empty() {
+                return None;
+            }
+            ConfigDiff::Unified(diff)
+        }
+        DiffFormat::Keys => ConfigDiff::Keys(key_changes(
+            &GrubFile::new_lenient(old),
+            &GrubFile::new_lenient(new),
+        )),
+        DiffFormat::SideBySide => ConfigDiff::SideBySide(side_by_side(old, new)),
+    };
+
+    Some(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_changes() {
+        let old = GrubFile::new("GRUB_TIMEOUT=8\nGRUB_DEFAULT=saved\nGRUB_GFXMODE=auto\n").unwrap();
+        let new =
+            GrubFile::new("GRUB_TIMEOUT=5\nGRUB_DEFAULT=saved\nGRUB_TERMINAL=console\n").unwrap();
+
+        assert_eq!(
+            key_changes(&old, &new),
+            vec![
+                KeyChange {
+                    key: "GRUB_TIMEOUT".into(),
+                    old: Some("8".into()),
+                    new: Some("5".into()),
+                },
+                KeyChange {
+                    key: "GRUB_GFXMODE".into(),
+                    old: Some("auto".into()),
+                    new: None,
+                },
+                KeyChange {
+                    key: "GRUB_TERMINAL".into(),
+                    old: None,
+                    new: Some("console".into()),
+                },
+            ]
+        );
+        assert!(key_changes(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_config_diff_formats() {
+        let old = "GRUB_TIMEOUT=8\nGRUB_DEFAULT=saved\n";
+        let new = "GRUB_TIMEOUT=5\nGRUB_DEFAULT=saved\nGRUB_TERMINAL=console\n";
+
+        assert_eq!(config_diff(old, old, DiffFormat::Unified), None);
+        assert_eq!(config_diff(old, new, DiffFormat::None), None);
+
+        let Some(ConfigDiff::Unified(unified)) = config_diff(old, new, DiffFormat::Unified) else {
+            panic!("expected a unified diff");
+        };
+        assert!(unified.contains("-GRUB_TIMEOUT=8\n+GRUB_TIMEOUT=5"));
+
+        let Some(ConfigDiff::Keys(keys)) = config_diff(old, new, DiffFormat::Keys) else {
+            panic!("expected key changes");
+        };
+        assert_eq!(keys.len(), 2);
+
+        let line = |old: Option<&str>, new: Option<&str>| SideBySideLine {
+            old: old.map(str::to_string),
+            new: new.map(str::to_string),
+        };
+        assert_eq!(
+            config_diff(old, new, DiffFormat::SideBySide),
+            Some(ConfigDiff::SideBySide(vec![
+                line(Some("GRUB_TIMEOUT=8"), Some("GRUB_TIMEOUT=5")),
+                line(Some("GRUB_DEFAULT=saved"), Some("GRUB_DEFAULT=saved")),
+                line(None, Some("GRUB_TERMINAL=console")),
+            ]))
+        );
+    }
+}
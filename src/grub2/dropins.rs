@@ -0,0 +1,145 @@
+//! Drop-in configs in /etc/default/grub.d.
+//!
+//! grub2-mkconfig sources the `*.cfg` drop-ins in name order after the grub
+//! file, so a key set in a drop-in overrides the grub file, and a later drop-in
+//! overrides the earlier ones. Changes to such keys are written to the drop-in
+//! that sets them, since changing the grub file would have no effect.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::grub2::GrubFile;
+
+/// Value of a key that a drop-in config sets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DropinValue {
+    /// File name of the drop-in in grub.d
+    pub file: String,
+    pub value: String,
+}
+
+/// The keys set in `dropins`, with the values in effect once all of them are sourced
+pub fn dropin_values(dropins: &BTreeMap<String, String>) -> BTreeMap<String, DropinValue> {
+    let mut values = BTreeMap::new();
+    for (file, contents) in dropins {
+        // drop-ins are shell fragments, only the assignments are of interest
+        let dropin = GrubFile::new_lenient(contents);
+        for (key, keyval) in dropin.keyvalues() {
+            values.insert(
+                key.clone(),
+                DropinValue {
+                    file: file.clone(),
+                    value: keyval.value.clone(),
+                },
+            );
+        }
+    }
+    values
+}
+
+/// Move the changes `new` makes to the `current` grub file into the drop-ins
+/// that set the changed keys. Returns the drop-ins with the changes, `new` is
+/// left with the grub file values of the moved keys.
+pub fn move_dropin_changes(
+    current: &GrubFile,
+    new: &mut GrubFile,
+    dropins: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let overridden = dropin_values(dropins);
+    let mut changed: BTreeMap<String, GrubFile> = BTreeMap::new();
+    let mut keys: Vec<(String, String)> = new
+        .keyvalues()
+        .iter()
+        .map(|(key, keyval)| (key.clone(), keyval.value.clone()))
+        .collect();
+    keys.sort();
+
+    for (key, value) in keys {
+        let Some(dropin) = overridden.get(&key) else {
+            continue;
+        };
+        if current.value(&key) == Some(value.as_str()) || dropin.value == value {
+            continue;
+        }
+
+        log::info!(
+            "{key} is set in the drop-in config {}, the change is written to it",
+            dropin.file
+        );
+        changed
+            .entry(dropin.file.clone())
+            .or_insert_with(|| GrubFile::new_lenient(&dropins[&dropin.file]))
+            .set_key_value(&key, &value);
+        match current.value(&key) {
+            Some(current) => new.set_key_value(&key, current),
+            None => new.remove_key(&key),
+        }
+    }
+
+    changed
+        .into_iter()
+        .map(|(file, dropin)| (file, dropin.as_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dropins() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            (
+                "40_custom.cfg".to_string(),
+                "GRUB_TIMEOUT=10\nGRUB_CMDLINE_LINUX_DEFAULT=\"quiet\"\n".to_string(),
+            ),
+            (
+                "50_cloud.cfg".to_string(),
+                "# set by the image\nGRUB_TIMEOUT=0\n".to_string(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_dropin_values() {
+        let values = dropin_values(&dropins());
+        assert_eq!(
+            values["GRUB_TIMEOUT"],
+            DropinValue {
+                file: "50_cloud.cfg".into(),
+                value: "0".into()
+            }
+        );
+        assert_eq!(values["GRUB_CMDLINE_LINUX_DEFAULT"].file, "40_custom.cfg");
+        assert!(!values.contains_key("GRUB_DEFAULT"));
+    }
+
+    #[test]
+    fn test_move_dropin_changes() {
+        let current = GrubFile::new("GRUB_DEFAULT=saved\nGRUB_TIMEOUT=8\n").unwrap();
+        let mut new = current.clone();
+        new.set_key_value("GRUB_DEFAULT", "0");
+        new.set_key_value("GRUB_TIMEOUT", "3");
+        new.set_key_value("GRUB_CMDLINE_LINUX_DEFAULT", "quiet splash");
+
+        let changed = move_dropin_changes(&current, &mut new, &dropins());
+        assert_eq!(
+            new.as_string(),
+            "GRUB_DEFAULT=0\nGRUB_TIMEOUT=8\n",
+            "only the key without a drop-in is changed in the grub file"
+        );
+        assert_eq!(
+            changed,
+            BTreeMap::from([
+                (
+                    "40_custom.cfg".to_string(),
+                    "GRUB_TIMEOUT=10\nGRUB_CMDLINE_LINUX_DEFAULT=\"quiet splash\"\n".to_string()
+                ),
+                (
+                    "50_cloud.cfg".to_string(),
+                    "# set by the image\nGRUB_TIMEOUT=3\n".to_string()
+                ),
+            ])
+        );
+    }
+}
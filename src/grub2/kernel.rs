@@ -0,0 +1,119 @@
+use std::cmp::Ordering;
+
+/// Get the kernel version from a kernel image path like `/boot/vmlinuz-6.17.5-1-default`
+pub fn version_from_image(image: &str) -> Option<&str> {
+    let name = image.rsplit('/').next()?;
+    let version = name
+        .strip_prefix("vmlinuz-")
+        .or_else(|| name.strip_prefix("vmlinux-"))
+        .or_else(|| name.strip_prefix("Image-"))
+        .or_else(|| name.strip_prefix("image-"))?;
+
+    if version.is_empty() {
+        None
+    } else {
+        Some(version)
+    }
+}
+
+/// Get the kernel version from an entry title like
+/// `openSUSE Tumbleweed, with Linux 6.17.5-1-default (recovery mode)`
+pub fn version_from_title(title: &str) -> Option<&str> {
+    let (_, rest) = title.split_once("with Linux ")?;
+    rest.split_whitespace().next()
+}
+
+/// Get the flavor of the kernel version, e.g. `default` from `6.17.5-1-default`
+/// or `rt` from `6.4.0-150600.10-rt`.
+///
+/// Versions without a flavor suffix, like Fedora's `6.5.6-300.fc39.x86_64`,
+/// don't have a flavor.
+pub fn flavor(version: &str) -> Option<&str> {
+    let (_, flavor) = version.rsplit_once('-')?;
+    if !flavor.is_empty() && flavor.chars().all(|c| c.is_ascii_alphanumeric()) {
+        // release numbers like "-1" are not flavors
+        if flavor
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic())
+        {
+            return Some(flavor);
+        }
+    }
+
+    None
+}
+
+/// Compare kernel versions by their numeric components so that
+/// `6.10.1-1-default` is newer than `6.9.12-3-default`
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|part| !part.is_empty())
+            .filter_map(|part| part.parse().ok())
+            .collect()
+    };
+
+    numbers(a).cmp(&numbers(b))
+}
+
+/// Is the initrd image a CPU microcode image, like `/boot/intel-ucode.img` or
+/// the `$early_ucode` variable that some distros set to the microcode images
+pub fn is_microcode_image(image: &str) -> bool {
+    let name = image
+        .rsplit('/')
+        .next()
+        .unwrap_or(image)
+        .to_ascii_lowercase();
+    name.contains("ucode") || name.contains("microcode")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_versions() {
+        assert_eq!(
+            version_from_image("/boot/vmlinuz-6.17.5-1-default"),
+            Some("6.17.5-1-default")
+        );
+        assert_eq!(version_from_image("/boot/vmlinuz"), None);
+        assert_eq!(
+            version_from_title("openSUSE Tumbleweed, with Linux 6.17.5-1-rt (recovery mode)"),
+            Some("6.17.5-1-rt")
+        );
+        assert_eq!(version_from_title("UEFI Firmware Settings"), None);
+    }
+
+    #[test]
+    fn test_kernel_flavors() {
+        assert_eq!(flavor("6.17.5-1-default"), Some("default"));
+        assert_eq!(flavor("6.4.0-150600.10-rt"), Some("rt"));
+        assert_eq!(flavor("6.12.3-1-longterm"), Some("longterm"));
+        assert_eq!(flavor("6.5.6-300.fc39.x86_64"), None);
+        assert_eq!(flavor("6.5.6-1"), None);
+    }
+
+    #[test]
+    fn test_kernel_version_ordering() {
+        assert_eq!(
+            compare_versions("6.10.1-1-default", "6.9.12-3-default"),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_versions("6.4.0-150600.10-rt", "6.4.0-150600.10-rt"),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn test_microcode_images() {
+        assert!(is_microcode_image("/boot/intel-ucode.img"));
+        assert!(is_microcode_image("/amd-ucode.img"));
+        assert!(is_microcode_image("$early_ucode"));
+        assert!(is_microcode_image("/boot/microcode.cpio"));
+        assert!(!is_microcode_image("/boot/initrd-6.17.5-1-default"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Documented keys of /etc/default/grub and the formats of their values.
+//!
+//! Rarely used keys get the same checks as the common ones, a malformed
+//! GRUB_BADRAM or GRUB_INIT_TUNE is as easy to miss and as hard to debug.
+
+use serde::{Deserialize, Serialize};
+
+use crate::grub2::GrubFile;
+
+/// Format of a key's value
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ValueKind {
+    /// Any text
+    Text,
+    /// One of the listed values
+    Choice { values: &'static [&'static str] },
+    /// Whole number of at least `min`
+    Integer { min: i64 },
+    /// Comma separated address and mask pairs, like `0x01234567,0xfefefefe`
+    AddressMasks,
+    /// Space separated grub module names
+    Modules,
+    /// Tempo followed by pitch and duration pairs, like `480 440 1`
+    Tune,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeySchema {
+    pub key: &'static str,
+    pub kind: ValueKind,
+    pub description: &'static str,
+}
+
+const BOOL: ValueKind = ValueKind::Choice {
+    values: &["true", "false"],
+};
+
+pub const KEYS: &[KeySchema] = &[
+    KeySchema {
+        key: "GRUB_DEFAULT",
+        kind: ValueKind::Text,
+        description: "Default entry, by index, title or id, or 'saved'",
+    },
+    KeySchema {
+        key: "GRUB_SAVEDEFAULT",
+        kind: BOOL,
+        description: "Save the booted entry as the new default",
+    },
+    KeySchema {
+        key: "GRUB_TIMEOUT",
+        kind: ValueKind::Integer { min: -1 },
+        description: "Seconds before booting the default entry, -1 waits forever",
+    },
+    KeySchema {
+        key: "GRUB_TIMEOUT_STYLE",
+        kind: ValueKind::Choice {
+            values: &["menu", "countdown", "hidden"],
+        },
+        description: "What is shown during the timeout",
+    },
+    KeySchema {
+        key: "GRUB_RECORDFAIL_TIMEOUT",
+        kind: ValueKind::Integer { min: -1 },
+        description: "Timeout after a failed boot, -1 waits forever",
+    },
+    KeySchema {
+        key: "GRUB_DISABLE_RECOVERY",
+        kind: BOOL,
+        description: "Don't generate recovery mode entries",
+    },
+    KeySchema {
+        key: "GRUB_DISABLE_SUBMENU",
+        kind: BOOL,
+        description: "Show all kernels on the top level menu",
+    },
+    KeySchema {
+        key: "GRUB_DISABLE_OS_PROBER",
+        kind: BOOL,
+        description: "Don't add entries for other operating systems",
+    },
+    KeySchema {
+        key: "GRUB_DISABLE_LINUX_UUID",
+        kind: BOOL,
+        description: "Pass the root device by name instead of by UUID",
+    },
+    KeySchema {
+        key: "GRUB_DISABLE_LINUX_PARTUUID",
+        kind: BOOL,
+        description: "Don't pass the root device by partition UUID",
+    },
+    KeySchema {
+        key: "GRUB_ENABLE_CRYPTODISK",
+        kind: ValueKind::Choice {
+            values: &["y", "n"],
+        },
+        description: "Unlock encrypted disks to read /boot",
+    },
+    KeySchema {
+        key: "GRUB_BADRAM",
+        kind: ValueKind::AddressMasks,
+        description: "Memory regions the kernel must not use",
+    },
+    KeySchema {
+        key: "GRUB_PRELOAD_MODULES",
+        kind: ValueKind::Modules,
+        description: "Modules loaded before anything else",
+    },
+    KeySchema {
+        key: "GRUB_INIT_TUNE",
+        kind: ValueKind::Tune,
+        description: "Tune played by the PC speaker when grub starts",
+    },
+];
+
+/// Value of a key that doesn't have the documented format
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvalidValue {
+    pub key: String,
+    pub value: String,
+    pub problem: String,
+}
+
+fn is_hex(value: &str) -> bool {
+    value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Why `value` isn't of the `kind`, `None` if it is
+fn value_problem(kind: ValueKind, value: &str) -> Option<String> {
+    match kind {
+        ValueKind::Text => None,
+        ValueKind::Choice { values } => {
+            (!values.contains(&value)).then(|| format!("must be one of '{}'", values.join("', '")))
+        }
+        ValueKind::Integer { min } => match value.parse::<i64>() {
+            Ok(number) if number >= min => None,
+            Ok(_) => Some(format!("must be at least {min}")),
+            Err(_) => Some("must be a whole number".into()),
+        },
+        ValueKind::AddressMasks => {
+            let parts: Vec<_> = value.split(',').map(str::trim).collect();
+            if parts.len() % 2 != 0 {
+                Some("must be address and mask pairs".into())
+            } else {
+                parts
+                    .iter()
+                    .find(|part| !is_hex(part))
+                    .map(|part| format!("'{part}' is not a hexadecimal number like 0x01234567"))
+            }
+        }
+        ValueKind::Modules => value
+            .split_whitespace()
+            .find(|module| {
+                !module
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            })
+            .map(|module| format!("'{module}' is not a module name")),
+        ValueKind::Tune => {
+            let numbers: Vec<_> = value.split_whitespace().collect();
+            if let Some(number) = numbers.iter().find(|number| number.parse::<u32>().is_err()) {
+                Some(format!("'{number}' is not a number"))
+            } else if numbers.len() < 3 || numbers.len() % 2 == 0 {
+                Some("must be a tempo followed by pitch and duration pairs".into())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Values of the documented keys that don't have the documented format. Empty
+/// values are left out, they make grub use the default.
+pub fn invalid_values(grub: &GrubFile) -> Vec<InvalidValue> {
+    KEYS.iter()
+        .filter_map(|schema| {
+            let value = grub.value(schema.key).filter(|value| !value.is_empty())?;
+            let problem = value_problem(schema.kind, value)?;
+            Some(InvalidValue {
+                key: schema.key.into(),
+                value: value.into(),
+                problem,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_problem() {
+        assert!(value_problem(ValueKind::AddressMasks, "0x01234567,0xfefefefe").is_none());
+        assert!(value_problem(
+            ValueKind::AddressMasks,
+            "0x01234567,0xfefefefe,0x89abcdef,0xefefefef"
+        )
+        .is_none());
+        assert!(value_problem(ValueKind::AddressMasks, "0x01234567").is_some());
+        assert!(value_problem(ValueKind::AddressMasks, "0x01234567,fefefefe").is_some());
+
+        assert!(value_problem(ValueKind::Tune, "480 440 1").is_none());
+        assert!(value_problem(ValueKind::Tune, "480 440 1 880 1").is_none());
+        assert!(value_problem(ValueKind::Tune, "480 440").is_some());
+        assert!(value_problem(ValueKind::Tune, "480 a4 1").is_some());
+
+        assert!(value_problem(ValueKind::Modules, "lvm luks2 part_gpt").is_none());
+        assert!(value_problem(ValueKind::Modules, "lvm,luks2").is_some());
+
+        assert!(value_problem(ValueKind::Integer { min: -1 }, "-1").is_none());
+        assert!(value_problem(ValueKind::Integer { min: -1 }, "-2").is_some());
+        assert!(value_problem(BOOL, "true").is_none());
+        assert!(value_problem(BOOL, "yes").is_some());
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let grub = GrubFile::new(
+            "GRUB_TIMEOUT=8\nGRUB_BADRAM=\"0x01234567\"\nGRUB_INIT_TUNE=\"\"\nGRUB_FOO=bar\n",
+        )
+        .unwrap();
+        let invalid = invalid_values(&grub);
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].key, "GRUB_BADRAM");
+        assert_eq!(invalid[0].value, "0x01234567");
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+
+use crate::grub2::GrubFile;
+
+/// Timeout that grub uses when GRUB_TIMEOUT is not set
+const DEFAULT_TIMEOUT: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutStyle {
+    /// Menu is shown during the timeout
+    Menu,
+    /// Only a countdown is shown during the timeout
+    Countdown,
+    /// Nothing is shown during the timeout
+    Hidden,
+}
+
+/// What the user sees when grub starts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MenuPreview {
+    pub style: TimeoutStyle,
+    /// Seconds before the default entry is booted, `None` waits forever
+    pub timeout: Option<i64>,
+    /// Can the menu be opened at all before the default entry is booted
+    pub reachable: bool,
+    /// Human readable description of the boot countdown
+    pub description: String,
+    pub warnings: Vec<String>,
+}
+
+impl MenuPreview {
+    pub fn new(grub: &GrubFile) -> Self {
+        let mut warnings = Vec::new();
+
+        let style = match grub.value("GRUB_TIMEOUT_STYLE") {
+            None | Some("") | Some("menu") => TimeoutStyle::Menu,
+            Some("countdown") => TimeoutStyle::Countdown,
+            Some("hidden") => TimeoutStyle::Hidden,
+            Some(style) => {
+                warnings.push(format!(
+                    "GRUB_TIMEOUT_STYLE '{style}' is not any of 'menu', 'countdown' or 'hidden'"
+                ));
+                TimeoutStyle::Menu
+            }
+        };
+
+        let timeout = match grub.value("GRUB_TIMEOUT") {
+            None | Some("") => DEFAULT_TIMEOUT,
+            Some(timeout) => timeout.trim().parse::<i64>().unwrap_or_else(|_| {
+                warnings.push(format!("GRUB_TIMEOUT '{timeout}' is not a number"));
+                DEFAULT_TIMEOUT
+            }),
+        };
+
+        if grub
+            .value("GRUB_HIDDEN_TIMEOUT")
+            .is_some_and(|val| !val.is_empty())
+        {
+            warnings.push(
+                "GRUB_HIDDEN_TIMEOUT is deprecated, use GRUB_TIMEOUT_STYLE instead".to_string(),
+            );
+        }
+
+        let reachable = timeout != 0;
+        let description = match (timeout, style) {
+            (..0, _) => "Menu is shown until an entry is selected".to_string(),
+            (0, _) => "Default entry is booted immediately without showing the menu".to_string(),
+            (secs, TimeoutStyle::Menu) => {
+                format!("Menu is shown for {secs} seconds before booting the default entry")
+            }
+            (secs, TimeoutStyle::Countdown) => format!(
+                "Countdown of {secs} seconds is shown, press Esc, F4 or hold Shift to show the menu"
+            ),
+            (secs, TimeoutStyle::Hidden) => format!(
+                "Nothing is shown for {secs} seconds, press Esc, F4 or hold Shift to show the menu"
+            ),
+        };
+
+        if !reachable {
+            let hint = if style == TimeoutStyle::Menu {
+                "GRUB_TIMEOUT is 0"
+            } else {
+                "GRUB_TIMEOUT is 0 and the menu is hidden"
+            };
+            warnings.push(format!("Boot menu cannot be accessed: {hint}"));
+        }
+
+        Self {
+            style,
+            timeout: (timeout >= 0).then_some(timeout),
+            reachable,
+            description,
+            warnings,
+        }
+    }
+}
+
+/// Minimal changes to `grub` that make the boot menu reachable again.
+/// Returns the changed keys with their new values.
+pub fn make_menu_accessible(grub: &mut GrubFile) -> Vec<(String, String)> {
+    let preview = MenuPreview::new(grub);
+    let mut changes = Vec::new();
+
+    if !preview.reachable {
+        // Keep the style but give the user time to interrupt the boot
+        changes.push(("GRUB_TIMEOUT".to_string(), DEFAULT_TIMEOUT.to_string()));
+    }
+
+    for (key, value) in &changes {
+        grub.set_key_value(key, value);
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_preview() {
+        let grub = GrubFile::new("GRUB_TIMEOUT=8\n").unwrap();
+        let preview = MenuPreview::new(&grub);
+        assert_eq!(preview.style, TimeoutStyle::Menu);
+        assert_eq!(preview.timeout, Some(8));
+        assert!(preview.reachable);
+        assert!(preview.warnings.is_empty());
+
+        let grub = GrubFile::new("GRUB_TIMEOUT=-1\n").unwrap();
+        let preview = MenuPreview::new(&grub);
+        assert_eq!(preview.timeout, None);
+        assert!(preview.reachable);
+    }
+
+    #[test]
+    fn test_menu_hidden_trap() {
+        let mut grub = GrubFile::new("GRUB_TIMEOUT_STYLE=hidden\nGRUB_TIMEOUT=0\n").unwrap();
+        let preview = MenuPreview::new(&grub);
+        assert_eq!(preview.style, TimeoutStyle::Hidden);
+        assert!(!preview.reachable);
+        assert_eq!(
+            preview.warnings,
+            vec!["Boot menu cannot be accessed: GRUB_TIMEOUT is 0 and the menu is hidden"]
+        );
+
+        let changes = make_menu_accessible(&mut grub);
+        assert_eq!(changes, vec![("GRUB_TIMEOUT".to_string(), "5".to_string())]);
+        assert_eq!(
+            grub.as_string(),
+            "GRUB_TIMEOUT_STYLE=hidden\nGRUB_TIMEOUT=5\n"
+        );
+        assert!(MenuPreview::new(&grub).reachable);
+        assert!(make_menu_accessible(&mut grub).is_empty());
+    }
+}
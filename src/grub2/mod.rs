@@ -3,11 +3,28 @@ use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt::Display, fs::read_to_string, path::Path};
 
 use crate::{
-    config::{GRUB_CFG_PATH, GRUB_ENV_PATH},
+    config::Paths,
     dctx,
-    errors::{DError, DRes, DResult},
+    errors::{DError, DErrorType, DRes, DResult},
+    grub2::{
+        comments::{attach_comments, disabled_key, Section},
+        shell::{logical_lines, parse_assignment},
+    },
 };
 
+pub mod bls;
+pub mod boot;
+pub mod cmdline;
+pub mod comments;
+pub mod diff;
+pub mod dropins;
+pub mod kernel;
+pub mod keys;
+pub mod menu;
+pub mod params;
+pub mod shell;
+pub mod titles;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyValue {
     line: usize,
@@ -15,7 +32,11 @@ pub struct KeyValue {
     changed: bool,
 
     pub key: String,
+    /// The value as the shell reads it, without quotes and escapes
     pub value: String,
+    /// Documentation comments of the key, without the leading '#'
+    #[serde(default)]
+    pub comments: Vec<String>,
 }
 
 impl KeyValue {
@@ -26,6 +47,7 @@ impl KeyValue {
             key: "".into(),
             value: "".into(),
             original: original.into(),
+            comments: Vec::new(),
         };
 
         kv.parse()?;
@@ -39,33 +61,89 @@ impl KeyValue {
             changed: true,
             key: key.into(),
             value: value.into(),
+            comments: Vec::new(),
         }
     }
 
     fn parse(&mut self) -> DResult<()> {
-        // TODO: save the type of quotes so they can be returned to orignal
-        let trimmed = self.original.trim();
-        let split = if let Some(split) = trimmed.split_once('=') {
-            split
-        } else {
-            return Err(DError::grub_parse_error(
-                dctx!(),
-                format!("Expected '=' on line: {}", self.line + 1),
-            ));
-        };
-        self.key = split.0.into();
-        self.value = split.1.replace(['\'', '"'], "");
+        let assignment = parse_assignment(&self.original).map_err(|message| {
+            DError::grub_parse_error(dctx!(), format!("{message} on line: {}", self.line + 1))
+        })?;
+        self.key = assignment.key.into();
+        self.value = assignment.value;
 
         Ok(())
     }
 
+    /// The line with the current value, written in the style of the original
+    /// line: its indentation, `export`, quotes and comment are kept. New keys
+    /// are written with double quotes. A value that was continued on several
+    /// lines is written on one. Changed values are meant literally, so in
+    /// double quotes nothing in them expands.
+    fn to_line(&self) -> String {
+        if !self.changed {
+            return self.original.clone();
+        }
+
+        let (indent, export, quote, tail) = match parse_assignment(&self.original) {
+            Ok(assignment) => (
+                assignment.indent,
+                assignment.export,
+                quote_of(assignment.word),
+                assignment.tail,
+            ),
+            Err(_) => ("", "", Some('"'), ""),
+        };
+
+        let is_plain = |c: char| c.is_ascii_alphanumeric() || "-_.,/:=+@%".contains(c);
+        let quote = match quote {
+            None if !self.value.is_empty() && self.value.chars().all(is_plain) => None,
+            Some('\'') if !self.value.contains('\'') => Some('\''),
+            _ => Some('"'),
+        };
+        let value = match quote {
+            Some('"') => escape_double_quoted(&self.value),
+            _ => self.value.clone(),
+        };
+        let quote = quote.map(String::from).unwrap_or_default();
+        format!("{indent}{export}{}={quote}{value}{quote}{tail}", self.key)
+    }
+
+    /// Comment after the value, like `seconds` of `GRUB_TIMEOUT=5 # seconds`
+    fn inline_comment(&self) -> Option<String> {
+        let assignment = parse_assignment(&self.original).ok()?;
+        let comment = assignment.tail.trim().strip_prefix('#')?.trim();
+        (!comment.is_empty()).then(|| comment.to_string())
+    }
+
     fn update<V: Into<String>>(&mut self, value: V) {
         let new_value = value.into();
         if self.value != new_value {
-            self.changed = true;
             self.value = new_value;
+            // a value changed back keeps the line as it was written
+            self.changed = KeyValue::new(self.line, &self.original)
+                .map_or(true, |original| original.value != self.value);
+        }
+    }
+}
+
+/// Quote character `value` is enclosed in as a whole, if any
+fn quote_of(value: &str) -> Option<char> {
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    (value.len() >= 2 && value.ends_with(quote)).then_some(quote)
+}
+
+/// Escape the characters that are special in double quotes, the ones the
+/// shell unescapes when it reads the value
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$' | '`') {
+            escaped.push('\\');
         }
+        escaped.push(c);
     }
+    escaped
 }
 
 impl From<KeyValue> for String {
@@ -73,18 +151,14 @@ impl From<KeyValue> for String {
         if !value.changed {
             value.original
         } else {
-            format!("{}=\"{}\"", value.key, value.value)
+            value.to_line()
         }
     }
 }
 
 impl From<&KeyValue> for String {
     fn from(value: &KeyValue) -> Self {
-        if !value.changed {
-            value.original.clone()
-        } else {
-            format!("{}=\"{}\"", value.key, value.value)
-        }
+        value.to_line()
     }
 }
 
@@ -113,35 +187,107 @@ impl From<&GrubLine> for String {
     }
 }
 
-#[derive(Debug)]
+/// Line of a grub config that cannot be parsed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParseError {
+    /// Line number, starting from 1
+    pub line: usize,
+    pub raw_line: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
 pub struct GrubFile {
     lines: Vec<GrubLine>,
     keyvals: HashMap<String, KeyValue>,
+    sections: Vec<Section>,
 }
 
 impl GrubFile {
     pub fn new(file: &str) -> DResult<Self> {
         let mut lines = Vec::new();
-        let mut keyvals = HashMap::new();
 
-        // use split instead of lines to save the trailing empty new line
         // this doesn't handle \r\n but this is very unlikely to run on
         // windows anyways
-        for (idx, line) in file.split('\n').enumerate() {
+        for (idx, line) in logical_lines(file) {
             let trimmed = line.trim();
             if trimmed.is_empty() || trimmed.starts_with('#') {
-                lines.push(GrubLine::String {
-                    raw_line: line.into(),
-                });
+                lines.push(GrubLine::String { raw_line: line });
                 continue;
             }
 
-            let keyval = KeyValue::new(idx, line)?;
-            keyvals.insert(keyval.key.clone(), keyval.clone());
+            let keyval = KeyValue::new(idx, &line)?;
             lines.push(GrubLine::KeyValue(keyval));
         }
 
-        Ok(Self { lines, keyvals })
+        Ok(Self::from_parsed_lines(lines))
+    }
+
+    /// Parse the config, keeping the lines that cannot be parsed as raw lines
+    pub fn new_lenient(file: &str) -> Self {
+        let lines = logical_lines(file)
+            .into_iter()
+            .map(|(idx, line)| {
+                let trimmed = line.trim();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    return GrubLine::String { raw_line: line };
+                }
+
+                match KeyValue::new(idx, &line) {
+                    Ok(keyval) => GrubLine::KeyValue(keyval),
+                    Err(_) => GrubLine::String { raw_line: line },
+                }
+            })
+            .collect();
+
+        Self::from_parsed_lines(lines)
+    }
+
+    /// All the lines of the config that cannot be parsed, empty if the config is valid
+    pub fn parse_errors(file: &str) -> Vec<ParseError> {
+        logical_lines(file)
+            .into_iter()
+            .filter(|(_, line)| {
+                let trimmed = line.trim();
+                !trimmed.is_empty() && !trimmed.starts_with('#')
+            })
+            .filter_map(|(idx, line)| {
+                let err = KeyValue::new(idx, &line).err()?;
+                let message = match err.error() {
+                    DErrorType::GrubParse(message) => message.clone(),
+                    error => error.as_string(),
+                };
+                Some(ParseError {
+                    line: idx + 1,
+                    raw_line: line,
+                    message,
+                })
+            })
+            .collect()
+    }
+
+    fn from_parsed_lines(mut lines: Vec<GrubLine>) -> Self {
+        // lines may have been moved around, keep the key indices in sync
+        for (idx, line) in lines.iter_mut().enumerate() {
+            if let GrubLine::KeyValue(keyval) = line {
+                keyval.line = idx;
+            }
+        }
+
+        let sections = attach_comments(&mut lines);
+        let keyvals = lines
+            .iter()
+            .filter_map(|line| match line {
+                GrubLine::KeyValue(keyval) => Some((keyval.key.clone(), keyval.clone())),
+                GrubLine::String { .. } => None,
+            })
+            .collect();
+
+        Self {
+            lines,
+            keyvals,
+            sections,
+        }
     }
 
     pub fn set_key_value(&mut self, key: &str, value: &str) {
@@ -152,10 +298,63 @@ impl GrubFile {
                 keyval.update(value);
             }
         } else {
-            // else add a new value
-            let keyval = KeyValue::from_key_val(self.lines.len(), key, value);
-            self.keyvals.insert(keyval.key.clone(), keyval.clone());
-            self.lines.push(GrubLine::KeyValue(keyval));
+            // else add a new value next to the related keys
+            let idx = self.insert_position(key);
+            let keyval = KeyValue::from_key_val(idx, key, value);
+            let mut lines = std::mem::take(&mut self.lines);
+            lines.insert(idx, GrubLine::KeyValue(keyval));
+            *self = Self::from_parsed_lines(lines);
+        }
+    }
+
+    /// Remove the line of `key`, if the config has it
+    pub fn remove_key(&mut self, key: &str) {
+        if let Some(keyval) = self.keyvals.get(key) {
+            let mut lines = std::mem::take(&mut self.lines);
+            lines.remove(keyval.line);
+            *self = Self::from_parsed_lines(lines);
+        }
+    }
+
+    /// Line index where a new `key` should be inserted so the file stays organized
+    /// like the distro template:
+    ///  1. right after a commented out line of the same key, e.g. `# GRUB_SAVEDEFAULT="true"`
+    ///  2. after the key sharing the longest name prefix, e.g. GRUB_CMDLINE_XEN after GRUB_CMDLINE_LINUX
+    ///  3. at the end of the file, before the trailing new line
+    fn insert_position(&self, key: &str) -> usize {
+        let disabled = self.lines.iter().position(|line| match line {
+            GrubLine::String { raw_line } => disabled_key(raw_line) == Some(key),
+            GrubLine::KeyValue(_) => false,
+        });
+        if let Some(idx) = disabled {
+            return idx + 1;
+        }
+
+        // Keys need to share more than the "GRUB_" prefix to be related
+        let shared_segments = |other: &str| {
+            key.split('_')
+                .zip(other.split('_'))
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        let related = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, line)| match line {
+                GrubLine::KeyValue(keyval) => Some((idx, shared_segments(&keyval.key))),
+                GrubLine::String { .. } => None,
+            })
+            .filter(|(_, shared)| *shared >= 2)
+            // last of the keys with the longest shared prefix
+            .max_by_key(|(idx, shared)| (*shared, *idx));
+        if let Some((idx, _)) = related {
+            return idx + 1;
+        }
+
+        match self.lines.last() {
+            Some(GrubLine::String { raw_line }) if raw_line.is_empty() => self.lines.len() - 1,
+            _ => self.lines.len(),
         }
     }
 
@@ -166,17 +365,7 @@ impl GrubFile {
     }
 
     pub fn from_lines(grub_lines: &[GrubLine]) -> Self {
-        let mut lines = Vec::new();
-        let mut keyvals = HashMap::new();
-
-        for line in grub_lines {
-            lines.push(line.clone());
-            if let GrubLine::KeyValue(keyval) = line {
-                keyvals.insert(keyval.key.clone(), keyval.clone());
-            }
-        }
-
-        Self { lines, keyvals }
+        Self::from_parsed_lines(grub_lines.to_vec())
     }
 
     pub fn lines(&self) -> &[GrubLine] {
@@ -187,6 +376,14 @@ impl GrubFile {
         &self.keyvals
     }
 
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.keyvals.get(key).map(|keyval| keyval.value.as_str())
+    }
+
     pub fn as_string(&self) -> String {
         let lines: Vec<String> = self.lines().iter().map(|val| val.into()).collect();
         lines.join("\n")
@@ -211,21 +408,81 @@ impl Display for GrubEnvValue<'_> {
     }
 }
 
+/// What a boot entry boots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    Linux,
+    /// Linux in recovery mode
+    Recovery,
+    /// Firmware setup menu
+    Firmware,
+    /// Another operating system, chainloaded by grub
+    ForeignOs,
+    Other,
+}
+
+impl EntryKind {
+    const ALL: [Self; 5] = [
+        Self::Linux,
+        Self::Recovery,
+        Self::Firmware,
+        Self::ForeignOs,
+        Self::Other,
+    ];
+
+    /// Name of the kind, same as the serialized value
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Linux => "linux",
+            Self::Recovery => "recovery",
+            Self::Firmware => "firmware",
+            Self::ForeignOs => "foreign-os",
+            Self::Other => "other",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GrubBootEntry {
     /// The actual name of the entry
     entry: String,
     /// (nested) submenus
     submenus: Vec<String>,
+    /// Kernel image loaded by the entry, if any
+    kernel: Option<String>,
+    /// Kernel command line of the entry
+    options: Option<String>,
+    /// Initrd images loaded by the entry, in load order
+    initrds: Vec<String>,
+    /// Kind detected from the commands of the entry
+    kind: EntryKind,
+    /// Id of the BLS entry the entry was made from by `blscfg`, which grub uses
+    /// instead of the title to select the entry
+    bls_id: Option<String>,
 }
 
 impl GrubBootEntry {
     fn new(entry: String, submenus: Vec<String>) -> Self {
-        Self { entry, submenus }
+        Self {
+            entry,
+            submenus,
+            kernel: None,
+            options: None,
+            initrds: Vec::new(),
+            kind: EntryKind::Other,
+            bls_id: None,
+        }
     }
 
-    fn parse_entries(contents: &str) -> DResult<Vec<GrubBootEntry>> {
-        let mut entries = Vec::new();
+    /// Parse the menu entries of grub.cfg, `bls_entries` are the entries that the
+    /// `blscfg` command adds to the menu
+    fn parse_entries(contents: &str, bls_entries: &[GrubBootEntry]) -> DResult<Vec<GrubBootEntry>> {
+        let mut entries: Vec<GrubBootEntry> = Vec::new();
         let mut submenus = Vec::new();
         // these are unrecovable error so panic is appropriate
         let entry_re = Regex::new(r"menuentry\s+'([^']+)").expect("Invalid regex");
@@ -244,7 +501,34 @@ impl GrubBootEntry {
                 continue;
             }
 
-            if line.starts_with("menuentry") {
+            let mut words = line.split_whitespace();
+            let command = words.next();
+            if menuentry_open && matches!(command, Some("linux" | "linuxefi" | "linux16")) {
+                if let Some(entry) = entries.last_mut() {
+                    entry.kernel = words.next().map(str::to_string);
+                    let options: Vec<_> = words.collect();
+                    entry.options = (!options.is_empty()).then(|| options.join(" "));
+                    entry.kind = EntryKind::Linux;
+                }
+            } else if menuentry_open && matches!(command, Some("initrd" | "initrdefi" | "initrd16"))
+            {
+                if let Some(entry) = entries.last_mut() {
+                    entry.initrds = words.map(str::to_string).collect();
+                }
+            } else if menuentry_open && matches!(command, Some("fwsetup" | "chainloader")) {
+                if let Some(entry) = entries.last_mut() {
+                    entry.kind = if command == Some("fwsetup") {
+                        EntryKind::Firmware
+                    } else {
+                        EntryKind::ForeignOs
+                    };
+                }
+            } else if !menuentry_open && command == Some("blscfg") {
+                entries.extend(bls_entries.iter().map(|entry| GrubBootEntry {
+                    submenus: submenus.clone(),
+                    ..entry.clone()
+                }));
+            } else if line.starts_with("menuentry") {
                 menuentry_open = true;
                 // TODO: error if this fails
                 if let Some(capture) = entry_re.captures(line) {
@@ -265,8 +549,58 @@ impl GrubBootEntry {
         &self.entry
     }
 
+    /// Kernel version of the entry, read from the kernel image or the title
+    pub fn kernel_version(&self) -> Option<&str> {
+        self.kernel
+            .as_deref()
+            .and_then(kernel::version_from_image)
+            .or_else(|| kernel::version_from_title(&self.entry))
+    }
+
+    /// Kernel flavor of the entry, like `default`, `rt` or `longterm`
+    pub fn flavor(&self) -> Option<&str> {
+        self.kernel_version().and_then(kernel::flavor)
+    }
+
+    /// Main initrd image of the entry, the last one that is not CPU microcode
+    pub fn initrd(&self) -> Option<&str> {
+        self.initrds
+            .iter()
+            .rev()
+            .find(|initrd| !kernel::is_microcode_image(initrd))
+            .map(String::as_str)
+    }
+
+    /// Does the entry load CPU microcode from a separate initrd image.
+    ///
+    /// Microcode that is built into the main initrd can't be seen from grub.cfg.
+    pub fn has_microcode(&self) -> bool {
+        self.initrds
+            .iter()
+            .any(|initrd| kernel::is_microcode_image(initrd))
+    }
+
+    pub fn is_recovery(&self) -> bool {
+        self.entry.ends_with("(recovery mode)")
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        if self.is_recovery() {
+            EntryKind::Recovery
+        } else {
+            self.kind
+        }
+    }
+
+    /// Titles of the submenus the entry is in, outermost first
+    pub fn submenus(&self) -> &[String] {
+        &self.submenus
+    }
+
     pub fn full_path(&self) -> String {
-        if self.submenus.is_empty() {
+        if let Some(bls_id) = &self.bls_id {
+            bls_id.clone()
+        } else if self.submenus.is_empty() {
             self.entry.clone()
         } else {
             format!("{}>{}", self.submenus.join(">"), self.entry)
@@ -274,81 +608,112 @@ impl GrubBootEntry {
     }
 }
 
+/// Raw value of the grubenv variable `key`, like the entry name or index in `saved_entry`
+pub fn grub_env_value<'a>(grub_env: &'a str, key: &str) -> Option<&'a str> {
+    grub_env
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+        .map(str::trim)
+}
+
+/// Does grub.cfg read the kernel entries from BLS files, like on Fedora
+fn uses_blscfg(grub_config: &str) -> bool {
+    grub_config
+        .lines()
+        .any(|line| line.split_whitespace().next() == Some("blscfg"))
+}
+
+/// BLS entries that grub.cfg reads, if it uses them
+fn cfg_bls_entries(paths: &Paths, grub_config: &str) -> DResult<Vec<GrubBootEntry>> {
+    if uses_blscfg(grub_config) {
+        bls::read_bls_entries(paths.bls_entries())
+    } else {
+        Ok(Vec::new())
+    }
+}
+
 #[derive(Debug)]
 pub struct GrubBootEntries {
     entries: Vec<GrubBootEntry>,
     selected: Option<GrubBootEntry>,
+    /// One-shot entry for the next boot, set with grub2-reboot
+    next: Option<GrubBootEntry>,
 }
 
 impl GrubBootEntries {
-    pub fn new() -> DResult<Self> {
-        log::debug!("Reading kenrnel boot entries from {GRUB_CFG_PATH}");
-        let config =
-            read_to_string(GRUB_CFG_PATH).ctx(dctx!(), format!("Cannot read {GRUB_CFG_PATH}"))?;
+    pub fn new(paths: &Paths) -> DResult<Self> {
+        let grub_cfg = paths.grub_cfg();
+        log::debug!("Reading kenrnel boot entries from {grub_cfg:?}");
+        let config = read_to_string(grub_cfg).ctx(dctx!(), format!("Cannot read {grub_cfg:?}"))?;
 
-        log::debug!("Reading default boot entry from {GRUB_ENV_PATH}");
+        let grub_env = paths.grub_env();
+        log::debug!("Reading default boot entry from {grub_env:?}");
         let grub_env =
-            read_to_string(GRUB_ENV_PATH).ctx(dctx!(), format!("Cannot read {GRUB_ENV_PATH}"))?;
-
-        Self::from_contents(&config, &grub_env)
-    }
-
-    fn from_contents(grub_config: &str, grub_env: &str) -> DResult<Self> {
-        let entries = GrubBootEntry::parse_entries(grub_config)?;
-
-        let selected_idx = grub_env
-            .lines()
-            .find(|line| line.starts_with("saved_entry"))
-            .map(|entry| {
-                let split = entry.split_once("=").ok_or_else(|| {
-                    DError::grub_parse_error(
-                        dctx!(),
-                        "Malformed grubenv. Expected '=' after saved_entry",
-                    )
-                })?;
-
-                let value = split.1.trim();
-                if value.is_empty() {
-                    return Err(DError::grub_parse_error(
-                        dctx!(),
-                        "Malformed grubenv. Expected value after saved_entry",
-                    ));
-                }
+            read_to_string(grub_env).ctx(dctx!(), format!("Cannot read {grub_env:?}"))?;
 
-                let value = if let Ok(index) = value.parse::<usize>() {
-                    GrubEnvValue::Index(index)
-                } else {
-                    GrubEnvValue::Name(value)
-                };
+        Self::from_contents(&config, &grub_env, &cfg_bls_entries(paths, &config)?)
+    }
 
-                Ok(value)
-            });
-
-        let selected = if let Some(value) = selected_idx {
-            let value = value?;
-            let entry = match value {
-                GrubEnvValue::Index(idx) => entries.get(idx).cloned(),
-                GrubEnvValue::Name(name) => entries
-                    .iter()
-                    .find(|entry| entry.full_path() == name)
-                    .cloned(),
-            };
-
-            if entry.is_none() {
-                log::warn!("Saved kernel '{value}' was defined as saved_entry but not found in grub. Assuming default kernel.");
-            }
+    /// Boot entries of the grub.cfg `grub_config`, e.g. one that was just generated
+    pub fn parse_cfg(paths: &Paths, grub_config: &str) -> DResult<Vec<GrubBootEntry>> {
+        GrubBootEntry::parse_entries(grub_config, &cfg_bls_entries(paths, grub_config)?)
+    }
 
-            entry
-        } else {
+    fn from_contents(
+        grub_config: &str,
+        grub_env: &str,
+        bls_entries: &[GrubBootEntry],
+    ) -> DResult<Self> {
+        let entries = GrubBootEntry::parse_entries(grub_config, bls_entries)?;
+        let selected = Self::env_entry(&entries, grub_env, "saved_entry")?;
+        if selected.is_none() {
             log::debug!("No default kernel entry selected, defaulting to first available kernel");
-            None
-        };
+        }
+        let next = Self::env_entry(&entries, grub_env, "next_entry")?;
 
-        Ok(Self { entries, selected })
+        Ok(Self {
+            entries,
+            selected,
+            next,
+        })
     }
 
-    pub fn entry_names(&self) -> Vec<&str> {
-        self.entries.iter().map(|entry| entry.entry()).collect()
+    /// Entry that the grubenv variable `key` points to
+    fn env_entry(
+        entries: &[GrubBootEntry],
+        grub_env: &str,
+        key: &str,
+    ) -> DResult<Option<GrubBootEntry>> {
+        let Some(value) = grub_env_value(grub_env, key) else {
+            return Ok(None);
+        };
+
+        if value.is_empty() {
+            return Err(DError::grub_parse_error(
+                dctx!(),
+                format!("Malformed grubenv. Expected value after {key}"),
+            ));
+        }
+
+        let value = if let Ok(index) = value.parse::<usize>() {
+            GrubEnvValue::Index(index)
+        } else {
+            GrubEnvValue::Name(value)
+        };
+
+        let entry = match value {
+            GrubEnvValue::Index(idx) => entries.get(idx).cloned(),
+            GrubEnvValue::Name(name) => entries
+                .iter()
+                .find(|entry| entry.full_path() == name)
+                .cloned(),
+        };
+
+        if entry.is_none() {
+            log::warn!("Kernel '{value}' was defined as {key} but not found in grub. Assuming default kernel.");
+        }
+
+        Ok(entry)
     }
 
     pub fn entries(&self) -> &[GrubBootEntry] {
@@ -356,6 +721,29 @@ impl GrubBootEntries {
         &self.entries
     }
 
+    pub fn selected_entry(&self) -> Option<&GrubBootEntry> {
+        self.selected.as_ref()
+    }
+
+    pub fn next_entry(&self) -> Option<&GrubBootEntry> {
+        self.next.as_ref()
+    }
+
+    /// Newest non-recovery entry booting a kernel of the given flavor
+    pub fn newest_of_flavor(&self, flavor: &str) -> Option<&GrubBootEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.is_recovery() && entry.flavor() == Some(flavor))
+            // max_by returns the last maximum, prefer the first one in menu order
+            .rev()
+            .max_by(|a, b| {
+                kernel::compare_versions(
+                    a.kernel_version().unwrap_or_default(),
+                    b.kernel_version().unwrap_or_default(),
+                )
+            })
+    }
+
     pub fn selected(&self) -> Option<&str> {
         if let Some(selected) = &self.selected {
             Some(selected.entry())
@@ -416,6 +804,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_grub2_parse_errors() {
+        let config = "# comment\nGRUB_TIMEOUT=8\nGRUB_BROKEN\n\nexport\n";
+        let errors = GrubFile::parse_errors(config);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(errors[0].raw_line, "GRUB_BROKEN");
+        assert_eq!(errors[0].message, "Expected '=' on line: 3");
+        assert_eq!(errors[1].line, 5);
+        assert!(GrubFile::parse_errors("GRUB_TIMEOUT=8\n").is_empty());
+
+        let lenient = GrubFile::new_lenient(config);
+        assert_eq!(lenient.value("GRUB_TIMEOUT"), Some("8"));
+        assert_eq!(lenient.as_string(), config);
+    }
+
     #[test]
     fn test_grub2_parsing_simple() {
         let file_data = read_to_string("test_data/grub_simple").unwrap();
@@ -532,11 +936,129 @@ mod tests {
         assert_eq!(file.as_string(), file_data);
     }
 
+    #[test]
+    fn test_grub2_insert_related_keys() {
+        let mut file = GrubFile::new(
+            "GRUB_TIMEOUT=8\nGRUB_CMDLINE_LINUX_DEFAULT=\"quiet\"\nGRUB_CMDLINE_LINUX=\"\"\n# GRUB_SAVEDEFAULT=\"true\"\nGRUB_TERMINAL=gfxterm\n",
+        )
+        .unwrap();
+
+        file.set_key_value("GRUB_CMDLINE_XEN_DEFAULT", "vga=gfx-1024x768x16");
+        file.set_key_value("GRUB_SAVEDEFAULT", "true");
+        file.set_key_value("GRUB_TIMEOUT_STYLE", "menu");
+        file.set_key_value("GRUB_GFXMODE", "auto");
+
+        assert_eq!(
+            file.as_string(),
+            "GRUB_TIMEOUT=8\nGRUB_TIMEOUT_STYLE=\"menu\"\nGRUB_CMDLINE_LINUX_DEFAULT=\"quiet\"\nGRUB_CMDLINE_LINUX=\"\"\nGRUB_CMDLINE_XEN_DEFAULT=\"vga=gfx-1024x768x16\"\n# GRUB_SAVEDEFAULT=\"true\"\nGRUB_SAVEDEFAULT=\"true\"\nGRUB_TERMINAL=gfxterm\nGRUB_GFXMODE=\"auto\"\n"
+        );
+
+        // existing keys can still be updated after the lines have moved
+        file.set_key_value("GRUB_TERMINAL", "console");
+        assert_eq!(file.value("GRUB_TERMINAL"), Some("console"));
+        assert!(file.as_string().contains("GRUB_TERMINAL=console\n"));
+    }
+
+    #[test]
+    fn test_grub2_keep_line_style() {
+        let mut file = GrubFile::new(
+            "GRUB_CMDLINE_LINUX_DEFAULT='quiet splash'\n  GRUB_TIMEOUT=8  \nGRUB_DISTRIBUTOR=\"$(sed 's, release .*$,,g' /etc/system-release)\"\nGRUB_TERMINAL=console\n",
+        )
+        .unwrap();
+        assert_eq!(
+            file.value("GRUB_DISTRIBUTOR"),
+            Some("$(sed 's, release .*$,,g' /etc/system-release)")
+        );
+
+        file.set_key_value("GRUB_CMDLINE_LINUX_DEFAULT", "quiet");
+        file.set_key_value("GRUB_TIMEOUT", "3");
+        file.set_key_value("GRUB_TERMINAL", "gfxterm console");
+        assert_eq!(
+            file.as_string(),
+            "GRUB_CMDLINE_LINUX_DEFAULT='quiet'\n  GRUB_TIMEOUT=3  \nGRUB_DISTRIBUTOR=\"$(sed 's, release .*$,,g' /etc/system-release)\"\nGRUB_TERMINAL=\"gfxterm console\"\n"
+        );
+
+        // quotes in the value need the other kind of quotes around it
+        file.set_key_value("GRUB_CMDLINE_LINUX_DEFAULT", "acpi_osi='Windows 2015'");
+        assert!(file
+            .as_string()
+            .starts_with("GRUB_CMDLINE_LINUX_DEFAULT=\"acpi_osi='Windows 2015'\"\n"));
+        file.set_key_value("GRUB_TERMINAL", "gfxterm \"console\"");
+        assert!(file
+            .as_string()
+            .ends_with("GRUB_TERMINAL=\"gfxterm \\\"console\\\"\"\n"));
+
+        // values changed back are written as they were
+        file.set_key_value("GRUB_CMDLINE_LINUX_DEFAULT", "quiet splash");
+        file.set_key_value("GRUB_TIMEOUT", "8");
+        assert!(file
+            .as_string()
+            .starts_with("GRUB_CMDLINE_LINUX_DEFAULT='quiet splash'\n  GRUB_TIMEOUT=8  \n"));
+    }
+
+    #[test]
+    fn test_grub2_quote_escapes() {
+        let mut file =
+            GrubFile::new("GRUB_THEME=\"/boot/grub2/theme\"\nGRUB_DISTRIBUTOR='openSUSE'\n")
+                .unwrap();
+
+        // a backslash at the end doesn't escape the closing quote
+        file.set_key_value("GRUB_THEME", "C:\\");
+        assert!(file.as_string().starts_with("GRUB_THEME=\"C:\\\\\"\n"));
+
+        // single quoted text stays literal in double quotes
+        file.set_key_value("GRUB_DISTRIBUTOR", "it's $NAME `uname` \"a\\b\"");
+        assert!(file
+            .as_string()
+            .ends_with("GRUB_DISTRIBUTOR=\"it's \\$NAME \\`uname\\` \\\"a\\\\b\\\"\"\n"));
+
+        // without a single quote in the value it stays single quoted
+        file.set_key_value("GRUB_DISTRIBUTOR", "$NAME `uname` a\\b");
+        assert!(file
+            .as_string()
+            .ends_with("GRUB_DISTRIBUTOR='$NAME `uname` a\\b'\n"));
+
+        // values read back are the values that were written
+        for value in [r#"say "hi""#, "$NAME `uname`", "C:\\", r#"it's "$`\"#] {
+            file.set_key_value("GRUB_THEME", value);
+            let written = GrubFile::new(&file.as_string()).unwrap();
+            assert_eq!(written.value("GRUB_THEME"), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_grub2_shell_lines() {
+        let config = "export GRUB_TIMEOUT=5 # seconds\nGRUB_CMDLINE_LINUX_DEFAULT=\"quiet \\\n    splash\"\nGRUB_DEFAULT=saved\n";
+        let mut file = GrubFile::new(config).unwrap();
+        assert_eq!(file.value("GRUB_TIMEOUT"), Some("5"));
+        assert_eq!(
+            file.value("GRUB_CMDLINE_LINUX_DEFAULT"),
+            Some("quiet     splash")
+        );
+        assert_eq!(file.as_string(), config);
+
+        file.set_key_value("GRUB_TIMEOUT", "3");
+        file.set_key_value("GRUB_CMDLINE_LINUX_DEFAULT", "quiet");
+        file.set_key_value("GRUB_DEFAULT", "0");
+        assert_eq!(
+            file.as_string(),
+            "export GRUB_TIMEOUT=3 # seconds\nGRUB_CMDLINE_LINUX_DEFAULT=\"quiet\"\nGRUB_DEFAULT=0\n"
+        );
+
+        let errors = GrubFile::parse_errors("GRUB_A=\"one\ntwo\"\nGRUB_B=1 2\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(
+            errors[0].message,
+            "Unexpected '2' after the value on line: 3"
+        );
+    }
+
     #[test]
     fn test_grub2_bootentries_noselect() {
         let config = read_to_string("test_data/grub.cfg").unwrap();
         let grub_env = read_to_string("test_data/grubenv_empty").unwrap();
-        let entries = GrubBootEntries::from_contents(&config, &grub_env).unwrap();
+        let entries = GrubBootEntries::from_contents(&config, &grub_env, &[]).unwrap();
 
         assert_eq!(entries.entries().len(), 4);
         assert_eq!(entries.entries()[0].entry, "openSUSE Tumbleweed Minimal");
@@ -560,5 +1082,48 @@ mod tests {
         assert_eq!(entries.entries()[3].entry, "UEFI Firmware Settings");
         assert_eq!(entries.entries()[3].submenus, Vec::<String>::new());
         assert_eq!(entries.selected(), None);
+
+        assert_eq!(
+            entries.entries()[0].kernel.as_deref(),
+            Some("/boot/vmlinuz-6.17.5-1-default")
+        );
+        assert_eq!(entries.entries()[0].flavor(), Some("default"));
+        assert_eq!(entries.entries()[3].flavor(), None);
+        assert_eq!(
+            entries
+                .newest_of_flavor("default")
+                .map(|entry| entry.entry()),
+            Some("openSUSE Tumbleweed Minimal")
+        );
+        assert!(entries.newest_of_flavor("rt").is_none());
+        assert_eq!(
+            entries.entries()[0].initrds,
+            vec!["/boot/initrd-6.17.5-1-default"]
+        );
+        assert!(!entries.entries()[0].has_microcode());
+        assert_eq!(entries.entries()[0].kind(), EntryKind::Linux);
+        assert_eq!(entries.entries()[2].kind(), EntryKind::Recovery);
+        assert_eq!(entries.entries()[3].kind(), EntryKind::Firmware);
+    }
+
+    #[test]
+    fn test_grub2_bootentries_microcode() {
+        let config = "menuentry 'Arch Linux' {\n\tlinux /vmlinuz-linux\n\tinitrd /intel-ucode.img /initramfs-linux.img\n}\nmenuentry 'Fedora' {\n\tlinux /vmlinuz-6.5.6-300.fc39.x86_64\n\tinitrd $early_ucode /initramfs-6.5.6-300.fc39.x86_64.img\n}\nmenuentry 'Other' {\n\tlinux /vmlinuz-6.1\n\tinitrd /initrd-6.1\n}\n";
+        let entries = GrubBootEntries::from_contents(config, "", &[]).unwrap();
+
+        assert!(entries.entries()[0].has_microcode());
+        assert!(entries.entries()[1].has_microcode());
+        assert!(!entries.entries()[2].has_microcode());
+    }
+
+    #[test]
+    fn test_grub_env_value() {
+        let grub_env = read_to_string("test_data/grubenv_saved").unwrap();
+        assert_eq!(
+            grub_env_value(&grub_env, "saved_entry"),
+            Some("Advanced options for openSUSE Tumbleweed Minimal>openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default")
+        );
+        assert_eq!(grub_env_value(&grub_env, "saved"), None);
+        assert_eq!(grub_env_value(&grub_env, "next_entry"), None);
     }
 }
@@ -0,0 +1,280 @@
+//! Kernel parameters that leave most systems unbootable, or booting into an
+//! emergency shell, when they are set by mistake
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::grub2::{cmdline::Cmdline, GrubFile};
+
+/// Config keys that hold kernel command lines
+pub const CMDLINE_KEYS: &[&str] = &["GRUB_CMDLINE_LINUX", "GRUB_CMDLINE_LINUX_DEFAULT"];
+
+struct DangerRule {
+    name: &'static str,
+    /// Values that are dangerous, any value if empty
+    values: &'static [&'static str],
+    reason: &'static str,
+}
+
+const DANGER_RULES: &[DangerRule] = &[
+    DangerRule {
+        name: "root",
+        values: &[],
+        reason: "Wrong root device makes the system unable to mount its root filesystem",
+    },
+    DangerRule {
+        name: "rootflags",
+        values: &[],
+        reason: "Invalid mount options make the root filesystem fail to mount",
+    },
+    DangerRule {
+        name: "rootfstype",
+        values: &[],
+        reason: "Wrong filesystem type makes the root filesystem fail to mount",
+    },
+    DangerRule {
+        name: "init",
+        values: &[],
+        reason: "Kernel panics if the init program cannot be started",
+    },
+    DangerRule {
+        name: "rd.break",
+        values: &[],
+        reason: "Boot stops in an initrd shell",
+    },
+    DangerRule {
+        name: "systemd.unit",
+        values: &["emergency.target", "rescue.target", "emergency", "rescue"],
+        reason: "System boots into an emergency or rescue shell",
+    },
+    DangerRule {
+        name: "emergency",
+        values: &[],
+        reason: "System boots into an emergency shell",
+    },
+    DangerRule {
+        name: "single",
+        values: &[],
+        reason: "System boots into a rescue shell",
+    },
+];
+
+/// Dangerous parameter that was added to, or modified on, a kernel command line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DangerousParam {
+    /// Config key of the command line, like `GRUB_CMDLINE_LINUX`
+    pub key: String,
+    /// The parameter as written, like `init=/bin/sh`
+    pub param: String,
+    pub reason: String,
+}
+
+/// Parameters that reference a block device
+const DEVICE_PARAMS: &[&str] = &["root", "resume"];
+
+/// Parameter referencing a device that doesn't exist or isn't the expected one
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceProblem {
+    /// Config key of the command line, like `GRUB_CMDLINE_LINUX`
+    pub key: String,
+    /// The parameter as written, like `root=UUID=1234`
+    pub param: String,
+    pub problem: String,
+}
+
+/// Path of the device node referenced by a device spec like `UUID=1234` or `/dev/sda2`,
+/// `None` for specs that cannot be checked, like network or image roots
+fn device_spec_path(dev_dir: &Path, spec: &str) -> Option<PathBuf> {
+    let by_kind = [
+        ("UUID=", "disk/by-uuid"),
+        ("PARTUUID=", "disk/by-partuuid"),
+        ("LABEL=", "disk/by-label"),
+        ("PARTLABEL=", "disk/by-partlabel"),
+    ];
+    for (prefix, dir) in by_kind {
+        if let Some(id) = spec.strip_prefix(prefix) {
+            return Some(dev_dir.join(dir).join(id));
+        }
+    }
+
+    spec.strip_prefix("/dev/")
+        .map(|device| dev_dir.join(device))
+}
+
+/// Check the devices of added or modified root= and resume= parameters of `new`.
+///
+/// `resolve` returns the real device node of a path if it exists. The root= device
+/// must be `root_device`, the device of the current root filesystem, when it's known.
+pub fn device_problems(
+    old: &GrubFile,
+    new: &GrubFile,
+    dev_dir: &Path,
+    resolve: impl Fn(&Path) -> Option<PathBuf>,
+    root_device: Option<&Path>,
+) -> Vec<DeviceProblem> {
+    let mut problems = Vec::new();
+    for key in CMDLINE_KEYS {
+        let old_cmdline = Cmdline::parse(old.value(key).unwrap_or(""));
+        let old_params: Vec<_> = old_cmdline.params().collect();
+        for param in Cmdline::parse(new.value(key).unwrap_or("")).params() {
+            let Some((name, spec)) = param.split_once('=') else {
+                continue;
+            };
+            if !DEVICE_PARAMS.contains(&name) || old_params.contains(&param) {
+                continue;
+            }
+            let Some(path) = device_spec_path(dev_dir, spec) else {
+                continue;
+            };
+
+            let problem = match (resolve(&path), root_device) {
+                (None, _) => format!("Device {path:?} does not exist"),
+                (Some(device), Some(root)) if name == "root" && root != device => {
+                    format!("Device {device:?} is not the current root filesystem {root:?}")
+                }
+                _ => continue,
+            };
+            problems.push(DeviceProblem {
+                key: key.to_string(),
+                param: param.into(),
+                problem,
+            });
+        }
+    }
+
+    problems
+}
+
+/// Why `param` is dangerous, `None` if it isn't
+fn danger_reason(param: &str) -> Option<&'static str> {
+    let (name, value) = match param.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (param, None),
+    };
+
+    DANGER_RULES
+        .iter()
+        .find(|rule| {
+            rule.name == name
+                && (rule.values.is_empty()
+                    || value.is_some_and(|value| rule.values.contains(&value)))
+        })
+        .map(|rule| rule.reason)
+}
+
+/// Dangerous parameters in the command lines of `new` that are not the same in `old`
+pub fn dangerous_changes(old: &GrubFile, new: &GrubFile) -> Vec<DangerousParam> {
+    let mut changes = Vec::new();
+    for key in CMDLINE_KEYS {
+        let old_cmdline = Cmdline::parse(old.value(key).unwrap_or(""));
+        let old_params: Vec<_> = old_cmdline.params().collect();
+        for param in Cmdline::parse(new.value(key).unwrap_or("")).params() {
+            if old_params.contains(&param) {
+                continue;
+            }
+
+            if let Some(reason) = danger_reason(param) {
+                changes.push(DangerousParam {
+                    key: key.to_string(),
+                    param: param.into(),
+                    reason: reason.into(),
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_danger_reason() {
+        assert!(danger_reason("root=UUID=1234").is_some());
+        assert!(danger_reason("init=/bin/sh").is_some());
+        assert!(danger_reason("systemd.unit=emergency.target").is_some());
+        assert!(danger_reason("systemd.unit=graphical.target").is_none());
+        assert!(danger_reason("single").is_some());
+        assert!(danger_reason("quiet").is_none());
+        assert!(danger_reason("rootwait").is_none());
+    }
+
+    #[test]
+    fn test_dangerous_changes() {
+        let old = GrubFile::new(
+            "GRUB_CMDLINE_LINUX=\"root=UUID=1234 quiet\"\nGRUB_CMDLINE_LINUX_DEFAULT=\"splash\"",
+        )
+        .unwrap();
+
+        let same_root = GrubFile::new(
+            "GRUB_CMDLINE_LINUX=\"quiet root=UUID=1234 nomodeset\"\nGRUB_CMDLINE_LINUX_DEFAULT=\"splash\"",
+        )
+        .unwrap();
+        assert!(dangerous_changes(&old, &same_root).is_empty());
+
+        let changed = GrubFile::new(
+            "GRUB_CMDLINE_LINUX=\"root=UUID=5678 quiet\"\nGRUB_CMDLINE_LINUX_DEFAULT=\"splash init=/bin/sh\"",
+        )
+        .unwrap();
+        let changes = dangerous_changes(&old, &changed);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].key, "GRUB_CMDLINE_LINUX");
+        assert_eq!(changes[0].param, "root=UUID=5678");
+        assert_eq!(changes[1].key, "GRUB_CMDLINE_LINUX_DEFAULT");
+        assert_eq!(changes[1].param, "init=/bin/sh");
+
+        // removing parameters is never dangerous
+        let removed = GrubFile::new("GRUB_CMDLINE_LINUX=\"quiet\"").unwrap();
+        assert!(dangerous_changes(&old, &removed).is_empty());
+    }
+
+    #[test]
+    fn test_device_spec_path() {
+        let dev = Path::new("/dev");
+        assert_eq!(
+            device_spec_path(dev, "UUID=1234"),
+            Some(PathBuf::from("/dev/disk/by-uuid/1234"))
+        );
+        assert_eq!(
+            device_spec_path(dev, "PARTLABEL=root"),
+            Some(PathBuf::from("/dev/disk/by-partlabel/root"))
+        );
+        assert_eq!(
+            device_spec_path(dev, "/dev/sda2"),
+            Some(PathBuf::from("/dev/sda2"))
+        );
+        assert_eq!(device_spec_path(dev, "live:CDLABEL=x"), None);
+    }
+
+    #[test]
+    fn test_device_problems() {
+        let resolve = |path: &Path| match path.to_str() {
+            Some("/dev/disk/by-uuid/1234") | Some("/dev/vda2") => Some(PathBuf::from("/dev/vda2")),
+            Some("/dev/disk/by-uuid/5678") => Some(PathBuf::from("/dev/vda3")),
+            _ => None,
+        };
+        let root = Some(Path::new("/dev/vda2"));
+        let old = GrubFile::new("GRUB_CMDLINE_LINUX=\"root=UUID=1234 resume=/dev/vda1\"").unwrap();
+        let dev = Path::new("/dev");
+
+        // unchanged parameters are not checked again
+        assert!(device_problems(&old, &old, dev, resolve, root).is_empty());
+
+        let same_device = GrubFile::new("GRUB_CMDLINE_LINUX=\"root=/dev/vda2\"").unwrap();
+        assert!(device_problems(&old, &same_device, dev, resolve, root).is_empty());
+
+        let typo = GrubFile::new("GRUB_CMDLINE_LINUX=\"root=UUID=1243 resume=UUID=5678\"").unwrap();
+        let problems = device_problems(&old, &typo, dev, resolve, root);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].param, "root=UUID=1243");
+
+        let other_root = GrubFile::new("GRUB_CMDLINE_LINUX=\"root=UUID=5678\"").unwrap();
+        let problems = device_problems(&old, &other_root, dev, resolve, root);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].problem.contains("not the current root"));
+        assert!(device_problems(&old, &other_root, dev, resolve, None).is_empty());
+    }
+}
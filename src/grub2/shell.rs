@@ -0,0 +1,196 @@
+//! Shell syntax of the grub file.
+//!
+//! grub2-mkconfig sources the grub file as a shell script, so besides plain
+//! `KEY="value"` lines it may have `export KEY=value`, comments after the value
+//! and values that continue on the next lines, either after a backslash or in
+//! an open quote.
+
+/// Assignment of a variable on a line of the grub file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment<'a> {
+    /// Whitespace before the assignment
+    pub indent: &'a str,
+    /// `export` and the whitespace after it, empty if the key isn't exported
+    pub export: &'a str,
+    pub key: &'a str,
+    /// The value as written, with its quotes
+    pub word: &'a str,
+    /// The value without the quotes, escapes and line continuations.
+    /// Expansions like `$(cmd)` are kept as they are written.
+    pub value: String,
+    /// Comment and whitespace after the value
+    pub tail: &'a str,
+}
+
+fn is_name(key: &str) -> bool {
+    key.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split the shell word at the start of `text`, returns the index where it
+/// ends and its value. A backslash escapes any character outside of quotes,
+/// but in double quotes only the ones that are special there.
+fn scan_word(text: &str) -> Result<(usize, String), String> {
+    let mut value = String::new();
+    let mut quote = None;
+    let mut chars = text.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() || ";&|<>".contains(c) => return Ok((idx, value)),
+            (None | Some('"'), '\\') => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, next)) if quote.is_none() || "\\\"$`".contains(next) => value.push(next),
+                Some((_, next)) => {
+                    value.push('\\');
+                    value.push(next);
+                }
+                None => value.push('\\'),
+            },
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (_, c) => value.push(c),
+        }
+    }
+
+    match quote {
+        Some(open) => Err(format!("Missing closing {open}")),
+        None => Ok((text.len(), value)),
+    }
+}
+
+/// Parse the assignment on `line`, which may span several lines when it's continued
+pub fn parse_assignment(line: &str) -> Result<Assignment<'_>, String> {
+    let body = line.trim_start();
+    let indent = &line[..line.len() - body.len()];
+    let (export, body) = match body.strip_prefix("export") {
+        Some(rest) if rest.starts_with([' ', '\t']) => {
+            let rest = rest.trim_start();
+            (&body[..body.len() - rest.len()], rest)
+        }
+        _ => ("", body),
+    };
+
+    let Some((key, rest)) = body.split_once('=') else {
+        return Err("Expected '='".into());
+    };
+    if !is_name(key) {
+        return Err(format!("'{key}' is not a variable name"));
+    }
+
+    let (end, value) = scan_word(rest)?;
+    let (word, tail) = rest.split_at(end);
+    let comment = tail.trim();
+    if !comment.is_empty() && !comment.starts_with('#') {
+        return Err(format!("Unexpected '{comment}' after the value"));
+    }
+
+    Ok(Assignment {
+        indent,
+        export,
+        key,
+        word,
+        value,
+        tail,
+    })
+}
+
+/// Whether the shell reads the line after `text` as part of it: `text` ends
+/// with a backslash, or has a quote that isn't closed. Comments are never continued.
+pub fn is_continued(text: &str) -> bool {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut word_start = true;
+    for c in text.chars() {
+        if escaped {
+            escaped = false;
+            word_start = false;
+            continue;
+        }
+        match (quote, c) {
+            (None, '#') if word_start => return false,
+            (None | Some('"'), '\\') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            _ => {}
+        }
+        word_start = quote.is_none() && c.is_whitespace();
+    }
+    escaped || quote.is_some()
+}
+
+/// Lines of `file` with the index of their first line, the continued lines
+/// joined with the lines they continue on
+pub fn logical_lines(file: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    // split instead of lines to keep the trailing empty line
+    for (idx, line) in file.split('\n').enumerate() {
+        let (start, text) = current.get_or_insert_with(|| (idx, String::new()));
+        if *start != idx {
+            text.push('\n');
+        }
+        text.push_str(line);
+        if !is_continued(text) {
+            lines.extend(current.take());
+        }
+    }
+    lines.extend(current);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_assignment() {
+        let assignment = parse_assignment("  export GRUB_TIMEOUT=5  # seconds").unwrap();
+        assert_eq!(assignment.indent, "  ");
+        assert_eq!(assignment.export, "export ");
+        assert_eq!(assignment.key, "GRUB_TIMEOUT");
+        assert_eq!(assignment.word, "5");
+        assert_eq!(assignment.value, "5");
+        assert_eq!(assignment.tail, "  # seconds");
+
+        let assignment =
+            parse_assignment("GRUB_CMDLINE_LINUX=\"quiet \\\n  splash\"'#1' # x").unwrap();
+        assert_eq!(assignment.value, "quiet   splash#1");
+        assert_eq!(assignment.tail, " # x");
+
+        let assignment =
+            parse_assignment("GRUB_DISTRIBUTOR=\"$(sed 's, .*$,,g' /etc/os)\"").unwrap();
+        assert_eq!(assignment.value, "$(sed 's, .*$,,g' /etc/os)");
+
+        // only the special characters are escaped in double quotes
+        let assignment = parse_assignment(r#"GRUB_A="\"\$\`\\\x"a\ b'\y'"#).unwrap();
+        assert_eq!(assignment.value, r#""$`\\xa b\y"#);
+
+        assert_eq!(parse_assignment("GRUB_BROKEN").unwrap_err(), "Expected '='");
+        assert_eq!(
+            parse_assignment("GRUB_A=1 GRUB_B=2").unwrap_err(),
+            "Unexpected 'GRUB_B=2' after the value"
+        );
+        assert_eq!(
+            parse_assignment("GRUB_A=\"open").unwrap_err(),
+            "Missing closing \""
+        );
+        assert!(parse_assignment("[ -x foo ] && GRUB_A=1").is_err());
+    }
+
+    #[test]
+    fn test_logical_lines() {
+        let file = "# comment \\\nGRUB_A=\"one\n two\"\nGRUB_B=a\\\nb # c \\\nGRUB_C='x\\'\n";
+        assert_eq!(
+            logical_lines(file),
+            vec![
+                (0, "# comment \\".to_string()),
+                (1, "GRUB_A=\"one\n two\"".to_string()),
+                (3, "GRUB_B=a\\\nb # c \\".to_string()),
+                (5, "GRUB_C='x\\'".to_string()),
+                (6, String::new()),
+            ]
+        );
+    }
+}
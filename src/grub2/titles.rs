@@ -0,0 +1,228 @@
+//! Localized display names of boot entries.
+//!
+//! grub.cfg titles are generated from a handful of English patterns. Matching
+//! them lets every frontend show the same translated names instead of each one
+//! maintaining its own string table.
+
+use serde::Serialize;
+
+/// Title patterns of grub-mkconfig and os-prober, most specific first. Placeholders
+/// are `{os}`, `{version}` and `{device}`.
+const PATTERNS: &[(&str, &str)] = &[
+    ("advanced-options", "Advanced options for {os}"),
+    (
+        "linux-recovery",
+        "{os}, with Linux {version} (recovery mode)",
+    ),
+    ("linux", "{os}, with Linux {version}"),
+    ("firmware-settings", "UEFI Firmware Settings"),
+    ("foreign-os", "{os} (on {device})"),
+];
+
+/// Translated patterns by language and pattern id
+const TRANSLATIONS: &[(&str, &str, &str)] = &[
+    ("de", "advanced-options", "Erweiterte Optionen für {os}"),
+    (
+        "de",
+        "linux-recovery",
+        "{os}, mit Linux {version} (Wiederherstellungsmodus)",
+    ),
+    ("de", "linux", "{os}, mit Linux {version}"),
+    ("de", "firmware-settings", "UEFI-Firmware-Einstellungen"),
+    ("de", "foreign-os", "{os} (auf {device})"),
+    ("es", "advanced-options", "Opciones avanzadas para {os}"),
+    (
+        "es",
+        "linux-recovery",
+        "{os}, con Linux {version} (modo de recuperación)",
+    ),
+    ("es", "linux", "{os}, con Linux {version}"),
+    ("es", "firmware-settings", "Configuración del firmware UEFI"),
+    ("es", "foreign-os", "{os} (en {device})"),
+    ("fr", "advanced-options", "Options avancées pour {os}"),
+    (
+        "fr",
+        "linux-recovery",
+        "{os}, avec Linux {version} (mode de dépannage)",
+    ),
+    ("fr", "linux", "{os}, avec Linux {version}"),
+    (
+        "fr",
+        "firmware-settings",
+        "Paramètres du micrologiciel UEFI",
+    ),
+    ("fr", "foreign-os", "{os} (sur {device})"),
+];
+
+/// Part of a display name, kernel versions are separate so they can be highlighted
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TitleSegment {
+    pub text: String,
+    pub kernel_version: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LocalizedTitle {
+    /// Id of the matched pattern, like `linux-recovery`, `None` if the title is shown as is
+    pub pattern: Option<&'static str>,
+    pub title: String,
+    pub segments: Vec<TitleSegment>,
+}
+
+/// Language code of a locale like `de_DE.UTF-8`
+fn language(locale: &str) -> &str {
+    locale.split(['_', '.', '@']).next().unwrap_or(locale)
+}
+
+/// Literal parts and placeholder names of a pattern, in order
+fn template_parts(template: &str) -> Vec<(bool, &str)> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        if start > 0 {
+            parts.push((false, &rest[..start]));
+        }
+        parts.push((true, &rest[start + 1..start + end]));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push((false, rest));
+    }
+
+    parts
+}
+
+/// Values of the placeholders if `title` matches the `template`
+fn match_template<'a>(template: &str, title: &'a str) -> Option<Vec<(&'static str, &'a str)>> {
+    let parts = template_parts(template);
+    let mut values = Vec::new();
+    let mut rest = title;
+    for (idx, (placeholder, text)) in parts.iter().enumerate() {
+        if !placeholder {
+            rest = rest.strip_prefix(text)?;
+            continue;
+        }
+
+        // a placeholder runs until the next literal, or to the end of the title
+        let value = match parts.get(idx + 1) {
+            Some((_, literal)) => &rest[..rest.rfind(literal)?],
+            None => rest,
+        };
+        if value.is_empty() || (*text == "version" && value.contains(char::is_whitespace)) {
+            return None;
+        }
+        let name = ["os", "version", "device"]
+            .into_iter()
+            .find(|name| name == text)?;
+        values.push((name, value));
+        rest = &rest[value.len()..];
+    }
+
+    rest.is_empty().then_some(values)
+}
+
+fn render(template: &str, values: &[(&str, &str)]) -> Vec<TitleSegment> {
+    let mut segments: Vec<TitleSegment> = Vec::new();
+    for (placeholder, text) in template_parts(template) {
+        let (text, kernel_version) = if placeholder {
+            let value = values
+                .iter()
+                .find(|(name, _)| *name == text)
+                .map_or("", |(_, value)| value);
+            (value, text == "version")
+        } else {
+            (text, false)
+        };
+
+        match segments.last_mut() {
+            Some(last) if !last.kernel_version && !kernel_version => last.text.push_str(text),
+            _ => segments.push(TitleSegment {
+                text: text.into(),
+                kernel_version,
+            }),
+        }
+    }
+
+    segments
+}
+
+/// Display name of an entry or submenu `title` in the language of `locale`.
+/// Titles that don't match a known pattern, or languages without translations,
+/// keep the original text.
+pub fn localized_title(title: &str, locale: &str) -> LocalizedTitle {
+    let language = language(locale);
+    for (id, template) in PATTERNS {
+        let Some(values) = match_template(template, title) else {
+            continue;
+        };
+
+        let translated = TRANSLATIONS
+            .iter()
+            .find(|(lang, pattern, _)| *lang == language && pattern == id)
+            .map_or(*template, |(_, _, translated)| translated);
+        let segments = render(translated, &values);
+        return LocalizedTitle {
+            pattern: Some(id),
+            title: segments
+                .iter()
+                .map(|segment| segment.text.as_str())
+                .collect(),
+            segments,
+        };
+    }
+
+    LocalizedTitle {
+        pattern: None,
+        title: title.into(),
+        segments: vec![TitleSegment {
+            text: title.into(),
+            kernel_version: false,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_localized_title() {
+        let title = localized_title(
+            "openSUSE Tumbleweed, with Linux 6.17.5-1-default (recovery mode)",
+            "de_DE.UTF-8",
+        );
+        assert_eq!(title.pattern, Some("linux-recovery"));
+        assert_eq!(
+            title.title,
+            "openSUSE Tumbleweed, mit Linux 6.17.5-1-default (Wiederherstellungsmodus)"
+        );
+        assert_eq!(title.segments.len(), 3);
+        assert_eq!(title.segments[1].text, "6.17.5-1-default");
+        assert!(title.segments[1].kernel_version);
+
+        let title = localized_title("Advanced options for openSUSE Tumbleweed", "fr");
+        assert_eq!(title.title, "Options avancées pour openSUSE Tumbleweed");
+
+        let title = localized_title("Windows Boot Manager (on /dev/nvme0n1p1)", "es_ES");
+        assert_eq!(title.pattern, Some("foreign-os"));
+        assert_eq!(title.title, "Windows Boot Manager (en /dev/nvme0n1p1)");
+    }
+
+    #[test]
+    fn test_untranslated_title() {
+        // English and unknown languages keep the pattern, with the version highlighted
+        let title = localized_title("openSUSE Tumbleweed, with Linux 6.17.5-1-default", "C");
+        assert_eq!(title.pattern, Some("linux"));
+        assert_eq!(
+            title.title,
+            "openSUSE Tumbleweed, with Linux 6.17.5-1-default"
+        );
+
+        let title = localized_title("Memory test (memtest86+)", "de");
+        assert_eq!(title.pattern, None);
+        assert_eq!(title.title, "Memory test (memtest86+)");
+    }
+}
@@ -0,0 +1,157 @@
+//! Summary of the initramfs contents, parsed from the `lsinitrd` listing.
+//!
+//! A kernel that doesn't boot often has an initrd that is missing the storage
+//! driver of the root disk, the crypto modules for an encrypted root, or resume
+//! support for hibernation.
+
+use serde::Serialize;
+
+/// Driver directories of the kernel tree with storage drivers
+const STORAGE_DRIVER_DIRS: &[&str] = &[
+    "drivers/ata/",
+    "drivers/block/",
+    "drivers/md/",
+    "drivers/mmc/",
+    "drivers/nvme/",
+    "drivers/scsi/",
+    "drivers/usb/storage/",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct InitrdSummary {
+    /// Kernel modules of storage drivers, like `nvme` or `ahci`
+    pub storage_drivers: Vec<String>,
+    /// Kernel modules of network drivers, needed for network root filesystems
+    pub network_drivers: Vec<String>,
+    /// Kernel crypto modules and dm-crypt, needed for encrypted root filesystems
+    pub crypto_modules: Vec<String>,
+    /// Dracut modules the initrd was built with
+    pub dracut_modules: Vec<String>,
+    /// Initrd can resume from hibernation
+    pub resume: bool,
+}
+
+/// Module name of a kernel module path like `.../drivers/nvme/host/nvme.ko.zst`
+fn module_name(path: &str) -> Option<&str> {
+    let file = path.rsplit('/').next()?;
+    let (name, _) = file.split_once(".ko")?;
+    Some(name)
+}
+
+fn push_unique(list: &mut Vec<String>, name: &str) {
+    if !list.iter().any(|item| item == name) {
+        list.push(name.into());
+    }
+}
+
+impl InitrdSummary {
+    /// Parse the default output of `lsinitrd <image>`
+    pub fn parse(listing: &str) -> Self {
+        let mut summary = Self::default();
+        let mut in_dracut_modules = false;
+        for line in listing.lines() {
+            let line = line.trim();
+            if line == "dracut modules:" {
+                in_dracut_modules = true;
+                continue;
+            }
+            if in_dracut_modules {
+                if line.is_empty() || line.starts_with("===") {
+                    in_dracut_modules = false;
+                } else {
+                    push_unique(&mut summary.dracut_modules, line);
+                }
+                continue;
+            }
+
+            // file listing in `ls -l` format, the path is the last field before a link target
+            let Some(path) = line
+                .split(" -> ")
+                .next()
+                .and_then(|line| line.split_whitespace().last())
+            else {
+                continue;
+            };
+            if path.contains("hibernate-resume") {
+                summary.resume = true;
+            }
+
+            if !path.contains("/modules/") {
+                continue;
+            }
+            let Some(name) = module_name(path) else {
+                continue;
+            };
+            if STORAGE_DRIVER_DIRS.iter().any(|dir| path.contains(dir)) {
+                if name.starts_with("dm-crypt") {
+                    push_unique(&mut summary.crypto_modules, name);
+                } else {
+                    push_unique(&mut summary.storage_drivers, name);
+                }
+            } else if path.contains("drivers/net/") {
+                push_unique(&mut summary.network_drivers, name);
+            } else if path.contains("/kernel/crypto/") || path.contains("/arch/x86/crypto/") {
+                push_unique(&mut summary.crypto_modules, name);
+            }
+        }
+
+        if summary
+            .dracut_modules
+            .iter()
+            .any(|module| module == "resume")
+        {
+            summary.resume = true;
+        }
+
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = "\
+Image: /boot/initrd-6.17.5-1-default: 21M
+========================================================================
+Version: dracut-059+suse.500.g12345678
+
+Arguments: --force --kver '6.17.5-1-default'
+
+dracut modules:
+systemd
+crypt
+resume
+nvmf
+========================================================================
+drwxr-xr-x  12 root     root            0 Nov  4 10:00 .
+lrwxrwxrwx   1 root     root            7 Nov  4 10:00 bin -> usr/bin
+-rw-r--r--   1 root     root        65000 Nov  4 10:00 usr/lib/modules/6.17.5-1-default/kernel/drivers/nvme/host/nvme.ko.zst
+-rw-r--r--   1 root     root        32000 Nov  4 10:00 usr/lib/modules/6.17.5-1-default/kernel/drivers/ata/ahci.ko.zst
+-rw-r--r--   1 root     root        40000 Nov  4 10:00 usr/lib/modules/6.17.5-1-default/kernel/drivers/md/dm-crypt.ko.zst
+-rw-r--r--   1 root     root        12000 Nov  4 10:00 usr/lib/modules/6.17.5-1-default/kernel/crypto/xts.ko.zst
+-rw-r--r--   1 root     root        90000 Nov  4 10:00 usr/lib/modules/6.17.5-1-default/kernel/drivers/net/ethernet/intel/e1000e/e1000e.ko.zst
+-rw-r--r--   1 root     root          800 Nov  4 10:00 usr/lib/systemd/system/systemd-hibernate-resume.service
+========================================================================
+";
+
+    #[test]
+    fn test_parse_listing() {
+        let summary = InitrdSummary::parse(LISTING);
+        assert_eq!(summary.storage_drivers, vec!["nvme", "ahci"]);
+        assert_eq!(summary.network_drivers, vec!["e1000e"]);
+        assert_eq!(summary.crypto_modules, vec!["dm-crypt", "xts"]);
+        assert_eq!(
+            summary.dracut_modules,
+            vec!["systemd", "crypt", "resume", "nvmf"]
+        );
+        assert!(summary.resume);
+    }
+
+    #[test]
+    fn test_parse_empty_listing() {
+        let summary = InitrdSummary::parse("");
+        assert_eq!(summary, InitrdSummary::default());
+        assert!(!summary.resume);
+    }
+}
@@ -1,21 +1,36 @@
 use clap::Parser;
-use std::future::pending;
+use std::{future::pending, time::Duration};
 
+mod bootloader;
 mod config;
 mod db;
 mod dbus;
 mod errors;
 mod events;
+mod firmware;
 mod grub2;
+mod initrd;
 mod logging;
+mod policy;
+#[cfg(feature = "rescue")]
+mod rescue;
+mod restart;
+mod services;
+mod systemd_boot;
+mod zypp;
 
 use crate::{
-    config::ConfigArgs,
+    config::{ConfigArgs, Paths},
     db::Database,
-    dbus::connection::create_connection,
+    dbus::{connection::create_connection, namespace::Namespace},
     errors::{DRes, DResult},
-    events::listen_files,
+    events::{
+        listen_files, signal_drift, watch_backend, watch_pause, watch_properties, watch_snapshots,
+    },
     logging::setup_logging,
+    policy::check_policy_files,
+    restart::InFlight,
+    services::{job::set_command_timeout, Services},
 };
 
 #[tokio::main]
@@ -23,15 +38,75 @@ async fn main() -> DResult<()> {
     let args = ConfigArgs::parse();
 
     setup_logging(&args)?;
+    set_command_timeout(args.command_timeout_secs);
+    #[cfg(feature = "rescue")]
+    if let Some(command) = &args.rescue {
+        return rescue::run(command, args.storage, &args.db_path).await;
+    }
     log::info!("Starting bootkit service");
 
-    let db = Database::new().await?;
-    db.initialize().await?;
+    let mut paths = Paths::host();
+    paths.set_database(&args.db_path);
+    paths.set_grub_backups(args.grub_backups);
+    paths.detect_grub_tools(&args.grub_tools);
+    paths.detect_boot_partitions();
+    paths.detect_grub_dir(args.grub_dir.as_deref());
+    let db = Database::new(&paths, args.storage).await?;
+    db.initialize(&paths).await?;
 
-    let connection = create_connection(&args, &db)
+    if let Some(link) = paths.grub_file_link() {
+        log::warn!(
+            "{}, changes are written to the linked file",
+            link.description(paths.grub_file())
+        );
+    }
+
+    let services = Services::new(db, paths.clone(), InFlight::default(), args.max_snapshots);
+    tokio::spawn(services.config.clone().watch_pending_operations());
+    let enforced = match services.state.changes.turn().await {
+        Ok(_turn) => services.entries.enforce_preferred_flavor().await,
+        Err(err) => Err(err),
+    };
+    if enforced.is_err() {
+        log::warn!("Failed to keep the default boot entry on the preferred kernel flavor");
+    }
+
+    // Session bus doesn't need policy files for owning the name
+    let policy = if args.session {
+        Vec::new()
+    } else {
+        check_policy_files(args.install_policy)
+    };
+
+    let namespace = Namespace::new(&args);
+    let connection = create_connection(&args, &namespace, services.clone(), policy)
         .await
         .ctx(dctx!(), "Failed to create Zbus connection")?;
-    listen_files(&connection)
+    tokio::spawn(watch_backend(
+        connection.clone(),
+        namespace.clone(),
+        services.clone(),
+    ));
+    tokio::spawn(watch_pause(
+        connection.clone(),
+        namespace.clone(),
+        services.clone(),
+    ));
+    tokio::spawn(watch_properties(
+        connection.clone(),
+        namespace.clone(),
+        services.clone(),
+    ));
+    tokio::spawn(watch_snapshots(
+        connection.clone(),
+        namespace.clone(),
+        services.clone(),
+    ));
+    if let Err(err) = signal_drift(&connection, &namespace, &services).await {
+        log::warn!("Failed to signal the changes made while the daemon wasn't running: {err}");
+    }
+    let debounce = Duration::from_millis(args.debounce_ms);
+    listen_files(&connection, &namespace, &services, debounce)
         .await
         .ctx(dctx!(), "Failed to listen file events")?;
     pending::<()>().await;
@@ -0,0 +1,107 @@
+//! D-Bus policy and polkit action files required by the daemon.
+//!
+//! Without the D-Bus policy the daemon cannot own its name on the system bus and
+//! clients get "access denied", so the files are checked on startup and can be
+//! installed from the copies embedded in the binary.
+
+use std::{
+    fs::{create_dir_all, read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{DBUS_POLICY_DIRS, POLKIT_ACTION_DIRS},
+    dctx,
+    errors::{DRes, DResult},
+};
+
+struct PolicyAsset {
+    name: &'static str,
+    dirs: &'static [&'static str],
+    contents: &'static str,
+}
+
+const POLICY_ASSETS: &[PolicyAsset] = &[
+    PolicyAsset {
+        name: "org.opensuse.bootkit.conf",
+        dirs: DBUS_POLICY_DIRS,
+        contents: include_str!("../dbus/org.opensuse.bootkit.conf"),
+    },
+    PolicyAsset {
+        name: "org.opensuse.bootkit.policy",
+        dirs: POLKIT_ACTION_DIRS,
+        contents: include_str!("../dbus/org.opensuse.bootkit.policy"),
+    },
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyStatus {
+    name: String,
+    /// Where the file was found, or installed to
+    path: Option<PathBuf>,
+    /// File was installed from the embedded default on this startup
+    installed: bool,
+    /// File differs from the default shipped with this version
+    modified: bool,
+    pub problem: Option<String>,
+}
+
+fn install(asset: &PolicyAsset) -> DResult<PathBuf> {
+    let dir = Path::new(asset.dirs[0]);
+    create_dir_all(dir).ctx(dctx!(), format!("Cannot create directory {dir:?}"))?;
+    let path = dir.join(asset.name);
+    write(&path, asset.contents).ctx(dctx!(), format!("Cannot write {path:?}"))?;
+    log::info!("Installed default {} to {path:?}", asset.name);
+    Ok(path)
+}
+
+fn check(asset: &PolicyAsset, install_missing: bool) -> PolicyStatus {
+    let mut status = PolicyStatus {
+        name: asset.name.into(),
+        path: None,
+        installed: false,
+        modified: false,
+        problem: None,
+    };
+
+    let found = asset
+        .dirs
+        .iter()
+        .map(|dir| Path::new(dir).join(asset.name))
+        .find(|path| path.exists());
+
+    if let Some(path) = found {
+        status.modified = read_to_string(&path).map_or(true, |data| data != asset.contents);
+        status.path = Some(path);
+    } else if install_missing {
+        match install(asset) {
+            Ok(path) => {
+                status.installed = true;
+                status.path = Some(path);
+            }
+            Err(_) => {
+                status.problem = Some(format!("{} is missing and cannot be installed", asset.name))
+            }
+        }
+    } else {
+        status.problem = Some(format!(
+            "{} is missing from {:?}, start with --install-policy to install it",
+            asset.name, asset.dirs
+        ));
+    }
+
+    if let Some(problem) = &status.problem {
+        log::warn!("{problem}");
+    }
+    status
+}
+
+/// Check that the policy files are in place, installing the missing ones if requested
+pub fn check_policy_files(install_missing: bool) -> Vec<PolicyStatus> {
+    POLICY_ASSETS
+        .iter()
+        .map(|asset| check(asset, install_missing))
+        .collect()
+}
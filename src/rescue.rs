@@ -0,0 +1,133 @@
+//! Rescue mode for systems that don't boot to userspace.
+//!
+//! Runs from the initrd or a rescue system without D-Bus, and works on the
+//! snapshots stored in the root filesystem mounted at `--root`, like `/sysroot`.
+
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+
+use crate::{
+    config::Paths,
+    db::{Database, StorageKind},
+    dctx,
+    errors::{DError, DRes, DResult},
+    restart::InFlight,
+    services::{snapshot::SelectSnapshotData, Services},
+};
+
+#[derive(Subcommand, Debug)]
+pub enum RescueCommand {
+    /// List the config snapshots of the system mounted at ROOT, without D-Bus
+    ListSnapshots {
+        /// Root filesystem of the system to rescue
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+    },
+    /// Restore a config snapshot to the system mounted at ROOT and regenerate
+    /// grub.cfg, without D-Bus
+    RestoreSnapshot {
+        snapshot_id: i64,
+        /// Root filesystem of the system to rescue
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+        /// End a change freeze of the system before restoring
+        #[arg(long, default_value_t = false)]
+        ignore_freeze: bool,
+    },
+}
+
+/// Services of the system at `root`, using its existing storage. `db_path` is
+/// only used for the host system, other roots have their own.
+async fn rescue_services(root: &Path, storage: StorageKind, db_path: &Path) -> DResult<Services> {
+    let root = root
+        .canonicalize()
+        .ctx(dctx!(), format!("Cannot resolve root {root:?}"))?;
+    let mut paths = if root == Path::new("/") {
+        let mut paths = Paths::host();
+        paths.set_database(db_path);
+        paths
+    } else {
+        Paths::with_root(&root)
+    };
+    paths.detect_grub_tools(&[]);
+    paths.detect_boot_partitions();
+    paths.detect_grub_dir(None);
+
+    let storage_path = match storage {
+        StorageKind::Sqlite => paths.database().to_path_buf(),
+        StorageKind::Files => paths.storage_file(),
+        StorageKind::Memory => {
+            return Err(DError::generic(
+                dctx!(),
+                "Memory storage has no snapshots to rescue from",
+            ))
+        }
+    };
+    if !storage_path.exists() {
+        return Err(DError::generic(
+            dctx!(),
+            format!("No snapshots stored in {storage_path:?}"),
+        ));
+    }
+
+    // the initial snapshot of a new database is not wanted here, only migrations
+    let db = Database::new(&paths, storage).await?;
+    db.migrate().await?;
+    Ok(Services::new(db, paths, InFlight::default(), None))
+}
+
+pub async fn run(command: &RescueCommand, storage: StorageKind, db_path: &Path) -> DResult<()> {
+    match command {
+        RescueCommand::ListSnapshots { root } => {
+            let services = rescue_services(root, storage, db_path).await?;
+            let db = &services.state.db;
+            let selected = db.selected_snapshot().await?.grub2_snapshot_id;
+            let snapshots = db.grub2_snapshots().await?;
+            let selected = selected.or(snapshots.first().map(|snapshot| snapshot.id));
+
+            println!(
+                "{:>6}  {:<19}  {:<7}  {:<8}  DEFAULT ENTRY",
+                "ID", "CREATED (UTC)", "APPLIED", "SELECTED"
+            );
+            for snapshot in snapshots {
+                println!(
+                    "{:>6}  {:<19}  {:<7}  {:<8}  {}",
+                    snapshot.id,
+                    snapshot.created.format("%Y-%m-%d %H:%M:%S"),
+                    if snapshot.applied { "yes" } else { "no" },
+                    if Some(snapshot.id) == selected {
+                        "*"
+                    } else {
+                        ""
+                    },
+                    snapshot.selected_kernel.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        RescueCommand::RestoreSnapshot {
+            snapshot_id,
+            root,
+            ignore_freeze,
+        } => {
+            let services = rescue_services(root, storage, db_path).await?;
+            let _turn = services.state.changes.turn().await?;
+            if *ignore_freeze && services.state.freeze().await?.is_some() {
+                log::warn!("Ending the change freeze to restore snapshot {snapshot_id}");
+                services.state.unfreeze().await?;
+            }
+
+            let result = services
+                .snapshots
+                .select_snapshot(SelectSnapshotData {
+                    snapshot_id: *snapshot_id,
+                })
+                .await?;
+            let result = serde_json::to_string_pretty(&result)
+                .ctx(dctx!(), "Cannot turn the result into json")?;
+            println!("Restored snapshot {snapshot_id}\n{result}");
+        }
+    }
+
+    Ok(())
+}
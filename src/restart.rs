@@ -0,0 +1,158 @@
+//! Restarting the daemon without interrupting changes, e.g. after a package update.
+//!
+//! Applying a config runs several commands, and killing the daemon between them
+//! leaves /etc/default/grub and grub.cfg out of sync. New changes are refused
+//! while the running ones finish, and only then is the daemon restarted.
+
+use std::{
+    env,
+    os::unix::process::CommandExt,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{sync::watch, time::timeout};
+
+use crate::{
+    dctx,
+    errors::{DError, DResult},
+};
+
+/// systemd unit of the daemon
+const SERVICE_UNIT: &str = "bootkitd.service";
+
+/// Longest wait for the changes in progress before a restart is given up
+pub const RESTART_DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time for the reply and the restart signal to reach the clients
+pub const RESTART_DELAY: Duration = Duration::from_millis(500);
+
+/// Changes that are in progress on any of the managed systems
+#[derive(Debug, Clone)]
+pub struct InFlight {
+    count: Arc<watch::Sender<usize>>,
+    draining: Arc<AtomicBool>,
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self {
+            count: Arc::new(watch::Sender::new(0)),
+            draining: Arc::default(),
+        }
+    }
+}
+
+/// Change in progress, done when dropped
+pub struct InFlightGuard {
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.count.send_modify(|count| *count -= 1);
+    }
+}
+
+impl InFlight {
+    /// Start a change, refused when the daemon is restarting
+    pub fn start(&self) -> DResult<InFlightGuard> {
+        if self.draining.load(Ordering::SeqCst) {
+            return Err(DError::generic(
+                dctx!(),
+                "Daemon is restarting, try again once it's back",
+            ));
+        }
+
+        self.count.send_modify(|count| *count += 1);
+        Ok(InFlightGuard {
+            count: self.count.clone(),
+        })
+    }
+
+    /// Wait until no changes are in progress
+    pub async fn idle(&self) {
+        let mut count = self.count.subscribe();
+        // the sender lives as long as self, so this cannot fail
+        let _ = count.wait_for(|count| *count == 0).await;
+    }
+
+    /// Accept changes again after draining
+    pub fn resume(&self) {
+        self.draining.store(false, Ordering::SeqCst);
+    }
+
+    /// Refuse new changes and wait up to `limit` for the running ones to finish.
+    /// Changes are accepted again if they don't finish in time.
+    pub async fn drain(&self, limit: Duration) -> DResult<()> {
+        self.draining.store(true, Ordering::SeqCst);
+        let mut count = self.count.subscribe();
+        if timeout(limit, count.wait_for(|count| *count == 0))
+            .await
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        self.resume();
+        Err(DError::generic(
+            dctx!(),
+            format!(
+                "Changes are still running after {} seconds, not restarting",
+                limit.as_secs()
+            ),
+        ))
+    }
+}
+
+/// Restart the daemon, through systemd when it's running as a unit and by
+/// executing itself again otherwise. systemd stops the daemon after this returns.
+pub fn restart_process() -> DResult<()> {
+    // systemd sets INVOCATION_ID for the processes of its units
+    if env::var_os("INVOCATION_ID").is_some() {
+        log::info!("Asking systemd to restart {SERVICE_UNIT}");
+        let status = Command::new("systemctl")
+            .args(["--no-block", "restart", SERVICE_UNIT])
+            .status()
+            .map_err(|err| DError::generic(dctx!(), format!("Cannot run systemctl: {err}")))?;
+        if !status.success() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("systemctl restart {SERVICE_UNIT} exited with {status}"),
+            ));
+        }
+        return Ok(());
+    }
+
+    let exe = env::current_exe()
+        .map_err(|err| DError::generic(dctx!(), format!("Cannot find own executable: {err}")))?;
+    log::info!("Restarting {exe:?}");
+    let err = Command::new(&exe).args(env::args_os().skip(1)).exec();
+    Err(DError::generic(
+        dctx!(),
+        format!("Cannot execute {exe:?}: {err}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain() {
+        let in_flight = InFlight::default();
+        let guard = in_flight.start().unwrap();
+        assert!(in_flight.drain(Duration::from_millis(10)).await.is_err());
+        // a failed drain accepts changes again
+        drop(in_flight.start().unwrap());
+
+        drop(guard);
+        in_flight.idle().await;
+        in_flight.drain(Duration::from_millis(10)).await.unwrap();
+        assert!(in_flight.start().is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! Changes that run in the background, so the D-Bus call starting them returns
+//! before slow commands like grub2-mkconfig finish
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    dctx,
+    errors::{DError, DResult},
+    services::job::{ApplyResult, ApplyStage, ExecutedCommand, JobControl},
+};
+
+/// How many finished jobs are kept for clients that missed the finished signal
+const FINISHED_JOBS_KEPT: usize = 32;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobIdData {
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum JobState {
+    /// `stage` is `null` until the first stage is reached
+    Running {
+        stage: Option<ApplyStage>,
+    },
+    Finished {
+        result: ApplyResult,
+    },
+    Failed {
+        error: String,
+        /// Commands that were run before the failure
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        commands: Vec<ExecutedCommand>,
+    },
+    /// Cancelled with CancelJob, the previous config was restored
+    Cancelled,
+}
+
+/// Background jobs of a single managed system by their id
+#[derive(Clone, Default)]
+pub struct BackgroundJobs {
+    jobs: Arc<Mutex<BTreeMap<u64, JobState>>>,
+    /// Controls of the jobs that are still running
+    controls: Arc<Mutex<HashMap<u64, Arc<JobControl>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl BackgroundJobs {
+    /// Register a new running job that reports its stages to `progress`
+    pub fn start(&self, progress: UnboundedSender<ApplyStage>) -> (u64, Arc<JobControl>) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let control = Arc::new(JobControl::new(progress));
+        self.lock().insert(id, JobState::Running { stage: None });
+        self.lock_controls().insert(id, control.clone());
+        (id, control)
+    }
+
+    pub fn cancel(&self, id: u64) -> DResult<()> {
+        if let Some(control) = self.lock_controls().get(&id) {
+            log::info!("Cancelling job {id}");
+            return control.cancel();
+        }
+
+        match self.job(id) {
+            Some(_) => Err(DError::generic(
+                dctx!(),
+                format!("Job {id} has already finished"),
+            )),
+            None => Err(DError::generic(dctx!(), format!("Job {id} is not found"))),
+        }
+    }
+
+    pub fn set_stage(&self, id: u64, stage: ApplyStage) {
+        if let Some(job) = self.lock().get_mut(&id) {
+            *job = JobState::Running { stage: Some(stage) };
+        }
+    }
+
+    /// Store the result of the job, forgetting the oldest finished jobs
+    pub fn finish(&self, id: u64, result: DResult<ApplyResult>) -> JobState {
+        let cancelled = self
+            .lock_controls()
+            .remove(&id)
+            .is_some_and(|control| control.is_cancelled());
+        let state = match result {
+            Ok(result) => JobState::Finished { result },
+            Err(_) if cancelled => JobState::Cancelled,
+            Err(err) => JobState::Failed {
+                error: err.error().as_string(),
+                commands: err.commands().to_vec(),
+            },
+        };
+
+        let mut jobs = self.lock();
+        jobs.insert(id, state.clone());
+        let finished: Vec<u64> = jobs
+            .iter()
+            .filter(|(_, job)| !matches!(job, JobState::Running { .. }))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(FINISHED_JOBS_KEPT))
+        {
+            jobs.remove(id);
+        }
+
+        state
+    }
+
+    pub fn job(&self, id: u64) -> Option<JobState> {
+        self.lock().get(&id).cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, JobState>> {
+        self.jobs.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn lock_controls(&self) -> MutexGuard<'_, HashMap<u64, Arc<JobControl>>> {
+        self.controls.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    #[test]
+    fn test_background_jobs() {
+        let jobs = BackgroundJobs::default();
+        let (progress, _stages) = unbounded_channel();
+        let (first, _) = jobs.start(progress.clone());
+        jobs.set_stage(first, ApplyStage::Mkconfig);
+        assert!(matches!(
+            jobs.job(first),
+            Some(JobState::Running {
+                stage: Some(ApplyStage::Mkconfig)
+            })
+        ));
+
+        jobs.finish(first, Ok(ApplyResult::default()));
+        assert!(matches!(jobs.job(first), Some(JobState::Finished { .. })));
+
+        assert!(jobs.cancel(first).is_err());
+
+        let ids: Vec<u64> = (0..FINISHED_JOBS_KEPT)
+            .map(|_| jobs.start(progress.clone()).0)
+            .collect();
+        for id in &ids {
+            jobs.finish(*id, Ok(ApplyResult::default()));
+        }
+        assert!(jobs.job(first).is_none());
+        assert!(jobs.job(ids[0]).is_some());
+        assert!(jobs.job(first + 100).is_none());
+    }
+
+    #[test]
+    fn test_cancel_job() {
+        let jobs = BackgroundJobs::default();
+        let (progress, _stages) = unbounded_channel();
+        let (id, control) = jobs.start(progress);
+        assert!(jobs.cancel(id).is_ok());
+        assert!(control.is_cancelled());
+        // cancelling twice is not an error
+        assert!(jobs.cancel(id).is_ok());
+
+        let error = DError::generic(dctx!(), "Apply was cancelled");
+        assert!(matches!(jobs.finish(id, Err(error)), JobState::Cancelled));
+    }
+}
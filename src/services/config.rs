@@ -0,0 +1,1448 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{canonicalize, read_to_string},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use crate::{
+    bootloader::Backend,
+    config::{mounts::mount_source, DEV_PATH},
+    db::{
+        audit_log::{self, AuditEntry},
+        grub2::{Grub2Snapshot, SnapshotFilter},
+        pending_operation::{self, PendingOperation},
+    },
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::{
+        boot::BootPreview,
+        cmdline::Cmdline,
+        comments::Section,
+        diff::{config_diff, key_changes, ConfigDiff, DiffFormat, KeyChange},
+        dropins::{dropin_values, move_dropin_changes, DropinValue},
+        keys::{invalid_values, InvalidValue, KeySchema, KEYS},
+        menu::{make_menu_accessible, MenuPreview},
+        params::{dangerous_changes, device_problems, DangerousParam, DeviceProblem, CMDLINE_KEYS},
+        GrubBootEntries, GrubFile, GrubLine, ParseError,
+    },
+    services::{
+        entry::EntryService,
+        job::{
+            run_blocking, ApplyOptions, ApplyResult, ExecutedCommand, JobService, SnapshotFiles,
+        },
+        snapshot::{valid_labels, SnapshotService},
+        AppState,
+    },
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigData {
+    value_map: Value,
+    value_list: Value,
+    config_diff: Option<Value>,
+    selected_kernel: Option<String>,
+    /// How the config should be applied, only used when saving
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    apply_options: Option<ApplyOptions>,
+    /// Boot menu behavior of the config
+    #[serde(default)]
+    menu: Option<MenuPreview>,
+    /// Sections of the config, formed by the comments in the file
+    #[serde(default)]
+    sections: Vec<Section>,
+    /// Lines that cannot be parsed. The config only has raw lines when there are
+    /// errors, and it must be fixed with SaveRawConfig.
+    #[serde(default)]
+    parse_errors: Vec<ParseError>,
+    /// Values of documented keys that don't have the documented format
+    #[serde(default)]
+    invalid_values: Vec<InvalidValue>,
+    /// grub.cfg is not the one the daemon generated for the compared snapshot,
+    /// e.g. it was edited by hand or regenerated by another tool
+    #[serde(default)]
+    grub_cfg_modified: bool,
+    /// Hex SHA-256 of the grub file and the drop-in configs the config was read
+    /// from. Saving refuses to overwrite them if either has changed since, saving
+    /// without it always writes. Queued configs get the one of the files they were
+    /// queued over, so changes made before they are applied aren't overwritten.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    /// Keys set in the drop-in configs of grub.d, which override the values of
+    /// the grub file. Changes to them are saved to the drop-in that sets them.
+    #[serde(default)]
+    dropin_values: BTreeMap<String, DropinValue>,
+}
+
+/// Version of a grub file and its drop-in configs that changes whenever the
+/// contents of any of them change
+fn contents_etag(contents: &str, dropins: &BTreeMap<String, String>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(contents);
+    for (name, dropin) in dropins {
+        // lengths keep the boundaries of the files in the hash
+        hasher.update(format!("\0{}:{name}\0{}:", name.len(), dropin.len()));
+        hasher.update(dropin);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+impl ConfigData {
+    /// Values of the config keys in effect, the drop-in values override the grub file
+    pub fn values(&self) -> HashMap<String, String> {
+        let file_values =
+            self.value_map
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, keyvalue)| {
+                    Some((key.clone(), keyvalue.get("value")?.as_str()?.to_string()))
+                });
+        let dropin_values = self
+            .dropin_values
+            .iter()
+            .map(|(key, dropin)| (key.clone(), dropin.value.clone()));
+        file_values.chain(dropin_values).collect()
+    }
+
+    /// Documentation comments of the keys in the grub file that have them
+    pub fn comments(&self) -> HashMap<String, Vec<String>> {
+        self.value_map
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, keyvalue)| {
+                let comments: Vec<String> = keyvalue
+                    .get("comments")?
+                    .as_array()?
+                    .iter()
+                    .filter_map(|comment| Some(comment.as_str()?.to_string()))
+                    .collect();
+                (!comments.is_empty()).then(|| (key.clone(), comments))
+            })
+            .collect()
+    }
+
+    pub fn selected_kernel(&self) -> Option<&str> {
+        self.selected_kernel.as_deref()
+    }
+
+    pub fn parse_errors(&self) -> &[ParseError] {
+        &self.parse_errors
+    }
+
+    pub fn invalid_values(&self) -> &[InvalidValue] {
+        &self.invalid_values
+    }
+
+    fn grub_file(&self) -> DResult<GrubFile> {
+        let value_list = Vec::<GrubLine>::deserialize(&self.value_list)
+            .ctx(dctx!(), "Cannot turn json into GrubLines")?;
+        Ok(GrubFile::from_lines(&value_list))
+    }
+}
+
+/// What saving a config would do, without applying it
+#[derive(Debug, Serialize)]
+pub struct ConfigValidation {
+    /// SaveConfig would apply the config as is
+    valid: bool,
+    /// Grub file that would be written
+    contents: String,
+    /// Unified diff from the current grub file, `None` if nothing changes
+    diff: Option<String>,
+    parse_errors: Vec<ParseError>,
+    /// Invalid values that the current config doesn't already have
+    invalid_values: Vec<InvalidValue>,
+    /// Dangerous parameters that are not acknowledged
+    dangerous_params: Vec<DangerousParam>,
+    /// Problems with root= and resume= devices that are not forced
+    device_problems: Vec<DeviceProblem>,
+    /// Selected kernel that has no boot entry
+    unknown_kernel: Option<String>,
+}
+
+/// Invalid values of `new`, except the ones `current` already has
+fn new_invalid_values(current: &GrubFile, new: &GrubFile) -> Vec<InvalidValue> {
+    invalid_values(new)
+        .into_iter()
+        .filter(|invalid| current.value(&invalid.key) != Some(invalid.value.as_str()))
+        .collect()
+}
+
+/// What the client wants to receive with the config or the snapshots
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DiffOptions {
+    #[serde(default)]
+    pub diff_format: DiffFormat,
+    /// Read the config again even if it hasn't changed, only used with the config
+    #[serde(default)]
+    pub force_refresh: bool,
+    /// Which snapshots are listed, only used with the snapshots
+    #[serde(flatten)]
+    pub filter: SnapshotFilter,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RawConfigData {
+    /// Full contents of the grub config file
+    pub contents: String,
+    #[serde(default)]
+    pub apply_options: Option<ApplyOptions>,
+}
+
+/// Extensions of the grub file variants left by the package manager
+pub const CONFIG_VARIANTS: &[&str] = &["rpmnew", "rpmsave"];
+
+/// Tag of the snapshots taken when the grub file is edited outside the daemon
+pub const EXTERNAL_CHANGE_TAG: &str = "external change";
+/// Tag of the snapshots taken when the grub file was edited while the daemon
+/// wasn't running
+pub const DRIFT_TAG: &str = "drift";
+
+#[derive(Debug, Serialize)]
+pub struct ConfigVariantData {
+    /// Extension of the variant, `rpmnew` or `rpmsave`
+    kind: String,
+    path: PathBuf,
+    /// Keys that differ from the current config
+    changes: Vec<KeyChange>,
+    /// Unified diff from the current config to the variant
+    diff: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MergeRpmnewData {
+    /// Keys to take from the rpmnew file
+    keys: Vec<String>,
+}
+
+/// How often the pending operations are retried while the boot partition is read-only
+const PENDING_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Keys that are kept from the current config when resetting to distro defaults
+const DEFAULT_PRESERVED_KEYS: &[&str] = &[
+    "GRUB_CMDLINE_LINUX",
+    "GRUB_CMDLINE_LINUX_DEFAULT",
+    "GRUB_DEFAULT",
+    "GRUB_DISTRIBUTOR",
+];
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResetDefaultsData {
+    /// Keys to keep from the current config, `DEFAULT_PRESERVED_KEYS` if not given
+    preserve_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResetDefaultsResult {
+    /// Template the config was reset to
+    template: PathBuf,
+    /// Keys whose values were kept from the previous config
+    preserved: Vec<String>,
+    commands: Vec<ExecutedCommand>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MergeRpmnewResult {
+    /// Keys that were changed by the merge
+    changes: Vec<KeyChange>,
+    commands: Vec<ExecutedCommand>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MenuAccessData {
+    /// Changed keys and their new values, empty if menu was already accessible
+    changes: Vec<(String, String)>,
+    menu: MenuPreview,
+    commands: Vec<ExecutedCommand>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DefaultEntryData {
+    /// Id of the grub boot entry, like `Advanced options>openSUSE, with Linux 6.4.0`
+    entry: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct KernelParamData {
+    /// Parameter name, like `quiet` or `console`
+    name: String,
+    /// Value of the parameter, `None` for flags like `quiet`. Not used when removing.
+    #[serde(default)]
+    value: Option<String>,
+    /// Command line key of the parameter, GRUB_CMDLINE_LINUX_DEFAULT if not given
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    apply_options: Option<ApplyOptions>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditEntryData {
+    id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntryDiff {
+    id: i64,
+    action: String,
+    created: NaiveDateTime,
+    /// Keys changed by the action, with their old and new values
+    changes: Vec<KeyChange>,
+    /// Unique bus name of the client, `null` for changes the daemon made by itself
+    sender: Option<String>,
+    uid: Option<i64>,
+    /// D-Bus method the change was made with
+    method: Option<String>,
+    /// Commands that applied the change, with their exit codes and durations
+    commands: Vec<ExecutedCommand>,
+}
+
+impl TryFrom<AuditEntry> for AuditEntryDiff {
+    type Error = DError;
+
+    fn try_from(entry: AuditEntry) -> DResult<Self> {
+        let changes = serde_json::from_str(&entry.changes)
+            .ctx(dctx!(), "Malformed key changes in audit log")?;
+        let commands = serde_json::from_str(&entry.commands)
+            .ctx(dctx!(), "Malformed commands in audit log")?;
+        Ok(Self {
+            id: entry.id,
+            action: entry.action,
+            created: entry.created,
+            changes,
+            sender: entry.sender,
+            uid: entry.uid,
+            method: entry.method,
+            commands,
+        })
+    }
+}
+
+/// Filters of the audit log, entries must match all the given ones
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AuditLogQuery {
+    /// Only the entries created at or after this UTC time
+    #[serde(default)]
+    since: Option<NaiveDateTime>,
+    /// Only the entries created before this UTC time
+    #[serde(default)]
+    until: Option<NaiveDateTime>,
+    /// Only the entries that changed this key, like `GRUB_CMDLINE_LINUX_DEFAULT`
+    #[serde(default)]
+    key: Option<String>,
+    /// Only the changes made by this user
+    #[serde(default)]
+    uid: Option<i64>,
+    /// At most this many of the newest matching entries
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+impl AuditLogQuery {
+    fn matches(&self, entry: &AuditEntryDiff) -> bool {
+        self.since.is_none_or(|since| entry.created >= since)
+            && self.until.is_none_or(|until| entry.created < until)
+            && self.uid.is_none_or(|uid| entry.uid == Some(uid))
+            && self
+                .key
+                .as_ref()
+                .is_none_or(|key| entry.changes.iter().any(|change| &change.key == key))
+    }
+}
+
+/// The grub config file and its variants
+#[derive(Clone)]
+pub struct ConfigService {
+    state: AppState,
+    jobs: JobService,
+    entries: EntryService,
+    snapshots: SnapshotService,
+    /// Config read in each diff format, and the generation of the config it was read at
+    cache: Arc<Mutex<HashMap<DiffFormat, (u64, ConfigData)>>>,
+}
+
+impl ConfigService {
+    pub fn new(
+        state: AppState,
+        jobs: JobService,
+        entries: EntryService,
+        snapshots: SnapshotService,
+    ) -> Self {
+        Self {
+            state,
+            jobs,
+            entries,
+            snapshots,
+            cache: Arc::default(),
+        }
+    }
+
+    /// The current config, cached until the watchers notice a change
+    pub async fn config(&self, options: DiffOptions) -> DResult<ConfigData> {
+        let format = options.diff_format;
+        // taken before reading so changes made while reading make the result stale
+        let generation = self.state.config_changes.generation();
+        if let Some(generation) = generation.filter(|_| !options.force_refresh) {
+            let cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some((_, config)) = cache
+                .get(&format)
+                .filter(|(cached, _)| *cached == generation)
+            {
+                log::debug!("Config has not changed, using the cached config");
+                return Ok(config.clone());
+            }
+        }
+
+        let config = self.read_config(format).await?;
+        if let Some(generation) = generation {
+            self.cache
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(format, (generation, config.clone()));
+        }
+        Ok(config)
+    }
+
+    /// Snapshot the config is diffed against, the selected or the latest one
+    async fn compared_snapshot(&self) -> DResult<Grub2Snapshot> {
+        let db = &self.state.db;
+        let selected = db.selected_snapshot().await?;
+        if let Some(id) = selected.grub2_snapshot_id {
+            db.grub2_snapshot(id).await
+        } else {
+            db.latest_grub2().await
+        }
+    }
+
+    /// Unified diff from the compared snapshot to the grub file on disk, empty
+    /// when they are the same
+    pub async fn file_diff(&self) -> DResult<String> {
+        let grub_path = self.state.paths.grub_file().to_owned();
+        let contents = run_blocking(move || {
+            read_to_string(&grub_path).ctx(dctx!(), format!("Error reading {grub_path:?}"))
+        })
+        .await?;
+        let snapshot = self.compared_snapshot().await?;
+        match config_diff(&snapshot.grub_config, &contents, DiffFormat::Unified) {
+            Some(ConfigDiff::Unified(diff)) => Ok(diff),
+            _ => Ok(String::new()),
+        }
+    }
+
+    /// Snapshot the grub file, tagged with `tag`, if it was edited outside the
+    /// daemon, so the edit is kept in the history. Returns true if a snapshot
+    /// was taken.
+    pub async fn snapshot_external_change(&self, tag: &str) -> DResult<bool> {
+        let _turn = self.state.changes.turn().await?;
+        // changes made by the daemon are snapshotted once they finish
+        self.state.in_flight.idle().await;
+        let current = self.read_grub_file().await?;
+        let snapshot = self.compared_snapshot().await?;
+        if current.as_string() == snapshot.grub_config {
+            return Ok(false);
+        }
+
+        log::info!("Grub config was edited outside the daemon, saving a snapshot of it");
+        let previous = GrubFile::new(&snapshot.grub_config)?;
+        self.state
+            .audit_changes(audit_log::EXTERNAL_CHANGE, &previous, &current, &[])
+            .await?;
+        let selected_kernel = self.read_selected_kernel().await?;
+        let snapshot_id = self
+            .state
+            .db
+            .save_grub2(&current, selected_kernel.as_deref(), true, Some(tag))
+            .await?;
+        self.snapshots.capture_files(snapshot_id).await?;
+        // the edited config is the one in use now
+        self.state.db.set_selected_snapshot(None).await?;
+        self.state.snapshot_changes.changed();
+        self.snapshots.prune_snapshots().await?;
+        Ok(true)
+    }
+
+    async fn read_config(&self, diff_format: DiffFormat) -> DResult<ConfigData> {
+        let paths = self.state.paths.clone();
+        let grub_path = paths.grub_file().to_owned();
+        let contents = run_blocking(move || {
+            read_to_string(&grub_path).ctx(dctx!(), format!("Error reading {grub_path:?}"))
+        })
+        .await?;
+        let jobs = self.jobs.clone();
+        let dropins = run_blocking(move || jobs.read_dropins()).await?;
+        let parse_errors = GrubFile::parse_errors(&contents);
+        if !parse_errors.is_empty() {
+            return self.degraded_config(&contents, &dropins, parse_errors);
+        }
+
+        let grub = GrubFile::new(&contents)?;
+        let kernel_entries = run_blocking(move || GrubBootEntries::new(&paths)).await?;
+        let selected_grub = self.compared_snapshot().await?;
+        // TODO: add the potential difference in kernel entries to config_diff as well
+        let config_diff = if diff_format == DiffFormat::None {
+            None
+        } else {
+            config_diff(&selected_grub.grub_config, &grub.as_string(), diff_format)
+                .map(|diff| serde_json::to_value(diff).ctx(dctx!(), "Cannot turn diff into json"))
+                .transpose()?
+        };
+        // snapshots taken before the hashes were recorded are not known to differ
+        let grub_cfg_modified = match &selected_grub.grub_cfg_sha256 {
+            Some(generated) => {
+                let jobs = self.jobs.clone();
+                run_blocking(move || jobs.grub_cfg_sha256()).await?.as_ref() != Some(generated)
+            }
+            None => false,
+        };
+
+        let value_map = serde_json::to_value(grub.keyvalues())
+            .ctx(dctx!(), "Cannot turn grub keyvalues into json")?;
+        let value_list =
+            serde_json::to_value(grub.lines()).ctx(dctx!(), "Cannot turn grub lines into json")?;
+
+        Ok(ConfigData {
+            value_list,
+            value_map,
+            config_diff,
+            selected_kernel: kernel_entries.selected().map(str::to_string),
+            apply_options: None,
+            menu: Some(MenuPreview::new(&grub)),
+            sections: grub.sections().to_vec(),
+            parse_errors: Vec::new(),
+            invalid_values: invalid_values(&grub),
+            grub_cfg_modified,
+            etag: Some(contents_etag(&contents, &dropins)),
+            dropin_values: dropin_values(&dropins),
+        })
+    }
+
+    /// Config with only the raw lines so a broken file can still be shown and fixed
+    fn degraded_config(
+        &self,
+        contents: &str,
+        dropins: &BTreeMap<String, String>,
+        parse_errors: Vec<ParseError>,
+    ) -> DResult<ConfigData> {
+        log::warn!(
+            "{:?} has {} unparseable lines, returning raw config",
+            self.state.paths.grub_file(),
+            parse_errors.len()
+        );
+        let lines: Vec<GrubLine> = contents
+            .split('\n')
+            .map(|line| GrubLine::String {
+                raw_line: line.into(),
+            })
+            .collect();
+        let value_list =
+            serde_json::to_value(lines).ctx(dctx!(), "Cannot turn grub lines into json")?;
+
+        Ok(ConfigData {
+            value_map: Value::Object(Default::default()),
+            value_list,
+            config_diff: None,
+            selected_kernel: self.selected_kernel().ok().flatten(),
+            apply_options: None,
+            menu: None,
+            sections: Vec::new(),
+            parse_errors,
+            invalid_values: Vec::new(),
+            grub_cfg_modified: false,
+            etag: Some(contents_etag(contents, dropins)),
+            dropin_values: BTreeMap::new(),
+        })
+    }
+
+    /// Replace the config with the given file contents, the way to fix a config
+    /// that cannot be parsed
+    pub async fn save_raw_config(&self, raw: RawConfigData) -> DResult<ApplyResult> {
+        let parse_errors = GrubFile::parse_errors(&raw.contents);
+        if !parse_errors.is_empty() {
+            let errors: Vec<String> = parse_errors
+                .iter()
+                .map(|error| format!("line {}: {}", error.line, error.message))
+                .collect();
+            return Err(DError::generic(
+                dctx!(),
+                format!("Config cannot be parsed: {}", errors.join(", ")),
+            ));
+        }
+
+        let grub = GrubFile::new(&raw.contents)?;
+        let value_list =
+            serde_json::to_value(grub.lines()).ctx(dctx!(), "Cannot turn grub lines into json")?;
+        let config = ConfigData {
+            value_map: Value::Object(Default::default()),
+            value_list,
+            config_diff: None,
+            // keep the current default entry
+            selected_kernel: self.read_selected_kernel().await?,
+            apply_options: raw.apply_options,
+            menu: None,
+            sections: Vec::new(),
+            parse_errors: Vec::new(),
+            invalid_values: Vec::new(),
+            grub_cfg_modified: false,
+            etag: None,
+            dropin_values: BTreeMap::new(),
+        };
+        self.save_config(config).await
+    }
+
+    /// Apply the config, or queue it if the boot partition is read-only
+    pub async fn save_config(&self, mut config: ConfigData) -> DResult<ApplyResult> {
+        self.state.require_unfrozen().await?;
+        let (dangerous_params, device_problems) = self.check_config(&config)?;
+        if !dangerous_params.is_empty() || !device_problems.is_empty() {
+            for param in &dangerous_params {
+                log::warn!(
+                    "Refusing unacknowledged dangerous parameter '{}' in {}",
+                    param.param,
+                    param.key
+                );
+            }
+            for problem in &device_problems {
+                log::warn!("Refusing '{}': {}", problem.param, problem.problem);
+            }
+            return Ok(ApplyResult::refused(dangerous_params, device_problems));
+        }
+
+        if !self.state.paths.is_boot_writable() {
+            if config.etag.is_none() {
+                config.etag = Some(self.current_etag()?);
+            }
+            let data =
+                serde_json::to_string(&config).ctx(dctx!(), "Failed to serialize grub2 config")?;
+            let id = self
+                .state
+                .db
+                .add_pending_operation(pending_operation::SAVE_CONFIG, &data)
+                .await?;
+            log::info!("Boot partition is read-only, config was queued as pending operation {id}");
+            return Ok(ApplyResult::pending(id));
+        }
+
+        self.apply_config_data(config).await
+    }
+
+    /// Refuse the config if it can't be saved over the files as they are now.
+    /// Returns the unacknowledged dangerous parameters and the unforced device
+    /// problems, the config is only saved if both are empty.
+    fn check_config(
+        &self,
+        config: &ConfigData,
+    ) -> DResult<(Vec<DangerousParam>, Vec<DeviceProblem>)> {
+        // raw lines of a degraded config would write the broken lines back
+        if !GrubFile::parse_errors(&config.grub_file()?.as_string()).is_empty() {
+            return Err(DError::generic(
+                dctx!(),
+                "Config has lines that cannot be parsed, fix them with SaveRawConfig",
+            ));
+        }
+        if let Some(etag) = &config.etag {
+            self.require_unchanged(etag, &config.grub_file()?)?;
+        }
+
+        let options = config.apply_options.clone().unwrap_or_default();
+        let current = self.current_config()?;
+        let new = config.grub_file()?;
+        // values that were already invalid don't block saving other changes
+        let invalid: Vec<String> = new_invalid_values(&current, &new)
+            .into_iter()
+            .map(|invalid| format!("{} '{}' {}", invalid.key, invalid.value, invalid.problem))
+            .collect();
+        if !invalid.is_empty() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Invalid values: {}", invalid.join(", ")),
+            ));
+        }
+        let dangerous_params = if options.acknowledge_dangerous {
+            Vec::new()
+        } else {
+            dangerous_changes(&current, &new)
+        };
+        let device_problems = if options.force_devices {
+            Vec::new()
+        } else {
+            self.device_problems(&current, &new)
+        };
+        Ok((dangerous_params, device_problems))
+    }
+
+    /// etag of the grub file and the drop-in configs as they are now
+    fn current_etag(&self) -> DResult<String> {
+        let grub_path = self.state.paths.grub_file();
+        let contents =
+            read_to_string(grub_path).ctx(dctx!(), format!("Error reading {grub_path:?}"))?;
+        Ok(contents_etag(&contents, &self.jobs.read_dropins()?))
+    }
+
+    /// Refuse to save `new` if the grub file or its drop-ins are no longer the
+    /// ones with `etag`, with the changes the save would make to the file as it is now
+    fn require_unchanged(&self, etag: &str, new: &GrubFile) -> DResult<()> {
+        if self.current_etag()? == etag {
+            return Ok(());
+        }
+
+        let grub_path = self.state.paths.grub_file();
+        let contents =
+            read_to_string(grub_path).ctx(dctx!(), format!("Error reading {grub_path:?}"))?;
+
+        let diff = match config_diff(&contents, &new.as_string(), DiffFormat::Unified) {
+            Some(ConfigDiff::Unified(diff)) => diff,
+            _ => String::new(),
+        };
+        Err(DError::conflict(
+            dctx!(),
+            format!(
+                "{grub_path:?} or its drop-in configs were changed since the config was read, get the config again. Saving would change the file as it is now:\n{diff}"
+            ),
+        ))
+    }
+
+    /// Check the config like SaveConfig does and return the file it would
+    /// write, without writing anything
+    pub async fn validate_config(&self, config: ConfigData) -> DResult<ConfigValidation> {
+        let options = config.apply_options.clone().unwrap_or_default();
+        let current = self.current_config()?;
+        let mut new = config.grub_file()?;
+        let parse_errors = GrubFile::parse_errors(&new.as_string());
+        if !parse_errors.is_empty() {
+            return Ok(ConfigValidation {
+                valid: false,
+                contents: new.as_string(),
+                diff: None,
+                parse_errors,
+                invalid_values: Vec::new(),
+                dangerous_params: Vec::new(),
+                device_problems: Vec::new(),
+                unknown_kernel: None,
+            });
+        }
+
+        let unknown_kernel = match &config.selected_kernel {
+            Some(kernel) => {
+                // the default kernel is only booted with GRUB_DEFAULT=saved
+                new.set_key_value("GRUB_DEFAULT", "saved");
+                let entries = GrubBootEntries::new(&self.state.paths)?;
+                (!entries
+                    .entries()
+                    .iter()
+                    .any(|entry| entry.entry() == kernel))
+                .then(|| kernel.clone())
+            }
+            None => None,
+        };
+        let invalid_values = new_invalid_values(&current, &new);
+        let dangerous_params = if options.acknowledge_dangerous {
+            Vec::new()
+        } else {
+            dangerous_changes(&current, &new)
+        };
+        let device_problems = if options.force_devices {
+            Vec::new()
+        } else {
+            self.device_problems(&current, &new)
+        };
+
+        let contents = new.as_string();
+        let diff = match config_diff(&current.as_string(), &contents, DiffFormat::Unified) {
+            Some(ConfigDiff::Unified(diff)) => Some(diff),
+            _ => None,
+        };
+        Ok(ConfigValidation {
+            valid: invalid_values.is_empty()
+                && dangerous_params.is_empty()
+                && device_problems.is_empty()
+                && unknown_kernel.is_none(),
+            contents,
+            diff,
+            parse_errors: Vec::new(),
+            invalid_values,
+            dangerous_params,
+            device_problems,
+            unknown_kernel,
+        })
+    }
+
+    async fn apply_config_data(&self, config: ConfigData) -> DResult<ApplyResult> {
+        let mut grub_file = config.grub_file()?;
+        let options = config.apply_options.unwrap_or_default();
+        // keys set in drop-ins are changed in them instead of in the grub file
+        let mut dropins = self.jobs.read_dropins()?;
+        let changed = move_dropin_changes(&self.current_config()?, &mut grub_file, &dropins);
+        let files = if changed.is_empty() {
+            None
+        } else {
+            dropins.extend(changed);
+            Some(SnapshotFiles {
+                saved_entry: None,
+                dropins,
+            })
+        };
+        let commands = self
+            .apply_grub2_config(
+                audit_log::SAVE_CONFIG,
+                &mut grub_file,
+                config.selected_kernel,
+                &options,
+                files.as_ref(),
+            )
+            .await?;
+        Ok(ApplyResult::applied(commands))
+    }
+
+    /// What grub does on the next boot with the config that is applied next,
+    /// the latest queued config if there are pending operations
+    pub async fn preview_boot_behavior(&self) -> DResult<BootPreview> {
+        let pending_config = self
+            .state
+            .db
+            .pending_operations()
+            .await?
+            .into_iter()
+            .rev()
+            .find(|operation| operation.kind == pending_operation::SAVE_CONFIG);
+
+        let grub = if let Some(operation) = pending_config {
+            let config: ConfigData =
+                serde_json::from_str(&operation.data).ctx(dctx!(), "Malformed pending config")?;
+            let value_list: Vec<GrubLine> = serde_json::from_value(config.value_list)
+                .ctx(dctx!(), "Cannot turn json into GrubLines")?;
+            GrubFile::from_lines(&value_list)
+        } else {
+            self.read_grub_file().await?
+        };
+        let paths = self.state.paths.clone();
+        let entries = run_blocking(move || GrubBootEntries::new(&paths)).await?;
+
+        Ok(BootPreview::new(&grub, &entries))
+    }
+
+    /// Keys changed by an applied change in the audit log
+    pub async fn audit_entry_diff(&self, entry_data: AuditEntryData) -> DResult<AuditEntryDiff> {
+        self.state.db.audit_entry(entry_data.id).await?.try_into()
+    }
+
+    /// Audit log entries matching the query, newest first
+    pub async fn audit_log(&self, query: AuditLogQuery) -> DResult<Vec<AuditEntryDiff>> {
+        let mut entries = Vec::new();
+        for entry in self.state.db.audit_entries().await? {
+            let entry = AuditEntryDiff::try_from(entry)?;
+            if query.matches(&entry) {
+                entries.push(entry);
+            }
+            if query.limit.is_some_and(|limit| entries.len() >= limit) {
+                break;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn pending_operations(&self) -> DResult<Vec<PendingOperation>> {
+        self.state.db.pending_operations().await
+    }
+
+    async fn apply_pending_operation(&self, operation: &PendingOperation) -> DResult<ApplyResult> {
+        match operation.kind.as_str() {
+            pending_operation::SAVE_CONFIG => {
+                let config: ConfigData = serde_json::from_str(&operation.data)
+                    .ctx(dctx!(), "Malformed pending config")?;
+                // the files or the devices may have changed since it was queued
+                let (dangerous_params, device_problems) = self.check_config(&config)?;
+                let refused: Vec<String> = dangerous_params
+                    .iter()
+                    .map(|param| format!("dangerous parameter '{}' in {}", param.param, param.key))
+                    .chain(
+                        device_problems
+                            .iter()
+                            .map(|problem| format!("'{}': {}", problem.param, problem.problem)),
+                    )
+                    .collect();
+                if !refused.is_empty() {
+                    return Err(DError::generic(
+                        dctx!(),
+                        format!("Pending config was refused, {}", refused.join(", ")),
+                    ));
+                }
+                self.apply_config_data(config).await
+            }
+            kind => Err(DError::generic(
+                dctx!(),
+                format!("Unknown pending operation '{kind}'"),
+            )),
+        }
+    }
+
+    /// Apply the pending operations in the order they were queued, stopping at the first failure
+    pub async fn apply_pending_operations(&self) -> DResult<Vec<ApplyResult>> {
+        let operations = self.state.db.pending_operations().await?;
+        if operations.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if !self.state.paths.is_boot_writable() {
+            return Err(DError::generic(
+                dctx!(),
+                "Boot partition is still read-only, cannot apply pending operations",
+            ));
+        }
+
+        let mut results = Vec::new();
+        for operation in operations {
+            log::info!(
+                "Applying pending operation {} ({})",
+                operation.id,
+                operation.kind
+            );
+            match self.apply_pending_operation(&operation).await {
+                Ok(result) => {
+                    self.state.db.remove_pending_operation(operation.id).await?;
+                    results.push(result);
+                }
+                Err(err) => {
+                    self.state
+                        .db
+                        .set_pending_operation_error(operation.id, &err.message())
+                        .await?;
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Apply the pending operations once the boot partition becomes writable
+    pub async fn watch_pending_operations(self) {
+        let mut interval = tokio::time::interval(PENDING_RETRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Don't interfere with maintenance that paused the watchers
+            if self.state.watchers.is_paused() || !self.state.paths.is_boot_writable() {
+                continue;
+            }
+            // queued changes wait for the end of a change freeze
+            if self
+                .state
+                .freeze()
+                .await
+                .is_ok_and(|freeze| freeze.is_some())
+            {
+                continue;
+            }
+
+            let has_pending = self
+                .state
+                .db
+                .pending_operations()
+                .await
+                .is_ok_and(|operations| !operations.is_empty());
+            if !has_pending {
+                continue;
+            }
+            // Errors are logged when they're dropped and the operation is retried later
+            let applied = match self.state.changes.turn().await {
+                Ok(_turn) => self.apply_pending_operations().await,
+                Err(err) => Err(err),
+            };
+            if applied.is_err() {
+                log::warn!("Failed to apply pending operations, retrying later");
+            }
+        }
+    }
+
+    /// Apply a new grub config to the system and save it as the latest snapshot
+    async fn apply_grub2_config(
+        &self,
+        action: &str,
+        grub_file: &mut GrubFile,
+        selected_kernel: Option<String>,
+        options: &ApplyOptions,
+        files: Option<&SnapshotFiles>,
+    ) -> DResult<Vec<ExecutedCommand>> {
+        // the written file is only known to be the daemon's once it's snapshotted
+        let _in_flight = self.state.in_flight.start()?;
+        let labels = valid_labels(&options.labels)?;
+        for warning in MenuPreview::new(grub_file).warnings {
+            log::warn!("Applying grub config with a warning: {warning}");
+        }
+
+        let previous = self.current_config()?;
+
+        let commands = self
+            .jobs
+            .set_grub_system(grub_file, &selected_kernel, false, files, options)
+            .await?;
+        self.entries
+            .drop_conflicting_flavor(&selected_kernel)
+            .await?;
+
+        // if everything is okay, save the snapshot to a database
+        self.state
+            .audit_changes(action, &previous, grub_file, &commands)
+            .await?;
+        let snapshot_id = self
+            .state
+            .db
+            .save_grub2(grub_file, selected_kernel.as_deref(), true, None)
+            .await?;
+        self.snapshots.capture_files(snapshot_id).await?;
+        self.snapshots.record_grub_cfg(snapshot_id).await?;
+        if !labels.is_empty() {
+            self.state
+                .db
+                .set_snapshot_labels(snapshot_id, &labels)
+                .await?;
+        }
+        // latest snapshot should be null so it's assumed that latest snapshot is selected
+        self.state.db.set_selected_snapshot(None).await?;
+        self.state.config_changes.changed();
+        self.state.snapshot_changes.changed();
+        self.snapshots.prune_snapshots().await?;
+
+        Ok(commands)
+    }
+
+    /// Problems with the devices of root= and resume= parameters changed by `new`
+    fn device_problems(&self, current: &GrubFile, new: &GrubFile) -> Vec<DeviceProblem> {
+        // other targets don't use the root filesystem the daemon runs on
+        let root_device = if self.state.paths.is_host() {
+            mount_source(Path::new("/")).and_then(|(_, device)| canonicalize(device).ok())
+        } else {
+            None
+        };
+
+        device_problems(
+            current,
+            new,
+            Path::new(DEV_PATH),
+            |path| canonicalize(path).ok(),
+            root_device.as_deref(),
+        )
+    }
+
+    /// The current config, unparseable lines are kept as raw lines
+    fn current_config(&self) -> DResult<GrubFile> {
+        let grub_file = self.state.paths.grub_file();
+        let contents =
+            read_to_string(grub_file).ctx(dctx!(), format!("Error reading {grub_file:?}"))?;
+        Ok(GrubFile::new_lenient(&contents))
+    }
+
+    /// Kernel entry that is currently booted by default
+    fn selected_kernel(&self) -> DResult<Option<String>> {
+        Ok(GrubBootEntries::new(&self.state.paths)?
+            .selected()
+            .map(str::to_string))
+    }
+
+    /// `selected_kernel` read on the blocking thread pool
+    async fn read_selected_kernel(&self) -> DResult<Option<String>> {
+        let service = self.clone();
+        run_blocking(move || service.selected_kernel()).await
+    }
+
+    /// The grub file parsed on the blocking thread pool
+    async fn read_grub_file(&self) -> DResult<GrubFile> {
+        let grub_path = self.state.paths.grub_file().to_owned();
+        run_blocking(move || GrubFile::from_file(grub_path)).await
+    }
+
+    /// Replace the grub config with the packaged template, keeping the values of
+    /// the preserved keys. The current config is snapshotted before the reset.
+    pub async fn reset_to_distro_defaults(
+        &self,
+        reset_data: ResetDefaultsData,
+    ) -> DResult<ResetDefaultsResult> {
+        self.state.require_unfrozen().await?;
+        let paths = &self.state.paths;
+        let preserve_keys = reset_data.preserve_keys.unwrap_or_else(|| {
+            DEFAULT_PRESERVED_KEYS
+                .iter()
+                .map(|key| key.to_string())
+                .collect()
+        });
+
+        let templates = paths.grub_templates().to_vec();
+        let grub_path = paths.grub_file().to_owned();
+        let read = run_blocking(move || {
+            let Some(template_path) = templates.into_iter().find(|path| path.exists()) else {
+                return Ok(None);
+            };
+            log::debug!("Resetting grub config to distro defaults from {template_path:?}");
+            let current = GrubFile::from_file(&grub_path)?;
+            let template = GrubFile::from_file(&template_path)?;
+            Ok(Some((template_path, current, template)))
+        })
+        .await?;
+        let Some((template_path, current, mut template)) = read else {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "No distro default grub config found from {:?}",
+                    paths.grub_templates()
+                ),
+            ));
+        };
+
+        let mut preserved = Vec::new();
+        for key in preserve_keys {
+            if let Some(value) = current.value(&key) {
+                template.set_key_value(&key, value);
+                preserved.push(key);
+            }
+        }
+
+        let selected_kernel = self.read_selected_kernel().await?;
+        // Snapshot the current config so the reset can be undone
+        let snapshot_id = self
+            .state
+            .db
+            .save_grub2(&current, selected_kernel.as_deref(), true, None)
+            .await?;
+        self.snapshots.capture_files(snapshot_id).await?;
+        self.state.snapshot_changes.changed();
+        let commands = self
+            .apply_grub2_config(
+                audit_log::RESET_TO_DISTRO_DEFAULTS,
+                &mut template,
+                selected_kernel,
+                &ApplyOptions::default(),
+                None,
+            )
+            .await?;
+
+        Ok(ResetDefaultsResult {
+            template: template_path,
+            preserved,
+            commands,
+        })
+    }
+
+    /// Boot menu timeout of the bootloader, empty when it's not set
+    pub async fn timeout(&self) -> DResult<String> {
+        let service = self.clone();
+        run_blocking(move || service.read_timeout()).await
+    }
+
+    fn read_timeout(&self) -> DResult<String> {
+        let key = match self.state.backend() {
+            Backend::SystemdBoot => "timeout",
+            _ => "GRUB_TIMEOUT",
+        };
+        let mut config = self.state.bootloader()?.read_config()?;
+        Ok(config.remove(key).unwrap_or_default())
+    }
+
+    /// Documented keys and the formats of their values
+    pub fn key_schema(&self) -> &'static [KeySchema] {
+        KEYS
+    }
+
+    /// Get the rpmnew and rpmsave variants of the grub file and how they differ
+    /// from the current config
+    pub async fn config_variants(&self) -> DResult<Vec<ConfigVariantData>> {
+        let service = self.clone();
+        run_blocking(move || service.read_config_variants()).await
+    }
+
+    fn read_config_variants(&self) -> DResult<Vec<ConfigVariantData>> {
+        let paths = &self.state.paths;
+        let current = GrubFile::from_file(paths.grub_file())?;
+        let current_string = current.as_string();
+
+        let mut variants = Vec::new();
+        for kind in CONFIG_VARIANTS {
+            let path = paths.grub_file_variant(kind);
+            if !path.exists() {
+                continue;
+            }
+
+            let variant = GrubFile::from_file(&path)?;
+            let diff = TextDiff::from_lines(&current_string, &variant.as_string())
+                .unified_diff()
+                .to_string();
+            variants.push(ConfigVariantData {
+                kind: kind.to_string(),
+                changes: key_changes(&current, &variant),
+                path,
+                diff,
+            });
+        }
+
+        Ok(variants)
+    }
+
+    /// Adopt the values of the given keys from the rpmnew file
+    pub async fn merge_rpmnew(&self, merge_data: MergeRpmnewData) -> DResult<MergeRpmnewResult> {
+        let paths = self.state.paths.clone();
+        let rpmnew_path = paths.grub_file_variant("rpmnew");
+        let (rpmnew, current) = {
+            let rpmnew_path = rpmnew_path.clone();
+            run_blocking(move || {
+                if !rpmnew_path.exists() {
+                    return Err(DError::generic(
+                        dctx!(),
+                        format!("No {rpmnew_path:?} to merge"),
+                    ));
+                }
+                let rpmnew = GrubFile::from_file(&rpmnew_path)?;
+                Ok((rpmnew, GrubFile::from_file(paths.grub_file())?))
+            })
+            .await?
+        };
+        let mut merged = GrubFile::from_lines(current.lines());
+        for key in &merge_data.keys {
+            let Some(value) = rpmnew.value(key) else {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Key '{key}' is not set in {rpmnew_path:?}"),
+                ));
+            };
+            merged.set_key_value(key, value);
+        }
+
+        let changes = key_changes(&current, &merged);
+        let mut commands = Vec::new();
+        if !changes.is_empty() {
+            let selected_kernel = self.read_selected_kernel().await?;
+            commands = self
+                .apply_grub2_config(
+                    audit_log::MERGE_RPMNEW,
+                    &mut merged,
+                    selected_kernel,
+                    &ApplyOptions::default(),
+                    None,
+                )
+                .await?;
+        }
+
+        Ok(MergeRpmnewResult { changes, commands })
+    }
+
+    /// Apply the smallest change that makes the boot menu reachable again
+    pub async fn make_menu_accessible(&self) -> DResult<MenuAccessData> {
+        let mut grub_file = self.read_grub_file().await?;
+        let changes = make_menu_accessible(&mut grub_file);
+
+        let mut commands = Vec::new();
+        if changes.is_empty() {
+            log::debug!("Boot menu is already accessible, nothing to change");
+        } else {
+            let selected_kernel = self.read_selected_kernel().await?;
+            commands = self
+                .apply_grub2_config(
+                    audit_log::MAKE_MENU_ACCESSIBLE,
+                    &mut grub_file,
+                    selected_kernel,
+                    &ApplyOptions::default(),
+                    None,
+                )
+                .await?;
+        }
+
+        Ok(MenuAccessData {
+            changes,
+            menu: MenuPreview::new(&grub_file),
+            commands,
+        })
+    }
+
+    /// Set a kernel parameter on a command line, in place of the earlier values of it
+    pub async fn add_kernel_param(&self, param: KernelParamData) -> DResult<ApplyResult> {
+        if let Some(value) = &param.value {
+            if value.contains('"') || value.contains('\n') {
+                return Err(DError::invalid_data(
+                    dctx!(),
+                    format!(
+                        "Value of kernel parameter '{}' cannot have quotes or newlines",
+                        param.name
+                    ),
+                ));
+            }
+        }
+        let value = param.value.clone();
+        self.edit_cmdline(param, |cmdline, name| cmdline.set(name, value.as_deref()))
+            .await
+    }
+
+    /// Remove all the values of a kernel parameter from a command line
+    pub async fn remove_kernel_param(&self, param: KernelParamData) -> DResult<ApplyResult> {
+        self.edit_cmdline(param, |cmdline, name| cmdline.remove(name))
+            .await
+    }
+
+    /// Save the config with the command line of the parameter changed by `edit`.
+    /// The rest of the command line keeps its order and quoting.
+    async fn edit_cmdline(
+        &self,
+        param: KernelParamData,
+        edit: impl FnOnce(&mut Cmdline, &str) -> bool,
+    ) -> DResult<ApplyResult> {
+        self.state.require_grub2()?;
+        let key = param
+            .key
+            .unwrap_or_else(|| "GRUB_CMDLINE_LINUX_DEFAULT".to_string());
+        if !CMDLINE_KEYS.contains(&key.as_str()) {
+            return Err(DError::invalid_data(
+                dctx!(),
+                format!("{key} is not a kernel command line key"),
+            ));
+        }
+        let name = param.name;
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '=' || c == '"') {
+            return Err(DError::invalid_data(
+                dctx!(),
+                format!("'{name}' is not a kernel parameter name"),
+            ));
+        }
+
+        let current = self.read_grub_file().await?;
+        // a drop-in value is the one in effect, the change is moved to the drop-in
+        let jobs = self.jobs.clone();
+        let dropins = dropin_values(&run_blocking(move || jobs.read_dropins()).await?);
+        let value = match dropins.get(&key) {
+            Some(dropin) => dropin.value.as_str(),
+            None => current.value(&key).unwrap_or(""),
+        };
+        let mut cmdline = Cmdline::parse(value);
+        if !edit(&mut cmdline, &name) {
+            log::debug!("{key} already has the change of '{name}', nothing to change");
+            return Ok(ApplyResult::applied(Vec::new()));
+        }
+
+        let mut grub = current;
+        grub.set_key_value(&key, &cmdline.to_string());
+        let value_list =
+            serde_json::to_value(grub.lines()).ctx(dctx!(), "Cannot turn grub lines into json")?;
+        let config = ConfigData {
+            value_map: Value::Object(Default::default()),
+            value_list,
+            config_diff: None,
+            // keep the current default entry
+            selected_kernel: self.read_selected_kernel().await?,
+            apply_options: param.apply_options,
+            menu: None,
+            sections: Vec::new(),
+            parse_errors: Vec::new(),
+            invalid_values: Vec::new(),
+            grub_cfg_modified: false,
+            etag: None,
+            dropin_values: BTreeMap::new(),
+        };
+        self.save_config(config).await
+    }
+
+    /// Make the grub entry the default without changing the rest of the config
+    pub async fn set_default_entry(&self, default_data: DefaultEntryData) -> DResult<ApplyResult> {
+        self.state.require_grub2()?;
+        self.state.require_unfrozen().await?;
+        let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+        let Some(entry) = grub_entries
+            .entries()
+            .iter()
+            .find(|entry| entry.full_path() == default_data.entry)
+        else {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Boot entry '{}' is not found", default_data.entry),
+            ));
+        };
+        if !self.state.paths.is_boot_writable() {
+            return Err(DError::generic(
+                dctx!(),
+                "Boot partition is read-only, default entry cannot be changed",
+            ));
+        }
+
+        let mut grub_file = self.current_config()?;
+        let commands = self
+            .apply_grub2_config(
+                audit_log::SET_DEFAULT_ENTRY,
+                &mut grub_file,
+                Some(entry.entry().to_string()),
+                &ApplyOptions::default(),
+                None,
+            )
+            .await?;
+        Ok(ApplyResult::applied(commands))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    use super::*;
+    use crate::{
+        config::Paths,
+        db::{Database, StorageKind},
+        errors::DErrorType,
+        restart::InFlight,
+        services::Services,
+    };
+
+    #[tokio::test]
+    async fn test_stale_pending_config_is_not_applied() {
+        let root = std::env::temp_dir().join(format!("bootkit-pending-{}", std::process::id()));
+        let paths = Paths::with_root(&root);
+        for path in [paths.grub_file(), paths.grub_cfg()] {
+            create_dir_all(path.parent().unwrap()).unwrap();
+        }
+        write(paths.grub_file(), "GRUB_TIMEOUT=8\n").unwrap();
+        let db = Database::new(&paths, StorageKind::Memory).await.unwrap();
+        db.migrate().await.unwrap();
+        let services = Services::new(db, paths.clone(), InFlight::default(), None);
+
+        // queued over a grub file that was edited before the boot partition became writable
+        let grub = GrubFile::new("GRUB_TIMEOUT=3\n").unwrap();
+        let config = ConfigData {
+            value_map: Value::Object(Default::default()),
+            value_list: serde_json::to_value(grub.lines()).unwrap(),
+            config_diff: None,
+            selected_kernel: None,
+            apply_options: None,
+            menu: None,
+            sections: Vec::new(),
+            parse_errors: Vec::new(),
+            invalid_values: Vec::new(),
+            grub_cfg_modified: false,
+            etag: Some(contents_etag("GRUB_TIMEOUT=0\n", &BTreeMap::new())),
+            dropin_values: BTreeMap::new(),
+        };
+        services
+            .state
+            .db
+            .add_pending_operation(
+                pending_operation::SAVE_CONFIG,
+                &serde_json::to_string(&config).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let applied = services.config.apply_pending_operations().await;
+        assert!(matches!(
+            applied.unwrap_err().error(),
+            DErrorType::Conflict(_)
+        ));
+        let pending = services.config.pending_operations().await.unwrap();
+        assert!(pending[0].last_error.is_some());
+        assert_eq!(
+            read_to_string(paths.grub_file()).unwrap(),
+            "GRUB_TIMEOUT=8\n"
+        );
+        remove_dir_all(&root).unwrap();
+    }
+}
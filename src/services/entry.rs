@@ -0,0 +1,775 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fs::{create_dir_all, metadata, read_to_string, write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    db::{audit_log, entry_override::EntryOverride, settings},
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::{
+        bls::{bls_entries, BlsEntry},
+        diff::KeyChange,
+        titles::{localized_title, LocalizedTitle},
+        EntryKind, GrubBootEntries,
+    },
+    initrd::InitrdSummary,
+    services::{
+        job::{command_output, run_blocking, ApplyResult, JobService},
+        AppState,
+    },
+    zypp::{kernel_events, KernelPackageEvent},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootEntryData {
+    entries: Value,
+    selected_kernel: Value,
+    /// Additional information of each entry in `entries`
+    details: Vec<BootEntryDetails>,
+    /// Kernel flavor the default entry is kept on
+    preferred_flavor: Option<String>,
+    /// Menu timeout of systemd-boot, grub keeps it in GRUB_TIMEOUT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    loader_timeout: Option<String>,
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootEntryDetails {
+    pub entry: String,
+    pub full_path: String,
+    pub kernel_version: Option<String>,
+    pub flavor: Option<String>,
+    /// Entry loads CPU microcode from a separate initrd
+    pub microcode: bool,
+    /// Kind of the entry, overridden by the user or detected from grub.cfg
+    pub kind: EntryKind,
+    /// Entry is hidden from frontends
+    pub hidden: bool,
+}
+
+impl BootEntryData {
+    pub fn details(&self) -> &[BootEntryDetails] {
+        &self.details
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EntriesHiddenData {
+    /// Full paths of the entries
+    entries: Vec<String>,
+    hidden: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EntriesKindData {
+    /// Full paths of the entries
+    entries: Vec<String>,
+    /// Kind to show the entries as, `null` uses the detected kind
+    kind: Option<EntryKind>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PreferFlavorData {
+    /// Flavor to prefer, `null` removes the preference
+    flavor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BootOnceData {
+    /// Full path of the entry, or the id of a systemd-boot entry
+    entry: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoaderDefaultData {
+    /// Id of the systemd-boot entry, its file name like `6.17.5-1-default.conf`
+    entry: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoaderTimeoutData {
+    /// Seconds, or `menu-force` or `menu-hidden`
+    timeout: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportBlsData {
+    /// Absolute path of the directory to write the entries to, like `/boot/loader/entries`
+    directory: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportBlsResult {
+    /// Written entry files
+    files: Vec<PathBuf>,
+    /// Grub entries that have no BLS equivalent, like firmware settings or other systems
+    skipped: Vec<String>,
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KernelInitrd {
+    kernel_version: Option<String>,
+    /// Initrd image as referenced in grub.cfg
+    image: String,
+    /// Size of the image in bytes, `null` if the image was not found
+    size: Option<u64>,
+    summary: Option<InitrdSummary>,
+    /// Why the image could not be inspected
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EntryTitlesData {
+    /// Locale to translate the titles to, like `de_DE.UTF-8`
+    locale: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EntryTitle {
+    /// Full path of the entry, the stable id used by the other methods
+    full_path: String,
+    title: LocalizedTitle,
+    /// Display names of the submenus the entry is in, outermost first
+    submenus: Vec<LocalizedTitle>,
+}
+
+/// Event on the boot timeline
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum TimelineEvent {
+    /// Kernel package was installed or removed, adding or removing boot entries
+    KernelPackage(KernelPackageEvent),
+    /// Config was snapshotted
+    Snapshot {
+        time: NaiveDateTime,
+        snapshot_id: i64,
+        applied: bool,
+    },
+    /// Change made through the daemon
+    Audit {
+        time: NaiveDateTime,
+        audit_id: i64,
+        action: String,
+    },
+}
+
+impl TimelineEvent {
+    pub fn time(&self) -> NaiveDateTime {
+        match self {
+            Self::KernelPackage(event) => event.time,
+            Self::Snapshot { time, .. } | Self::Audit { time, .. } => *time,
+        }
+    }
+}
+
+/// Inspected initrd images and their modification times
+type InitrdCache = HashMap<PathBuf, (SystemTime, InitrdSummary)>;
+
+/// Boot entries of the generated grub config
+#[derive(Clone)]
+pub struct EntryService {
+    state: AppState,
+    jobs: JobService,
+    initrds: Arc<Mutex<InitrdCache>>,
+}
+
+impl EntryService {
+    pub fn new(state: AppState, jobs: JobService) -> Self {
+        Self {
+            state,
+            jobs,
+            initrds: Arc::default(),
+        }
+    }
+
+    /// User overrides of the entries by the entry id
+    async fn entry_overrides(&self) -> DResult<HashMap<String, EntryOverride>> {
+        Ok(self
+            .state
+            .db
+            .entry_overrides()
+            .await?
+            .into_iter()
+            .map(|entry_override| (entry_override.entry.clone(), entry_override))
+            .collect())
+    }
+
+    /// Title of the entry booted by default, empty when there are no entries
+    pub async fn default_kernel(&self) -> DResult<String> {
+        let entries = self.clone();
+        run_blocking(move || entries.read_default_kernel()).await
+    }
+
+    fn read_default_kernel(&self) -> DResult<String> {
+        let boot_entries = self
+            .state
+            .bootloader()?
+            .list_entries()
+            .ctx(dctx!(), "Couldn't read kernel entries")?;
+        Ok(boot_entries
+            .default_entry()
+            .or(boot_entries.entries.first())
+            .map(|entry| entry.title.clone())
+            .unwrap_or_default())
+    }
+
+    pub async fn boot_entries(&self) -> DResult<BootEntryData> {
+        let boot_entries = self
+            .state
+            .bootloader()?
+            .list_entries()
+            .ctx(dctx!(), "Couldn't read kernel entries")?;
+        let titles: Vec<_> = boot_entries
+            .entries
+            .iter()
+            .map(|entry| entry.title.as_str())
+            .collect();
+        let entries =
+            serde_json::to_value(titles).ctx(dctx!(), "Cannot trun kernel entries into json")?;
+        let selected_kernel = serde_json::to_value(&boot_entries.selected)
+            .ctx(dctx!(), "Cannot trun kernel entries into json")?;
+        let overrides = self.entry_overrides().await?;
+        let details = boot_entries
+            .entries
+            .iter()
+            .map(|entry| {
+                let entry_override = overrides.get(&entry.id);
+                BootEntryDetails {
+                    entry: entry.title.clone(),
+                    kernel_version: entry.kernel_version.clone(),
+                    flavor: entry.flavor.clone(),
+                    microcode: entry.microcode,
+                    kind: entry_override
+                        .and_then(|entry_override| entry_override.kind.as_deref())
+                        .and_then(EntryKind::from_name)
+                        .unwrap_or(entry.kind),
+                    hidden: entry_override.is_some_and(|entry_override| entry_override.hidden),
+                    full_path: entry.id.clone(),
+                }
+            })
+            .collect();
+
+        let mut warnings = Vec::new();
+        let default_entry = boot_entries
+            .default_entry()
+            .or(boot_entries.entries.first());
+        if let Some(default_entry) = default_entry {
+            if !default_entry.microcode && boot_entries.entries.iter().any(|entry| entry.microcode)
+            {
+                warnings.push(format!(
+                    "Default entry '{}' doesn't load CPU microcode while other entries do",
+                    default_entry.title
+                ));
+            }
+        }
+        if let Some(next_entry) = boot_entries.next_entry() {
+            warnings.push(format!(
+                "One-shot entry '{}' overrides the default entry on the next boot",
+                next_entry.id
+            ));
+        }
+        let preferred_flavor = self.state.db.setting(settings::PREFERRED_FLAVOR).await?;
+
+        Ok(BootEntryData {
+            entries,
+            selected_kernel,
+            details,
+            preferred_flavor,
+            loader_timeout: boot_entries.timeout,
+            warnings,
+        })
+    }
+
+    /// Boot the entry on the next boot only, like a rescue kernel, keeping the
+    /// permanent default entry
+    pub async fn boot_once(&self, once_data: BootOnceData) -> DResult<ApplyResult> {
+        self.state.require_unfrozen().await?;
+        let boot_entries = self.state.bootloader()?.list_entries()?;
+        let entry = &once_data.entry;
+        if boot_entries.entry(entry).is_none() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Boot entry '{entry}' is not found"),
+            ));
+        }
+
+        let _in_flight = self.state.in_flight.start()?;
+        let mut commands = Vec::new();
+        self.jobs
+            .boot_once(entry, &mut commands)
+            .map_err(|err| err.with_commands(&commands))?;
+        let change = KeyChange {
+            key: "next_entry".into(),
+            old: boot_entries.next_entry().map(|entry| entry.id.clone()),
+            new: Some(entry.clone()),
+        };
+        self.state
+            .audit_key_change(audit_log::BOOT_ONCE, change, &commands)
+            .await?;
+        Ok(ApplyResult::applied(commands))
+    }
+
+    /// Common checks of the systemd-boot changes made with bootctl
+    async fn require_loader_changes(&self) -> DResult<()> {
+        self.state.require_systemd_boot()?;
+        self.state.require_unfrozen().await
+    }
+
+    /// Set the default systemd-boot entry with bootctl
+    pub async fn set_loader_default(
+        &self,
+        default_data: LoaderDefaultData,
+    ) -> DResult<ApplyResult> {
+        self.require_loader_changes().await?;
+        let boot_entries = self.state.bootloader()?.list_entries()?;
+        let entry = &default_data.entry;
+        if boot_entries.entry(entry).is_none() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("systemd-boot entry '{entry}' is not found"),
+            ));
+        }
+
+        let _in_flight = self.state.in_flight.start()?;
+        let mut commands = Vec::new();
+        self.jobs
+            .set_default_entry(entry, &mut commands)
+            .map_err(|err| err.with_commands(&commands))?;
+        let change = KeyChange {
+            key: "default".into(),
+            old: boot_entries.selected,
+            new: Some(entry.clone()),
+        };
+        self.state
+            .audit_key_change(audit_log::SET_LOADER_DEFAULT, change, &commands)
+            .await?;
+        Ok(ApplyResult::applied(commands))
+    }
+
+    /// Set the systemd-boot menu timeout with bootctl
+    pub async fn set_loader_timeout(
+        &self,
+        timeout_data: LoaderTimeoutData,
+    ) -> DResult<ApplyResult> {
+        self.require_loader_changes().await?;
+        let old = self.state.bootloader()?.list_entries()?.timeout;
+        let timeout = timeout_data.timeout;
+
+        let _in_flight = self.state.in_flight.start()?;
+        let mut commands = Vec::new();
+        let changes = BTreeMap::from([("timeout".to_string(), timeout.clone())]);
+        self.jobs
+            .apply_config(&changes, &mut commands)
+            .map_err(|err| err.with_commands(&commands))?;
+        let change = KeyChange {
+            key: "timeout".into(),
+            old,
+            new: Some(timeout),
+        };
+        self.state
+            .audit_key_change(audit_log::SET_LOADER_TIMEOUT, change, &commands)
+            .await?;
+        Ok(ApplyResult::applied(commands))
+    }
+
+    /// Contents of the generated grub.cfg
+    pub async fn grub_cfg(&self) -> DResult<String> {
+        let grub_cfg = self.state.paths.grub_cfg().to_owned();
+        run_blocking(move || {
+            read_to_string(&grub_cfg).ctx(dctx!(), format!("Cannot read {grub_cfg:?}"))
+        })
+        .await
+    }
+
+    /// Forget the inspected initrds that have been removed, e.g. with their kernel
+    pub fn prune_initrd_cache(&self) {
+        self.initrds
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain(|path, _| path.exists());
+    }
+
+    /// Inspect the initrd image with lsinitrd, cached until the image changes
+    fn inspect_initrd(&self, path: &Path) -> DResult<InitrdSummary> {
+        let modified = metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ctx(dctx!(), format!("Cannot read metadata of {path:?}"))?;
+        let mut cache = self.initrds.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some((cached_modified, summary)) = cache.get(path) {
+            if *cached_modified == modified {
+                return Ok(summary.clone());
+            }
+        }
+
+        self.state.require_tools(&["lsinitrd"])?;
+        let mut lsinitrd = self.state.paths.command("lsinitrd");
+        lsinitrd.arg(self.state.paths.in_target(path));
+        let output = command_output(lsinitrd)
+            .ctx(dctx!(), format!("Failed to run lsinitrd for {path:?}"))?;
+        if !output.status.success() {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "lsinitrd failed for {path:?}: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+
+        let summary = InitrdSummary::parse(&String::from_utf8_lossy(&output.stdout));
+        cache.insert(path.into(), (modified, summary.clone()));
+        Ok(summary)
+    }
+
+    /// Localized display names of the boot entries
+    pub async fn entry_titles(&self, titles_data: EntryTitlesData) -> DResult<Vec<EntryTitle>> {
+        let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+        let locale = &titles_data.locale;
+        Ok(grub_entries
+            .entries()
+            .iter()
+            .map(|entry| EntryTitle {
+                full_path: entry.full_path(),
+                title: localized_title(entry.entry(), locale),
+                submenus: entry
+                    .submenus()
+                    .iter()
+                    .map(|submenu| localized_title(submenu, locale))
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// Kernel package transactions, snapshots and changes made through the daemon,
+    /// newest first. All the times are in UTC.
+    pub async fn timeline(&self) -> DResult<Vec<TimelineEvent>> {
+        let mut timeline = Vec::new();
+
+        let history_path = self.state.paths.zypp_history().to_owned();
+        let history = run_blocking(move || {
+            if !history_path.exists() {
+                log::debug!("No zypp history in {history_path:?}");
+                return Ok(None);
+            }
+            read_to_string(&history_path)
+                .map(Some)
+                .ctx(dctx!(), format!("Cannot read {history_path:?}"))
+        })
+        .await?;
+        if let Some(history) = history {
+            // zypp writes local times while the database has UTC times
+            timeline.extend(kernel_events(&history).into_iter().map(|mut event| {
+                if let Some(time) = Local.from_local_datetime(&event.time).earliest() {
+                    event.time = time.naive_utc();
+                }
+                TimelineEvent::KernelPackage(event)
+            }));
+        }
+
+        let db = &self.state.db;
+        timeline.extend(db.grub2_snapshots().await?.into_iter().map(|snapshot| {
+            TimelineEvent::Snapshot {
+                time: snapshot.created,
+                snapshot_id: snapshot.id,
+                applied: snapshot.applied,
+            }
+        }));
+        timeline.extend(
+            db.audit_entries()
+                .await?
+                .into_iter()
+                .map(|entry| TimelineEvent::Audit {
+                    time: entry.created,
+                    audit_id: entry.id,
+                    action: entry.action,
+                }),
+        );
+
+        timeline.sort_by_key(|event| Reverse(event.time()));
+        Ok(timeline)
+    }
+
+    /// Contents of the initrd of each installed kernel
+    pub async fn initrd_summaries(&self) -> DResult<Vec<KernelInitrd>> {
+        // lsinitrd takes a while for each initrd
+        let entries = self.clone();
+        run_blocking(move || entries.read_initrd_summaries()).await
+    }
+
+    fn read_initrd_summaries(&self) -> DResult<Vec<KernelInitrd>> {
+        let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+        let mut initrds: Vec<KernelInitrd> = Vec::new();
+        for entry in grub_entries.entries() {
+            let Some(image) = entry.initrd() else {
+                continue;
+            };
+            if initrds.iter().any(|initrd| initrd.image == image) {
+                continue;
+            }
+
+            let mut initrd = KernelInitrd {
+                kernel_version: entry.kernel_version().map(str::to_string),
+                image: image.into(),
+                size: None,
+                summary: None,
+                error: None,
+            };
+            match self.state.paths.boot_file(image) {
+                Some(path) => {
+                    initrd.size = metadata(&path).ok().map(|metadata| metadata.len());
+                    match self.inspect_initrd(&path) {
+                        Ok(summary) => initrd.summary = Some(summary),
+                        Err(err) => initrd.error = Some(err.error().as_string()),
+                    }
+                }
+                None => initrd.error = Some(format!("Initrd image {image} was not found")),
+            }
+            initrds.push(initrd);
+        }
+
+        Ok(initrds)
+    }
+
+    /// Make sure all the given entries exist so typos don't create dangling overrides
+    fn check_entries_exist(&self, entries: &[String]) -> DResult<()> {
+        let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+        for entry in entries {
+            if !grub_entries
+                .entries()
+                .iter()
+                .any(|grub_entry| &grub_entry.full_path() == entry)
+            {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Boot entry '{entry}' is not found from grub configs"),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hide or show entries in frontends without touching grub.cfg
+    pub async fn set_entries_hidden(&self, hidden_data: EntriesHiddenData) -> DResult<()> {
+        self.state.require_unfrozen().await?;
+        self.check_entries_exist(&hidden_data.entries)?;
+        let overrides = self.entry_overrides().await?;
+        self.state
+            .db
+            .set_entries_hidden(&hidden_data.entries, hidden_data.hidden)
+            .await?;
+
+        let changes: Vec<KeyChange> = hidden_data
+            .entries
+            .iter()
+            .map(|entry| KeyChange {
+                key: entry.clone(),
+                old: Some(
+                    overrides
+                        .get(entry)
+                        .is_some_and(|entry_override| entry_override.hidden)
+                        .to_string(),
+                ),
+                new: Some(hidden_data.hidden.to_string()),
+            })
+            .collect();
+        self.state
+            .audit_key_changes(audit_log::SET_ENTRIES_HIDDEN, &changes, &[])
+            .await
+    }
+
+    /// Override the detected kind of the entries
+    pub async fn set_entries_kind(&self, kind_data: EntriesKindData) -> DResult<()> {
+        self.state.require_unfrozen().await?;
+        self.check_entries_exist(&kind_data.entries)?;
+        let overrides = self.entry_overrides().await?;
+        let kind = kind_data.kind.map(|kind| kind.name());
+        self.state
+            .db
+            .set_entries_kind(&kind_data.entries, kind)
+            .await?;
+
+        // `None` is the detected kind
+        let changes: Vec<KeyChange> = kind_data
+            .entries
+            .iter()
+            .map(|entry| KeyChange {
+                key: entry.clone(),
+                old: overrides
+                    .get(entry)
+                    .and_then(|entry_override| entry_override.kind.clone()),
+                new: kind.map(str::to_string),
+            })
+            .collect();
+        self.state
+            .audit_key_changes(audit_log::SET_ENTRIES_KIND, &changes, &[])
+            .await
+    }
+
+    /// Write the Linux boot entries as Boot Loader Specification entry files.
+    ///
+    /// Existing files are never overwritten so an export cannot break entries of an
+    /// already installed systemd-boot.
+    pub async fn export_bls(&self, export_data: ExportBlsData) -> DResult<ExportBlsResult> {
+        self.state.require_unfrozen().await?;
+        let directory = export_data.directory;
+        if !directory.is_absolute() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Export directory {directory:?} must be an absolute path"),
+            ));
+        }
+
+        let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+        let bls = bls_entries(grub_entries.entries());
+        if let Some(existing) = bls
+            .iter()
+            .map(|entry| directory.join(&entry.file_name))
+            .find(|path| path.exists())
+        {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Cannot overwrite existing boot entry {existing:?}"),
+            ));
+        }
+
+        let mut warnings = Vec::new();
+
+        create_dir_all(&directory).ctx(dctx!(), format!("Cannot create {directory:?}"))?;
+        let mut files = Vec::new();
+        for entry in &bls {
+            if entry.uses_grub_variables() {
+                warnings.push(format!(
+                    "Entry '{}' uses grub variables that must be replaced by hand",
+                    entry.source
+                ));
+            }
+
+            let path = directory.join(&entry.file_name);
+            write(&path, &entry.contents).ctx(dctx!(), format!("Cannot write {path:?}"))?;
+            log::info!("Exported boot entry '{}' to {path:?}", entry.source);
+            files.push(path);
+        }
+
+        let skipped = grub_entries
+            .entries()
+            .iter()
+            .filter(|entry| BlsEntry::from_grub(entry).is_none())
+            .map(|entry| entry.full_path())
+            .collect();
+
+        Ok(ExportBlsResult {
+            files,
+            skipped,
+            warnings,
+        })
+    }
+
+    /// Keep the default boot entry on the given kernel flavor, even after kernel updates
+    pub async fn prefer_flavor(&self, prefer_data: PreferFlavorData) -> DResult<ApplyResult> {
+        self.state.require_unfrozen().await?;
+        if let Some(flavor) = &prefer_data.flavor {
+            let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+            if grub_entries.newest_of_flavor(flavor).is_none() {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("No boot entries with kernel flavor '{flavor}' found"),
+                ));
+            }
+        }
+
+        let previous = self.state.db.setting(settings::PREFERRED_FLAVOR).await?;
+        self.state
+            .db
+            .set_setting(settings::PREFERRED_FLAVOR, prefer_data.flavor.as_deref())
+            .await?;
+        let change = KeyChange {
+            key: settings::PREFERRED_FLAVOR.into(),
+            old: previous,
+            new: prefer_data.flavor,
+        };
+        self.state
+            .audit_key_change(audit_log::PREFER_FLAVOR, change, &[])
+            .await?;
+        self.enforce_preferred_flavor().await
+    }
+
+    /// Make sure the default boot entry uses the preferred kernel flavor, if one is set.
+    ///
+    /// Kernel updates remove the entry that was saved as default, which makes grub fall
+    /// back to the first entry that might be a different flavor.
+    pub async fn enforce_preferred_flavor(&self) -> DResult<ApplyResult> {
+        let mut result = ApplyResult::default();
+        let Some(flavor) = self.state.db.setting(settings::PREFERRED_FLAVOR).await? else {
+            return Ok(result);
+        };
+        if self.state.freeze().await?.is_some() {
+            log::info!("Not enforcing preferred kernel flavor '{flavor}' during a change freeze");
+            return Ok(result);
+        }
+
+        let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+        if grub_entries
+            .selected_entry()
+            .is_some_and(|entry| entry.flavor() == Some(flavor.as_str()))
+        {
+            log::debug!("Default boot entry already uses preferred kernel flavor '{flavor}'");
+            return Ok(result);
+        }
+
+        let Some(entry) = grub_entries.newest_of_flavor(&flavor) else {
+            log::warn!("No boot entries with preferred kernel flavor '{flavor}' found");
+            return Ok(result);
+        };
+
+        log::info!(
+            "Setting '{}' as default to keep preferred kernel flavor '{flavor}'",
+            entry.entry()
+        );
+        let _in_flight = self.state.in_flight.start()?;
+        self.jobs
+            .set_default_entry(&entry.full_path(), &mut result.commands)
+            .map_err(|err| err.with_commands(&result.commands))?;
+        Ok(result)
+    }
+
+    /// Explicitly selecting a kernel of another flavor overrides the flavor preference
+    pub async fn drop_conflicting_flavor(&self, selected_kernel: &Option<String>) -> DResult<()> {
+        let Some(kernel) = selected_kernel else {
+            return Ok(());
+        };
+        let Some(flavor) = self.state.db.setting(settings::PREFERRED_FLAVOR).await? else {
+            return Ok(());
+        };
+
+        let grub_entries = GrubBootEntries::new(&self.state.paths)?;
+        let selected_flavor = grub_entries
+            .entries()
+            .iter()
+            .find(|entry| entry.entry() == kernel)
+            .and_then(|entry| entry.flavor());
+
+        if selected_flavor != Some(flavor.as_str()) {
+            log::info!(
+                "Selected kernel '{kernel}' is not '{flavor}' flavor, removing flavor preference"
+            );
+            self.state
+                .db
+                .set_setting(settings::PREFERRED_FLAVOR, None)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
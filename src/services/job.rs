@@ -0,0 +1,981 @@
+use std::{
+    collections::BTreeMap,
+    fs::read_to_string,
+    future::Future,
+    os::unix::process::CommandExt,
+    process::{Child, Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc::UnboundedSender, task::spawn_blocking};
+
+use crate::{
+    bootloader::grub2::Grub2,
+    config::{tools::GrubTool, DEFAULT_COMMAND_TIMEOUT_SECS},
+    dctx,
+    errors::{DError, DErrorType, DRes, DResult},
+    grub2::{
+        grub_env_value,
+        params::{DangerousParam, DeviceProblem},
+        GrubBootEntries, GrubFile,
+    },
+    services::AppState,
+};
+
+/// Seconds a command may run before it's killed, 0 for no limit
+static COMMAND_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(DEFAULT_COMMAND_TIMEOUT_SECS);
+
+/// How long the output of a killed command is still waited for. A process that
+/// left its process group survives the kill and can keep the pipes open.
+const KILLED_OUTPUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Files besides the grub file that a snapshot restores
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotFiles {
+    /// Raw saved_entry of grubenv, set when the snapshot has no selected kernel
+    pub saved_entry: Option<String>,
+    /// Drop-in configs by file name
+    pub dropins: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApplyOptions {
+    /// Refuse, and revert, changes that would remove the current default boot entry
+    #[serde(default)]
+    safe_mode: bool,
+    /// Set the current default boot entry as the grub fallback entry,
+    /// so it's booted if the new default fails
+    #[serde(default)]
+    set_fallback: bool,
+    /// Apply even if dangerous kernel parameters are added or modified
+    #[serde(default)]
+    pub acknowledge_dangerous: bool,
+    /// Apply even if root= or resume= reference unknown devices, or root= is not
+    /// the current root filesystem
+    #[serde(default)]
+    pub force_devices: bool,
+    /// Labels of the snapshot the applied config is saved as
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+/// Command that was run while applying changes, so it can be reproduced manually
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutedCommand {
+    /// Command line as it would be typed in a shell
+    command: String,
+    /// `None` if the command was terminated by a signal
+    exit_code: Option<i32>,
+    duration_ms: u64,
+}
+
+impl ExecutedCommand {
+    /// The command exited with 0
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    fn status(&self) -> String {
+        match self.exit_code {
+            Some(code) => format!("exited with {code}"),
+            None => "was terminated by a signal".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ExecutedCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({} after {} ms)",
+            self.command,
+            self.status(),
+            self.duration_ms
+        )
+    }
+}
+
+/// Result of an operation that only applies changes to the system
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ApplyResult {
+    pub commands: Vec<ExecutedCommand>,
+    /// Id of the pending operation if the changes were queued instead of applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_operation: Option<i64>,
+    /// Dangerous kernel parameters that must be acknowledged, nothing was applied if set
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dangerous_params: Vec<DangerousParam>,
+    /// Device parameters that failed validation, nothing was applied if set
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub device_problems: Vec<DeviceProblem>,
+}
+
+impl ApplyResult {
+    pub fn applied(commands: Vec<ExecutedCommand>) -> Self {
+        Self {
+            commands,
+            ..Default::default()
+        }
+    }
+
+    pub fn pending(id: i64) -> Self {
+        Self {
+            pending_operation: Some(id),
+            ..Default::default()
+        }
+    }
+
+    /// Nothing was applied because of unacknowledged dangerous parameters or
+    /// invalid devices
+    pub fn refused(
+        dangerous_params: Vec<DangerousParam>,
+        device_problems: Vec<DeviceProblem>,
+    ) -> Self {
+        Self {
+            dangerous_params,
+            device_problems,
+            ..Default::default()
+        }
+    }
+}
+
+/// Stage of applying changes, failures can be simulated on dev builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApplyStage {
+    /// Writing /etc/default/grub
+    Write,
+    /// Regenerating grub.cfg
+    Mkconfig,
+    /// Setting or unsetting the default entry
+    SetDefault,
+    /// Checking the result after grub.cfg is regenerated
+    Verification,
+}
+
+impl ApplyStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Write => "write",
+            Self::Mkconfig => "mkconfig",
+            Self::SetDefault => "set-default",
+            Self::Verification => "verification",
+        }
+    }
+}
+
+/// Whether an apply running as a background job can still be cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobPhase {
+    /// Cancellable, `command` is the process id of the command that is killed
+    /// when the job is cancelled
+    Running {
+        command: Option<u32>,
+    },
+    Cancelled,
+    /// grub.cfg was regenerated, the changes are kept
+    Committed,
+}
+
+/// Progress and cancellation of an apply running as a background job
+pub struct JobControl {
+    progress: UnboundedSender<ApplyStage>,
+    phase: Mutex<JobPhase>,
+}
+
+impl JobControl {
+    pub fn new(progress: UnboundedSender<ApplyStage>) -> Self {
+        Self {
+            progress,
+            phase: Mutex::new(JobPhase::Running { command: None }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, JobPhase> {
+        self.phase.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.lock() == JobPhase::Cancelled
+    }
+
+    /// Cancel the job, killing the running grub2-mkconfig with the processes it started. The apply restores
+    /// the previous config once it notices the cancellation.
+    pub fn cancel(&self) -> DResult<()> {
+        let mut phase = self.lock();
+        match *phase {
+            JobPhase::Running { command } => {
+                if let Some(pid) = command {
+                    log::info!("Killing process {pid} of the cancelled job");
+                    // the process may have just exited, the apply reverts either way
+                    if let Err(err) = killpg(Pid::from_raw(pid as i32), Signal::SIGTERM) {
+                        log::warn!("Cannot kill process {pid}: {err}");
+                    }
+                }
+                *phase = JobPhase::Cancelled;
+                Ok(())
+            }
+            JobPhase::Cancelled => Ok(()),
+            JobPhase::Committed => Err(DError::generic(
+                dctx!(),
+                "grub.cfg was already regenerated, the job cannot be cancelled anymore",
+            )),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Background job the apply running in the current task belongs to
+    static JOB: Arc<JobControl>;
+}
+
+/// Run `future` as the background job controlled by `control`
+pub async fn with_job<F: Future>(control: Arc<JobControl>, future: F) -> F::Output {
+    JOB.scope(control, future).await
+}
+
+fn report_stage(stage: ApplyStage) {
+    // nobody is listening unless the apply runs as a background job
+    let _ = JOB.try_with(|job| job.progress.send(stage));
+}
+
+/// Run blocking file access and commands on the blocking thread pool, so other
+/// D-Bus calls are served meanwhile. `f` stays part of the background job the
+/// current task belongs to.
+pub async fn run_blocking<T, F>(f: F) -> DResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> DResult<T> + Send + 'static,
+{
+    let job = JOB.try_with(Arc::clone).ok();
+    spawn_blocking(move || match job {
+        Some(job) => JOB.sync_scope(job, f),
+        None => f(),
+    })
+    .await
+    .map_err(|err| DError::generic(dctx!(), format!("Blocking task failed: {err}")))?
+}
+
+/// Keep the changes of the current background job, `false` if it was cancelled
+fn commit_job() -> bool {
+    JOB.try_with(|job| {
+        let mut phase = job.lock();
+        if *phase == JobPhase::Cancelled {
+            return false;
+        }
+        *phase = JobPhase::Committed;
+        true
+    })
+    .unwrap_or(true)
+}
+
+#[cfg(feature = "dev")]
+#[derive(Debug, Deserialize)]
+pub struct SimulateFailureData {
+    pub stage: ApplyStage,
+}
+
+/// Quote `arg` for a shell if needed
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Fail if the last command in `commands` didn't exit with 0
+fn require_success(commands: &[ExecutedCommand]) -> DResult<()> {
+    let Some(command) = commands.last().filter(|command| !command.succeeded()) else {
+        return Ok(());
+    };
+    Err(DError::generic(
+        dctx!(),
+        format!("{} {}", command.command, command.status()),
+    ))
+}
+
+/// Log the output of a finished command and record it to `commands`
+fn record_output(
+    program: &str,
+    command_line: String,
+    started: Instant,
+    output: Output,
+    commands: &mut Vec<ExecutedCommand>,
+) {
+    let duration = started.elapsed();
+
+    log::debug!(
+        "{program} stdout: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    log::debug!(
+        "{program} stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    if !output.status.success() {
+        log::warn!("{command_line} exited with {}", output.status);
+    }
+
+    log::debug!("Calling {command_line}, done");
+    commands.push(ExecutedCommand {
+        command: command_line,
+        exit_code: output.status.code(),
+        duration_ms: duration.as_millis() as u64,
+    });
+}
+
+/// How long a command may run before it's killed, `None` if there's no limit
+fn command_timeout() -> Option<Duration> {
+    match COMMAND_TIMEOUT_SECS.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Kill commands that run longer than `secs` seconds, 0 lets them run forever
+pub fn set_command_timeout(secs: u64) {
+    COMMAND_TIMEOUT_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// Start `command` in its own process group, so the processes it starts, like
+/// os-prober, are killed with it
+fn spawn_command(command: &mut Command, program: &str) -> DResult<Child> {
+    command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .ctx(dctx!(), format!("Failed to start {program}"))
+}
+
+/// Wait for the output of `child`, killing it if it runs longer than `timeout`
+fn wait_output(child: Child, program: &str, timeout: Option<Duration>) -> DResult<Output> {
+    let pid = child.id();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    // output is read while waiting, so the command doesn't block on full pipes
+    std::thread::spawn(move || {
+        let _ = sender.send(child.wait_with_output());
+    });
+
+    let output = match timeout {
+        Some(timeout) => match receiver.recv_timeout(timeout) {
+            Ok(output) => output,
+            Err(RecvTimeoutError::Timeout) => {
+                log::warn!("Killing {program} that did not finish in {timeout:?}");
+                if let Err(err) = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL) {
+                    log::warn!("Cannot kill process {pid}: {err}");
+                }
+                // reaped by the waiting thread, which is left to finish on its own
+                // if the pipes stay open
+                if receiver.recv_timeout(KILLED_OUTPUT_TIMEOUT).is_err() {
+                    log::warn!("Not waiting for the output of the killed {program} anymore");
+                }
+                return Err(DError::generic(
+                    dctx!(),
+                    format!(
+                        "{program} did not finish in {} seconds and was killed",
+                        timeout.as_secs()
+                    ),
+                ));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Lost the output of {program}"),
+                ))
+            }
+        },
+        None => receiver
+            .recv()
+            .map_err(|_| DError::generic(dctx!(), format!("Lost the output of {program}")))?,
+    };
+    output.ctx(dctx!(), format!("Failed to read output from {program}"))
+}
+
+/// Output of `command`, which is killed if it runs longer than the command timeout
+pub fn command_output(mut command: Command) -> DResult<Output> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let child = spawn_command(&mut command, &program)?;
+    wait_output(child, &program, command_timeout())
+}
+
+/// Run `command` and record it to `commands`, failing if it doesn't exit with 0
+pub fn run_command(command: Command, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+    run_command_unchecked(command, commands)?;
+    require_success(commands)
+}
+
+/// Same as `run_command`, but exiting with an error is not a failure, for
+/// commands that report their result with the exit code. It's the exit code of
+/// the last command in `commands`.
+pub fn run_command_unchecked(command: Command, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let command_line = command_line(&command);
+    log::debug!("Calling {command_line}");
+
+    let started = Instant::now();
+    let output = command_output(command)?;
+    record_output(&program, command_line, started, output, commands);
+    Ok(())
+}
+
+/// Same as `run_command`, but the command is killed if the background job the
+/// current task belongs to is cancelled. Not run at all if it already was, and
+/// run like `run_command` once the job can't be cancelled anymore.
+pub fn run_cancellable_command(
+    mut command: Command,
+    commands: &mut Vec<ExecutedCommand>,
+) -> DResult<()> {
+    let Ok(job) = JOB.try_with(Arc::clone) else {
+        return run_command(command, commands);
+    };
+
+    let program = command.get_program().to_string_lossy().to_string();
+    let command_line = command_line(&command);
+    log::debug!("Calling {command_line}");
+
+    let started = Instant::now();
+    let child = {
+        let mut phase = job.lock();
+        match *phase {
+            JobPhase::Running { .. } => {}
+            JobPhase::Cancelled => {
+                log::debug!("Job was cancelled, not calling {command_line}");
+                return Ok(());
+            }
+            // like a revert after the new grub.cfg failed the safe mode check
+            JobPhase::Committed => {
+                drop(phase);
+                return run_command(command, commands);
+            }
+        }
+        let child = spawn_command(&mut command, &program)?;
+        *phase = JobPhase::Running {
+            command: Some(child.id()),
+        };
+        child
+    };
+    let output = wait_output(child, &program, command_timeout());
+    {
+        let mut phase = job.lock();
+        if let JobPhase::Running { command } = &mut *phase {
+            *command = None;
+        }
+    }
+    record_output(&program, command_line, started, output?, commands);
+    require_success(commands)
+}
+
+/// Runs the operations that modify the bootloader of the system
+#[derive(Clone)]
+pub struct JobService {
+    state: AppState,
+    /// The grub config is applied in stages that the other bootloaders don't have
+    grub: Arc<Grub2>,
+    /// Stage where the next apply fails
+    #[cfg(feature = "dev")]
+    simulated_failure: Arc<Mutex<Option<ApplyStage>>>,
+}
+
+impl JobService {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            grub: Arc::new(Grub2::new(state.paths.clone())),
+            state,
+            #[cfg(feature = "dev")]
+            simulated_failure: Arc::default(),
+        }
+    }
+
+    /// Make the next apply fail at `stage`
+    #[cfg(feature = "dev")]
+    pub fn simulate_failure(&self, stage: ApplyStage) {
+        log::warn!("Next apply fails at {} stage", stage.name());
+        *self
+            .simulated_failure
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Some(stage);
+    }
+
+    /// Should the apply fail at `stage`, the simulated failure is cleared once it's hit
+    #[cfg(feature = "dev")]
+    fn simulated_failure(&self, stage: ApplyStage) -> bool {
+        let mut simulated = self
+            .simulated_failure
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if *simulated == Some(stage) {
+            *simulated = None;
+            return true;
+        }
+
+        false
+    }
+
+    #[cfg(not(feature = "dev"))]
+    fn simulated_failure(&self, _stage: ApplyStage) -> bool {
+        false
+    }
+
+    /// Report the progress to `stage`, failing there if a failure is simulated
+    fn start_stage(&self, stage: ApplyStage) -> DResult<()> {
+        report_stage(stage);
+        if self.simulated_failure(stage) {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Simulated failure at {} stage", stage.name()),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Make the entry with the id `entry` the default of the active bootloader
+    pub fn set_default_entry(
+        &self,
+        entry: &str,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        let bootloader = self.state.bootloader()?;
+        self.state.require_tools(&bootloader.tools())?;
+        bootloader.set_default(entry, commands)
+    }
+
+    /// Boot the entry with the id `entry` of the active bootloader on the next boot only
+    pub fn boot_once(&self, entry: &str, commands: &mut Vec<ExecutedCommand>) -> DResult<()> {
+        let bootloader = self.state.bootloader()?;
+        self.state.require_tools(&bootloader.tools())?;
+        bootloader.boot_once(entry, commands)
+    }
+
+    /// Change settings of the active bootloader and update the files it boots from
+    pub fn apply_config(
+        &self,
+        changes: &BTreeMap<String, String>,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        let bootloader = self.state.bootloader()?;
+        self.state.require_tools(&bootloader.tools())?;
+        log::debug!(
+            "Applying {} changed settings to {}",
+            changes.len(),
+            bootloader.backend()
+        );
+        bootloader.apply(changes, commands)
+    }
+
+    /// Hex SHA-256 of the generated grub.cfg, `None` if it doesn't exist
+    pub fn grub_cfg_sha256(&self) -> DResult<Option<String>> {
+        self.grub.grub_cfg_sha256()
+    }
+
+    /// Drop-in configs of grub2-mkconfig by file name
+    pub fn read_dropins(&self) -> DResult<BTreeMap<String, String>> {
+        self.grub.read_dropins()
+    }
+
+    /// saved_entry of grubenv, `None` if it's not set
+    fn saved_entry(&self) -> Option<String> {
+        let grub_env = read_to_string(self.state.paths.grub_env()).unwrap_or_default();
+        grub_env_value(&grub_env, "saved_entry").map(str::to_string)
+    }
+
+    /// Put back the previous config, saved_entry and drop-in configs, and
+    /// regenerate grub.cfg from them
+    fn revert(
+        &self,
+        previous_config: &str,
+        previous_saved_entry: Option<&str>,
+        previous_dropins: Option<&BTreeMap<String, String>>,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        self.restore(
+            previous_config,
+            previous_saved_entry,
+            previous_dropins,
+            commands,
+        )?;
+        self.grub.replace_cfg(commands)
+    }
+
+    /// Put back the files that were changed before the new grub.cfg was
+    /// generated, when it doesn't replace the current one
+    fn restore(
+        &self,
+        previous_config: &str,
+        previous_saved_entry: Option<&str>,
+        previous_dropins: Option<&BTreeMap<String, String>>,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        self.grub.discard_new_cfg();
+        self.grub.restore_config(previous_config)?;
+        self.restore_unwritten(previous_saved_entry, previous_dropins, commands)
+    }
+
+    /// Put back the drop-in configs and saved_entry, which are changed before
+    /// the grub file is written
+    fn restore_unwritten(
+        &self,
+        previous_saved_entry: Option<&str>,
+        previous_dropins: Option<&BTreeMap<String, String>>,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        if let Some(previous_dropins) = previous_dropins {
+            self.grub.write_dropins(previous_dropins)?;
+        }
+        // the default entry may have been changed before the failure
+        match previous_saved_entry {
+            Some(entry) => self
+                .grub
+                .edit_env(&["set", &format!("saved_entry={entry}")], commands)?,
+            None => self.grub.edit_env(&["unset", "saved_entry"], commands)?,
+        }
+
+        Ok(())
+    }
+
+    /// Write the grub config, set the default kernel and regenerate grub.cfg.
+    /// `files` of a snapshot are restored with the config.
+    pub async fn set_grub_system(
+        &self,
+        grub_file: &mut GrubFile,
+        selected_kernel: &Option<String>,
+        from_snapshot: bool,
+        files: Option<&SnapshotFiles>,
+        options: &ApplyOptions,
+    ) -> DResult<Vec<ExecutedCommand>> {
+        self.state.require_grub2()?;
+        self.state.require_tools(
+            &[
+                GrubTool::Mkconfig,
+                GrubTool::SetDefault,
+                GrubTool::Editenv,
+                GrubTool::ScriptCheck,
+            ]
+            .map(|tool| self.grub.tool(tool)),
+        )?;
+        self.state.require_unfrozen().await?;
+        let _in_flight = self.state.in_flight.start()?;
+
+        let service = self.clone();
+        let mut grub = grub_file.clone();
+        let selected_kernel = selected_kernel.clone();
+        let files = files.cloned();
+        let options = options.clone();
+        let (commands, grub) = run_blocking(move || {
+            let mut commands = Vec::new();
+            service
+                .apply_grub_system(
+                    &mut grub,
+                    &selected_kernel,
+                    from_snapshot,
+                    files.as_ref(),
+                    &options,
+                    &mut commands,
+                )
+                .map_err(|err| err.with_commands(&commands))?;
+            Ok((commands, grub))
+        })
+        .await?;
+        *grub_file = grub;
+        Ok(commands)
+    }
+
+    /// Blocking part of `set_grub_system`, run once it's allowed to change the
+    /// system. The commands it runs are recorded to `commands`, also when it fails.
+    fn apply_grub_system(
+        &self,
+        grub_file: &mut GrubFile,
+        selected_kernel: &Option<String>,
+        from_snapshot: bool,
+        files: Option<&SnapshotFiles>,
+        options: &ApplyOptions,
+        commands: &mut Vec<ExecutedCommand>,
+    ) -> DResult<()> {
+        let paths = &self.state.paths;
+        // Entry that is booted by default before applying the changes
+        let previous_entry = if options.safe_mode || options.set_fallback {
+            let kernel_entries = GrubBootEntries::new(paths)?;
+            kernel_entries
+                .selected_entry()
+                .or(kernel_entries.entries().first())
+                .map(|entry| entry.full_path())
+        } else {
+            None
+        };
+        // restored whenever the changes are reverted
+        let previous_saved_entry = self.saved_entry();
+        let previous_config =
+            read_to_string(paths.grub_file()).ctx(dctx!(), "Failed to read current grub config")?;
+        let previous_dropins = match files {
+            Some(_) => Some(self.grub.read_dropins()?),
+            None => None,
+        };
+
+        if let Some(kernel) = &selected_kernel {
+            let kernel_entries = GrubBootEntries::new(paths)?;
+            let kernel_entry = if let Some(entry) = kernel_entries
+                .entries()
+                .iter()
+                .find(|entry| entry.entry() == kernel)
+            {
+                entry.full_path()
+            } else {
+                return Err(DError::new(
+                    dctx!(),
+                    DErrorType::Error(format!(
+                        "Kernel entry '{kernel}' is not found from grub configs"
+                    )),
+                ));
+            };
+
+            self.start_stage(ApplyStage::SetDefault)?;
+            self.set_default_entry(&kernel_entry, commands)?;
+
+            // Only update grub file when selecting a snapshot
+            // old snapshots should always be set back the way they were
+            if !from_snapshot {
+                // make sure GRUB_DEFAULT is set to saved as it's required by grub
+                grub_file.set_key_value("GRUB_DEFAULT", "saved");
+            }
+        } else if let Some(saved_entry) = files.and_then(|files| files.saved_entry.as_deref()) {
+            // the entry may not exist anymore, but grub falls back to the first one
+            log::debug!("Restoring saved_entry '{saved_entry}' of the snapshot");
+            self.start_stage(ApplyStage::SetDefault)?;
+            self.grub
+                .edit_env(&["set", &format!("saved_entry={saved_entry}")], commands)?;
+        } else {
+            log::debug!("Removing default seleceted kernel");
+            self.start_stage(ApplyStage::SetDefault)?;
+            // grub2-editenv /boot/grub2/grubenv unset saved_entry
+            self.grub.edit_env(&["unset", "saved_entry"], commands)?;
+            log::debug!("Removing default seleceted kernel done");
+        }
+
+        let written = self.start_stage(ApplyStage::Write).and_then(|()| {
+            if let Some(files) = files {
+                self.grub.write_dropins(&files.dropins)?;
+            }
+            self.grub
+                .write_config(&grub_file.as_string(), &previous_config)
+        });
+        if let Err(err) = written {
+            // the grub file may have been changed by another program, it's kept
+            log::warn!("Grub config was not written, restoring the default entry and drop-ins");
+            self.restore_unwritten(
+                previous_saved_entry.as_deref(),
+                previous_dropins.as_ref(),
+                commands,
+            )?;
+            return Err(err);
+        }
+        self.start_stage(ApplyStage::Mkconfig)?;
+        // grub.cfg is generated next to the current one, which is only
+        // replaced once the new one is verified
+        let generated = self.grub.mkconfig_new(commands);
+        if !commit_job() {
+            log::info!("Apply was cancelled, restoring the previous config");
+            self.restore(
+                &previous_config,
+                previous_saved_entry.as_deref(),
+                previous_dropins.as_ref(),
+                commands,
+            )?;
+            return Err(DError::generic(
+                dctx!(),
+                "Apply was cancelled. The changes were reverted",
+            ));
+        }
+
+        report_stage(ApplyStage::Verification);
+        let verified = match generated {
+            Ok(()) if self.simulated_failure(ApplyStage::Verification) => {
+                Ok(Some("Simulated failure at verification stage".to_string()))
+            }
+            Ok(()) => self.grub.verify_new_cfg(commands),
+            Err(err) => Err(err),
+        };
+        let installed = match verified {
+            Ok(None) => self.grub.install_new_cfg(),
+            Ok(Some(problem)) => Err(DError::generic(
+                dctx!(),
+                format!("{problem}. The changes were reverted"),
+            )),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = installed {
+            log::warn!("grub.cfg was not replaced, restoring the previous config");
+            self.restore(
+                &previous_config,
+                previous_saved_entry.as_deref(),
+                previous_dropins.as_ref(),
+                commands,
+            )?;
+            return Err(err);
+        }
+
+        let Some(previous_entry) = previous_entry else {
+            return Ok(());
+        };
+
+        if options.safe_mode {
+            let kernel_entries = GrubBootEntries::new(paths)?;
+            if !kernel_entries
+                .entries()
+                .iter()
+                .any(|entry| entry.full_path() == previous_entry)
+            {
+                log::warn!("Safe mode: previous default entry '{previous_entry}' was removed by the changes, reverting");
+                self.revert(
+                    &previous_config,
+                    previous_saved_entry.as_deref(),
+                    previous_dropins.as_ref(),
+                    commands,
+                )?;
+
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Changes would remove the previous default boot entry '{previous_entry}'. The changes were reverted"),
+                ));
+            }
+        }
+
+        if options.set_fallback {
+            log::debug!("Setting '{previous_entry}' as fallback boot entry");
+            self.grub
+                .edit_env(&["set", &format!("fallback={previous_entry}")], commands)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        let mut command = Command::new("grub2-set-default");
+        command.arg("Advanced options for openSUSE>openSUSE, with Linux 6.4.0");
+        assert_eq!(
+            command_line(&command),
+            "grub2-set-default 'Advanced options for openSUSE>openSUSE, with Linux 6.4.0'"
+        );
+
+        let mut command = Command::new("grub2-editenv");
+        command.args(["/boot/grub2/grubenv", "set", "fallback=it's"]);
+        assert_eq!(
+            command_line(&command),
+            "grub2-editenv /boot/grub2/grubenv set 'fallback=it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn test_run_command_exit_code() {
+        let mut commands = Vec::new();
+        assert!(run_command(Command::new("true"), &mut commands).is_ok());
+        assert!(run_command(Command::new("false"), &mut commands).is_err());
+        assert_eq!(commands.len(), 2, "failed commands are recorded too");
+
+        assert!(run_command_unchecked(Command::new("false"), &mut commands).is_ok());
+        assert!(!commands[2].succeeded());
+    }
+
+    #[test]
+    fn test_error_commands() {
+        let mut commands = Vec::new();
+        run_command(Command::new("true"), &mut commands).unwrap();
+        let err = run_command(Command::new("false"), &mut commands)
+            .map_err(|err| err.with_commands(&commands))
+            .unwrap_err();
+        assert_eq!(err.commands().len(), 2);
+        let message = err.message();
+        assert!(message.contains("\ntrue (exited with 0 after "));
+        assert!(message.contains("\nfalse (exited with 1 after "));
+    }
+
+    #[tokio::test]
+    async fn test_cancellable_command_phases() {
+        let (progress, _) = tokio::sync::mpsc::unbounded_channel();
+        let control = Arc::new(JobControl::new(progress));
+        let commands = with_job(
+            control.clone(),
+            run_blocking(|| {
+                let mut commands = Vec::new();
+                run_cancellable_command(Command::new("true"), &mut commands)?;
+                assert!(commit_job());
+                // reverting the changes of a committed job regenerates grub.cfg again
+                run_cancellable_command(Command::new("true"), &mut commands)?;
+                Ok(commands)
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(commands.len(), 2);
+        assert!(control.cancel().is_err());
+
+        let (progress, _) = tokio::sync::mpsc::unbounded_channel();
+        let control = Arc::new(JobControl::new(progress));
+        control.cancel().unwrap();
+        let commands = with_job(
+            control,
+            run_blocking(|| {
+                let mut commands = Vec::new();
+                run_cancellable_command(Command::new("true"), &mut commands)?;
+                assert!(!commit_job());
+                Ok(commands)
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let mut echo = Command::new("echo");
+        echo.arg("done");
+        let child = spawn_command(&mut echo, "echo").unwrap();
+        let output = wait_output(child, "echo", Some(Duration::from_secs(10))).unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "done\n");
+
+        let started = Instant::now();
+        let mut sleep = Command::new("sleep");
+        sleep.arg("2");
+        let child = spawn_command(&mut sleep, "sleep").unwrap();
+        assert!(wait_output(child, "sleep", Some(Duration::from_millis(100))).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // the sleep in its own session is not killed and keeps stdout open
+        let started = Instant::now();
+        let mut shell = Command::new("sh");
+        shell.args(["-c", "setsid sleep 30 & sleep 30"]);
+        let child = spawn_command(&mut shell, "sh").unwrap();
+        assert!(wait_output(child, "sh", Some(Duration::from_millis(100))).is_err());
+        assert!(started.elapsed() < KILLED_OUTPUT_TIMEOUT + Duration::from_secs(5));
+    }
+}
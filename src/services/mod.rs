@@ -0,0 +1,376 @@
+//! Domain logic of the daemon, split per domain.
+//!
+//! The D-Bus interfaces are thin adapters that turn the JSON data received from
+//! clients into the typed data used by these services, and back.
+
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bootloader::{Backend, Bootloader},
+    config::{
+        tools::{missing_tools, MissingTool},
+        Paths,
+    },
+    db::{
+        audit_log::{self, Caller},
+        settings, Database,
+    },
+    dctx,
+    errors::{DError, DRes, DResult},
+    events::{
+        changes::{ConfigChanges, SnapshotChanges},
+        pause::WatcherPause,
+    },
+    grub2::{
+        diff::{key_changes, KeyChange},
+        GrubFile,
+    },
+    restart::InFlight,
+    services::{
+        config::ConfigService,
+        entry::EntryService,
+        job::{ExecutedCommand, JobService},
+        queue::ChangeQueue,
+        report::ReportService,
+        snapshot::SnapshotService,
+    },
+};
+
+pub mod background;
+pub mod config;
+pub mod entry;
+pub mod job;
+pub mod queue;
+pub mod report;
+pub mod snapshot;
+pub mod uefi;
+
+/// Window during which all bootloader changes are refused, for change-control
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Freeze {
+    /// RFC 3339 time when the freeze ends
+    pub until: DateTime<Utc>,
+    pub reason: String,
+}
+
+/// Changed settings of replacing the `old` freeze with the `new` one
+fn freeze_changes(old: Option<Freeze>, new: Option<&Freeze>) -> Vec<KeyChange> {
+    vec![
+        KeyChange {
+            key: settings::FROZEN_UNTIL.into(),
+            old: old.as_ref().map(|freeze| freeze.until.to_rfc3339()),
+            new: new.map(|freeze| freeze.until.to_rfc3339()),
+        },
+        KeyChange {
+            key: settings::FREEZE_REASON.into(),
+            old: old.map(|freeze| freeze.reason),
+            new: new.map(|freeze| freeze.reason.clone()),
+        },
+    ]
+}
+
+/// State shared by all the services of a single managed system
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Database,
+    pub paths: Paths,
+    /// Pause of the file watchers, only watched on the host system
+    pub watchers: WatcherPause,
+    /// Changes of the config files, only watched on the host system
+    pub config_changes: ConfigChanges,
+    /// Changes of the snapshots, signaled to clients on the host system
+    pub snapshot_changes: SnapshotChanges,
+    /// Changes in progress, shared by all the managed systems
+    pub in_flight: InFlight,
+    /// Turns of the changes to this system, taken by D-Bus calls and background tasks
+    pub changes: ChangeQueue,
+    /// Most snapshots kept, on top of the retention policy
+    pub max_snapshots: Option<usize>,
+    backend: Arc<RwLock<Backend>>,
+    /// Tools that were not installed when they were last looked up
+    missing_tools: Arc<RwLock<Vec<MissingTool>>>,
+}
+
+impl AppState {
+    pub fn backend(&self) -> Backend {
+        *self.backend.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Record the changed keys between the `old` and `new` config, with the
+    /// `commands` that applied them, to the audit log
+    pub async fn audit_changes(
+        &self,
+        action: &str,
+        old: &GrubFile,
+        new: &GrubFile,
+        commands: &[ExecutedCommand],
+    ) -> DResult<()> {
+        self.add_audit_entry(action, &key_changes(old, new), commands)
+            .await
+    }
+
+    /// Record a single changed setting, of a config that is not a grub file, to
+    /// the audit log
+    pub async fn audit_key_change(
+        &self,
+        action: &str,
+        change: KeyChange,
+        commands: &[ExecutedCommand],
+    ) -> DResult<()> {
+        self.audit_key_changes(action, &[change], commands).await
+    }
+
+    /// Record changed settings, of a config that is not a grub file, to the audit log
+    pub async fn audit_key_changes(
+        &self,
+        action: &str,
+        changes: &[KeyChange],
+        commands: &[ExecutedCommand],
+    ) -> DResult<()> {
+        self.add_audit_entry(action, changes, commands).await
+    }
+
+    async fn add_audit_entry(
+        &self,
+        action: &str,
+        changes: &[KeyChange],
+        commands: &[ExecutedCommand],
+    ) -> DResult<()> {
+        let changes =
+            serde_json::to_string(changes).ctx(dctx!(), "Cannot turn key changes into json")?;
+        let commands =
+            serde_json::to_string(commands).ctx(dctx!(), "Cannot turn commands into json")?;
+        self.db
+            .add_audit_entry(action, &changes, &commands, &Caller::current())
+            .await
+    }
+
+    /// The active change freeze, `None` if changes aren't frozen or the freeze has ended
+    pub async fn freeze(&self) -> DResult<Option<Freeze>> {
+        let Some(until) = self.db.setting(settings::FROZEN_UNTIL).await? else {
+            return Ok(None);
+        };
+        let Ok(until) = DateTime::parse_from_rfc3339(&until) else {
+            log::warn!("Ignoring freeze with a malformed end time '{until}'");
+            return Ok(None);
+        };
+        let until = until.with_timezone(&Utc);
+        if until <= Utc::now() {
+            return Ok(None);
+        }
+
+        let reason = self
+            .db
+            .setting(settings::FREEZE_REASON)
+            .await?
+            .unwrap_or_default();
+        Ok(Some(Freeze { until, reason }))
+    }
+
+    /// Refuse all bootloader changes until the end of the `freeze`
+    pub async fn set_freeze(&self, freeze: &Freeze) -> DResult<()> {
+        if freeze.until <= Utc::now() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Freeze end time {} is in the past", freeze.until),
+            ));
+        }
+        if freeze.reason.trim().is_empty() {
+            return Err(DError::generic(dctx!(), "Freeze needs a reason"));
+        }
+
+        let previous = self.freeze().await?;
+        self.db
+            .set_setting(settings::FREEZE_REASON, Some(&freeze.reason))
+            .await?;
+        self.db
+            .set_setting(settings::FROZEN_UNTIL, Some(&freeze.until.to_rfc3339()))
+            .await?;
+        log::info!(
+            "Bootloader changes frozen until {}: {}",
+            freeze.until,
+            freeze.reason
+        );
+        self.audit_key_changes(
+            audit_log::SET_FREEZE,
+            &freeze_changes(previous, Some(freeze)),
+            &[],
+        )
+        .await
+    }
+
+    /// End the change freeze before its end time
+    pub async fn unfreeze(&self) -> DResult<()> {
+        let previous = self.freeze().await?;
+        self.db.set_setting(settings::FROZEN_UNTIL, None).await?;
+        self.db.set_setting(settings::FREEZE_REASON, None).await?;
+        log::info!("Bootloader changes unfrozen");
+        self.audit_key_changes(audit_log::UNFREEZE, &freeze_changes(previous, None), &[])
+            .await
+    }
+
+    /// Refuse changes while a change freeze is active
+    pub async fn require_unfrozen(&self) -> DResult<()> {
+        match self.freeze().await? {
+            None => Ok(()),
+            Some(freeze) => Err(DError::frozen(
+                dctx!(),
+                format!(
+                    "Bootloader changes are frozen until {}: {}",
+                    freeze.until, freeze.reason
+                ),
+            )),
+        }
+    }
+
+    pub fn missing_tools(&self) -> Vec<MissingTool> {
+        self.missing_tools
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Refuse operations that need `programs` if any of them is not installed.
+    /// Tools that were missing are looked up again, in case they were installed since.
+    pub fn require_tools<S: AsRef<str>>(&self, programs: &[S]) -> DResult<()> {
+        let mut missing_tools = self
+            .missing_tools
+            .write()
+            .unwrap_or_else(|err| err.into_inner());
+        missing_tools.retain(|tool| {
+            let installed = self.paths.find_program(&tool.program).is_some();
+            if installed {
+                log::info!("{} was installed", tool.program);
+            }
+            !installed
+        });
+
+        let missing: Vec<String> = missing_tools
+            .iter()
+            .filter(|tool| {
+                programs
+                    .iter()
+                    .any(|program| program.as_ref() == tool.program)
+            })
+            .map(MissingTool::description)
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(DError::tool_missing(dctx!(), missing.join(", ")))
+        }
+    }
+
+    /// Refuse grub operations if the system has switched to another bootloader
+    pub fn require_grub2(&self) -> DResult<()> {
+        match self.backend() {
+            Backend::Grub2 => Ok(()),
+            backend => Err(DError::generic(
+                dctx!(),
+                format!("Active bootloader is {backend}, not grub2. Check GetStatus for the active bootloader"),
+            )),
+        }
+    }
+
+    /// Implementation of the active bootloader
+    pub fn bootloader(&self) -> DResult<Box<dyn Bootloader>> {
+        self.backend().bootloader(&self.paths).ok_or_else(|| {
+            DError::generic(
+                dctx!(),
+                "No supported bootloader found. Check GetStatus for the active bootloader",
+            )
+        })
+    }
+
+    /// Refuse systemd-boot operations if the system boots with another bootloader
+    pub fn require_systemd_boot(&self) -> DResult<()> {
+        match self.backend() {
+            Backend::SystemdBoot => Ok(()),
+            backend => Err(DError::generic(
+                dctx!(),
+                format!("Active bootloader is {backend}, not systemd-boot. Check GetStatus for the active bootloader"),
+            )),
+        }
+    }
+}
+
+/// All the services of a single managed system
+#[derive(Clone)]
+pub struct Services {
+    pub state: AppState,
+    pub config: ConfigService,
+    pub snapshots: SnapshotService,
+    pub entries: EntryService,
+    pub report: ReportService,
+    #[cfg(feature = "dev")]
+    pub jobs: JobService,
+}
+
+impl Services {
+    pub fn new(
+        db: Database,
+        paths: Paths,
+        in_flight: InFlight,
+        max_snapshots: Option<usize>,
+    ) -> Self {
+        let backend = Backend::detect(&paths);
+        log::info!("Detected {backend} bootloader");
+        let missing_tools = missing_tools(&paths);
+        for tool in &missing_tools {
+            log::warn!("{}, operations using it are disabled", tool.description());
+        }
+        let changes = ChangeQueue::new(&paths.grub_file_lock());
+        let state = AppState {
+            db,
+            paths,
+            watchers: WatcherPause::default(),
+            config_changes: ConfigChanges::default(),
+            snapshot_changes: SnapshotChanges::default(),
+            in_flight,
+            changes,
+            max_snapshots,
+            backend: Arc::new(RwLock::new(backend)),
+            missing_tools: Arc::new(RwLock::new(missing_tools)),
+        };
+        let jobs = JobService::new(state.clone());
+        let entries = EntryService::new(state.clone(), jobs.clone());
+        let snapshots = SnapshotService::new(state.clone(), jobs.clone());
+        let report = ReportService::new(state.clone(), entries.clone());
+        let config = ConfigService::new(
+            state.clone(),
+            jobs.clone(),
+            entries.clone(),
+            snapshots.clone(),
+        );
+
+        Self {
+            state,
+            config,
+            snapshots,
+            entries,
+            report,
+            #[cfg(feature = "dev")]
+            jobs,
+        }
+    }
+
+    /// Detect the bootloader again, returns the new one if it changed
+    pub fn refresh_backend(&self) -> Option<Backend> {
+        let detected = Backend::detect(&self.state.paths);
+        let mut backend = self
+            .state
+            .backend
+            .write()
+            .unwrap_or_else(|err| err.into_inner());
+        if *backend == detected {
+            return None;
+        }
+
+        log::warn!("Bootloader changed from {} to {detected}", *backend);
+        *backend = detected;
+        Some(detected)
+    }
+}
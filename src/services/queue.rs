@@ -0,0 +1,184 @@
+//! Changes of a managed system run one at a time.
+//!
+//! Saving a config reads the grub file, stores a snapshot and regenerates
+//! grub.cfg, so two saves running at once can interleave and leave the files
+//! and the database out of sync. Later callers wait for their turn instead,
+//! whether they're D-Bus calls or tasks the daemon runs by itself.
+//!
+//! A turn also holds an advisory lock of the lock file next to the grub file,
+//! `/etc/default/grub.lock`, so the rescue commands and other cooperating
+//! programs that lock it (like `flock /etc/default/grub.lock vi /etc/default/grub`)
+//! don't change the files at the same time. The lock only covers programs that
+//! take it: yast2-bootloader and editors don't, so an edit they make during a
+//! turn is only detected when the grub file is written, which refuses to
+//! overwrite it.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg},
+};
+use tokio::{
+    sync::{Mutex, OwnedMutexGuard},
+    time::timeout,
+};
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+    services::job::run_blocking,
+};
+
+/// Longest wait for the changes before a call, shorter than the default 25
+/// second timeout of D-Bus clients so they get the reason
+pub const QUEUE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How long to wait for other programs to unlock the lock file
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Turn of a change, the next one may start once it's dropped
+pub struct ChangeTurn {
+    _turn: OwnedMutexGuard<()>,
+    /// `None` if the lock file cannot be created
+    _lock: Option<Flock<File>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeQueue {
+    turn: Arc<Mutex<()>>,
+    /// Lock file that is locked during a turn
+    lock_file: PathBuf,
+}
+
+impl ChangeQueue {
+    pub fn new(lock_file: &Path) -> Self {
+        Self {
+            turn: Arc::default(),
+            lock_file: lock_file.to_path_buf(),
+        }
+    }
+
+    /// Wait until the changes started before are done, refused as busy if they
+    /// take longer than `QUEUE_TIMEOUT`
+    pub async fn turn(&self) -> DResult<ChangeTurn> {
+        let turn = timeout(QUEUE_TIMEOUT, self.turn.clone().lock_owned())
+            .await
+            .map_err(|_| {
+                DError::busy(
+                    dctx!(),
+                    "Another change of the bootloader is still running, try again later",
+                )
+            })?;
+        let lock = lock_file(&self.lock_file).await?;
+        Ok(ChangeTurn {
+            _turn: turn,
+            _lock: lock,
+        })
+    }
+}
+
+/// Result of a single try to lock the lock file
+enum LockAttempt {
+    Locked(Flock<File>),
+    /// Another program holds the lock
+    Busy,
+    /// The lock file cannot be created, e.g. the grub file's directory is
+    /// missing or read-only, so the grub file cannot be written either
+    Unavailable,
+}
+
+/// Take an exclusive advisory lock of `path`, created if it doesn't exist.
+/// `None` if it cannot be created.
+async fn lock_file(path: &Path) -> DResult<Option<Flock<File>>> {
+    let started = Instant::now();
+    loop {
+        let attempt = {
+            let path = path.to_path_buf();
+            run_blocking(move || try_lock(&path)).await?
+        };
+        match attempt {
+            LockAttempt::Locked(lock) => return Ok(Some(lock)),
+            LockAttempt::Unavailable => return Ok(None),
+            LockAttempt::Busy => {}
+        }
+
+        if started.elapsed() >= LOCK_TIMEOUT {
+            return Err(DError::busy(
+                dctx!(),
+                format!("{path:?} is locked by another program"),
+            ));
+        }
+        tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+    }
+}
+
+fn try_lock(path: &Path) -> DResult<LockAttempt> {
+    let file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ReadOnlyFilesystem
+            ) =>
+        {
+            log::debug!("Cannot create lock file {path:?}: {err}");
+            return Ok(LockAttempt::Unavailable);
+        }
+        Err(err) => return Err(err).ctx(dctx!(), format!("Cannot open {path:?}")),
+    };
+    match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+        Ok(lock) => Ok(LockAttempt::Locked(lock)),
+        Err((_, Errno::EAGAIN)) => Ok(LockAttempt::Busy),
+        Err((_, errno)) => Err(DError::generic(
+            dctx!(),
+            format!("Cannot lock {path:?}: {errno}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_change_queue() {
+        let dir = std::env::temp_dir().join(format!("bootkit-queue-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lock_file = dir.join("grub.lock");
+        let queue = ChangeQueue::new(&lock_file);
+        let turn = queue.turn().await.unwrap();
+        let waiting = timeout(Duration::from_millis(50), queue.turn()).await;
+        assert!(waiting.is_err());
+
+        // other programs can't lock the lock file during the turn
+        let other = File::open(&lock_file).unwrap();
+        assert!(Flock::lock(other, FlockArg::LockExclusiveNonblock).is_err());
+
+        let next = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.turn().await.is_ok() }
+        });
+        drop(turn);
+        assert!(next.await.unwrap());
+
+        // nothing to lock without the directory, the grub file can't be written either
+        let missing = ChangeQueue::new(&dir.join("missing").join("grub.lock"));
+        assert!(missing.turn().await.is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,399 @@
+use std::{collections::BTreeMap, fmt::Write, fs::read_dir, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bootloader::{Backend, BootEntries},
+    db::pending_operation::PendingOperation,
+    dctx,
+    errors::{DRes, DResult},
+    firmware::{secure_boot, EspStatus, FirmwareMode},
+    grub2::kernel::{compare_versions, version_from_image},
+    services::{
+        entry::{EntryService, TimelineEvent},
+        AppState, Freeze,
+    },
+};
+
+/// Newest timeline events included in a report
+const REPORT_HISTORY_LIMIT: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportFormat {
+    /// Plain text for reading and attaching to support tickets
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ReportOptions {
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+/// Kernel image installed in /boot
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstalledKernel {
+    version: String,
+    image: PathBuf,
+    /// Initrd of the kernel exists
+    initrd: bool,
+    /// Kernel has a boot entry
+    boot_entry: bool,
+}
+
+/// Boot configuration of the whole managed system
+#[derive(Debug, Serialize)]
+pub struct Report {
+    generated: DateTime<Utc>,
+    version: String,
+    backend: Backend,
+    /// Firmware of the host, also for targets
+    firmware: FirmwareMode,
+    secure_boot: Option<bool>,
+    /// EFI system partition, if one is mounted
+    esp: Option<EspStatus>,
+    default_entry: Option<String>,
+    kernels: Vec<InstalledKernel>,
+    /// Settings of the bootloader, like the values of /etc/default/grub
+    config: BTreeMap<String, String>,
+    freeze: Option<Freeze>,
+    pending_operations: Vec<PendingOperation>,
+    /// Newest events of the boot timeline, newest first
+    history: Vec<TimelineEvent>,
+    problems: Vec<String>,
+}
+
+fn describe_event(event: &TimelineEvent) -> String {
+    match event {
+        TimelineEvent::KernelPackage(event) => format!(
+            "{:?} {} {} ({})",
+            event.action, event.package, event.version, event.arch
+        ),
+        TimelineEvent::Snapshot {
+            snapshot_id,
+            applied,
+            ..
+        } => {
+            let state = if *applied { "applied" } else { "draft" };
+            format!("Snapshot {snapshot_id} ({state})")
+        }
+        TimelineEvent::Audit {
+            audit_id, action, ..
+        } => format!("Change {audit_id}: {action}"),
+    }
+}
+
+impl Report {
+    /// Human readable form of the report
+    pub fn to_text(&self) -> String {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        let mut text = String::new();
+        // writing to a String cannot fail
+        let _ = writeln!(text, "Boot configuration report");
+        let _ = writeln!(text, "Generated: {}", self.generated.to_rfc3339());
+        let _ = writeln!(text, "bootkit version: {}", self.version);
+
+        let _ = writeln!(text, "\n== System ==");
+        let _ = writeln!(text, "Bootloader: {}", self.backend);
+        let _ = writeln!(text, "Firmware: {}", self.firmware.name());
+        let secure_boot = match self.secure_boot {
+            Some(true) => "enabled",
+            Some(false) => "disabled",
+            None => "unknown",
+        };
+        let _ = writeln!(text, "Secure boot: {secure_boot}");
+        match &self.esp {
+            Some(esp) => {
+                let _ = writeln!(
+                    text,
+                    "EFI system partition: {:?} on {}, {} MiB free of {} MiB",
+                    esp.mount_point,
+                    esp.device,
+                    esp.free_bytes / 1024 / 1024,
+                    esp.total_bytes / 1024 / 1024
+                );
+            }
+            None => {
+                let _ = writeln!(text, "EFI system partition: not mounted");
+            }
+        }
+        match &self.freeze {
+            Some(freeze) => {
+                let _ = writeln!(
+                    text,
+                    "Change freeze: until {} ({})",
+                    freeze.until.to_rfc3339(),
+                    freeze.reason
+                );
+            }
+            None => {
+                let _ = writeln!(text, "Change freeze: none");
+            }
+        }
+
+        let _ = writeln!(text, "\n== Boot entries ==");
+        let _ = writeln!(
+            text,
+            "Default entry: {}",
+            self.default_entry.as_deref().unwrap_or("first entry")
+        );
+        for kernel in &self.kernels {
+            let _ = writeln!(
+                text,
+                "Kernel {} ({:?}): initrd {}, boot entry {}",
+                kernel.version,
+                kernel.image,
+                yes_no(kernel.initrd),
+                yes_no(kernel.boot_entry)
+            );
+        }
+
+        let _ = writeln!(text, "\n== Configuration ==");
+        for (key, value) in &self.config {
+            let _ = writeln!(text, "{key}=\"{value}\"");
+        }
+
+        let _ = writeln!(text, "\n== Pending changes ==");
+        if self.pending_operations.is_empty() {
+            let _ = writeln!(text, "None");
+        }
+        for operation in &self.pending_operations {
+            let _ = write!(
+                text,
+                "{} {} queued {}",
+                operation.id, operation.kind, operation.created
+            );
+            match &operation.last_error {
+                Some(error) => {
+                    let _ = writeln!(text, ", last error: {error}");
+                }
+                None => {
+                    let _ = writeln!(text);
+                }
+            }
+        }
+
+        let _ = writeln!(text, "\n== Recent history (UTC) ==");
+        for event in &self.history {
+            let _ = writeln!(text, "{} {}", event.time(), describe_event(event));
+        }
+
+        let _ = writeln!(text, "\n== Problems ==");
+        if self.problems.is_empty() {
+            let _ = writeln!(text, "None");
+        }
+        for problem in &self.problems {
+            let _ = writeln!(text, "- {problem}");
+        }
+
+        text
+    }
+}
+
+/// Reports of the boot configuration, for support tickets and compliance archives
+#[derive(Clone)]
+pub struct ReportService {
+    state: AppState,
+    entries: EntryService,
+}
+
+impl ReportService {
+    pub fn new(state: AppState, entries: EntryService) -> Self {
+        Self { state, entries }
+    }
+
+    /// Kernel images in /boot, newest first
+    fn installed_kernels(&self, boot_entries: Option<&BootEntries>) -> Vec<InstalledKernel> {
+        let boot_dir = self.state.paths.boot_dir();
+        let Ok(dir) = read_dir(&boot_dir) else {
+            log::debug!("Cannot list kernels in {boot_dir:?}");
+            return Vec::new();
+        };
+
+        let mut kernels: Vec<_> = dir
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                let version = version_from_image(&name)?.to_string();
+                Some(InstalledKernel {
+                    image: boot_dir.join(&name),
+                    initrd: boot_dir.join(format!("initrd-{version}")).exists(),
+                    boot_entry: boot_entries.is_some_and(|boot_entries| {
+                        boot_entries
+                            .entries
+                            .iter()
+                            .any(|entry| entry.kernel_version.as_deref() == Some(version.as_str()))
+                    }),
+                    version,
+                })
+            })
+            .collect();
+        kernels.sort_by(|a, b| compare_versions(&b.version, &a.version));
+        kernels
+    }
+
+    pub async fn report(&self) -> DResult<Report> {
+        let state = &self.state;
+        let mut problems: Vec<_> = state
+            .missing_tools()
+            .iter()
+            .map(|tool| tool.description())
+            .collect();
+
+        let firmware = FirmwareMode::detect();
+        let esp = EspStatus::detect(&state.paths);
+        match &esp {
+            Some(esp) => problems.extend(esp.problems.iter().cloned()),
+            None if firmware == FirmwareMode::Uefi => {
+                problems.push("No EFI system partition is mounted".into())
+            }
+            None => {}
+        }
+
+        let bootloader = state.backend().bootloader(&state.paths);
+        if bootloader.is_none() {
+            problems.push("No supported bootloader found".into());
+        }
+
+        let boot_entries =
+            bootloader
+                .as_ref()
+                .and_then(|bootloader| match bootloader.list_entries() {
+                    Ok(boot_entries) => Some(boot_entries),
+                    Err(_) => {
+                        problems.push(format!(
+                            "Cannot read the boot entries of {}",
+                            bootloader.backend()
+                        ));
+                        None
+                    }
+                });
+        let default_entry = boot_entries
+            .as_ref()
+            .and_then(|boot_entries| boot_entries.selected.clone());
+        if let Some(boot_entries) = &boot_entries {
+            if boot_entries.selected.is_some() && boot_entries.default_entry().is_none() {
+                problems.push(format!(
+                    "Default entry '{}' doesn't exist",
+                    default_entry.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+
+        let kernels = self.installed_kernels(boot_entries.as_ref());
+        for kernel in &kernels {
+            if !kernel.initrd {
+                problems.push(format!("Kernel {} has no initrd", kernel.version));
+            }
+            if boot_entries.is_some() && !kernel.boot_entry {
+                problems.push(format!("Kernel {} has no boot entry", kernel.version));
+            }
+        }
+
+        let config = bootloader
+            .as_ref()
+            .and_then(|bootloader| match bootloader.read_config() {
+                Ok(config) => Some(config),
+                Err(_) => {
+                    problems.push(format!(
+                        "Cannot read the config of {}",
+                        bootloader.backend()
+                    ));
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        let pending_operations = state.db.pending_operations().await?;
+        problems.extend(pending_operations.iter().filter_map(|operation| {
+            let error = operation.last_error.as_ref()?;
+            Some(format!("Pending change {} failed: {error}", operation.id))
+        }));
+
+        let mut history = self
+            .entries
+            .timeline()
+            .await
+            .ctx(dctx!(), "Cannot read the boot timeline")?;
+        history.truncate(REPORT_HISTORY_LIMIT);
+
+        Ok(Report {
+            generated: Utc::now(),
+            version: env!("CARGO_PKG_VERSION").into(),
+            backend: state.backend(),
+            firmware,
+            secure_boot: secure_boot(),
+            esp,
+            default_entry,
+            kernels,
+            config,
+            freeze: state.freeze().await?,
+            pending_operations,
+            history,
+            problems,
+        })
+    }
+
+    /// The report in the format the client asked for
+    pub async fn generate_report(&self, options: ReportOptions) -> DResult<String> {
+        let report = self.report().await?;
+        match options.format {
+            ReportFormat::Text => Ok(report.to_text()),
+            ReportFormat::Json => {
+                serde_json::to_string_pretty(&report).ctx(dctx!(), "Cannot turn report into json")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn test_report_text() {
+        let time = NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let report = Report {
+            generated: Utc::now(),
+            version: "1.0.0".into(),
+            backend: Backend::Grub2,
+            firmware: FirmwareMode::Bios,
+            secure_boot: None,
+            esp: None,
+            default_entry: Some("openSUSE Tumbleweed".into()),
+            kernels: vec![InstalledKernel {
+                version: "6.17.5-1-default".into(),
+                image: PathBuf::from("/boot/vmlinuz-6.17.5-1-default"),
+                initrd: true,
+                boot_entry: false,
+            }],
+            config: BTreeMap::from([("GRUB_TIMEOUT".into(), "8".into())]),
+            freeze: None,
+            pending_operations: Vec::new(),
+            history: vec![TimelineEvent::Snapshot {
+                time,
+                snapshot_id: 3,
+                applied: true,
+            }],
+            problems: vec!["Kernel 6.17.5-1-default has no boot entry".into()],
+        };
+
+        let text = report.to_text();
+        assert!(text.contains("Firmware: bios\n"));
+        assert!(text.contains("Secure boot: unknown\n"));
+        assert!(text.contains("Default entry: openSUSE Tumbleweed\n"));
+        assert!(text.contains("initrd yes, boot entry no\n"));
+        assert!(text.contains("GRUB_TIMEOUT=\"8\"\n"));
+        assert!(text.contains("== Pending changes ==\nNone\n"));
+        assert!(text.contains("2026-03-01 10:00:00 Snapshot 3 (applied)\n"));
+        assert!(text.contains("- Kernel 6.17.5-1-default has no boot entry\n"));
+    }
+}
@@ -0,0 +1,1245 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fs::{read_to_string, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::available_parallelism,
+};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::spawn_blocking};
+
+use crate::{
+    db::{audit_log, grub2::Grub2Snapshot, selected_snapshot::SelectedSnapshot, settings},
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::{
+        diff::{config_diff, key_changes, ConfigDiff, DiffFormat, KeyChange},
+        grub_env_value, GrubFile,
+    },
+    services::{
+        config::DiffOptions,
+        job::{run_blocking, ApplyOptions, ApplyResult, JobService, SnapshotFiles},
+        AppState,
+    },
+};
+
+#[derive(Debug, Serialize)]
+struct Grub2SnapshotData {
+    /// snapshot in the database
+    snapshot: Grub2Snapshot,
+    /// user defined labels, in alphabetical order
+    labels: Vec<String>,
+    /// drop-in configs captured with the snapshot, by file name
+    dropins: BTreeMap<String, String>,
+    /// diff against the current config
+    diff: Option<ConfigDiff>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotData {
+    snapshots: Vec<Grub2SnapshotData>,
+    selected: SelectedSnapshot,
+    /// Snapshots that pass the filters, including the ones on other pages
+    total: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RemoveSnapshotData {
+    snapshot_id: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SelectSnapshotData {
+    pub snapshot_id: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnapshotLabelsData {
+    snapshot_id: i64,
+    /// All the labels of the snapshot, the earlier ones are replaced
+    labels: Vec<String>,
+}
+
+/// Longest label of a snapshot, in characters
+const MAX_LABEL_LENGTH: usize = 64;
+
+/// Trimmed, sorted and deduplicated `labels`, refused if any is empty or too long
+pub fn valid_labels(labels: &[String]) -> DResult<Vec<String>> {
+    let mut valid = Vec::with_capacity(labels.len());
+    for label in labels {
+        let label = label.trim();
+        if label.is_empty() || label.chars().count() > MAX_LABEL_LENGTH {
+            return Err(DError::invalid_data(
+                dctx!(),
+                format!(
+                    "Snapshot labels must be 1 to {MAX_LABEL_LENGTH} characters, got '{label}'"
+                ),
+            ));
+        }
+        if label.chars().any(char::is_control) {
+            return Err(DError::invalid_data(
+                dctx!(),
+                format!("Snapshot label {label:?} has control characters"),
+            ));
+        }
+        valid.push(label.to_string());
+    }
+    valid.sort();
+    valid.dedup();
+    Ok(valid)
+}
+
+/// Two snapshots to compare, in either order
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompareSnapshotsData {
+    old_snapshot_id: i64,
+    new_snapshot_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotComparison {
+    /// Unified diff from the old to the new snapshot, `None` if they're the same
+    diff: Option<String>,
+    /// Keys that differ between the snapshots
+    changed_keys: Vec<KeyChange>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnapshotDescriptionData {
+    snapshot_id: i64,
+    /// Empty text removes the description
+    description: String,
+}
+
+/// Longest description of a snapshot, in characters
+const MAX_DESCRIPTION_LENGTH: usize = 1024;
+
+/// Trimmed description, `None` if it's empty
+fn valid_description(description: &str) -> DResult<Option<&str>> {
+    let description = description.trim();
+    if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+        return Err(DError::invalid_data(
+            dctx!(),
+            format!("Snapshot descriptions can be at most {MAX_DESCRIPTION_LENGTH} characters"),
+        ));
+    }
+    Ok((!description.is_empty()).then_some(description))
+}
+
+/// Tag of the snapshots imported from an archive
+pub const IMPORTED_TAG: &str = "imported";
+
+/// Version of the snapshot archives written by this daemon
+const SNAPSHOT_ARCHIVE_VERSION: u32 = 1;
+
+/// Snapshot in a file of its own, for moving a known good config to another machine
+#[derive(Debug, Deserialize, Serialize)]
+struct SnapshotArchive {
+    version: u32,
+    grub_config: String,
+    selected_kernel: Option<String>,
+    /// When the snapshot was created on the exporting machine
+    created: NaiveDateTime,
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+impl SnapshotArchive {
+    fn parse(contents: &str) -> DResult<Self> {
+        let archive: Self = serde_json::from_str(contents).map_err(|err| {
+            DError::invalid_data(dctx!(), format!("Malformed snapshot archive: {err}"))
+        })?;
+        if archive.version > SNAPSHOT_ARCHIVE_VERSION {
+            return Err(DError::invalid_data(
+                dctx!(),
+                format!(
+                    "Snapshot archive version {} is newer than the supported {SNAPSHOT_ARCHIVE_VERSION}",
+                    archive.version
+                ),
+            ));
+        }
+        GrubFile::new(&archive.grub_config)
+            .ctx(dctx!(), "Snapshot archive has an invalid grub config")?;
+        Ok(archive)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportSnapshotData {
+    snapshot_id: i64,
+    /// Absolute path on the host system, the file must not exist
+    path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    /// Id of the new snapshot, saved as a draft
+    snapshot_id: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PinSnapshotData {
+    snapshot_id: i64,
+    /// Pinned snapshots are never removed by the retention policy
+    pub pinned: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SnapshotIdData {
+    snapshot_id: i64,
+}
+
+/// File the stored data is backed up to or restored from, or a snapshot is imported from
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DatabaseFileData {
+    /// Absolute path on the host system
+    path: PathBuf,
+}
+
+impl DatabaseFileData {
+    fn path(&self) -> DResult<&Path> {
+        absolute_path(&self.path)
+    }
+}
+
+/// Paths are resolved by the daemon, so relative ones would not be what the client meant
+fn absolute_path(path: &Path) -> DResult<&Path> {
+    if !path.is_absolute() {
+        return Err(DError::invalid_data(
+            dctx!(),
+            format!("Path {path:?} is not absolute"),
+        ));
+    }
+    Ok(path)
+}
+
+/// How many of the largest snapshots are listed in the storage stats
+const LARGEST_SNAPSHOT_COUNT: usize = 5;
+
+#[derive(Debug, Serialize)]
+struct SnapshotSize {
+    snapshot_id: i64,
+    created: NaiveDateTime,
+    /// Bytes of the snapshotted config
+    size: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageStats {
+    /// Bytes the storage takes on disk, 0 for storage kept in memory
+    database_size: u64,
+    snapshot_count: usize,
+    /// Bytes of all the snapshotted configs
+    snapshots_size: usize,
+    /// Largest snapshots, largest first
+    largest_snapshots: Vec<SnapshotSize>,
+    /// Bytes used by snapshots that are identical to an older snapshot
+    duplicate_bytes: usize,
+    /// Bytes freed by removing all but the latest and the selected snapshot
+    prunable_bytes: usize,
+    /// Bytes of unused space in the database file, reclaimable by vacuuming
+    free_bytes: i64,
+}
+
+/// How many snapshots are kept, all of them if a limit isn't set.
+///
+/// Applied snapshots are the history of what the system booted with, so they're
+/// usually kept longer than drafts that were only saved.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct RetentionPolicy {
+    /// How many of the newest applied snapshots are kept
+    keep_applied: Option<usize>,
+    /// How many of the newest never applied snapshots are kept
+    keep_drafts: Option<usize>,
+    /// Most snapshots kept in total, set with the --max-snapshots option of the daemon
+    #[serde(default, skip_deserializing)]
+    max_snapshots: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PruneResult {
+    /// Ids of the removed snapshots
+    removed: Vec<i64>,
+}
+
+/// Audit log change of removing the snapshot `id`
+fn removed_snapshot(id: i64) -> KeyChange {
+    KeyChange {
+        key: "snapshot".into(),
+        old: Some(id.to_string()),
+        new: None,
+    }
+}
+
+/// Ids of the `snapshots` (newest first) that the `policy` doesn't keep.
+/// The latest, the selected and the pinned snapshots are always kept.
+fn prunable_snapshots(
+    snapshots: &[Grub2Snapshot],
+    selected_id: i64,
+    policy: &RetentionPolicy,
+) -> Vec<i64> {
+    let kept_always = |idx: usize, snapshot: &Grub2Snapshot| {
+        idx == 0 || snapshot.id == selected_id || snapshot.pinned
+    };
+    let mut applied = 0;
+    let mut drafts = 0;
+    let mut prunable = Vec::new();
+    for (idx, snapshot) in snapshots.iter().enumerate() {
+        if snapshot.pinned {
+            continue;
+        }
+        let (count, keep) = if snapshot.applied {
+            (&mut applied, policy.keep_applied)
+        } else {
+            (&mut drafts, policy.keep_drafts)
+        };
+        *count += 1;
+
+        if kept_always(idx, snapshot) {
+            continue;
+        }
+        if keep.is_some_and(|keep| *count > keep) {
+            prunable.push(snapshot.id);
+        }
+    }
+
+    // the oldest of the rest are removed until few enough are left
+    if let Some(max) = policy.max_snapshots {
+        let mut kept = snapshots.len() - prunable.len();
+        for (idx, snapshot) in snapshots.iter().enumerate().rev() {
+            if kept <= max {
+                break;
+            }
+            if kept_always(idx, snapshot) || prunable.contains(&snapshot.id) {
+                continue;
+            }
+            prunable.push(snapshot.id);
+            kept -= 1;
+        }
+    }
+
+    prunable
+}
+
+/// Diffs from the `current` config to each of the snapshots, in the order of the snapshots.
+///
+/// Diffing hundreds of snapshots takes a while so the diffs are computed on the
+/// blocking pool, at most one per CPU at a time.
+async fn snapshot_diffs(
+    current: String,
+    snapshots: &[Grub2Snapshot],
+    format: DiffFormat,
+) -> DResult<Vec<Option<ConfigDiff>>> {
+    if format == DiffFormat::None {
+        return Ok(vec![None; snapshots.len()]);
+    }
+
+    let current: Arc<str> = current.into();
+    let concurrency = available_parallelism().map_or(1, |count| count.get());
+    let limit = Arc::new(Semaphore::new(concurrency));
+
+    let mut tasks = Vec::with_capacity(snapshots.len());
+    for snapshot in snapshots {
+        let permit =
+            limit.clone().acquire_owned().await.map_err(|err| {
+                DError::generic(dctx!(), format!("Cannot start diff task: {err}"))
+            })?;
+        let current = current.clone();
+        let config = snapshot.grub_config.clone();
+        tasks.push(spawn_blocking(move || {
+            let _permit = permit;
+            config_diff(&current, &config, format)
+        }));
+    }
+
+    let mut diffs = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        diffs.push(
+            task.await
+                .map_err(|err| DError::generic(dctx!(), format!("Diff task failed: {err}")))?,
+        );
+    }
+
+    Ok(diffs)
+}
+
+/// Newest applied snapshot taken before the selected one, drafts were never in use.
+/// `snapshots` are newest first.
+fn previous_snapshot(snapshots: &[Grub2Snapshot], selected_id: i64) -> Option<&Grub2Snapshot> {
+    snapshots
+        .iter()
+        .find(|snapshot| snapshot.id < selected_id && snapshot.applied)
+}
+
+fn snapshot_comparison(old: &str, new: &str) -> SnapshotComparison {
+    let diff = match config_diff(old, new, DiffFormat::Unified) {
+        Some(ConfigDiff::Unified(diff)) => Some(diff),
+        _ => None,
+    };
+    let changed_keys = key_changes(&GrubFile::new_lenient(old), &GrubFile::new_lenient(new));
+    SnapshotComparison { diff, changed_keys }
+}
+
+/// Snapshots of the previously applied grub configs
+#[derive(Clone)]
+pub struct SnapshotService {
+    state: AppState,
+    jobs: JobService,
+}
+
+impl SnapshotService {
+    pub fn new(state: AppState, jobs: JobService) -> Self {
+        Self { state, jobs }
+    }
+
+    pub async fn snapshots(&self, options: DiffOptions) -> DResult<SnapshotData> {
+        let mut labels: HashMap<i64, Vec<String>> = HashMap::new();
+        for row in self.state.db.snapshot_labels().await? {
+            labels.entry(row.snapshot_id).or_default().push(row.label);
+        }
+        let filter = &options.filter;
+        if filter.limit.is_some_and(|limit| limit < 0) || filter.offset < 0 {
+            return Err(DError::invalid_data(
+                dctx!(),
+                "Snapshot limit and offset cannot be negative",
+            ));
+        }
+        let mut dropins: HashMap<i64, BTreeMap<String, String>> = HashMap::new();
+        for file in self.state.db.snapshot_files().await? {
+            dropins
+                .entry(file.snapshot_id)
+                .or_default()
+                .insert(file.name, file.contents);
+        }
+        let page = self.state.db.grub2_snapshot_page(filter).await?;
+        let db_snapshots = page.snapshots;
+
+        let selected = self.state.db.selected_snapshot().await?;
+        let grub = self.read_grub_file().await?;
+        let diffs = snapshot_diffs(grub.as_string(), &db_snapshots, options.diff_format).await?;
+        let snapshots: Vec<Grub2SnapshotData> = db_snapshots
+            .into_iter()
+            .zip(diffs)
+            .map(|(snapshot, diff)| Grub2SnapshotData {
+                labels: labels.remove(&snapshot.id).unwrap_or_default(),
+                dropins: dropins.remove(&snapshot.id).unwrap_or_default(),
+                snapshot,
+                diff,
+            })
+            .collect();
+
+        Ok(SnapshotData {
+            snapshots,
+            selected,
+            total: page.total,
+        })
+    }
+
+    /// Capture the saved_entry of grubenv and the drop-in configs with the
+    /// snapshot of the system's grub file
+    pub async fn capture_files(&self, snapshot_id: i64) -> DResult<()> {
+        let grub_env_path = self.state.paths.grub_env().to_owned();
+        let jobs = self.jobs.clone();
+        let (grub_env, dropins) = run_blocking(move || {
+            let grub_env = read_to_string(grub_env_path).unwrap_or_default();
+            Ok((grub_env, jobs.read_dropins()?))
+        })
+        .await?;
+        let saved_entry = grub_env_value(&grub_env, "saved_entry");
+        self.state
+            .db
+            .set_snapshot_files(snapshot_id, saved_entry, &dropins)
+            .await
+    }
+
+    /// Record the hash of the grub.cfg just generated from the snapshot, to
+    /// notice when it's changed outside the daemon
+    pub async fn record_grub_cfg(&self, snapshot_id: i64) -> DResult<()> {
+        let jobs = self.jobs.clone();
+        let Some(sha256) = run_blocking(move || jobs.grub_cfg_sha256()).await? else {
+            return Ok(());
+        };
+        self.state.db.set_grub2_cfg_hash(snapshot_id, &sha256).await
+    }
+
+    /// Files besides the grub file that selecting the snapshot restores, `None`
+    /// for snapshots taken before they were captured
+    async fn snapshot_files(&self, snapshot: &Grub2Snapshot) -> DResult<Option<SnapshotFiles>> {
+        if !snapshot.files_captured {
+            return Ok(None);
+        }
+        let dropins = self
+            .state
+            .db
+            .snapshot_files()
+            .await?
+            .into_iter()
+            .filter(|file| file.snapshot_id == snapshot.id)
+            .map(|file| (file.name, file.contents))
+            .collect();
+        Ok(Some(SnapshotFiles {
+            saved_entry: snapshot.saved_entry.clone(),
+            dropins,
+        }))
+    }
+
+    /// Difference between any two snapshots, instead of a snapshot and the current config
+    pub async fn compare_snapshots(
+        &self,
+        compare_data: CompareSnapshotsData,
+    ) -> DResult<SnapshotComparison> {
+        let old = self
+            .state
+            .db
+            .grub2_snapshot(compare_data.old_snapshot_id)
+            .await?;
+        let new = self
+            .state
+            .db
+            .grub2_snapshot(compare_data.new_snapshot_id)
+            .await?;
+
+        spawn_blocking(move || snapshot_comparison(&old.grub_config, &new.grub_config))
+            .await
+            .map_err(|err| DError::generic(dctx!(), format!("Diff task failed: {err}")))
+    }
+
+    /// Copy the snapshots, settings and the audit log to a new file, to move
+    /// them to another machine or keep them over a reinstall
+    pub async fn backup_database(&self, data: DatabaseFileData) -> DResult<()> {
+        let path = data.path()?;
+        if path.exists() {
+            return Err(DError::generic(
+                dctx!(),
+                format!("{path:?} already exists, backups don't overwrite files"),
+            ));
+        }
+        self.state.db.backup(path).await
+    }
+
+    /// Replace the snapshots, settings and the audit log with a backup. The
+    /// bootloader config itself is not changed.
+    pub async fn restore_database(&self, data: DatabaseFileData) -> DResult<()> {
+        let path = data.path()?;
+        // the freeze is a setting that the backup would replace
+        self.state.require_unfrozen().await?;
+        let _in_flight = self.state.in_flight.start()?;
+        self.state.db.restore(path).await?;
+        self.state.snapshot_changes.changed();
+        // the config is diffed against the restored snapshots
+        self.state.config_changes.changed();
+        Ok(())
+    }
+
+    /// Write the snapshot with its metadata to a file that can be imported on another machine
+    pub async fn export_snapshot(&self, export_data: ExportSnapshotData) -> DResult<()> {
+        let path = absolute_path(&export_data.path)?;
+        let id = export_data.snapshot_id;
+        let snapshot = self.state.db.grub2_snapshot(id).await?;
+        let labels = self
+            .state
+            .db
+            .snapshot_labels()
+            .await?
+            .into_iter()
+            .filter(|row| row.snapshot_id == id)
+            .map(|row| row.label)
+            .collect();
+        let archive = SnapshotArchive {
+            version: SNAPSHOT_ARCHIVE_VERSION,
+            grub_config: snapshot.grub_config,
+            selected_kernel: snapshot.selected_kernel,
+            created: snapshot.created,
+            tag: snapshot.tag,
+            description: snapshot.description,
+            labels,
+        };
+        let contents = serde_json::to_string_pretty(&archive)
+            .ctx(dctx!(), "Cannot turn snapshot archive into json")?;
+
+        let archive_path = path.to_owned();
+        run_blocking(move || {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&archive_path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()))
+                .ctx(
+                    dctx!(),
+                    format!("Cannot write snapshot archive {archive_path:?}"),
+                )
+        })
+        .await?;
+        log::info!("Snapshot {id} exported to {path:?}");
+        Ok(())
+    }
+
+    /// Save the snapshot of an exported archive as a draft, it's not applied
+    pub async fn import_snapshot(&self, data: DatabaseFileData) -> DResult<ImportResult> {
+        self.state.require_unfrozen().await?;
+        let path = data.path()?;
+        let contents = {
+            let path = path.to_owned();
+            run_blocking(move || {
+                read_to_string(&path).ctx(dctx!(), format!("Cannot read snapshot archive {path:?}"))
+            })
+            .await?
+        };
+        let archive = SnapshotArchive::parse(&contents)?;
+        let labels = valid_labels(&archive.labels)?;
+        let description = archive
+            .description
+            .as_deref()
+            .map(valid_description)
+            .transpose()?
+            .flatten();
+
+        let grub = GrubFile::new(&archive.grub_config)?;
+        let id = self
+            .state
+            .db
+            .save_grub2(
+                &grub,
+                archive.selected_kernel.as_deref(),
+                false,
+                Some(IMPORTED_TAG),
+            )
+            .await?;
+        if description.is_some() {
+            self.state.db.set_grub2_description(id, description).await?;
+        }
+        if !labels.is_empty() {
+            self.state.db.set_snapshot_labels(id, &labels).await?;
+        }
+        self.state.snapshot_changes.changed();
+        // the config is diffed against the latest snapshot
+        self.state.config_changes.changed();
+        self.state
+            .audit_key_change(
+                audit_log::IMPORT_SNAPSHOT,
+                KeyChange {
+                    key: "snapshot".into(),
+                    old: None,
+                    new: Some(id.to_string()),
+                },
+                &[],
+            )
+            .await?;
+        log::info!("Snapshot {id} imported from {path:?}");
+
+        self.prune_snapshots().await?;
+        Ok(ImportResult { snapshot_id: id })
+    }
+
+    /// Space used by the database and the snapshots, for deciding how many snapshots to keep
+    pub async fn storage_stats(&self) -> DResult<StorageStats> {
+        let database_size = self.state.db.size_bytes().await?;
+        // newest first
+        let snapshots = self.state.db.grub2_snapshots().await?;
+        let selected_id = self.selected_id().await?;
+        let latest_id = snapshots.first().map(|snapshot| snapshot.id);
+
+        let mut duplicate_bytes = 0;
+        let mut prunable_bytes = 0;
+        for (idx, snapshot) in snapshots.iter().enumerate() {
+            let size = snapshot.grub_config.len();
+            if snapshots[idx + 1..].iter().any(|older| {
+                older.grub_config == snapshot.grub_config
+                    && older.selected_kernel == snapshot.selected_kernel
+            }) {
+                duplicate_bytes += size;
+            }
+            if snapshot.id != selected_id && Some(snapshot.id) != latest_id && !snapshot.pinned {
+                prunable_bytes += size;
+            }
+        }
+
+        let mut largest_snapshots: Vec<SnapshotSize> = snapshots
+            .iter()
+            .map(|snapshot| SnapshotSize {
+                snapshot_id: snapshot.id,
+                created: snapshot.created,
+                size: snapshot.grub_config.len(),
+            })
+            .collect();
+        largest_snapshots.sort_by_key(|snapshot| Reverse(snapshot.size));
+        largest_snapshots.truncate(LARGEST_SNAPSHOT_COUNT);
+
+        Ok(StorageStats {
+            database_size,
+            snapshot_count: snapshots.len(),
+            snapshots_size: snapshots
+                .iter()
+                .map(|snapshot| snapshot.grub_config.len())
+                .sum(),
+            largest_snapshots,
+            duplicate_bytes,
+            prunable_bytes,
+            free_bytes: self.state.db.free_bytes().await?,
+        })
+    }
+
+    /// Id of the selected snapshot, 0 when the latest snapshot is used
+    pub async fn selected_snapshot_id(&self) -> DResult<i64> {
+        let selected = self.state.db.selected_snapshot().await?;
+        Ok(selected.grub2_snapshot_id.unwrap_or(0))
+    }
+
+    pub async fn retention_policy(&self) -> DResult<RetentionPolicy> {
+        let db = &self.state.db;
+        let limit = |value: Option<String>| value.and_then(|value| value.parse().ok());
+        Ok(RetentionPolicy {
+            keep_applied: limit(db.setting(settings::KEEP_APPLIED_SNAPSHOTS).await?),
+            keep_drafts: limit(db.setting(settings::KEEP_DRAFT_SNAPSHOTS).await?),
+            max_snapshots: self.state.max_snapshots,
+        })
+    }
+
+    /// Save the retention policy and remove the snapshots it doesn't keep
+    pub async fn set_retention_policy(&self, policy: RetentionPolicy) -> DResult<PruneResult> {
+        self.state.require_unfrozen().await?;
+        let db = &self.state.db;
+        let previous = self.retention_policy().await?;
+        db.set_setting(
+            settings::KEEP_APPLIED_SNAPSHOTS,
+            policy.keep_applied.map(|keep| keep.to_string()).as_deref(),
+        )
+        .await?;
+        db.set_setting(
+            settings::KEEP_DRAFT_SNAPSHOTS,
+            policy.keep_drafts.map(|keep| keep.to_string()).as_deref(),
+        )
+        .await?;
+        let changes = [
+            KeyChange {
+                key: settings::KEEP_APPLIED_SNAPSHOTS.into(),
+                old: previous.keep_applied.map(|keep| keep.to_string()),
+                new: policy.keep_applied.map(|keep| keep.to_string()),
+            },
+            KeyChange {
+                key: settings::KEEP_DRAFT_SNAPSHOTS.into(),
+                old: previous.keep_drafts.map(|keep| keep.to_string()),
+                new: policy.keep_drafts.map(|keep| keep.to_string()),
+            },
+        ];
+        self.state
+            .audit_key_changes(audit_log::SET_RETENTION_POLICY, &changes, &[])
+            .await?;
+
+        self.prune_snapshots().await
+    }
+
+    /// Remove the snapshots that the retention policy doesn't keep
+    pub async fn prune_snapshots(&self) -> DResult<PruneResult> {
+        self.state.require_unfrozen().await?;
+        let policy = self.retention_policy().await?;
+        let snapshots = self.state.db.grub2_snapshots().await?;
+        let removed = prunable_snapshots(&snapshots, self.selected_id().await?, &policy);
+        for id in &removed {
+            self.state.db.remove_grub2(*id).await?;
+        }
+
+        if !removed.is_empty() {
+            log::info!("Retention policy removed snapshots {removed:?}");
+            self.state.snapshot_changes.changed();
+            let changes: Vec<KeyChange> = removed.iter().map(|id| removed_snapshot(*id)).collect();
+            self.state
+                .audit_key_changes(audit_log::PRUNE_SNAPSHOTS, &changes, &[])
+                .await?;
+        }
+        Ok(PruneResult { removed })
+    }
+
+    /// Replace the user defined labels of a snapshot
+    pub async fn set_snapshot_labels(&self, labels_data: SnapshotLabelsData) -> DResult<()> {
+        self.state.require_unfrozen().await?;
+        let id = labels_data.snapshot_id;
+        let labels = valid_labels(&labels_data.labels)?;
+        // labels of removed snapshots would be left behind
+        self.state.db.grub2_snapshot(id).await?;
+        let previous: Vec<String> = self
+            .state
+            .db
+            .snapshot_labels()
+            .await?
+            .into_iter()
+            .filter(|row| row.snapshot_id == id)
+            .map(|row| row.label)
+            .collect();
+        if previous == labels {
+            return Ok(());
+        }
+
+        self.state.db.set_snapshot_labels(id, &labels).await?;
+        self.state.snapshot_changes.changed();
+        let joined = |labels: &[String]| (!labels.is_empty()).then(|| labels.join(", "));
+        self.state
+            .audit_key_change(
+                audit_log::SET_SNAPSHOT_LABELS,
+                KeyChange {
+                    key: format!("snapshot {id} labels"),
+                    old: joined(&previous),
+                    new: joined(&labels),
+                },
+                &[],
+            )
+            .await
+    }
+
+    /// Annotate the snapshot with why it was saved
+    pub async fn set_snapshot_description(
+        &self,
+        description_data: SnapshotDescriptionData,
+    ) -> DResult<()> {
+        self.state.require_unfrozen().await?;
+        let id = description_data.snapshot_id;
+        let description = valid_description(&description_data.description)?;
+
+        let previous = self.state.db.grub2_snapshot(id).await?;
+        if previous.description.as_deref() == description {
+            return Ok(());
+        }
+        self.state.db.set_grub2_description(id, description).await?;
+        self.state.snapshot_changes.changed();
+        self.state
+            .audit_key_change(
+                audit_log::SET_SNAPSHOT_DESCRIPTION,
+                KeyChange {
+                    key: format!("snapshot {id} description"),
+                    old: previous.description,
+                    new: description.map(str::to_string),
+                },
+                &[],
+            )
+            .await
+    }
+
+    /// Keep the snapshot from being removed by the retention policy, or let it be removed again
+    pub async fn set_snapshot_pinned(&self, pin_data: PinSnapshotData) -> DResult<()> {
+        self.state.require_unfrozen().await?;
+        let id = pin_data.snapshot_id;
+        let previous = self.state.db.grub2_snapshot(id).await?;
+        self.state.db.set_grub2_pinned(id, pin_data.pinned).await?;
+        self.state.snapshot_changes.changed();
+        let id = Some(id.to_string());
+        let (old, new) = if pin_data.pinned {
+            (None, id)
+        } else {
+            (id, None)
+        };
+        if previous.pinned != pin_data.pinned {
+            self.state
+                .audit_key_change(
+                    audit_log::SET_SNAPSHOT_PINNED,
+                    KeyChange {
+                        key: "pinned_snapshot".into(),
+                        old,
+                        new,
+                    },
+                    &[],
+                )
+                .await?;
+        }
+
+        // unpinning may leave more snapshots than the policy keeps
+        if !pin_data.pinned {
+            self.prune_snapshots().await?;
+        }
+        Ok(())
+    }
+
+    /// Keep the snapshot from being removed by the retention policy or RemoveSnapshot
+    pub async fn pin_snapshot(&self, id_data: SnapshotIdData) -> DResult<()> {
+        self.set_snapshot_pinned(PinSnapshotData {
+            snapshot_id: id_data.snapshot_id,
+            pinned: true,
+        })
+        .await
+    }
+
+    /// Let the snapshot be removed again, it may be pruned right away
+    pub async fn unpin_snapshot(&self, id_data: SnapshotIdData) -> DResult<()> {
+        self.set_snapshot_pinned(PinSnapshotData {
+            snapshot_id: id_data.snapshot_id,
+            pinned: false,
+        })
+        .await
+    }
+
+    /// The grub file parsed on the blocking thread pool
+    async fn read_grub_file(&self) -> DResult<GrubFile> {
+        let grub_path = self.state.paths.grub_file().to_owned();
+        run_blocking(move || {
+            GrubFile::from_file(&grub_path).ctx(dctx!(), "Failed to read grub file")
+        })
+        .await
+    }
+
+    /// Id of the selected snapshot, the latest snapshot if none is explicitly selected
+    async fn selected_id(&self) -> DResult<i64> {
+        let selected = self.state.db.selected_snapshot().await?;
+        if let Some(id) = selected.grub2_snapshot_id {
+            Ok(id)
+        } else {
+            Ok(self.state.db.latest_grub2().await?.id)
+        }
+    }
+
+    pub async fn remove_snapshot(&self, rm_data: RemoveSnapshotData) -> DResult<()> {
+        self.state.require_unfrozen().await?;
+        log::debug!("Trying to remove snapshot with id {}", rm_data.snapshot_id);
+
+        // Don't allow deleting the selected snapshot so things don't get confusing
+        if rm_data.snapshot_id == self.selected_id().await? {
+            return Err(DError::generic(
+                dctx!(),
+                "Cannot remove currently selected snapshot",
+            ));
+        }
+        if self
+            .state
+            .db
+            .grub2_snapshot(rm_data.snapshot_id)
+            .await?
+            .pinned
+        {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "Snapshot {} is pinned, unpin it before removing it",
+                    rm_data.snapshot_id
+                ),
+            ));
+        }
+
+        self.state.db.remove_grub2(rm_data.snapshot_id).await?;
+        self.state.snapshot_changes.changed();
+        // removing the latest snapshot changes what the config is diffed against
+        self.state.config_changes.changed();
+        self.state
+            .audit_key_change(
+                audit_log::REMOVE_SNAPSHOT,
+                removed_snapshot(rm_data.snapshot_id),
+                &[],
+            )
+            .await?;
+
+        log::debug!(
+            "Succesfully removed snapshot with id {}",
+            rm_data.snapshot_id
+        );
+        Ok(())
+    }
+
+    /// Roll back to the snapshot that was applied before the selected one
+    pub async fn revert_to_previous(&self) -> DResult<ApplyResult> {
+        let selected_id = self.selected_id().await?;
+        let snapshots = self.state.db.grub2_snapshots().await?;
+        let Some(previous) = previous_snapshot(&snapshots, selected_id) else {
+            return Err(DError::generic(
+                dctx!(),
+                format!("No applied snapshot before the selected snapshot {selected_id}"),
+            ));
+        };
+        log::info!(
+            "Reverting from snapshot {selected_id} to the previous snapshot {}",
+            previous.id
+        );
+
+        self.select_snapshot(SelectSnapshotData {
+            snapshot_id: previous.id,
+        })
+        .await
+    }
+
+    pub async fn select_snapshot(&self, select_data: SelectSnapshotData) -> DResult<ApplyResult> {
+        log::debug!(
+            "Trying to select snapshot with id {}",
+            select_data.snapshot_id
+        );
+
+        // Don't allow reselecting the selected snapshot so things don't get confusing
+        if select_data.snapshot_id == self.selected_id().await? {
+            return Err(DError::generic(
+                dctx!(),
+                "Cannot reselect currently selected snapshot",
+            ));
+        }
+
+        let snapshot = self
+            .state
+            .db
+            .grub2_snapshot(select_data.snapshot_id)
+            .await?;
+        let files = self.snapshot_files(&snapshot).await?;
+        // the written file is only known to be the daemon's once it's selected
+        let _in_flight = self.state.in_flight.start()?;
+        let previous = self.read_grub_file().await?;
+        let mut grub_file = GrubFile::new(&snapshot.grub_config)?;
+        let commands = self
+            .jobs
+            .set_grub_system(
+                &mut grub_file,
+                &snapshot.selected_kernel,
+                true,
+                files.as_ref(),
+                &ApplyOptions::default(),
+            )
+            .await?;
+        self.state
+            .audit_changes(audit_log::SELECT_SNAPSHOT, &previous, &grub_file, &commands)
+            .await?;
+        self.state
+            .db
+            .set_selected_snapshot(Some(select_data.snapshot_id))
+            .await?;
+        self.state
+            .db
+            .set_grub2_applied(select_data.snapshot_id)
+            .await?;
+        self.record_grub_cfg(select_data.snapshot_id).await?;
+        self.state.config_changes.changed();
+        self.state.snapshot_changes.changed();
+
+        log::debug!(
+            "Succesfully selected snapshot with id {}",
+            select_data.snapshot_id
+        );
+
+        Ok(ApplyResult::applied(commands))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+    use crate::{
+        config::Paths,
+        db::{Database, StorageKind},
+        restart::InFlight,
+        services::Services,
+    };
+
+    fn snapshot(id: i64, grub_config: &str) -> Grub2Snapshot {
+        Grub2Snapshot {
+            id,
+            grub_config: grub_config.into(),
+            selected_kernel: None,
+            applied: true,
+            created: NaiveDateTime::default(),
+            tag: None,
+            pinned: false,
+            description: None,
+            saved_entry: None,
+            files_captured: false,
+            grub_cfg_sha256: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_diffs_in_order() {
+        let current = read_to_string("test_data/grub_simple").unwrap();
+        let changed = current.replace("GRUB_TIMEOUT=8", "GRUB_TIMEOUT=3");
+        let snapshots: Vec<_> = (0..20)
+            .map(|id| snapshot(id, if id % 2 == 0 { &current } else { &changed }))
+            .collect();
+
+        let diffs = snapshot_diffs(current.clone(), &snapshots, DiffFormat::Unified)
+            .await
+            .unwrap();
+        assert_eq!(diffs.len(), snapshots.len());
+        for (id, diff) in diffs.iter().enumerate() {
+            if id % 2 == 0 {
+                assert_eq!(diff, &None);
+            } else {
+                let Some(ConfigDiff::Unified(diff)) = diff else {
+                    panic!("expected a unified diff");
+                };
+                assert!(diff.contains("+GRUB_TIMEOUT=3"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_prunable_snapshots() {
+        // newest first, odd ids are drafts
+        let snapshots: Vec<_> = (1..=8)
+            .rev()
+            .map(|id| Grub2Snapshot {
+                applied: id % 2 == 0,
+                ..snapshot(id, "GRUB_TIMEOUT=8")
+            })
+            .collect();
+
+        assert!(prunable_snapshots(&snapshots, 8, &RetentionPolicy::default()).is_empty());
+
+        let policy = RetentionPolicy {
+            keep_applied: Some(3),
+            keep_drafts: Some(1),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(prunable_snapshots(&snapshots, 8, &policy), vec![5, 3, 2, 1]);
+
+        // the selected and the latest snapshot are kept even over the limits
+        let policy = RetentionPolicy {
+            keep_applied: Some(0),
+            keep_drafts: Some(0),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(
+            prunable_snapshots(&snapshots, 3, &policy),
+            vec![7, 6, 5, 4, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_previous_snapshot() {
+        // newest first, 3 is a draft
+        let snapshots: Vec<_> = (1..=4)
+            .rev()
+            .map(|id| Grub2Snapshot {
+                applied: id != 3,
+                ..snapshot(id, "GRUB_TIMEOUT=8")
+            })
+            .collect();
+
+        let previous_id =
+            |selected_id| previous_snapshot(&snapshots, selected_id).map(|snapshot| snapshot.id);
+        assert_eq!(previous_id(4), Some(2));
+        assert_eq!(previous_id(2), Some(1));
+        assert_eq!(previous_id(1), None);
+    }
+
+    #[test]
+    fn test_snapshot_comparison() {
+        let old = "GRUB_TIMEOUT=8\nGRUB_DEFAULT=saved\n";
+        let new = "GRUB_TIMEOUT=3\nGRUB_DEFAULT=saved\n";
+        let comparison = snapshot_comparison(old, new);
+        assert!(comparison.diff.unwrap().contains("+GRUB_TIMEOUT=3"));
+        assert_eq!(
+            comparison.changed_keys,
+            vec![KeyChange {
+                key: "GRUB_TIMEOUT".into(),
+                old: Some("8".into()),
+                new: Some("3".into()),
+            }]
+        );
+
+        let same = snapshot_comparison(new, new);
+        assert_eq!(same.diff, None);
+        assert!(same.changed_keys.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_archive() {
+        let archive = SnapshotArchive {
+            version: SNAPSHOT_ARCHIVE_VERSION,
+            grub_config: "GRUB_TIMEOUT=8\n".into(),
+            selected_kernel: Some("openSUSE".into()),
+            created: NaiveDateTime::default(),
+            tag: None,
+            description: Some("known good".into()),
+            labels: vec!["stable".into()],
+        };
+        let contents = serde_json::to_string(&archive).unwrap();
+        let parsed = SnapshotArchive::parse(&contents).unwrap();
+        assert_eq!(parsed.grub_config, archive.grub_config);
+        assert_eq!(parsed.labels, archive.labels);
+
+        let newer = contents.replace(
+            &format!("\"version\":{SNAPSHOT_ARCHIVE_VERSION}"),
+            "\"version\":999",
+        );
+        assert!(SnapshotArchive::parse(&newer).is_err());
+        assert!(SnapshotArchive::parse("{}").is_err());
+    }
+
+    #[test]
+    fn test_valid_labels() {
+        let labels = [
+            "stable ".into(),
+            "before-nvidia-driver".into(),
+            "stable".into(),
+        ];
+        assert_eq!(
+            valid_labels(&labels).unwrap(),
+            vec!["before-nvidia-driver", "stable"]
+        );
+        assert!(valid_labels(&[" ".into()]).is_err());
+        assert!(valid_labels(&["a".repeat(MAX_LABEL_LENGTH + 1)]).is_err());
+        assert!(valid_labels(&["new\nline".into()]).is_err());
+    }
+
+    #[test]
+    fn test_max_snapshots() {
+        // newest first, the oldest one is pinned
+        let snapshots: Vec<_> = (1..=8)
+            .rev()
+            .map(|id| Grub2Snapshot {
+                pinned: id == 1,
+                ..snapshot(id, "GRUB_TIMEOUT=8")
+            })
+            .collect();
+
+        let policy = RetentionPolicy {
+            max_snapshots: Some(3),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(
+            prunable_snapshots(&snapshots, 8, &policy),
+            vec![2, 3, 4, 5, 6]
+        );
+
+        // pinned snapshots are kept over the per kind limits as well
+        let policy = RetentionPolicy {
+            keep_applied: Some(2),
+            max_snapshots: Some(4),
+            ..RetentionPolicy::default()
+        };
+        assert_eq!(
+            prunable_snapshots(&snapshots, 8, &policy),
+            vec![6, 5, 4, 3, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_storage_stats_in_memory() {
+        let root = std::env::temp_dir().join(format!("bootkit-stats-{}", std::process::id()));
+        let paths = Paths::with_root(&root);
+        let db = Database::new(&paths, StorageKind::Memory).await.unwrap();
+        db.migrate().await.unwrap();
+        let grub = GrubFile::new("GRUB_TIMEOUT=8\n").unwrap();
+        for _ in 0..3 {
+            db.save_grub2(&grub, None, true, None).await.unwrap();
+        }
+
+        let services = Services::new(db, paths, InFlight::default(), None);
+        let stats = services.snapshots.storage_stats().await.unwrap();
+        // nothing is written to disk
+        assert_eq!(stats.database_size, 0);
+        assert!(!root.exists());
+        assert_eq!(stats.snapshot_count, 3);
+        assert_eq!(stats.snapshots_size, 3 * "GRUB_TIMEOUT=8\n".len());
+        assert_eq!(stats.duplicate_bytes, 2 * "GRUB_TIMEOUT=8\n".len());
+        assert_eq!(stats.prunable_bytes, 2 * "GRUB_TIMEOUT=8\n".len());
+
+        // pinned snapshots can't be reclaimed
+        services
+            .snapshots
+            .pin_snapshot(SnapshotIdData { snapshot_id: 1 })
+            .await
+            .unwrap();
+        let stats = services.snapshots.storage_stats().await.unwrap();
+        assert_eq!(stats.prunable_bytes, "GRUB_TIMEOUT=8\n".len());
+    }
+}
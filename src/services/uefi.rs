@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::audit_log,
+    dctx,
+    errors::{DError, DResult},
+    firmware::{EfiBootManager, FirmwareMode},
+    grub2::diff::KeyChange,
+    services::{
+        job::{run_command, ApplyResult},
+        AppState,
+    },
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BootOrderData {
+    /// Boot option numbers in the order the firmware tries them, like `["0001", "0000"]`.
+    /// Options that are left out are not tried at all.
+    order: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BootEntryActiveData {
+    /// Boot option number, like `0001`
+    entry: String,
+    /// Inactive options stay in the boot order but are skipped when booting
+    active: bool,
+}
+
+/// Boot options of the firmware boot manager of the host, changed with efibootmgr
+#[derive(Clone)]
+pub struct UefiService {
+    state: AppState,
+}
+
+impl UefiService {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+
+    fn require_uefi(&self) -> DResult<()> {
+        if FirmwareMode::detect() != FirmwareMode::Uefi {
+            return Err(DError::generic(
+                dctx!(),
+                "System was not booted with UEFI firmware",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Common checks of the changes made with efibootmgr
+    async fn require_boot_changes(&self) -> DResult<()> {
+        self.require_uefi()?;
+        self.state.require_tools(&["efibootmgr"])?;
+        self.state.require_unfrozen().await
+    }
+
+    pub fn boot_entries(&self) -> DResult<EfiBootManager> {
+        self.require_uefi()?;
+        Ok(EfiBootManager::read())
+    }
+
+    /// Replace the firmware boot order
+    pub async fn set_boot_order(&self, order_data: BootOrderData) -> DResult<ApplyResult> {
+        self.require_boot_changes().await?;
+        let manager = EfiBootManager::read();
+        let order: Vec<String> = order_data
+            .order
+            .iter()
+            .map(|number| number.to_uppercase())
+            .collect();
+        if order.is_empty() {
+            return Err(DError::generic(dctx!(), "Boot order cannot be empty"));
+        }
+        for (index, number) in order.iter().enumerate() {
+            if manager.entry(number).is_none() {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Boot entry '{number}' is not found from the firmware"),
+                ));
+            }
+            if order[..index].contains(number) {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("Boot entry '{number}' is in the boot order more than once"),
+                ));
+            }
+        }
+
+        let _in_flight = self.state.in_flight.start()?;
+        let mut commands = Vec::new();
+        let mut efibootmgr = self.state.paths.command("efibootmgr");
+        efibootmgr.arg("--bootorder").arg(order.join(","));
+        run_command(efibootmgr, &mut commands).map_err(|err| err.with_commands(&commands))?;
+
+        let change = KeyChange {
+            key: "BootOrder".into(),
+            old: Some(manager.boot_order.join(",")),
+            new: Some(order.join(",")),
+        };
+        self.state
+            .audit_key_change(audit_log::SET_BOOT_ORDER, change, &commands)
+            .await?;
+        Ok(ApplyResult::applied(commands))
+    }
+
+    /// Enable or disable a boot option without removing it
+    pub async fn set_boot_entry_active(
+        &self,
+        active_data: BootEntryActiveData,
+    ) -> DResult<ApplyResult> {
+        self.require_boot_changes().await?;
+        let manager = EfiBootManager::read();
+        let number = active_data.entry.to_uppercase();
+        let Some(entry) = manager.entry(&number) else {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Boot entry '{number}' is not found from the firmware"),
+            ));
+        };
+
+        let _in_flight = self.state.in_flight.start()?;
+        let mut commands = Vec::new();
+        let mut efibootmgr = self.state.paths.command("efibootmgr");
+        efibootmgr
+            .arg("--bootnum")
+            .arg(&number)
+            .arg(if active_data.active {
+                "--active"
+            } else {
+                "--inactive"
+            });
+        run_command(efibootmgr, &mut commands).map_err(|err| err.with_commands(&commands))?;
+
+        let state_name = |active: bool| if active { "active" } else { "inactive" };
+        let change = KeyChange {
+            key: format!("Boot{number}"),
+            old: Some(state_name(entry.active).into()),
+            new: Some(state_name(active_data.active).into()),
+        };
+        self.state
+            .audit_key_change(audit_log::SET_BOOT_ENTRY_ACTIVE, change, &commands)
+            .await?;
+        Ok(ApplyResult::applied(commands))
+    }
+}
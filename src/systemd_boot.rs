@@ -0,0 +1,448 @@
+//! systemd-boot configuration, `loader/loader.conf` and the Boot Loader
+//! Specification entries in `loader/entries/*.conf`.
+//!
+//! systemd-boot has no generated config like grub.cfg. The default entry and the
+//! menu timeout are set with `bootctl`, which stores them in EFI variables that
+//! take precedence over loader.conf.
+//!
+//! See loader.conf(5) and <https://uapi-group.org/specifications/specs/boot_loader_specification/>
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs::{read_dir, read_to_string},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::Paths,
+    dctx,
+    errors::{DError, DRes, DResult},
+    firmware::efi_string_var,
+    grub2::{
+        kernel::{compare_versions, flavor, is_microcode_image, version_from_image},
+        EntryKind,
+    },
+};
+
+/// Vendor GUID of the EFI variables of systemd-boot
+const LOADER_GUID: &str = "4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+
+/// Kernel parameters that boot into a rescue shell
+const RECOVERY_PARAMS: &[&str] = &[
+    "single",
+    "emergency",
+    "systemd.unit=rescue.target",
+    "systemd.unit=emergency.target",
+];
+
+/// `key value` lines of loader.conf and the entry files, comments and empty lines
+/// are skipped
+fn key_values(contents: &str) -> impl Iterator<Item = (&str, &str)> {
+    contents.lines().filter_map(|line| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        match line.split_once(char::is_whitespace) {
+            Some((key, value)) => Some((key, value.trim())),
+            None => Some((line, "")),
+        }
+    })
+}
+
+/// Match `text` against a glob `pattern` with `*` and `?`, as used by the
+/// `default` of loader.conf
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // index in the pattern after the latest `*`, and the text index it was matched at
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // let the latest `*` match one more character
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Settings stored in EFI variables by `bootctl`, only on the host
+#[derive(Debug, Clone, Default)]
+pub struct LoaderEfiVars {
+    /// Default entry id, set with `bootctl set-default`
+    pub default: Option<String>,
+    /// Entry id for the next boot only, set with `bootctl set-oneshot`
+    pub oneshot: Option<String>,
+    /// Menu timeout, set with `bootctl set-timeout`
+    pub timeout: Option<String>,
+}
+
+impl LoaderEfiVars {
+    pub fn read() -> Self {
+        let var = |name: &str| efi_string_var(&format!("{name}-{LOADER_GUID}"));
+        Self {
+            default: var("LoaderEntryDefault"),
+            oneshot: var("LoaderEntryOneShot"),
+            timeout: var("LoaderConfigTimeout"),
+        }
+    }
+}
+
+/// Boot entry of a `loader/entries/*.conf` file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LoaderEntry {
+    /// File name of the entry, like `6.17.5-1-default.conf`, used by bootctl
+    /// and loader.conf to refer to the entry
+    pub id: String,
+    pub title: Option<String>,
+    pub version: Option<String>,
+    pub sort_key: Option<String>,
+    /// Kernel image, relative to the partition of the entry
+    pub linux: Option<String>,
+    pub initrds: Vec<String>,
+    /// Kernel command line, joined from all the `options` lines
+    pub options: Option<String>,
+    /// EFI program started instead of a kernel
+    pub efi: Option<String>,
+}
+
+impl LoaderEntry {
+    pub fn parse(id: &str, contents: &str) -> Self {
+        let mut entry = Self {
+            id: id.into(),
+            title: None,
+            version: None,
+            sort_key: None,
+            linux: None,
+            initrds: Vec::new(),
+            options: None,
+            efi: None,
+        };
+
+        for (key, value) in key_values(contents) {
+            match key {
+                "title" => entry.title = Some(value.into()),
+                "version" => entry.version = Some(value.into()),
+                "sort-key" => entry.sort_key = Some(value.into()),
+                "linux" => entry.linux = Some(value.into()),
+                "initrd" => entry.initrds.push(value.into()),
+                "options" => {
+                    entry.options = Some(match entry.options.take() {
+                        Some(options) => format!("{options} {value}"),
+                        None => value.into(),
+                    })
+                }
+                "efi" => entry.efi = Some(value.into()),
+                _ => {}
+            }
+        }
+
+        entry
+    }
+
+    /// Name shown in the menu, systemd-boot falls back to the id without a title
+    pub fn display_title(&self) -> &str {
+        self.title
+            .as_deref()
+            .unwrap_or_else(|| self.id.trim_end_matches(".conf"))
+    }
+
+    pub fn kernel_version(&self) -> Option<&str> {
+        self.version
+            .as_deref()
+            .or_else(|| version_from_image(self.linux.as_deref()?))
+    }
+
+    pub fn flavor(&self) -> Option<&str> {
+        flavor(self.kernel_version()?)
+    }
+
+    pub fn has_microcode(&self) -> bool {
+        self.initrds.iter().any(|initrd| is_microcode_image(initrd))
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        if self.linux.is_some() {
+            let options = self.options.as_deref().unwrap_or_default();
+            if options
+                .split_whitespace()
+                .any(|param| RECOVERY_PARAMS.contains(&param))
+            {
+                EntryKind::Recovery
+            } else {
+                EntryKind::Linux
+            }
+        } else if self.efi.is_some() {
+            EntryKind::ForeignOs
+        } else {
+            EntryKind::Other
+        }
+    }
+
+    /// Does the entry id match the `default` pattern of loader.conf or bootctl,
+    /// which may leave out the `.conf` suffix
+    fn matches(&self, pattern: &str) -> bool {
+        glob_match(pattern, &self.id) || glob_match(pattern, self.id.trim_end_matches(".conf"))
+    }
+}
+
+/// Menu order of systemd-boot: entries with a sort key first, ordered by it and
+/// then newest version first, the others by their id, newest version first
+pub fn menu_order(a: &LoaderEntry, b: &LoaderEntry) -> Ordering {
+    let version = |entry: &LoaderEntry| entry.version.clone().unwrap_or_default();
+    match (&a.sort_key, &b.sort_key) {
+        (Some(a_key), Some(b_key)) => a_key
+            .cmp(b_key)
+            .then_with(|| compare_versions(&version(b), &version(a))),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => compare_versions(&b.id, &a.id),
+    }
+}
+
+/// Parse the `*.conf` entry files in `entries_dir`, in no particular order
+pub fn read_entries(entries_dir: &Path) -> DResult<Vec<LoaderEntry>> {
+    log::debug!("Reading BLS entries from {entries_dir:?}");
+    let mut entries = Vec::new();
+    let dir = read_dir(entries_dir).ctx(dctx!(), format!("Cannot list {entries_dir:?}"))?;
+    for file in dir {
+        let path = file
+            .ctx(dctx!(), format!("Cannot list {entries_dir:?}"))?
+            .path();
+        let Some(id) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !id.ends_with(".conf") {
+            continue;
+        }
+        let contents = read_to_string(&path).ctx(dctx!(), format!("Cannot read {path:?}"))?;
+        entries.push(LoaderEntry::parse(id, &contents));
+    }
+
+    Ok(entries)
+}
+
+/// Boot entries of systemd-boot, in menu order
+#[derive(Debug, Clone)]
+pub struct SystemdBootEntries {
+    entries: Vec<LoaderEntry>,
+    /// Index of the default entry
+    selected: Option<usize>,
+    /// Index of the entry for the next boot only
+    next: Option<usize>,
+    /// Menu timeout, from bootctl or loader.conf
+    timeout: Option<String>,
+    /// Settings of loader.conf
+    config: BTreeMap<String, String>,
+}
+
+impl SystemdBootEntries {
+    pub fn new(paths: &Paths) -> DResult<Self> {
+        let loader_dirs = paths.loader_dirs();
+        if loader_dirs.is_empty() {
+            return Err(DError::generic(
+                dctx!(),
+                "No systemd-boot loader directory found on the EFI system partition",
+            ));
+        }
+
+        let mut loader_conf = String::new();
+        let mut entries = Vec::new();
+        for loader_dir in &loader_dirs {
+            let conf_path = loader_dir.join("loader.conf");
+            if loader_conf.is_empty() && conf_path.exists() {
+                log::debug!("Reading systemd-boot config from {conf_path:?}");
+                loader_conf = read_to_string(&conf_path)
+                    .ctx(dctx!(), format!("Cannot read {conf_path:?}"))?;
+            }
+
+            let entries_dir = loader_dir.join("entries");
+            if entries_dir.is_dir() {
+                entries.extend(read_entries(&entries_dir)?);
+            }
+        }
+
+        // EFI variables only describe the firmware of the host
+        let efi_vars = if paths.is_host() {
+            LoaderEfiVars::read()
+        } else {
+            LoaderEfiVars::default()
+        };
+        Ok(Self::from_entries(entries, &loader_conf, efi_vars))
+    }
+
+    fn from_entries(
+        mut entries: Vec<LoaderEntry>,
+        loader_conf: &str,
+        efi_vars: LoaderEfiVars,
+    ) -> Self {
+        entries.sort_by(menu_order);
+        let config: BTreeMap<_, _> = key_values(loader_conf)
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let find = |pattern: &str| entries.iter().position(|entry| entry.matches(pattern));
+
+        let conf_default = config
+            .get("default")
+            .map(String::as_str)
+            // the last booted entry is only known by systemd-boot itself
+            .filter(|default| *default != "@saved");
+        let selected = efi_vars
+            .default
+            .as_deref()
+            .and_then(find)
+            .or_else(|| conf_default.and_then(find));
+        let next = efi_vars.oneshot.as_deref().and_then(find);
+        let timeout = efi_vars.timeout.or_else(|| config.get("timeout").cloned());
+
+        Self {
+            entries,
+            selected,
+            next,
+            timeout,
+            config,
+        }
+    }
+
+    pub fn entries(&self) -> &[LoaderEntry] {
+        &self.entries
+    }
+
+    /// Explicitly selected default entry, the first entry is booted without one
+    pub fn selected_entry(&self) -> Option<&LoaderEntry> {
+        self.entries.get(self.selected?)
+    }
+
+    pub fn next_entry(&self) -> Option<&LoaderEntry> {
+        self.entries.get(self.next?)
+    }
+
+    pub fn timeout(&self) -> Option<&str> {
+        self.timeout.as_deref()
+    }
+
+    /// Settings of loader.conf, without the ones overridden by bootctl
+    pub fn config(&self) -> &BTreeMap<String, String> {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, contents: &str) -> LoaderEntry {
+        LoaderEntry::parse(id, contents)
+    }
+
+    fn entries() -> Vec<LoaderEntry> {
+        vec![
+            entry(
+                "6.16.1-1-default.conf",
+                "title openSUSE MicroOS\nversion 6.16.1-1-default\nlinux /vmlinuz-6.16.1-1-default\ninitrd /initrd-6.16.1-1-default",
+            ),
+            entry(
+                "6.17.5-1-default.conf",
+                "# comment\ntitle openSUSE MicroOS\nversion 6.17.5-1-default\nlinux /vmlinuz-6.17.5-1-default\ninitrd /amd-ucode.img\ninitrd /initrd-6.17.5-1-default\noptions root=UUID=1234\noptions quiet",
+            ),
+            entry(
+                "windows.conf",
+                "title Windows\nefi /EFI/Microsoft/Boot/bootmgfw.efi",
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        let entries = entries();
+        let entry = &entries[1];
+        assert_eq!(entry.display_title(), "openSUSE MicroOS");
+        assert_eq!(entry.kernel_version(), Some("6.17.5-1-default"));
+        assert_eq!(entry.flavor(), Some("default"));
+        assert_eq!(entry.options.as_deref(), Some("root=UUID=1234 quiet"));
+        assert!(entry.has_microcode());
+        assert_eq!(entry.kind(), EntryKind::Linux);
+        assert_eq!(entries[2].kind(), EntryKind::ForeignOs);
+
+        let untitled = LoaderEntry::parse(
+            "rescue.conf",
+            "linux /vmlinuz-6.17.5-1-default\noptions single",
+        );
+        assert_eq!(untitled.display_title(), "rescue");
+        assert_eq!(untitled.kernel_version(), Some("6.17.5-1-default"));
+        assert_eq!(untitled.kind(), EntryKind::Recovery);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("windows.conf", "windows.conf"));
+        assert!(glob_match("6.17*", "6.17.5-1-default"));
+        assert!(glob_match("*-default.conf", "6.17.5-1-default.conf"));
+        assert!(glob_match("6.1?.5*", "6.17.5-1-default"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("6.16*", "6.17.5-1-default"));
+        assert!(!glob_match("windows", "windows.conf"));
+    }
+
+    #[test]
+    fn test_menu_order_and_default() {
+        let loader_conf = "timeout 3\ndefault windows\n";
+        let sd_entries =
+            SystemdBootEntries::from_entries(entries(), loader_conf, LoaderEfiVars::default());
+        let ids: Vec<_> = sd_entries
+            .entries()
+            .iter()
+            .map(|entry| entry.id.as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                "6.17.5-1-default.conf",
+                "6.16.1-1-default.conf",
+                "windows.conf"
+            ]
+        );
+        assert_eq!(sd_entries.selected_entry().unwrap().id, "windows.conf");
+        assert_eq!(sd_entries.timeout(), Some("3"));
+        assert!(sd_entries.next_entry().is_none());
+
+        // bootctl settings take precedence over loader.conf
+        let efi_vars = LoaderEfiVars {
+            default: Some("6.16.1-1-default.conf".into()),
+            oneshot: Some("6.17*".into()),
+            timeout: Some("menu-force".into()),
+        };
+        let sd_entries = SystemdBootEntries::from_entries(entries(), loader_conf, efi_vars);
+        assert_eq!(
+            sd_entries.selected_entry().unwrap().id,
+            "6.16.1-1-default.conf"
+        );
+        assert_eq!(sd_entries.next_entry().unwrap().id, "6.17.5-1-default.conf");
+        assert_eq!(sd_entries.timeout(), Some("menu-force"));
+
+        let saved =
+            SystemdBootEntries::from_entries(entries(), "default @saved", LoaderEfiVars::default());
+        assert!(saved.selected_entry().is_none());
+    }
+}
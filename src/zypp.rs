@@ -0,0 +1,113 @@
+//! Kernel package installs and removals, parsed from the libzypp history log.
+//!
+//! Kernel updates add and remove boot entries, so they explain most of the boot
+//! menu changes that weren't made through the daemon.
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+/// Packages named `kernel-*` that don't contain a bootable kernel
+const NON_KERNEL_PACKAGES: &[&str] = &["devel", "docs", "firmware", "macros", "source", "syms"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageAction {
+    Install,
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KernelPackageEvent {
+    /// Time of the transaction, the history has local times
+    pub time: NaiveDateTime,
+    pub action: PackageAction,
+    /// Package name, like `kernel-default`
+    pub package: String,
+    pub version: String,
+    pub arch: String,
+}
+
+/// Is `package` a kernel image package, like `kernel-default` or `kernel-default-base`
+fn is_kernel_package(package: &str) -> bool {
+    let Some(flavor) = package.strip_prefix("kernel-") else {
+        return false;
+    };
+    let flavor = flavor.strip_suffix("-base").unwrap_or(flavor);
+    !flavor.is_empty() && !flavor.contains('-') && !NON_KERNEL_PACKAGES.contains(&flavor)
+}
+
+/// Parse the kernel package events of a `/var/log/zypp/history` file, oldest first
+pub fn kernel_events(history: &str) -> Vec<KernelPackageEvent> {
+    let mut events = Vec::new();
+    for line in history.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+
+        // time|action|name|version|arch|...
+        let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+        let [time, action, package, version, arch, ..] = fields[..] else {
+            continue;
+        };
+        let action = match action {
+            "install" => PackageAction::Install,
+            "remove" => PackageAction::Remove,
+            _ => continue,
+        };
+        if !is_kernel_package(package) {
+            continue;
+        }
+        let Ok(time) = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S") else {
+            log::debug!("Skipping zypp history line with malformed time: {line}");
+            continue;
+        };
+
+        events.push(KernelPackageEvent {
+            time,
+            action,
+            package: package.into(),
+            version: version.into(),
+            arch: arch.into(),
+        });
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HISTORY: &str = "\
+# 2025-11-04 10:00:00 kernel-default-6.17.5-1.1.x86_64.rpm installed ok
+# Additional rpm output:
+2025-11-04 10:00:00|command|root@host|'zypper' 'dup'|
+2025-11-04 10:00:05|install|kernel-default|6.17.5-1.1|x86_64|root@host|repo-oss|0123abcd|
+2025-11-04 10:00:06|install|kernel-firmware-intel|20251021-1.1|noarch|root@host|repo-oss|0123abcd|
+2025-11-04 10:00:07|install|kernel-default-devel|6.17.5-1.1|x86_64|root@host|repo-oss|0123abcd|
+2025-11-04 10:01:00|remove |kernel-default|6.16.1-1.1|x86_64|root@host|
+2025-11-04 10:02:00|install|kernel-rt-base|6.17.5-1.1|x86_64|root@host|repo-rt|0123abcd|
+";
+
+    #[test]
+    fn test_kernel_events() {
+        let events = kernel_events(HISTORY);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].action, PackageAction::Install);
+        assert_eq!(events[0].package, "kernel-default");
+        assert_eq!(events[0].version, "6.17.5-1.1");
+        assert_eq!(events[1].action, PackageAction::Remove);
+        assert_eq!(events[1].version, "6.16.1-1.1");
+        assert_eq!(events[2].package, "kernel-rt-base");
+    }
+
+    #[test]
+    fn test_is_kernel_package() {
+        assert!(is_kernel_package("kernel-default"));
+        assert!(is_kernel_package("kernel-64kb"));
+        assert!(!is_kernel_package("kernel-source"));
+        assert!(!is_kernel_package("kernel-default-devel"));
+        assert!(!is_kernel_package("kernel-firmware-intel"));
+        assert!(!is_kernel_package("grub2"));
+    }
+}